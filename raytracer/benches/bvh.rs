@@ -0,0 +1,50 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use raytracer::hit::Hittable;
+use raytracer::{scene_file, static_scene, Ray, Vec3, BVH::BvhNode};
+
+const SCENE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/../data/random_spheres.json");
+
+// A fixed fan of primary rays through the camera of random_spheres.json.
+fn rays() -> Vec<Ray> {
+    let lookfrom = Vec3::new(13.0, 2.0, 3.0);
+    let (nx, ny) = (64, 36);
+    let mut rays = Vec::with_capacity(nx * ny);
+    for j in 0..ny {
+        for i in 0..nx {
+            let target = Vec3::new(
+                0.0,
+                2.0 - 4.0 * j as f64 / ny as f64,
+                -4.0 + 8.0 * i as f64 / nx as f64,
+            );
+            rays.push(Ray::new(lookfrom, target - lookfrom, 0.0));
+        }
+    }
+    rays
+}
+
+fn trace<T: Hittable>(world: &T, rays: &[Ray]) -> usize {
+    rays.iter()
+        .filter(|r| world.hit(**r, 0.001, f64::INFINITY).is_some())
+        .count()
+}
+
+fn bvh(c: &mut Criterion) {
+    let rays = rays();
+    let static_world = static_scene!("../data/random_spheres.json");
+    let scene = scene_file::load(SCENE).expect("failed to load the scene");
+    let runtime_world = BvhNode::new(scene.world, 0.0, 1.0);
+    // both trees must agree before their speed is worth comparing
+    assert_eq!(trace(&static_world, &rays), trace(&runtime_world, &rays));
+
+    let mut group = c.benchmark_group("bvh");
+    group.bench_function("static", |b| {
+        b.iter(|| trace(black_box(&static_world), black_box(&rays)))
+    });
+    group.bench_function("runtime", |b| {
+        b.iter(|| trace(black_box(&runtime_world), black_box(&rays)))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bvh);
+criterion_main!(benches);
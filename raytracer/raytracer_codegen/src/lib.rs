@@ -0,0 +1,46 @@
+extern crate proc_macro;
+
+mod scene_gen;
+#[allow(dead_code, clippy::all, unused_parens, while_true)]
+mod vec3;
+
+use proc_macro::TokenStream;
+use quote::quote;
+use std::fs;
+use std::path::Path;
+use syn::{parse_macro_input, LitStr};
+
+// static_scene!("path/to/scene.json") expands to the scene's objects as a statically
+// dispatched BVH, numbered for the id pass as the runtime loader numbers them; its camera,
+// background and lights are left to the caller. The path is relative to the manifest of the
+// crate using the macro.
+#[proc_macro]
+pub fn static_scene(input: TokenStream) -> TokenStream {
+    let lit = parse_macro_input!(input as LitStr);
+    let manifest_dir = std::env::var("CARGO_MANIFEST_DIR").unwrap_or_default();
+    let filename = Path::new(&manifest_dir).join(lit.value());
+    let result =
+        load(&filename.to_string_lossy()).and_then(|desc| scene_gen::world(&desc, &filename));
+    match result {
+        Ok(world) => {
+            // rebuild whenever the scene file changes
+            let filename = filename.to_string_lossy().to_string();
+            quote!({
+                const _: &str = include_str!(#filename);
+                // numbers worked out from the scene, whatever they happen to come close to
+                #[allow(clippy::approx_constant)]
+                let world = #world;
+                world
+            })
+            .into()
+        }
+        Err(msg) => syn::Error::new(lit.span(), msg).to_compile_error().into(),
+    }
+}
+
+// The scene file's description, in the format `raytracer_scene` shares with the runtime loader.
+fn load(filename: &str) -> Result<raytracer_scene::SceneDesc, String> {
+    let text = fs::read_to_string(filename).map_err(|e| format!("{}: {}", filename, e))?;
+    raytracer_scene::parse(&text)
+        .map_err(|(line, column, msg)| format!("{}:{}:{}: {}", filename, line, column, msg))
+}
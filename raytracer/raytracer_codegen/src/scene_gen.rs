@@ -1,26 +1,43 @@
-use proc_macro2::TokenStream;
-use quote::quote;
-use rand::{rngs::SmallRng , Rng , SeedableRng};
 use crate::vec3::*;
-struct Object{
-    pub bounding_box_min:Vec3,
-    pub code:TokenStream,
-}
-
-fn bvh_build(objects:& mut Vec<Object>) -> TokenStream{
-    let axis = rand::thread_rng().gen_range(0 , 3);
-    match axis {
-        0 => objects.sort_by(|a , b|{
-            a.bounding_box_min.x.partial_cmp(&b.bounding_box_min.x).unwrap()
-        }),
-        1 => objects.sort_by(|a , b|{
-            a.bounding_box_min.y.partial_cmp(&b.bounding_box_min.y).unwrap()
-        }),
-        2 => objects.sort_by(|a , b|{
-                        a.bounding_box_min.z.partial_cmp(&b.bounding_box_min.z).unwrap()
-        }),
-        _ => panic!("axis error"),
+use proc_macro2::{Literal, TokenStream};
+use quote::{format_ident, quote};
+use raytracer_scene::{
+    grid, Color, DensityDesc, DispersionDesc, MaterialDesc, ObjectDesc, Param, SceneDesc,
+    TextureDesc,
+};
+use std::cell::Cell;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+pub struct Object {
+    pub bounding_box_min: Vec3,
+    pub bounding_box_max: Vec3,
+    pub code: TokenStream,
+}
+
+fn bvh_build(objects: &mut Vec<Object>) -> TokenStream {
+    // split along the longest axis of the centroids so the same scene always gives the same tree
+    let mut min = Vec3::new(f64::INFINITY, f64::INFINITY, f64::INFINITY);
+    let mut max = Vec3::new(-f64::INFINITY, -f64::INFINITY, -f64::INFINITY);
+    for object in objects.iter() {
+        let c = (object.bounding_box_min + object.bounding_box_max) / 2.0;
+        min = Vec3::new(min.x.min(c.x), min.y.min(c.y), min.z.min(c.z));
+        max = Vec3::new(max.x.max(c.x), max.y.max(c.y), max.z.max(c.z));
+    }
+    let extent = max - min;
+    let axis = if extent.x >= extent.y && extent.x >= extent.z {
+        0
+    } else if extent.y >= extent.z {
+        1
+    } else {
+        2
     };
+    objects.sort_by(|a, b| {
+        let ca = a.bounding_box_min.get(axis) + a.bounding_box_max.get(axis);
+        let cb = b.bounding_box_min.get(axis) + b.bounding_box_max.get(axis);
+        ca.partial_cmp(&cb).unwrap()
+    });
+
     let len = objects.len();
     if len == 1 {
         let tmp = objects.remove(0);
@@ -28,12 +45,714 @@ fn bvh_build(objects:& mut Vec<Object>) -> TokenStream{
         quote! {
             #code
         }
-    }else {
-        let mut objects2 = objects.split_off(objects.len() / 2);
+    } else {
+        // the node's box is known here already, the renderer needn't ask the children
+        let (mut min, mut max) = (objects[0].bounding_box_min, objects[0].bounding_box_max);
+        for object in objects.iter() {
+            min = surrounding_min(min, object.bounding_box_min);
+            max = surrounding_max(max, object.bounding_box_max);
+        }
+        let mut objects2 = objects.split_off(len / 2);
         let left = bvh_build(objects);
         let right = bvh_build(&mut objects2);
+        let (min, max) = (vec3([min.x, min.y, min.z]), vec3([max.x, max.y, max.z]));
         quote! {
-            Box::new(BvhNodeStatic::new(#left , #right , 0.0 , 1.0))
+            ::raytracer::BVH::BvhNodeStatic::new(
+                #left,
+                #right,
+                ::raytracer::AABB::Aabb::new(#min, #max),
+            )
+        }
+    }
+}
+
+// Fails for an object without a finite bounding box, which no BVH node could hold.
+fn bounded(object: Object, path: &str) -> Result<Object, String> {
+    let (min, max) = (object.bounding_box_min, object.bounding_box_max);
+    if [min.x, min.y, min.z, max.x, max.y, max.z]
+        .iter()
+        .all(|v| v.is_finite())
+    {
+        Ok(object)
+    } else {
+        Err(format!("{}: the object has no finite bounding box", path))
+    }
+}
+
+fn float(v: f64) -> TokenStream {
+    let lit = Literal::f64_suffixed(v.abs());
+    if v < 0.0 {
+        quote! { (-#lit) }
+    } else {
+        quote! { #lit }
+    }
+}
+
+fn vec3(v: Color) -> TokenStream {
+    let (x, y, z) = (float(v[0]), float(v[1]), float(v[2]));
+    quote! { ::raytracer::Vec3::new(#x, #y, #z) }
+}
+
+enum Index {
+    Constant(TokenStream),
+    Dispersive(TokenStream),
+}
+
+fn index_of_refraction(
+    ior: &Option<f64>,
+    dispersion: &Option<DispersionDesc>,
+    path: &str,
+) -> Result<Index, String> {
+    let floats = |v: &[f64; 3]| {
+        let (x, y, z) = (float(v[0]), float(v[1]), float(v[2]));
+        quote! { [#x, #y, #z] }
+    };
+    Ok(match (ior, dispersion) {
+        (Some(ior), None) => Index::Constant(float(*ior)),
+        (None, Some(DispersionDesc::Cauchy { a, b })) => {
+            let (a, b) = (float(*a), float(*b));
+            Index::Dispersive(quote! { ::raytracer::spectrum::Dispersion::Cauchy { a: #a, b: #b } })
+        }
+        (None, Some(DispersionDesc::Sellmeier { b, c })) => {
+            let (b, c) = (floats(b), floats(c));
+            Index::Dispersive(quote! {
+                ::raytracer::spectrum::Dispersion::Sellmeier { b: #b, c: #c }
+            })
+        }
+        (Some(_), Some(_)) => {
+            return Err(format!(
+                "{}: `ior` and `dispersion` are mutually exclusive",
+                path
+            ))
+        }
+        (None, None) => return Err(format!("{}: expected either `ior` or `dispersion`", path)),
+    })
+}
+
+fn to_vec3(v: Color) -> Vec3 {
+    Vec3::new(v[0], v[1], v[2])
+}
+
+fn solid(c: Color) -> TokenStream {
+    let c = vec3(c);
+    quote! { ::raytracer::texture::SolidColor::new(#c) }
+}
+
+fn arc(code: TokenStream) -> TokenStream {
+    quote! { ::std::sync::Arc::new(#code) }
+}
+
+// Named textures and materials are bound once, in `let`s ahead of the objects, and the
+// objects share them like the runtime loader's do: an image is loaded once, a noise texture
+// has one Perlin table.
+struct Generator {
+    base_dir: PathBuf,
+    // the names of those bindings
+    textures: BTreeMap<String, proc_macro2::Ident>,
+    materials: BTreeMap<String, proc_macro2::Ident>,
+    // the last number handed out for the id pass; the shapes get the ones the runtime loader
+    // gives them
+    last_id: Cell<u32>,
+}
+
+// Turns the objects of a scene file into one expression whose type is a tree of
+// `BvhNodeStatic`s, so the renderer never goes through `dyn Hittable` for them.
+pub fn world(desc: &SceneDesc, filename: &Path) -> Result<TokenStream, String> {
+    let mut gen = Generator {
+        base_dir: filename.parent().map(Path::to_path_buf).unwrap_or_default(),
+        textures: BTreeMap::new(),
+        materials: BTreeMap::new(),
+        last_id: Cell::new(0),
+    };
+    let mut bindings = Vec::new();
+    for (i, (name, tex)) in desc.textures.iter().enumerate() {
+        let texture = arc(gen.texture(tex, &format!("textures.{}", name))?);
+        let ident = format_ident!("texture_{}", i);
+        bindings.push(quote! {
+            #[allow(unused_variables)]
+            let #ident = #texture;
+        });
+        gen.textures.insert(name.clone(), ident);
+    }
+    for (i, (name, mat)) in desc.materials.iter().enumerate() {
+        let material = arc(gen.material(mat, &format!("materials.{}", name))?);
+        let ident = format_ident!("material_{}", i);
+        bindings.push(quote! {
+            #[allow(unused_variables)]
+            let #ident = #material;
+        });
+        gen.materials.insert(name.clone(), ident);
+    }
+    let mut objects = Vec::new();
+    for (i, object) in desc.objects.iter().enumerate() {
+        let path = format!("objects[{}]", i);
+        objects.push(bounded(gen.object(object, &path, false)?, &path)?);
+    }
+    if objects.is_empty() {
+        return Err(String::from("the scene has no objects"));
+    }
+    let tree = bvh_build(&mut objects);
+    Ok(quote! {{
+        #(#bindings)*
+        #tree
+    }})
+}
+
+impl Generator {
+    fn texture(&self, desc: &TextureDesc, path: &str) -> Result<TokenStream, String> {
+        Ok(match desc {
+            TextureDesc::Solid { color } => solid(*color),
+            TextureDesc::Checker { even, odd } => {
+                let (even, odd) = (solid(*even), solid(*odd));
+                quote! {
+                    ::raytracer::texture::CheckerTextureStatic { odd: #odd, even: #even }
+                }
+            }
+            TextureDesc::Noise { scale } => {
+                let scale = float(*scale);
+                quote! { ::raytracer::texture::NoiseTexture::new0(#scale) }
+            }
+            TextureDesc::Image { file } => {
+                let full = self.base_dir.join(file);
+                if !full.is_file() {
+                    return Err(format!("{}: image `{}` not found", path, full.display()));
+                }
+                let full = full.to_string_lossy().to_string();
+                quote! { ::raytracer::texture::ImageTexture::new(#full) }
+            }
+        })
+    }
+
+    fn density(
+        &self,
+        desc: &DensityDesc,
+        boundary: &Object,
+        path: &str,
+    ) -> Result<TokenStream, String> {
+        Ok(match desc {
+            DensityDesc::Grid {
+                resolution,
+                data,
+                file,
+            } => {
+                let (min, max) = (boundary.bounding_box_min, boundary.bounding_box_max);
+                let min = vec3([min.x, min.y, min.z]);
+                let max = vec3([max.x, max.y, max.z]);
+                let bounds = quote! { ::raytracer::AABB::Aabb::new(#min, #max) };
+                // a file's values are embedded like inline ones, the binary needn't find it
+                let mut track = quote! {};
+                let (resolution, data) = match (resolution, data, file) {
+                    (Some(resolution), Some(data), None) => (*resolution, data.clone()),
+                    (None, None, Some(file)) => {
+                        let full = self.base_dir.join(file);
+                        if !full.is_file() {
+                            return Err(format!("{}: grid `{}` not found", path, full.display()));
+                        }
+                        let full = full.to_string_lossy().to_string();
+                        // rebuild whenever the grid changes
+                        track = quote! { const _: &[u8] = include_bytes!(#full); };
+                        grid::read_vol(&full).map_err(|e| format!("{}: {}", path, e))?
+                    }
+                    (_, Some(_), Some(_)) => {
+                        return Err(format!(
+                            "{}: `data` and `file` are mutually exclusive",
+                            path
+                        ))
+                    }
+                    (None, Some(_), None) => {
+                        return Err(format!("{}: `data` needs `resolution`", path))
+                    }
+                    (Some(_), None, Some(_)) => {
+                        return Err(format!("{}: a `file` grid has its own `resolution`", path))
+                    }
+                    (_, None, None) => {
+                        return Err(format!("{}: expected either `data` or `file`", path))
+                    }
+                };
+                grid::check(resolution, &data).map_err(|e| format!("{}: {}", path, e))?;
+                let [nx, ny, nz] = resolution;
+                let data = data.iter().map(|d| float(*d));
+                quote! {{
+                    #track
+                    ::std::sync::Arc::new(
+                        ::raytracer::medium::GridDensity::new(
+                            [#nx, #ny, #nz], vec![#(#data),*], #bounds
+                        )
+                        .unwrap_or_else(|e| panic!("{}", e))
+                    )
+                }}
+            }
+            DensityDesc::Noise {
+                frequency,
+                octaves,
+                density,
+            } => {
+                if *density <= 0.0 || *octaves < 1 {
+                    return Err(format!(
+                        "{}: `density` and `octaves` must be positive",
+                        path
+                    ));
+                }
+                let (frequency, density) = (float(*frequency), float(*density));
+                quote! {
+                    ::std::sync::Arc::new(
+                        ::raytracer::medium::NoiseDensity::new(#frequency, #octaves, #density)
+                    )
+                }
+            }
+        })
+    }
+
+    fn texture_ref(&self, name: &str, path: &str) -> Result<TokenStream, String> {
+        match self.textures.get(name) {
+            Some(ident) => Ok(quote! { #ident.clone() }),
+            None => Err(format!("{}: unknown texture `{}`", path, name)),
+        }
+    }
+
+    fn color_or_texture(
+        &self,
+        color: &Option<Color>,
+        texture: &Option<String>,
+        color_field: &str,
+        path: &str,
+    ) -> Result<TokenStream, String> {
+        match (color, texture) {
+            (Some(c), None) => Ok(solid(*c)),
+            (None, Some(name)) => self.texture_ref(name, path),
+            (Some(_), Some(_)) => Err(format!(
+                "{}: `{}` and `texture` are mutually exclusive",
+                path, color_field
+            )),
+            (None, None) => Err(format!(
+                "{}: expected either `{}` or `texture`",
+                path, color_field
+            )),
+        }
+    }
+
+    // The fields of a `Principled` that were given, each boxed as an `Arc<dyn Texture>`.
+    fn params(
+        &self,
+        params: &[(&str, &Option<Param>)],
+        path: &str,
+    ) -> Result<Vec<TokenStream>, String> {
+        let mut fields = Vec::new();
+        for (name, param) in params.iter() {
+            let tex = match param {
+                None => continue,
+                Some(Param::Value(x)) => arc(solid([*x, *x, *x])),
+                Some(Param::Color(c)) => arc(solid(*c)),
+                // its binding is an Arc already
+                Some(Param::Texture(name)) => self.texture_ref(name, path)?,
+            };
+            let field = proc_macro2::Ident::new(name, proc_macro2::Span::call_site());
+            fields.push(quote! {
+                #field: #tex as ::std::sync::Arc<dyn ::raytracer::texture::Texture>
+            });
         }
+        Ok(fields)
     }
-}
\ No newline at end of file
+
+    fn material(&self, desc: &MaterialDesc, path: &str) -> Result<TokenStream, String> {
+        Ok(match desc {
+            MaterialDesc::Lambertian { albedo, texture } => {
+                let tex = self.color_or_texture(albedo, texture, "albedo", path)?;
+                quote! { ::raytracer::material::LambertianStatic::news(#tex) }
+            }
+            MaterialDesc::Metal { albedo, fuzz } => {
+                let (albedo, fuzz) = (vec3(*albedo), float(*fuzz));
+                quote! { ::raytracer::material::Metal::news(#albedo, #fuzz) }
+            }
+            MaterialDesc::Dielectric { ior, dispersion } => {
+                match index_of_refraction(ior, dispersion, path)? {
+                    Index::Constant(ior) => quote! { ::raytracer::material::Dielectric::new(#ior) },
+                    Index::Dispersive(d) => {
+                        quote! { ::raytracer::material::Dielectric::dispersive(#d) }
+                    }
+                }
+            }
+            MaterialDesc::Conductor {
+                eta,
+                k,
+                roughness,
+                anisotropy,
+            } => {
+                let (eta, k) = (vec3(*eta), vec3(*k));
+                let (roughness, anisotropy) = (float(*roughness), float(*anisotropy));
+                quote! {
+                    ::raytracer::material::Conductor::new(#eta, #k, #roughness, #anisotropy)
+                }
+            }
+            MaterialDesc::RoughDielectric {
+                ior,
+                dispersion,
+                roughness,
+                anisotropy,
+            } => {
+                let (roughness, anisotropy) = (float(*roughness), float(*anisotropy));
+                match index_of_refraction(ior, dispersion, path)? {
+                    Index::Constant(ior) => quote! {
+                        ::raytracer::material::RoughDielectric::new(#ior, #roughness, #anisotropy)
+                    },
+                    Index::Dispersive(d) => quote! {
+                        ::raytracer::material::RoughDielectric::dispersive(
+                            #d, #roughness, #anisotropy
+                        )
+                    },
+                }
+            }
+            MaterialDesc::Principled(p) => {
+                let fields = self.params(
+                    &[
+                        ("base_color", &p.base_color),
+                        ("metallic", &p.metallic),
+                        ("roughness", &p.roughness),
+                        ("specular", &p.specular),
+                        ("specular_tint", &p.specular_tint),
+                        ("sheen", &p.sheen),
+                        ("sheen_tint", &p.sheen_tint),
+                        ("clearcoat", &p.clearcoat),
+                        ("clearcoat_gloss", &p.clearcoat_gloss),
+                        ("transmission", &p.transmission),
+                        ("anisotropy", &p.anisotropy),
+                    ],
+                    path,
+                )?;
+                quote! {
+                    ::raytracer::material::Principled {
+                        #(#fields,)*
+                        ..::raytracer::material::Principled::default()
+                    }
+                }
+            }
+            MaterialDesc::DiffuseLight { emit, texture } => {
+                let tex = self.color_or_texture(emit, texture, "emit", path)?;
+                quote! { ::raytracer::material::DiffuseLightStatic::new(#tex) }
+            }
+            MaterialDesc::Isotropic { albedo, texture } => {
+                let tex = self.color_or_texture(albedo, texture, "albedo", path)?;
+                quote! { ::raytracer::material::IsotropiucStatic::new0(#tex) }
+            }
+        })
+    }
+
+    // Medium boundaries never shade anything, so their material may be omitted.
+    fn material_ref(
+        &self,
+        name: &Option<String>,
+        path: &str,
+        optional: bool,
+    ) -> Result<TokenStream, String> {
+        match name {
+            Some(name) => match self.materials.get(name) {
+                Some(ident) => Ok(quote! { #ident.clone() }),
+                None => Err(format!("{}: unknown material `{}`", path, name)),
+            },
+            None if optional => Ok(quote! { ::raytracer::material::NoMaterial {} }),
+            None => Err(format!("{}: missing field `material`", path)),
+        }
+    }
+
+    fn list(&self, objects: &[ObjectDesc], path: &str, optional: bool) -> Result<Object, String> {
+        if objects.is_empty() {
+            return Err(format!("{}: `objects` must not be empty", path));
+        }
+        let mut list = Vec::new();
+        for (i, object) in objects.iter().enumerate() {
+            let path = format!("{}.objects[{}]", path, i);
+            list.push(bounded(self.object(object, &path, optional)?, &path)?);
+        }
+        let mut min = Vec3::new(f64::INFINITY, f64::INFINITY, f64::INFINITY);
+        let mut max = Vec3::new(-f64::INFINITY, -f64::INFINITY, -f64::INFINITY);
+        for object in list.iter() {
+            min = surrounding_min(min, object.bounding_box_min);
+            max = surrounding_max(max, object.bounding_box_max);
+        }
+        Ok(Object {
+            bounding_box_min: min,
+            bounding_box_max: max,
+            code: bvh_build(&mut list),
+        })
+    }
+
+    fn object(&self, desc: &ObjectDesc, path: &str, optional: bool) -> Result<Object, String> {
+        let object = match desc {
+            ObjectDesc::Sphere {
+                center,
+                radius,
+                material,
+            } => {
+                let mat = self.material_ref(material, path, optional)?;
+                let (c, r) = (to_vec3(*center), radius.abs());
+                let (center, radius) = (vec3(*center), float(*radius));
+                Object {
+                    bounding_box_min: c - r,
+                    bounding_box_max: c + r,
+                    code: quote! { ::raytracer::hit::Sphere::new(#center, #radius, #mat) },
+                }
+            }
+            ObjectDesc::MovingSphere {
+                center0,
+                center1,
+                time0,
+                time1,
+                radius,
+                material,
+            } => {
+                if time0 >= time1 {
+                    return Err(format!("{}: `time0` must be less than `time1`", path));
+                }
+                let mat = self.material_ref(material, path, optional)?;
+                let (c0, c1, r) = (to_vec3(*center0), to_vec3(*center1), radius.abs());
+                let (center0, center1) = (vec3(*center0), vec3(*center1));
+                let (time0, time1, radius) = (float(*time0), float(*time1), float(*radius));
+                Object {
+                    bounding_box_min: surrounding_min(c0 - r, c1 - r),
+                    bounding_box_max: surrounding_max(c0 + r, c1 + r),
+                    code: quote! {
+                        ::raytracer::moving_sphere::MovingSphere::new(
+                            #center0, #center1, #time0, #time1, #radius, #mat
+                        )
+                    },
+                }
+            }
+            ObjectDesc::XyRect {
+                x0,
+                x1,
+                y0,
+                y1,
+                k,
+                material,
+            } => {
+                let mat = self.material_ref(material, path, optional)?;
+                let bbox = (
+                    Vec3::new(*x0, *y0, k - 0.0001),
+                    Vec3::new(*x1, *y1, k + 0.0001),
+                );
+                let (x0, x1, y0, y1, k) =
+                    (float(*x0), float(*x1), float(*y0), float(*y1), float(*k));
+                Object {
+                    bounding_box_min: bbox.0,
+                    bounding_box_max: bbox.1,
+                    code: quote! { ::raytracer::aarect::XyRect::new(#x0, #x1, #y0, #y1, #k, #mat) },
+                }
+            }
+            ObjectDesc::XzRect {
+                x0,
+                x1,
+                z0,
+                z1,
+                k,
+                material,
+            } => {
+                let mat = self.material_ref(material, path, optional)?;
+                let bbox = (
+                    Vec3::new(*x0, k - 0.0001, *z0),
+                    Vec3::new(*x1, k + 0.0001, *z1),
+                );
+                let (x0, x1, z0, z1, k) =
+                    (float(*x0), float(*x1), float(*z0), float(*z1), float(*k));
+                Object {
+                    bounding_box_min: bbox.0,
+                    bounding_box_max: bbox.1,
+                    code: quote! { ::raytracer::aarect::XzRect::new(#x0, #x1, #z0, #z1, #k, #mat) },
+                }
+            }
+            ObjectDesc::YzRect {
+                y0,
+                y1,
+                z0,
+                z1,
+                k,
+                material,
+            } => {
+                let mat = self.material_ref(material, path, optional)?;
+                let bbox = (
+                    Vec3::new(k - 0.0001, *y0, *z0),
+                    Vec3::new(k + 0.0001, *y1, *z1),
+                );
+                let (y0, y1, z0, z1, k) =
+                    (float(*y0), float(*y1), float(*z0), float(*z1), float(*k));
+                Object {
+                    bounding_box_min: bbox.0,
+                    bounding_box_max: bbox.1,
+                    code: quote! { ::raytracer::aarect::YzRect::new(#y0, #y1, #z0, #z1, #k, #mat) },
+                }
+            }
+            ObjectDesc::Triangle {
+                p0,
+                p1,
+                p2,
+                material,
+            } => {
+                let mat = self.material_ref(material, path, optional)?;
+                let (a, b, c) = (to_vec3(*p0), to_vec3(*p1), to_vec3(*p2));
+                let (p0, p1, p2) = (vec3(*p0), vec3(*p1), vec3(*p2));
+                // padded like Triangle::bounding_box, or a flat one would have no thickness
+                let pad = Vec3::new(0.0001, 0.0001, 0.0001);
+                Object {
+                    bounding_box_min: surrounding_min(surrounding_min(a, b), c) - pad,
+                    bounding_box_max: surrounding_max(surrounding_max(a, b), c) + pad,
+                    code: quote! { ::raytracer::aarect::Triangle::new(#p0, #p1, #p2, #mat) },
+                }
+            }
+            ObjectDesc::Box { min, max, material } => {
+                let mat = self.material_ref(material, path, optional)?;
+                let bbox = (to_vec3(*min), to_vec3(*max));
+                let (min, max) = (vec3(*min), vec3(*max));
+                Object {
+                    bounding_box_min: bbox.0,
+                    bounding_box_max: bbox.1,
+                    code: quote! { ::raytracer::Boxe::Boxes::new(#min, #max, #mat) },
+                }
+            }
+            ObjectDesc::ConstantMedium {
+                boundary,
+                density,
+                albedo,
+                texture,
+            } => {
+                if *density <= 0.0 {
+                    return Err(format!("{}: `density` must be positive", path));
+                }
+                let tex = self.color_or_texture(albedo, texture, "albedo", path)?;
+                let boundary = self.object(boundary, &format!("{}.boundary", path), true)?;
+                let code = boundary.code;
+                let density = float(*density);
+                Object {
+                    bounding_box_min: boundary.bounding_box_min,
+                    bounding_box_max: boundary.bounding_box_max,
+                    code: quote! {
+                        ::raytracer::constant_medium::ConstantMedium::<
+                            _,
+                            ::raytracer::material::NoMaterial,
+                        >::new0(#code, #density, #tex)
+                    },
+                }
+            }
+            ObjectDesc::HeterogeneousMedium {
+                boundary,
+                density,
+                sigma_a,
+                sigma_s,
+                emission,
+            } => {
+                let values = sigma_a.iter().chain(sigma_s).chain(emission);
+                if values.clone().any(|v| *v < 0.0 || !v.is_finite()) {
+                    return Err(format!(
+                        "{}: `sigma_a`, `sigma_s` and `emission` must not be negative",
+                        path
+                    ));
+                }
+                if sigma_a.iter().chain(sigma_s).all(|v| *v == 0.0) {
+                    return Err(format!("{}: a medium needs `sigma_a` or `sigma_s`", path));
+                }
+                let boundary = self.object(boundary, &format!("{}.boundary", path), true)?;
+                let density = self.density(density, &boundary, &format!("{}.density", path))?;
+                let code = boundary.code;
+                let (sigma_a, sigma_s, emission) =
+                    (vec3(*sigma_a), vec3(*sigma_s), vec3(*emission));
+                Object {
+                    bounding_box_min: boundary.bounding_box_min,
+                    bounding_box_max: boundary.bounding_box_max,
+                    code: quote! {
+                        ::raytracer::medium::HeterogeneousMedium::new(
+                            #code, #density, #sigma_a, #sigma_s, #emission
+                        )
+                    },
+                }
+            }
+            ObjectDesc::Translate { offset, object } => {
+                let inner = self.object(object, &format!("{}.object", path), optional)?;
+                let code = inner.code;
+                let d = to_vec3(*offset);
+                let offset = vec3(*offset);
+                Object {
+                    bounding_box_min: inner.bounding_box_min + d,
+                    bounding_box_max: inner.bounding_box_max + d,
+                    code: quote! { ::raytracer::hit::Translate::new(#code, #offset) },
+                }
+            }
+            ObjectDesc::RotateY { angle, object } => {
+                let inner = self.object(object, &format!("{}.object", path), optional)?;
+                let code = inner.code;
+                let (sin_theta, cos_theta) = angle.to_radians().sin_cos();
+                let mut min = Vec3::new(f64::INFINITY, f64::INFINITY, f64::INFINITY);
+                let mut max = Vec3::new(-f64::INFINITY, -f64::INFINITY, -f64::INFINITY);
+                for &x in [inner.bounding_box_min.x, inner.bounding_box_max.x].iter() {
+                    for &y in [inner.bounding_box_min.y, inner.bounding_box_max.y].iter() {
+                        for &z in [inner.bounding_box_min.z, inner.bounding_box_max.z].iter() {
+                            let tester = Vec3::new(
+                                cos_theta * x + sin_theta * z,
+                                y,
+                                -sin_theta * x + cos_theta * z,
+                            );
+                            min = surrounding_min(min, tester);
+                            max = surrounding_max(max, tester);
+                        }
+                    }
+                }
+                let angle = float(*angle);
+                Object {
+                    bounding_box_min: min,
+                    bounding_box_max: max,
+                    code: quote! { ::raytracer::hit::RotateY::new(#code, #angle) },
+                }
+            }
+            ObjectDesc::FlipFace { object } => {
+                let inner = self.object(object, &format!("{}.object", path), optional)?;
+                let code = inner.code;
+                Object {
+                    bounding_box_min: inner.bounding_box_min,
+                    bounding_box_max: inner.bounding_box_max,
+                    code: quote! { ::raytracer::hit::FlipFace::new(#code) },
+                }
+            }
+            ObjectDesc::Obj { .. } => {
+                return Err(format!(
+                    "{}: an `obj` model isn't built at compile time, load the scene at runtime",
+                    path
+                ))
+            }
+            ObjectDesc::PointLight { .. }
+            | ObjectDesc::SpotLight { .. }
+            | ObjectDesc::DirectionalLight { .. }
+            | ObjectDesc::EnvironmentMap { .. }
+            | ObjectDesc::Sky { .. } => {
+                return Err(format!(
+                    "{}: lights that aren't geometry go in `lights`",
+                    path
+                ))
+            }
+            ObjectDesc::List { objects } | ObjectDesc::Bvh { objects } => {
+                self.list(objects, path, optional)?
+            }
+        };
+        Ok(match desc {
+            // these number what they hold
+            ObjectDesc::Translate { .. }
+            | ObjectDesc::RotateY { .. }
+            | ObjectDesc::FlipFace { .. }
+            | ObjectDesc::List { .. }
+            | ObjectDesc::Bvh { .. } => object,
+            _ if optional => object,
+            _ => {
+                self.last_id.set(self.last_id.get() + 1);
+                let (id, code) = (self.last_id.get(), object.code);
+                Object {
+                    code: quote! { ::raytracer::hit::Tagged::new(#code, #id) },
+                    ..object
+                }
+            }
+        })
+    }
+}
+
+fn surrounding_min(a: Vec3, b: Vec3) -> Vec3 {
+    Vec3::new(a.x.min(b.x), a.y.min(b.y), a.z.min(b.z))
+}
+
+fn surrounding_max(a: Vec3, b: Vec3) -> Vec3 {
+    Vec3::new(a.x.max(b.x), a.y.max(b.y), a.z.max(b.z))
+}
@@ -1,6 +1,6 @@
+use rand::Rng;
 use std::f64::consts::PI;
 use std::ops::{Add, AddAssign, Div, DivAssign, Mul, MulAssign, Sub, SubAssign};
-use rand::Rng;
 
 pub fn random_double() -> f64 {
     let mut dou = rand::thread_rng();
@@ -268,7 +268,7 @@ impl Vec3 {
     pub fn unit_vector(v: Vec3) -> Vec3 {
         let divs = Vec3::length(&v);
         if divs == 0.0 {
-            return Vec3::new(0.0,0.0,0.0);
+            return Vec3::new(0.0, 0.0, 0.0);
         }
         return v / divs;
     }
@@ -0,0 +1,57 @@
+use std::fs;
+
+// Density grids for heterogeneous media, checked and read the same way by the runtime loader
+// and by `static_scene!`, which embeds their values.
+
+// Fails unless `data` fills a grid of `resolution` with finite densities that aren't negative.
+pub fn check(resolution: [usize; 3], data: &[f64]) -> Result<(), String> {
+    let count = resolution[0] * resolution[1] * resolution[2];
+    if count == 0 || data.len() != count {
+        return Err(format!(
+            "a {}x{}x{} grid needs {} values, got {}",
+            resolution[0],
+            resolution[1],
+            resolution[2],
+            count,
+            data.len()
+        ));
+    }
+    if data.iter().any(|d| *d < 0.0 || !d.is_finite()) {
+        return Err(String::from(
+            "grid densities must be finite and not negative",
+        ));
+    }
+    Ok(())
+}
+
+// Reads a grid in Mitsuba's binary .vol format: "VOL", version 3, float32 encoding (1),
+// the resolution, the channel count, a bounding box and the values. Channels are averaged.
+pub fn read_vol(filename: &str) -> Result<([usize; 3], Vec<f64>), String> {
+    let bytes = fs::read(filename).map_err(|e| format!("failed to read `{}`: {}", filename, e))?;
+    let invalid = |what: &str| format!("`{}` is not a float32 .vol grid: {}", filename, what);
+    if bytes.len() < 48 || &bytes[0..3] != b"VOL" || bytes[3] != 3 {
+        return Err(invalid("bad header"));
+    }
+    let int = |i: usize| i32::from_le_bytes([bytes[i], bytes[i + 1], bytes[i + 2], bytes[i + 3]]);
+    if int(4) != 1 {
+        return Err(invalid("unsupported encoding"));
+    }
+    let (nx, ny, nz, channels) = (int(8), int(12), int(16), int(20));
+    if nx <= 0 || ny <= 0 || nz <= 0 || channels <= 0 {
+        return Err(invalid("bad resolution"));
+    }
+    let resolution = [nx as usize, ny as usize, nz as usize];
+    let channels = channels as usize;
+    let count = resolution[0] * resolution[1] * resolution[2];
+    if bytes.len() < 48 + 4 * count * channels {
+        return Err(invalid("truncated"));
+    }
+    let float = |i: usize| {
+        let o = 48 + 4 * i;
+        f32::from_le_bytes([bytes[o], bytes[o + 1], bytes[o + 2], bytes[o + 3]]) as f64
+    };
+    let data = (0..count)
+        .map(|i| (0..channels).map(|c| float(i * channels + c)).sum::<f64>() / channels as f64)
+        .collect();
+    Ok((resolution, data))
+}
@@ -0,0 +1,368 @@
+pub mod grid;
+
+use serde::Deserialize;
+use std::collections::BTreeMap;
+
+// The scene file format, read by both `raytracer::scene_file` at runtime and `static_scene!`
+// at compile time. A scene file is a JSON document:
+//
+// {
+//   "camera": { "lookfrom": [278, 278, -800], "lookat": [278, 278, 0], "vfov": 40 },
+//   "background": [0, 0, 0],
+//   "textures": { "checker": { "type": "checker", "even": [0.2, 0.3, 0.1], "odd": [0.9, 0.9, 0.9] } },
+//   "materials": { "white": { "type": "lambertian", "albedo": [0.73, 0.73, 0.73] } },
+//   "objects": [ { "type": "sphere", "center": [0, 0, 0], "radius": 1, "material": "white" } ],
+//   "lights": [ { "type": "xz_rect", "x0": 213, "x1": 343, "z0": 227, "z1": 332, "k": 554 } ]
+// }
+//
+// Materials and textures are declared once by name and referenced from objects.
+// Entries in "lights" are only used for importance sampling, so they need no material.
+// Spheres, rects, triangles, translated, rotated or flipped ones and lists or bvhs of them
+// can be sampled, other shapes are refused there; a bvh of triangles is sampled uniformly by
+// area, like the mesh it is.
+// A Wavefront OBJ model goes in "objects" with its MTL materials, "material" covering the
+// faces without one; the faces whose MTL material has a `Ke` emission glow. Listed in
+// "lights" as well, each of those becomes a light of its own:
+//
+//   { "type": "obj", "file": "lamp.obj", "scale": 100, "material": "white" }
+// Point, spot and directional lights aren't geometry, so they go there and nowhere else:
+//
+//   { "type": "point_light", "position": [278, 500, 278], "intensity": [4e4, 4e4, 4e4] }
+//   { "type": "spot_light", "position": [278, 500, 278], "direction": [0, -1, 0],
+//     "intensity": [4e4, 4e4, 4e4], "angle": 30, "falloff_start": 20 }
+//   { "type": "directional_light", "direction": [1, -2, 1], "irradiance": [3, 3, 3],
+//     "angular_radius": 0.5 }
+//
+// A spot's "profile" may list relative intensities from its axis out to "angle" instead of
+// a "falloff_start"; "angular_radius" (degrees) softens a directional light's shadows.
+// An equirectangular .hdr or .exr image can light the scene from all around and replace the
+// background; "rotation" (degrees about y) and "intensity" are optional:
+//
+//   { "type": "environment_map", "file": "sky.hdr", "rotation": 90, "intensity": 1.5 }
+//
+// or, instead, a physical daylight sky with the sun in it. Only "sun_elevation" (degrees) is
+// needed; "sun_azimuth" turns the sun from +x towards +z, "turbidity" (1.7 to 10) makes the
+// air hazier, and "sun": false leaves out the sun disk (the ground stays sunlit):
+//
+//   { "type": "sky", "sun_elevation": 40, "sun_azimuth": 30, "turbidity": 3,
+//     "ground_albedo": [0.3, 0.3, 0.3], "intensity": 1 }
+
+pub type Color = [f64; 3];
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SceneDesc {
+    #[serde(default)]
+    pub camera: CameraDesc,
+    pub background: Option<Color>,
+    #[serde(default)]
+    pub textures: BTreeMap<String, TextureDesc>,
+    #[serde(default)]
+    pub materials: BTreeMap<String, MaterialDesc>,
+    pub objects: Vec<ObjectDesc>,
+    #[serde(default)]
+    pub lights: Vec<ObjectDesc>,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct CameraDesc {
+    pub lookfrom: [f64; 3],
+    pub lookat: [f64; 3],
+    pub vup: [f64; 3],
+    pub vfov: f64,
+    pub aperture: f64,
+    pub focus_dist: f64,
+    pub time0: f64,
+    pub time1: f64,
+}
+
+impl Default for CameraDesc {
+    fn default() -> Self {
+        Self {
+            lookfrom: [0.0, 0.0, 0.0],
+            lookat: [0.0, 0.0, -1.0],
+            vup: [0.0, 1.0, 0.0],
+            vfov: 40.0,
+            aperture: 0.0,
+            focus_dist: 10.0,
+            time0: 0.0,
+            time1: 1.0,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum TextureDesc {
+    Solid { color: Color },
+    Checker { even: Color, odd: Color },
+    Noise { scale: f64 },
+    Image { file: String },
+}
+
+// A material parameter: a number, a color or the name of a texture.
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum Param {
+    Value(f64),
+    Color(Color),
+    Texture(String),
+}
+
+// wavelengths in micrometers, see `raytracer::spectrum::Dispersion`
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum DispersionDesc {
+    Cauchy { a: f64, b: f64 },
+    Sellmeier { b: [f64; 3], c: [f64; 3] },
+}
+
+// how the density of a heterogeneous medium varies
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum DensityDesc {
+    // spans the bounding box of the boundary; `data` has x varying fastest, then y, then z,
+    // or `file` is a Mitsuba .vol grid
+    Grid {
+        resolution: Option<[usize; 3]>,
+        data: Option<Vec<f64>>,
+        file: Option<String>,
+    },
+    Noise {
+        #[serde(default = "one")]
+        frequency: f64,
+        #[serde(default = "default_octaves")]
+        octaves: i32,
+        #[serde(default = "one")]
+        density: f64,
+    },
+}
+
+fn default_octaves() -> i32 {
+    7
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum MaterialDesc {
+    Lambertian {
+        albedo: Option<Color>,
+        texture: Option<String>,
+    },
+    Metal {
+        albedo: Color,
+        #[serde(default)]
+        fuzz: f64,
+    },
+    // either a constant `ior` or the `dispersion` of a glass
+    Dielectric {
+        ior: Option<f64>,
+        dispersion: Option<DispersionDesc>,
+    },
+    // GGX metal with complex index of refraction eta + ik
+    Conductor {
+        eta: Color,
+        k: Color,
+        #[serde(default)]
+        roughness: f64,
+        #[serde(default)]
+        anisotropy: f64,
+    },
+    RoughDielectric {
+        ior: Option<f64>,
+        dispersion: Option<DispersionDesc>,
+        roughness: f64,
+        #[serde(default)]
+        anisotropy: f64,
+    },
+    // every parameter is optional and may be textured; see `raytracer::bsdf::PrincipledParams`
+    Principled(Box<PrincipledDesc>),
+    DiffuseLight {
+        emit: Option<Color>,
+        texture: Option<String>,
+    },
+    Isotropic {
+        albedo: Option<Color>,
+        texture: Option<String>,
+    },
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PrincipledDesc {
+    pub base_color: Option<Param>,
+    pub metallic: Option<Param>,
+    pub roughness: Option<Param>,
+    pub specular: Option<Param>,
+    pub specular_tint: Option<Param>,
+    pub sheen: Option<Param>,
+    pub sheen_tint: Option<Param>,
+    pub clearcoat: Option<Param>,
+    pub clearcoat_gloss: Option<Param>,
+    pub transmission: Option<Param>,
+    pub anisotropy: Option<Param>,
+}
+
+fn one() -> f64 {
+    1.0
+}
+
+fn yes() -> bool {
+    true
+}
+
+fn default_turbidity() -> f64 {
+    3.0
+}
+
+fn default_ground_albedo() -> Color {
+    [0.3, 0.3, 0.3]
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum ObjectDesc {
+    Sphere {
+        center: [f64; 3],
+        radius: f64,
+        material: Option<String>,
+    },
+    MovingSphere {
+        center0: [f64; 3],
+        center1: [f64; 3],
+        #[serde(default)]
+        time0: f64,
+        #[serde(default = "one")]
+        time1: f64,
+        radius: f64,
+        material: Option<String>,
+    },
+    XyRect {
+        x0: f64,
+        x1: f64,
+        y0: f64,
+        y1: f64,
+        k: f64,
+        material: Option<String>,
+    },
+    XzRect {
+        x0: f64,
+        x1: f64,
+        z0: f64,
+        z1: f64,
+        k: f64,
+        material: Option<String>,
+    },
+    YzRect {
+        y0: f64,
+        y1: f64,
+        z0: f64,
+        z1: f64,
+        k: f64,
+        material: Option<String>,
+    },
+    Triangle {
+        p0: [f64; 3],
+        p1: [f64; 3],
+        p2: [f64; 3],
+        material: Option<String>,
+    },
+    // `material` is for the faces the MTL file gives none
+    Obj {
+        file: String,
+        #[serde(default = "one")]
+        scale: f64,
+        material: Option<String>,
+    },
+    Box {
+        min: [f64; 3],
+        max: [f64; 3],
+        material: Option<String>,
+    },
+    ConstantMedium {
+        boundary: Box<ObjectDesc>,
+        density: f64,
+        albedo: Option<Color>,
+        texture: Option<String>,
+    },
+    // coefficients per channel at density 1
+    HeterogeneousMedium {
+        boundary: Box<ObjectDesc>,
+        density: DensityDesc,
+        #[serde(default)]
+        sigma_a: Color,
+        #[serde(default)]
+        sigma_s: Color,
+        #[serde(default)]
+        emission: Color,
+    },
+    Translate {
+        offset: [f64; 3],
+        object: Box<ObjectDesc>,
+    },
+    RotateY {
+        angle: f64,
+        object: Box<ObjectDesc>,
+    },
+    FlipFace {
+        object: Box<ObjectDesc>,
+    },
+    PointLight {
+        position: [f64; 3],
+        intensity: Color,
+    },
+    SpotLight {
+        position: [f64; 3],
+        direction: [f64; 3],
+        intensity: Color,
+        angle: f64,
+        falloff_start: Option<f64>,
+        profile: Option<Vec<f64>>,
+    },
+    DirectionalLight {
+        direction: [f64; 3],
+        irradiance: Color,
+        #[serde(default)]
+        angular_radius: f64,
+    },
+    EnvironmentMap {
+        file: String,
+        #[serde(default)]
+        rotation: f64,
+        #[serde(default = "one")]
+        intensity: f64,
+    },
+    Sky {
+        sun_elevation: f64,
+        #[serde(default)]
+        sun_azimuth: f64,
+        #[serde(default = "default_turbidity")]
+        turbidity: f64,
+        #[serde(default = "default_ground_albedo")]
+        ground_albedo: Color,
+        #[serde(default = "one")]
+        intensity: f64,
+        #[serde(default = "yes")]
+        sun: bool,
+    },
+    List {
+        objects: Vec<ObjectDesc>,
+    },
+    Bvh {
+        objects: Vec<ObjectDesc>,
+    },
+}
+
+// Parses a scene file's text; a syntax or schema error comes with its line and column.
+pub fn parse(text: &str) -> Result<SceneDesc, (usize, usize, String)> {
+    serde_json::from_str(text).map_err(|e| {
+        // serde_json appends " at line L column C" to its messages; callers print that as a prefix
+        let msg = e.to_string();
+        let msg = match msg.rfind(" at line ") {
+            Some(pos) => msg[..pos].to_string(),
+            None => msg,
+        };
+        (e.line(), e.column(), msg)
+    })
+}
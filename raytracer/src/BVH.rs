@@ -1,6 +1,7 @@
 use crate::hit::{HitRecord, Hittable, HittableList};
 use crate::material::Lambertian;
 use crate::moving_sphere::MovingSphere;
+use crate::sampler::sample_1d;
 use crate::Vec3;
 use crate::AABB::Aabb;
 use crate::{random_int, Ray};
@@ -10,6 +11,8 @@ pub struct BvhNode {
     pub left: Arc<dyn Hittable>,
     pub right: Arc<dyn Hittable>,
     pub box0: Aabb,
+    // of everything below, for sampling it uniformly by area
+    area: f64,
 }
 impl BvhNode {
     pub fn new(mut list: HittableList, time0: f64, time1: f64) -> Self {
@@ -42,9 +45,59 @@ impl Hittable for BvhNode {
         *output_box = self.box0;
         true
     }
+    fn transmittance(&self, r: Ray, t_min: f64, t_max: f64) -> Vec3 {
+        if !self.box0.hit(r, t_min, t_max) {
+            return Vec3::ones();
+        }
+        let left = self.left.transmittance(r, t_min, t_max);
+        // a node over a single object has it on both sides
+        if Arc::ptr_eq(&self.left, &self.right) {
+            return left;
+        }
+        left * self.right.transmittance(r, t_min, t_max)
+    }
+    // A point picked uniformly over the area of everything below, going down to either side
+    // in proportion to its area.
+    fn pdf_value(&self, o: Vec3, v: Vec3) -> f64 {
+        if !self.box0.hit(Ray::new(o, v, 0.0), 0.001, f64::INFINITY) {
+            return 0.0;
+        }
+        let (left, right) = self.weights();
+        let mut pdf = 0.0;
+        if left > 0.0 {
+            pdf += left * self.left.pdf_value(o, v);
+        }
+        if right > 0.0 {
+            pdf += right * self.right.pdf_value(o, v);
+        }
+        pdf
+    }
+    fn random(&self, o: Vec3) -> Vec3 {
+        if sample_1d() < self.weights().0 {
+            self.left.random(o)
+        } else {
+            self.right.random(o)
+        }
+    }
+    fn area(&self) -> f64 {
+        self.area
+    }
 }
 
 impl BvhNode {
+    // the chances of sampling the left and the right side, half each when neither has an area
+    fn weights(&self) -> (f64, f64) {
+        if Arc::ptr_eq(&self.left, &self.right) {
+            return (1.0, 0.0);
+        }
+        let (left, right) = (self.left.area(), self.right.area());
+        if left + right > 0.0 {
+            (left / (left + right), right / (left + right))
+        } else {
+            (0.5, 0.5)
+        }
+    }
+
     pub fn new0(
         src_objects: &mut Vec<Arc<dyn Hittable>>,
         start: usize,
@@ -125,10 +178,16 @@ impl BvhNode {
         {
             std::println!("Bo bounding box in bvh_node constructor.\n");
         }
+        let area = if Arc::ptr_eq(&lt, &rt) {
+            lt.area()
+        } else {
+            lt.area() + rt.area()
+        };
         Self {
             left: lt,
             right: rt,
             box0: MovingSphere::<Lambertian>::surrounding_box(box_left, box_right),
+            area,
         }
     }
     pub fn box_compare(a: Arc<dyn Hittable>, b: Arc<dyn Hittable>, axis: i32) -> bool {
@@ -178,6 +237,45 @@ impl BvhNode {
     }
 }
 
+// Same tree as BvhNode but with concrete children, built by `static_scene!` at compile time,
+// which works out the boxes too.
+pub struct BvhNodeStatic<L: Hittable, R: Hittable> {
+    pub left: L,
+    pub right: R,
+    pub box0: Aabb,
+}
+impl<L: Hittable, R: Hittable> BvhNodeStatic<L, R> {
+    pub fn new(left: L, right: R, box0: Aabb) -> Self {
+        Self { left, right, box0 }
+    }
+}
+impl<L: Hittable, R: Hittable> Hittable for BvhNodeStatic<L, R> {
+    fn hit(&self, r: Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        if !self.box0.hit(r, t_min, t_max) {
+            return None;
+        }
+        if let Some(rec_tmp) = self.left.hit(r, t_min, t_max) {
+            if let Some(rec_) = self.right.hit(r, t_min, rec_tmp.t) {
+                return Some(rec_);
+            } else {
+                return Some(rec_tmp);
+            }
+        }
+        self.right.hit(r, t_min, t_max)
+    }
+
+    fn bounding_box(&self, _time0: f64, _time1: f64, output_box: &mut Aabb) -> bool {
+        *output_box = self.box0;
+        true
+    }
+    fn transmittance(&self, r: Ray, t_min: f64, t_max: f64) -> Vec3 {
+        if !self.box0.hit(r, t_min, t_max) {
+            return Vec3::ones();
+        }
+        self.left.transmittance(r, t_min, t_max) * self.right.transmittance(r, t_min, t_max)
+    }
+}
+
 // fn quick_sort(mut arr:& mut Vec<Arc<dyn Hittable>> , l:usize , r:usize , func:fn(Arc<dyn Hittable> , Arc<dyn Hittable>)->bool){
 //     let mut left:usize = l;
 //     let mut right:usize = r;
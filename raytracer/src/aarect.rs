@@ -1,8 +1,9 @@
 use crate::hit::HitRecord;
 use crate::material::Material;
+use crate::sampler::sample_2d;
+use crate::Hittable;
 use crate::Vec3;
 use crate::AABB::Aabb;
-use crate::{random_double_lim, Hittable};
 use crate::{rtweekend::*, Ray};
 use std::f64::INFINITY;
 use std::ops::Mul;
@@ -57,6 +58,8 @@ impl<T: Material + Clone> Hittable for XyRect<T> {
             u: ui,
             v: vi,
             front_face,
+            medium_weight: None,
+            id: 0,
         })
     }
 
@@ -67,6 +70,31 @@ impl<T: Material + Clone> Hittable for XyRect<T> {
         );
         true
     }
+    fn pdf_value(&self, origin: Vec3, v: Vec3) -> f64 {
+        let rec = match self.hit(Ray::new(origin, v, 0.0), 0.001, f64::INFINITY) {
+            Some(rec) => rec,
+            None => return 0.0,
+        };
+        let distance_squared = rec.t * rec.t * v.length_squared();
+        let cosine = (Vec3::dot(v, rec.normal) / v.length()).abs();
+
+        distance_squared / (cosine * self.area())
+    }
+    fn random(&self, origin: Vec3) -> Vec3 {
+        let (s, t) = sample_2d();
+        let random_point = Vec3::new(
+            self.x0 + (self.x1 - self.x0) * s,
+            self.y0 + (self.y1 - self.y0) * t,
+            self.k,
+        );
+        random_point - origin
+    }
+    fn area(&self) -> f64 {
+        (self.x1 - self.x0) * (self.y1 - self.y0)
+    }
+    fn plane_normal(&self) -> Option<Vec3> {
+        Some(Vec3::new(0.0, 0.0, 1.0))
+    }
 }
 
 pub struct XzRect<T: Material + Clone> {
@@ -119,6 +147,8 @@ impl<T: Material + Clone> Hittable for XzRect<T> {
             u: ui,
             v: vi,
             front_face,
+            medium_weight: None,
+            id: 0,
         })
     }
 
@@ -157,7 +187,7 @@ impl<T: Material + Clone> Hittable for XzRect<T> {
                 return 0.0;
             }
         }
-        let area = (self.x1 - self.x0) * (self.z1 - self.x0);
+        let area = (self.x1 - self.x0) * (self.z1 - self.z0);
         let distance_squared = rec.t * rec.t * v.length_squared();
         let cosine = (Vec3::dot(v, rec.normal) / v.length()).abs();
 
@@ -165,13 +195,20 @@ impl<T: Material + Clone> Hittable for XzRect<T> {
     }
 
     fn random(&self, origin: Vec3) -> Vec3 {
+        let (s, t) = sample_2d();
         let random_point = Vec3::new(
-            random_double_lim(self.x0, self.x1),
+            self.x0 + (self.x1 - self.x0) * s,
             self.k,
-            random_double_lim(self.z0, self.z1),
+            self.z0 + (self.z1 - self.z0) * t,
         );
         random_point - origin
     }
+    fn area(&self) -> f64 {
+        (self.x1 - self.x0) * (self.z1 - self.z0)
+    }
+    fn plane_normal(&self) -> Option<Vec3> {
+        Some(Vec3::new(0.0, 1.0, 0.0))
+    }
 }
 pub struct YzRect<T: Material + Clone> {
     pub mp: T,
@@ -223,6 +260,8 @@ impl<T: Material + Clone> Hittable for YzRect<T> {
             u: ui,
             v: vi,
             front_face,
+            medium_weight: None,
+            id: 0,
         })
     }
 
@@ -233,12 +272,40 @@ impl<T: Material + Clone> Hittable for YzRect<T> {
         );
         true
     }
+    fn pdf_value(&self, origin: Vec3, v: Vec3) -> f64 {
+        let rec = match self.hit(Ray::new(origin, v, 0.0), 0.001, f64::INFINITY) {
+            Some(rec) => rec,
+            None => return 0.0,
+        };
+        let distance_squared = rec.t * rec.t * v.length_squared();
+        let cosine = (Vec3::dot(v, rec.normal) / v.length()).abs();
+
+        distance_squared / (cosine * self.area())
+    }
+    fn random(&self, origin: Vec3) -> Vec3 {
+        let (s, t) = sample_2d();
+        let random_point = Vec3::new(
+            self.k,
+            self.y0 + (self.y1 - self.y0) * s,
+            self.z0 + (self.z1 - self.z0) * t,
+        );
+        random_point - origin
+    }
+    fn area(&self) -> f64 {
+        (self.y1 - self.y0) * (self.z1 - self.z0)
+    }
+    fn plane_normal(&self) -> Option<Vec3> {
+        Some(Vec3::new(1.0, 0.0, 0.0))
+    }
 }
 #[derive(Copy, Clone)]
 pub struct Triangle<T: Material> {
     pub p0: Vec3,
     pub p1: Vec3,
     pub p2: Vec3,
+    // texture coordinates of the corners, interpolated across the face; without them u and v
+    // are distances along two of the edges
+    pub uv: Option<[(f64, f64); 3]>,
     pub mat_ptr: T,
 }
 impl<T: Material> Triangle<T> {
@@ -247,6 +314,16 @@ impl<T: Material> Triangle<T> {
             p0,
             p1,
             p2,
+            uv: None,
+            mat_ptr,
+        }
+    }
+    pub fn new_with_uv(p0: Vec3, p1: Vec3, p2: Vec3, uv: [(f64, f64); 3], mat_ptr: T) -> Self {
+        Self {
+            p0,
+            p1,
+            p2,
+            uv: Some(uv),
             mat_ptr,
         }
     }
@@ -279,7 +356,18 @@ impl<T: Material> Hittable for Triangle<T> {
 
             let mut u = 0.0;
             let mut v = 0.0;
-            get_triangle_uv(v_ab, v_bc, v_ap, v_bp, &mut u, &mut v);
+            match self.uv {
+                Some(uv) => {
+                    // barycentric weights of p1 and p2
+                    let n = Vec3::cross(dir1, dir2);
+                    let b1 = Vec3::dot(Vec3::cross(v_ap, dir2), n) / n.length_squared();
+                    let b2 = Vec3::dot(Vec3::cross(dir1, v_ap), n) / n.length_squared();
+                    let b0 = 1.0 - b1 - b2;
+                    u = b0 * uv[0].0 + b1 * uv[1].0 + b2 * uv[2].0;
+                    v = b0 * uv[0].1 + b1 * uv[1].1 + b2 * uv[2].1;
+                }
+                None => get_triangle_uv(v_ab, v_bc, v_ap, v_bp, &mut u, &mut v),
+            }
             Some(HitRecord {
                 p: r_,
                 normal: outward_normal * flag,
@@ -288,6 +376,8 @@ impl<T: Material> Hittable for Triangle<T> {
                 u,
                 v,
                 front_face,
+                medium_weight: None,
+                id: 0,
             })
         } else {
             None
@@ -299,12 +389,39 @@ impl<T: Material> Hittable for Triangle<T> {
             f_3_min(self.p0.x, self.p1.x, self.p2.x),
             f_3_min(self.p0.y, self.p1.y, self.p2.y),
             f_3_min(self.p0.z, self.p1.z, self.p2.z),
-        ) + Vec3::new(0.01, 0.01, 0.01);
+        ) - Vec3::new(0.0001, 0.0001, 0.0001);
         output_box.maximum = Vec3::new(
             f_3_max(self.p0.x, self.p1.x, self.p2.x),
             f_3_max(self.p0.y, self.p1.y, self.p2.y),
             f_3_max(self.p0.z, self.p1.z, self.p2.z),
-        ) - Vec3::new(0.01, 0.01, 0.01);
+        ) + Vec3::new(0.0001, 0.0001, 0.0001);
         true
     }
+    fn pdf_value(&self, origin: Vec3, v: Vec3) -> f64 {
+        let rec = match self.hit(Ray::new(origin, v, 0.0), 0.001, f64::INFINITY) {
+            Some(rec) => rec,
+            None => return 0.0,
+        };
+        let distance_squared = rec.t * rec.t * v.length_squared();
+        let cosine = (Vec3::dot(v, rec.normal) / v.length()).abs();
+
+        distance_squared / (cosine * self.area())
+    }
+    fn random(&self, origin: Vec3) -> Vec3 {
+        // uniform over the area, the square root keeps them from bunching up at p0
+        let (s, t) = sample_2d();
+        let s = s.sqrt();
+        let random_point =
+            self.p0 + (self.p1 - self.p0) * (s * (1.0 - t)) + (self.p2 - self.p0) * (s * t);
+        random_point - origin
+    }
+    fn area(&self) -> f64 {
+        Vec3::cross(self.p1 - self.p0, self.p2 - self.p0).length() / 2.0
+    }
+    fn plane_normal(&self) -> Option<Vec3> {
+        Some(Vec3::unit_vector(Vec3::cross(
+            self.p1 - self.p0,
+            self.p2 - self.p0,
+        )))
+    }
 }
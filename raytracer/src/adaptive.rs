@@ -0,0 +1,128 @@
+use crate::framebuffer::{FrameBuffer, OutputFormat};
+use crate::tonemap::luminance;
+use crate::{clamp, Vec3};
+use image::{ImageBuffer, Rgb};
+
+// Past `min_spp`, a pixel is sampled until the standard error of their mean luminance,
+// relative to that mean, drops below `threshold`, or `max_spp` is reached.
+#[derive(Copy, Clone, Debug)]
+pub struct AdaptiveOptions {
+    pub min_spp: u32,
+    pub max_spp: u32,
+    pub threshold: f64,
+}
+
+// Running mean of the color and Welford's mean/variance of the luminance.
+#[derive(Copy, Clone, Debug)]
+pub struct PixelStats {
+    pub n: u32,
+    pub sum: Vec3,
+    pub mean: f64,
+    pub m2: f64,
+}
+
+impl PixelStats {
+    pub fn new() -> Self {
+        Self {
+            n: 0,
+            sum: Vec3::zero(),
+            mean: 0.0,
+            m2: 0.0,
+        }
+    }
+
+    // A NaN or infinite sample counts as a black one: kept, it would make the pixel NaN
+    // for good, and look forever unconverged.
+    pub fn add(&mut self, c: Vec3) {
+        let c = if c.x.is_finite() && c.y.is_finite() && c.z.is_finite() {
+            c
+        } else {
+            Vec3::zero()
+        };
+        self.n += 1;
+        self.sum += c;
+        let l = luminance(c);
+        let delta = l - self.mean;
+        self.mean += delta / self.n as f64;
+        self.m2 += delta * (l - self.mean);
+    }
+
+    pub fn color(&self) -> Vec3 {
+        self.sum / self.n.max(1) as f64
+    }
+
+    pub fn variance(&self) -> f64 {
+        if self.n < 2 {
+            return 0.0;
+        }
+        self.m2 / (self.n - 1) as f64
+    }
+
+    // Relative standard error; dark pixels are measured against a floor so the
+    // background doesn't soak up the whole budget.
+    pub fn error(&self) -> f64 {
+        (self.variance() / self.n as f64).sqrt() / self.mean.max(0.01)
+    }
+
+    pub fn done(&self, opts: &AdaptiveOptions) -> bool {
+        if self.n >= opts.max_spp {
+            return true;
+        }
+        if self.n < opts.min_spp {
+            return false;
+        }
+        self.error() < opts.threshold
+    }
+}
+
+// Writes the per-pixel sample counts (stored in x). HDR formats get the raw counts,
+// 8-bit ones a blue to red ramp over min..max spp.
+pub fn save_heatmap(
+    filename: &str,
+    counts: &FrameBuffer,
+    opts: &AdaptiveOptions,
+) -> Result<(), String> {
+    if OutputFormat::from_path(filename)? != OutputFormat::Ldr {
+        let mut raw = counts.clone();
+        for c in raw.data.iter_mut() {
+            *c = Vec3::ones() * c.x;
+        }
+        return raw.save(filename, &Default::default());
+    }
+    let range = (opts.max_spp - opts.min_spp).max(1) as f64;
+    let img = ImageBuffer::from_fn(counts.width, counts.height, |x, y| {
+        let t = clamp((counts.get(x, y).x - opts.min_spp as f64) / range, 0.0, 1.0);
+        let c = heat(t);
+        let q = |v: f64| (255.0 * clamp(v, 0.0, 1.0) + 0.5) as u8;
+        Rgb([q(c.x), q(c.y), q(c.z)])
+    });
+    img.save(filename)
+        .map_err(|e| format!("failed to write `{}`: {}", filename, e))
+}
+
+// blue -> cyan -> green -> yellow -> red
+fn heat(t: f64) -> Vec3 {
+    let r = clamp(1.5 - (4.0 * t - 3.0).abs(), 0.0, 1.0);
+    let g = clamp(1.5 - (4.0 * t - 2.0).abs(), 0.0, 1.0);
+    let b = clamp(1.5 - (4.0 * t - 1.0).abs(), 0.0, 1.0);
+    Vec3::new(r, g, b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn non_finite_samples_count_as_black() {
+        let mut stats = PixelStats::new();
+        stats.add(Vec3::new(1.0, 2.0, 3.0));
+        stats.add(Vec3::new(f64::NAN, 1.0, 1.0));
+        stats.add(Vec3::new(1.0, f64::INFINITY, 1.0));
+        stats.add(Vec3::new(1.0, 2.0, 3.0));
+        assert_eq!(stats.n, 4);
+        let color = stats.color();
+        assert_eq!((color.x, color.y, color.z), (0.5, 1.0, 1.5));
+        assert!(stats.mean.is_finite() && stats.m2.is_finite());
+        assert!(stats.error().is_finite());
+    }
+}
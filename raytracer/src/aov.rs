@@ -0,0 +1,317 @@
+use crate::framebuffer::{write_exr, FrameBuffer, OutputFormat};
+use crate::hit::Hittable;
+use crate::tonemap::{srgb_oetf, DisplayTransform};
+use crate::{clamp, Ray, Vec3};
+use image::{ImageBuffer, Rgb};
+use std::path::Path;
+use std::str::FromStr;
+
+// Arbitrary output variables: first-hit data written next to the beauty pass.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Aov {
+    Depth,
+    Normal,
+    Albedo,
+    Position,
+    Uv,
+    Id,
+}
+
+pub const AOVS: [Aov; 6] = [
+    Aov::Depth,
+    Aov::Normal,
+    Aov::Albedo,
+    Aov::Position,
+    Aov::Uv,
+    Aov::Id,
+];
+
+impl Aov {
+    pub fn name(self) -> &'static str {
+        match self {
+            Aov::Depth => "depth",
+            Aov::Normal => "normal",
+            Aov::Albedo => "albedo",
+            Aov::Position => "position",
+            Aov::Uv => "uv",
+            Aov::Id => "id",
+        }
+    }
+    // channel names inside the EXR layer of the same name
+    fn channels(self) -> &'static [&'static str] {
+        match self {
+            Aov::Depth => &["Z"],
+            Aov::Normal | Aov::Position => &["X", "Y", "Z"],
+            Aov::Albedo => &["R", "G", "B"],
+            Aov::Uv => &["U", "V"],
+            Aov::Id => &["ID"],
+        }
+    }
+
+    // "all" or a comma separated list of names
+    pub fn parse_list(s: &str) -> Result<Vec<Aov>, String> {
+        if s == "all" {
+            return Ok(AOVS.to_vec());
+        }
+        let mut aovs = Vec::new();
+        for name in s.split(',').map(str::trim).filter(|n| !n.is_empty()) {
+            let aov: Aov = name.parse()?;
+            if !aovs.contains(&aov) {
+                aovs.push(aov);
+            }
+        }
+        if aovs.is_empty() {
+            return Err(String::from("`--aov` needs at least one pass"));
+        }
+        Ok(aovs)
+    }
+}
+
+impl FromStr for Aov {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, String> {
+        AOVS.iter().copied().find(|a| a.name() == s).ok_or_else(|| {
+            let names: Vec<&str> = AOVS.iter().map(|a| a.name()).collect();
+            format!(
+                "unknown AOV `{}` (expected all or some of {})",
+                s,
+                names.join(", ")
+            )
+        })
+    }
+}
+
+// What the camera ray sees first. `id` is the number the scene gave the object that was hit,
+// see `HittableList::tagged`; 0 is the background.
+#[derive(Copy, Clone, Debug)]
+pub struct AovSample {
+    pub depth: f64,
+    pub normal: Vec3,
+    pub albedo: Vec3,
+    pub position: Vec3,
+    pub u: f64,
+    pub v: f64,
+    pub id: u32,
+}
+
+impl AovSample {
+    // Rays that escape the scene leave every pass at zero.
+    pub fn trace(mut r: Ray, world: &dyn Hittable) -> Option<Self> {
+        let mut rec = world.hit(r, 0.001, f64::INFINITY)?;
+        let albedo = if let Some(bsdf) = rec.mat_ptr.bsdf(&rec) {
+            bsdf.albedo()
+        } else {
+            // lights don't scatter, their clamped emission is the closest thing to a color
+            let e = rec
+                .mat_ptr
+                .emitted(&mut r, &mut rec.clone(), rec.u, rec.v, &mut rec.p);
+            Vec3::new(
+                clamp(e.x, 0.0, 1.0),
+                clamp(e.y, 0.0, 1.0),
+                clamp(e.z, 0.0, 1.0),
+            )
+        };
+        Some(Self {
+            // camera rays are not normalized, so t alone is not a distance
+            depth: rec.t * r.dir.length(),
+            normal: rec.normal,
+            albedo,
+            position: rec.p,
+            u: rec.u,
+            v: rec.v,
+            id: rec.id,
+        })
+    }
+}
+
+// One FrameBuffer per requested pass; scalar passes only use x, uv uses x and y.
+pub struct AovBuffers {
+    pub aovs: Vec<Aov>,
+    pub layers: Vec<FrameBuffer>,
+}
+
+impl AovBuffers {
+    pub fn new(aovs: &[Aov], width: u32, height: u32) -> Self {
+        Self {
+            aovs: aovs.to_vec(),
+            layers: aovs
+                .iter()
+                .map(|_| FrameBuffer::new(width, height))
+                .collect(),
+        }
+    }
+
+    // Adds the samples of one pixel to the running sums, `resolve` turns them into means.
+    // The id is not summed: a pixel keeps the id of the first sample that hit something.
+    pub fn add(&mut self, x: u32, y: u32, samples: &[Option<AovSample>]) {
+        for (aov, layer) in self.aovs.iter().zip(self.layers.iter_mut()) {
+            let mut value = layer.get(x, y);
+            if *aov == Aov::Id {
+                if value.x == 0.0 {
+                    let id = samples.iter().flatten().next().map_or(0, |s| s.id);
+                    value.x = id as f64;
+                }
+            } else {
+                for s in samples.iter().flatten() {
+                    value += match aov {
+                        Aov::Depth => Vec3::new(s.depth, 0.0, 0.0),
+                        Aov::Normal => s.normal,
+                        Aov::Albedo => s.albedo,
+                        Aov::Position => s.position,
+                        Aov::Uv => Vec3::new(s.u, s.v, 0.0),
+                        Aov::Id => unreachable!(),
+                    };
+                }
+            }
+            layer.set(x, y, value);
+        }
+    }
+
+    // Adds the sums of a smaller buffer placed at (x0, y0).
+    pub fn add_tile(&mut self, src: &AovBuffers, x0: u32, y0: u32) {
+        for ((aov, dst), src) in self
+            .aovs
+            .iter()
+            .zip(self.layers.iter_mut())
+            .zip(src.layers.iter())
+        {
+            for y in 0..src.height {
+                for x in 0..src.width {
+                    let (old, new) = (dst.get(x0 + x, y0 + y), src.get(x, y));
+                    let value = match aov {
+                        Aov::Id if old.x != 0.0 => old,
+                        Aov::Id => new,
+                        _ => old + new,
+                    };
+                    dst.set(x0 + x, y0 + y, value);
+                }
+            }
+        }
+    }
+
+    // Means of the sums, given the samples per pixel in `counts.x`. Rays that missed
+    // everything count as zeros.
+    pub fn resolve(&self, counts: &FrameBuffer) -> AovBuffers {
+        let mut out = AovBuffers {
+            aovs: self.aovs.clone(),
+            layers: self.layers.clone(),
+        };
+        for (aov, layer) in out.aovs.iter().zip(out.layers.iter_mut()) {
+            if *aov == Aov::Id {
+                continue;
+            }
+            for (c, n) in layer.data.iter_mut().zip(counts.data.iter()) {
+                *c /= n.x.max(1.0);
+            }
+        }
+        out
+    }
+
+    pub fn layer(&self, aov: Aov) -> Option<&FrameBuffer> {
+        self.aovs
+            .iter()
+            .position(|a| *a == aov)
+            .map(|i| &self.layers[i])
+    }
+
+    // Drops the passes that were only rendered for internal use, e.g. to guide the denoiser.
+    pub fn retain(&mut self, aovs: &[Aov]) {
+        let mut i = 0;
+        while i < self.aovs.len() {
+            if aovs.contains(&self.aovs[i]) {
+                i += 1;
+            } else {
+                self.aovs.remove(i);
+                self.layers.remove(i);
+            }
+        }
+    }
+
+    fn channels(&self, aov: Aov, layer: &FrameBuffer) -> Vec<(String, Vec<f32>)> {
+        aov.channels()
+            .iter()
+            .enumerate()
+            .map(|(i, ch)| {
+                let data = layer.data.iter().map(|c| c.get(i as i32) as f32).collect();
+                (format!("{}.{}", aov.name(), ch), data)
+            })
+            .collect()
+    }
+}
+
+// output/test.png with the normal pass becomes output/test.normal.png
+pub fn aov_filename(output: &str, aov: Aov) -> String {
+    let path = Path::new(output);
+    let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("");
+    let ext = path.extension().and_then(|s| s.to_str()).unwrap_or("");
+    let name = format!("{}.{}.{}", stem, aov.name(), ext);
+    path.with_file_name(name).to_string_lossy().to_string()
+}
+
+// EXR output gets the passes as extra layers of the same file, every other format
+// writes one file per pass next to the beauty image.
+pub fn save_with_aovs(
+    output: &str,
+    beauty: &FrameBuffer,
+    aovs: &AovBuffers,
+    display: &DisplayTransform,
+) -> Result<(), String> {
+    if OutputFormat::from_path(output)? == OutputFormat::Exr {
+        let mut channels = beauty.channels();
+        for (aov, layer) in aovs.aovs.iter().zip(aovs.layers.iter()) {
+            channels.extend(aovs.channels(*aov, layer));
+        }
+        return write_exr(output, beauty.width, beauty.height, &channels)
+            .map_err(|e| format!("failed to write `{}`: {}", output, e));
+    }
+    beauty.save(output, display)?;
+    for (aov, layer) in aovs.aovs.iter().zip(aovs.layers.iter()) {
+        let filename = aov_filename(output, *aov);
+        if OutputFormat::from_path(output)? == OutputFormat::Ldr {
+            visualize(*aov, layer)
+                .save(&filename)
+                .map_err(|e| format!("failed to write `{}`: {}", filename, e))?;
+        } else {
+            layer.save(&filename, display)?;
+        }
+    }
+    Ok(())
+}
+
+// 8-bit formats can't hold the raw values, so each pass is squeezed into 0..1 for viewing.
+fn visualize(aov: Aov, layer: &FrameBuffer) -> image::RgbImage {
+    let max = |f: fn(&Vec3) -> f64| layer.data.iter().map(f).fold(0.0, f64::max);
+    let min = |f: fn(&Vec3) -> f64| layer.data.iter().map(f).fold(f64::INFINITY, f64::min);
+    let (lo, hi) = match aov {
+        Aov::Position => (
+            Vec3::new(min(|c| c.x), min(|c| c.y), min(|c| c.z)),
+            Vec3::new(max(|c| c.x), max(|c| c.y), max(|c| c.z)),
+        ),
+        _ => (Vec3::zero(), Vec3::ones()),
+    };
+    let max_depth = max(|c| c.x).max(1e-9);
+    ImageBuffer::from_fn(layer.width, layer.height, |x, y| {
+        let c = layer.get(x, y);
+        let c = match aov {
+            Aov::Depth => Vec3::ones() * (c.x / max_depth),
+            Aov::Normal => c * 0.5 + 0.5,
+            Aov::Albedo => Vec3::new(srgb_oetf(c.x), srgb_oetf(c.y), srgb_oetf(c.z)),
+            Aov::Position => (c - lo) / (hi - lo + 1e-9),
+            Aov::Uv => c,
+            Aov::Id => id_color(c.x as u64),
+        };
+        let q = |v: f64| (255.0 * clamp(v, 0.0, 1.0) + 0.5) as u8;
+        Rgb([q(c.x), q(c.y), q(c.z)])
+    })
+}
+
+// a stable, well spread color per id
+fn id_color(id: u64) -> Vec3 {
+    if id == 0 {
+        return Vec3::zero();
+    }
+    let mut h = id.wrapping_mul(0x9e37_79b9_7f4a_7c15);
+    h ^= h >> 29;
+    let channel = |shift: u32| 0.2 + 0.8 * ((h >> shift) & 0xff) as f64 / 255.0;
+    Vec3::new(channel(0), channel(8), channel(16))
+}
@@ -0,0 +1,1204 @@
+use crate::material::Dielectric;
+use crate::tonemap::luminance;
+use crate::{clamp, Vec3};
+use std::f64::consts::PI;
+use std::ops::BitOr;
+
+// Kinds of scattering a BSDF sample came from, combined with `|`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Lobe(pub u32);
+
+impl Lobe {
+    pub const DIFFUSE: Lobe = Lobe(1);
+    pub const GLOSSY: Lobe = Lobe(2);
+    // a delta distribution: `eval` and `pdf` are zero for every direction
+    pub const SPECULAR: Lobe = Lobe(4);
+    pub const REFLECTION: Lobe = Lobe(8);
+    pub const TRANSMISSION: Lobe = Lobe(16);
+
+    pub fn contains(self, other: Lobe) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl BitOr for Lobe {
+    type Output = Lobe;
+    fn bitor(self, other: Lobe) -> Lobe {
+        Lobe(self.0 | other.0)
+    }
+}
+
+pub struct BsdfSample {
+    pub wi: Vec3,
+    // eval(wo, wi) / pdf, what the path throughput gets multiplied by
+    pub weight: Vec3,
+    // for specular lobes the probability of having picked that lobe
+    pub pdf: f64,
+    pub lobe: Lobe,
+}
+
+// Scattering at a point, in the local frame of its shading normal: z is the normal (on the
+// side the ray came from), `wo` points back along the incoming ray and `wi` towards where
+// light arrives from, both unit vectors.
+pub trait Bsdf {
+    // The BSDF times |cos| of `wi`, so phase functions, which have no cosine, fit as well.
+    fn eval(&self, wo: Vec3, wi: Vec3) -> Vec3;
+    // Picks `wi` from uniform numbers: `uc` chooses between lobes, `u` the direction within
+    // one. None when the sample is absorbed.
+    fn sample(&self, wo: Vec3, uc: f64, u: (f64, f64)) -> Option<BsdfSample>;
+    // Density of `sample` returning `wi`, per unit solid angle.
+    fn pdf(&self, wo: Vec3, wi: Vec3) -> f64;
+    // every lobe the BSDF has; a purely specular one gains nothing from light sampling
+    fn lobes(&self) -> Lobe;
+    // color for the albedo AOV
+    fn albedo(&self) -> Vec3;
+    // whether it scatters each wavelength differently, so a spectral path can only follow
+    // its hero wavelength on from here
+    fn hero_only(&self) -> bool {
+        false
+    }
+}
+
+// A BSDF set up for the hero wavelength of a dispersive material.
+pub struct Dispersive(pub Box<dyn Bsdf>);
+
+impl Bsdf for Dispersive {
+    fn eval(&self, wo: Vec3, wi: Vec3) -> Vec3 {
+        self.0.eval(wo, wi)
+    }
+    fn sample(&self, wo: Vec3, uc: f64, u: (f64, f64)) -> Option<BsdfSample> {
+        self.0.sample(wo, uc, u)
+    }
+    fn pdf(&self, wo: Vec3, wi: Vec3) -> f64 {
+        self.0.pdf(wo, wi)
+    }
+    fn lobes(&self) -> Lobe {
+        self.0.lobes()
+    }
+    fn albedo(&self) -> Vec3 {
+        self.0.albedo()
+    }
+    fn hero_only(&self) -> bool {
+        true
+    }
+}
+
+fn same_hemisphere(a: Vec3, b: Vec3) -> bool {
+    a.z * b.z > 0.0
+}
+
+fn mirror(wo: Vec3) -> Vec3 {
+    Vec3::new(-wo.x, -wo.y, wo.z)
+}
+
+pub fn cosine_hemisphere(u: (f64, f64)) -> Vec3 {
+    let phi = 2.0 * PI * u.0;
+    let r = u.1.sqrt();
+    Vec3::new(phi.cos() * r, phi.sin() * r, (1.0 - u.1).max(0.0).sqrt())
+}
+
+pub fn uniform_sphere(u: (f64, f64)) -> Vec3 {
+    let z = 1.0 - 2.0 * u.1;
+    let r = (1.0 - z * z).max(0.0).sqrt();
+    let phi = 2.0 * PI * u.0;
+    Vec3::new(r * phi.cos(), r * phi.sin(), z)
+}
+
+pub struct LambertianBsdf {
+    pub albedo: Vec3,
+}
+
+impl Bsdf for LambertianBsdf {
+    fn eval(&self, wo: Vec3, wi: Vec3) -> Vec3 {
+        if !same_hemisphere(wo, wi) {
+            return Vec3::zero();
+        }
+        self.albedo * (wi.z.abs() / PI)
+    }
+    fn sample(&self, wo: Vec3, _uc: f64, u: (f64, f64)) -> Option<BsdfSample> {
+        let mut wi = cosine_hemisphere(u);
+        if wo.z < 0.0 {
+            wi.z = -wi.z;
+        }
+        if wi.z == 0.0 {
+            return None;
+        }
+        Some(BsdfSample {
+            wi,
+            weight: self.albedo,
+            pdf: wi.z.abs() / PI,
+            lobe: Lobe::DIFFUSE | Lobe::REFLECTION,
+        })
+    }
+    fn pdf(&self, wo: Vec3, wi: Vec3) -> f64 {
+        if !same_hemisphere(wo, wi) {
+            return 0.0;
+        }
+        wi.z.abs() / PI
+    }
+    fn lobes(&self) -> Lobe {
+        Lobe::DIFFUSE | Lobe::REFLECTION
+    }
+    fn albedo(&self) -> Vec3 {
+        self.albedo
+    }
+}
+
+// The mirror direction pushed by a random point of a ball of radius `fuzz`. That offset
+// has a closed-form density: the part of the ball's volume seen along `wi`, as a cone of
+// directions from the reflection point.
+pub struct FuzzyReflection {
+    pub albedo: Vec3,
+    pub fuzz: f64,
+}
+
+impl FuzzyReflection {
+    // Density of the direction of `r + f * (point in the unit ball)`, |r| = 1.
+    fn ball_pdf(&self, r: Vec3, wi: Vec3) -> f64 {
+        let cos_a = Vec3::dot(r, wi);
+        let d = self.fuzz * self.fuzz - (1.0 - cos_a * cos_a);
+        if d <= 0.0 {
+            return 0.0;
+        }
+        // where the line along wi enters and leaves the ball, clamped to the forward half
+        let t1 = (cos_a - d.sqrt()).max(0.0);
+        let t2 = (cos_a + d.sqrt()).max(0.0);
+        (t2.powi(3) - t1.powi(3)) / (4.0 * PI * self.fuzz.powi(3))
+    }
+}
+
+impl Bsdf for FuzzyReflection {
+    fn eval(&self, wo: Vec3, wi: Vec3) -> Vec3 {
+        if !same_hemisphere(wo, wi) {
+            return Vec3::zero();
+        }
+        // sampling is exact, so the weight of every sample is the albedo
+        self.albedo * self.ball_pdf(mirror(wo), wi)
+    }
+    fn sample(&self, wo: Vec3, uc: f64, u: (f64, f64)) -> Option<BsdfSample> {
+        let r = mirror(wo);
+        // uniform in the ball: a uniform direction at a cube-root distributed radius
+        let wi = Vec3::unit_vector(r + uniform_sphere(u) * (self.fuzz * uc.cbrt()));
+        if !same_hemisphere(wo, wi) {
+            return None;
+        }
+        Some(BsdfSample {
+            wi,
+            weight: self.albedo,
+            pdf: self.ball_pdf(r, wi),
+            lobe: Lobe::GLOSSY | Lobe::REFLECTION,
+        })
+    }
+    fn pdf(&self, wo: Vec3, wi: Vec3) -> f64 {
+        if !same_hemisphere(wo, wi) {
+            return 0.0;
+        }
+        self.ball_pdf(mirror(wo), wi)
+    }
+    fn lobes(&self) -> Lobe {
+        Lobe::GLOSSY | Lobe::REFLECTION
+    }
+    fn albedo(&self) -> Vec3 {
+        self.albedo
+    }
+}
+
+pub struct SpecularReflection {
+    pub albedo: Vec3,
+}
+
+impl Bsdf for SpecularReflection {
+    fn eval(&self, _wo: Vec3, _wi: Vec3) -> Vec3 {
+        Vec3::zero()
+    }
+    fn sample(&self, wo: Vec3, _uc: f64, _u: (f64, f64)) -> Option<BsdfSample> {
+        Some(BsdfSample {
+            wi: mirror(wo),
+            weight: self.albedo,
+            pdf: 1.0,
+            lobe: Lobe::SPECULAR | Lobe::REFLECTION,
+        })
+    }
+    fn pdf(&self, _wo: Vec3, _wi: Vec3) -> f64 {
+        0.0
+    }
+    fn lobes(&self) -> Lobe {
+        Lobe::SPECULAR | Lobe::REFLECTION
+    }
+    fn albedo(&self) -> Vec3 {
+        self.albedo
+    }
+}
+
+// Smooth glass: reflects with Schlick's Fresnel probability and refracts otherwise.
+pub struct SpecularDielectric {
+    // index of refraction on the side `wo` is on over the one across the surface
+    pub eta: f64,
+}
+
+impl Bsdf for SpecularDielectric {
+    fn eval(&self, _wo: Vec3, _wi: Vec3) -> Vec3 {
+        Vec3::zero()
+    }
+    fn sample(&self, wo: Vec3, uc: f64, _u: (f64, f64)) -> Option<BsdfSample> {
+        let cos_theta = wo.z.abs().min(1.0);
+        let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+        let reflect = if self.eta * sin_theta > 1.0 {
+            1.0
+        } else {
+            Dielectric::schlick(cos_theta, self.eta)
+        };
+        let n = Vec3::new(0.0, 0.0, wo.z.signum());
+        if uc < reflect {
+            Some(BsdfSample {
+                wi: mirror(wo),
+                weight: Vec3::ones(),
+                pdf: reflect,
+                lobe: Lobe::SPECULAR | Lobe::REFLECTION,
+            })
+        } else {
+            Some(BsdfSample {
+                wi: Vec3::unit_vector(Vec3::refract(wo * -1.0, n, self.eta)),
+                weight: Vec3::ones(),
+                pdf: 1.0 - reflect,
+                lobe: Lobe::SPECULAR | Lobe::TRANSMISSION,
+            })
+        }
+    }
+    fn pdf(&self, _wo: Vec3, _wi: Vec3) -> f64 {
+        0.0
+    }
+    fn lobes(&self) -> Lobe {
+        Lobe::SPECULAR | Lobe::REFLECTION | Lobe::TRANSMISSION
+    }
+    fn albedo(&self) -> Vec3 {
+        Vec3::ones()
+    }
+}
+
+// The phase function of a medium that scatters equally in every direction.
+pub struct IsotropicPhase {
+    pub albedo: Vec3,
+}
+
+impl Bsdf for IsotropicPhase {
+    fn eval(&self, _wo: Vec3, _wi: Vec3) -> Vec3 {
+        self.albedo / (4.0 * PI)
+    }
+    fn sample(&self, _wo: Vec3, _uc: f64, u: (f64, f64)) -> Option<BsdfSample> {
+        Some(BsdfSample {
+            wi: uniform_sphere(u),
+            weight: self.albedo,
+            pdf: 1.0 / (4.0 * PI),
+            lobe: Lobe::DIFFUSE | Lobe::REFLECTION | Lobe::TRANSMISSION,
+        })
+    }
+    fn pdf(&self, _wo: Vec3, _wi: Vec3) -> f64 {
+        1.0 / (4.0 * PI)
+    }
+    fn lobes(&self) -> Lobe {
+        Lobe::DIFFUSE | Lobe::REFLECTION | Lobe::TRANSMISSION
+    }
+    fn albedo(&self) -> Vec3 {
+        self.albedo
+    }
+}
+
+// Trowbridge-Reitz (GGX) distribution of microfacet normals, stretched by `alpha_x` along
+// the frame's x axis and `alpha_y` along y.
+#[derive(Copy, Clone, Debug)]
+pub struct TrowbridgeReitz {
+    pub alpha_x: f64,
+    pub alpha_y: f64,
+}
+
+impl TrowbridgeReitz {
+    pub fn new(alpha_x: f64, alpha_y: f64) -> Self {
+        // below this the lobes get too narrow to evaluate in floating point
+        Self {
+            alpha_x: alpha_x.max(1e-4),
+            alpha_y: alpha_y.max(1e-4),
+        }
+    }
+
+    // alpha = roughness², which makes roughness perceptually linear. `anisotropy` in [0, 1]
+    // stretches the highlight along x at constant area.
+    pub fn from_roughness(roughness: f64, anisotropy: f64) -> Self {
+        let alpha = roughness * roughness;
+        let aspect = (1.0 - 0.9 * clamp(anisotropy, 0.0, 1.0)).sqrt();
+        TrowbridgeReitz::new(alpha / aspect, alpha * aspect)
+    }
+
+    // too narrow to sample as anything but a perfect mirror
+    pub fn effectively_smooth(&self) -> bool {
+        self.alpha_x.max(self.alpha_y) < 1e-3
+    }
+
+    pub fn d(&self, wm: Vec3) -> f64 {
+        let cos2 = wm.z * wm.z;
+        let cos4 = cos2 * cos2;
+        if cos4 < 1e-16 {
+            return 0.0;
+        }
+        let tan2 = (1.0 - cos2).max(0.0) / cos2;
+        let (cos2_phi, sin2_phi) = cos_sin2_phi(wm);
+        let e = tan2
+            * (cos2_phi / (self.alpha_x * self.alpha_x) + sin2_phi / (self.alpha_y * self.alpha_y));
+        1.0 / (PI * self.alpha_x * self.alpha_y * cos4 * (1.0 + e) * (1.0 + e))
+    }
+
+    fn lambda(&self, w: Vec3) -> f64 {
+        let cos2 = w.z * w.z;
+        if cos2 == 0.0 {
+            return 0.0;
+        }
+        let tan2 = (1.0 - cos2).max(0.0) / cos2;
+        let (cos2_phi, sin2_phi) = cos_sin2_phi(w);
+        let alpha2 =
+            cos2_phi * self.alpha_x * self.alpha_x + sin2_phi * self.alpha_y * self.alpha_y;
+        ((1.0 + alpha2 * tan2).sqrt() - 1.0) / 2.0
+    }
+
+    // Smith masking of one direction
+    pub fn g1(&self, w: Vec3) -> f64 {
+        1.0 / (1.0 + self.lambda(w))
+    }
+
+    // height-correlated Smith masking-shadowing
+    pub fn g(&self, wo: Vec3, wi: Vec3) -> f64 {
+        1.0 / (1.0 + self.lambda(wo) + self.lambda(wi))
+    }
+
+    // Density of the normals visible from `w`, which is what `sample_wm` draws from.
+    pub fn pdf(&self, w: Vec3, wm: Vec3) -> f64 {
+        if w.z == 0.0 {
+            return 0.0;
+        }
+        self.g1(w) / w.z.abs() * self.d(wm) * Vec3::dot(w, wm).abs()
+    }
+
+    // Samples a normal visible from `w` (Heitz 2018): the view is stretched to the
+    // isotropic unit-roughness configuration, where the visible normals project to a disk.
+    pub fn sample_wm(&self, w: Vec3, u: (f64, f64)) -> Vec3 {
+        let mut wh = Vec3::unit_vector(Vec3::new(self.alpha_x * w.x, self.alpha_y * w.y, w.z));
+        if wh.z < 0.0 {
+            wh *= -1.0;
+        }
+        let t1 = if wh.z < 0.99999 {
+            Vec3::unit_vector(Vec3::cross(Vec3::new(0.0, 0.0, 1.0), wh))
+        } else {
+            Vec3::new(1.0, 0.0, 0.0)
+        };
+        let t2 = Vec3::cross(wh, t1);
+        let r = u.0.sqrt();
+        let phi = 2.0 * PI * u.1;
+        let (px, py) = (r * phi.cos(), r * phi.sin());
+        // squeeze the disk into the part of the hemisphere seen from wh
+        let h = (1.0 - px * px).max(0.0).sqrt();
+        let t = (1.0 + wh.z) / 2.0;
+        let py = (1.0 - t) * h + t * py;
+        let pz = (1.0 - px * px - py * py).max(0.0).sqrt();
+        let nh = t1 * px + t2 * py + wh * pz;
+        Vec3::unit_vector(Vec3::new(
+            self.alpha_x * nh.x,
+            self.alpha_y * nh.y,
+            nh.z.max(1e-6),
+        ))
+    }
+}
+
+fn cos_sin2_phi(w: Vec3) -> (f64, f64) {
+    let sin2 = w.x * w.x + w.y * w.y;
+    if sin2 == 0.0 {
+        return (1.0, 0.0);
+    }
+    (w.x * w.x / sin2, w.y * w.y / sin2)
+}
+
+fn reflect(wo: Vec3, n: Vec3) -> Vec3 {
+    n * (2.0 * Vec3::dot(wo, n)) - wo
+}
+
+// Refracts `wi` through a surface with normal `n` and relative index `eta` (across the
+// surface over wi's side, for n on wi's side). Also returns the eta actually used, None on
+// total internal reflection.
+fn refract(wi: Vec3, mut n: Vec3, mut eta: f64) -> Option<(Vec3, f64)> {
+    let mut cos_i = Vec3::dot(n, wi);
+    if cos_i < 0.0 {
+        eta = 1.0 / eta;
+        cos_i = -cos_i;
+        n *= -1.0;
+    }
+    let sin2_i = (1.0 - cos_i * cos_i).max(0.0);
+    let sin2_t = sin2_i / (eta * eta);
+    if sin2_t >= 1.0 {
+        return None;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    Some((wi * (-1.0 / eta) + n * (cos_i / eta - cos_t), eta))
+}
+
+// Unpolarized Fresnel reflectance of a dielectric interface, `eta` as in `refract`.
+pub fn fresnel_dielectric(cos_i: f64, mut eta: f64) -> f64 {
+    let mut cos_i = clamp(cos_i, -1.0, 1.0);
+    if cos_i < 0.0 {
+        eta = 1.0 / eta;
+        cos_i = -cos_i;
+    }
+    let sin2_t = (1.0 - cos_i * cos_i) / (eta * eta);
+    if sin2_t >= 1.0 {
+        return 1.0;
+    }
+    let cos_t = (1.0 - sin2_t).max(0.0).sqrt();
+    let r_parl = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    let r_perp = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    (r_parl * r_parl + r_perp * r_perp) / 2.0
+}
+
+#[derive(Copy, Clone)]
+struct Complex {
+    re: f64,
+    im: f64,
+}
+
+impl Complex {
+    fn new(re: f64, im: f64) -> Self {
+        Self { re, im }
+    }
+    fn add(self, o: Complex) -> Complex {
+        Complex::new(self.re + o.re, self.im + o.im)
+    }
+    fn sub(self, o: Complex) -> Complex {
+        Complex::new(self.re - o.re, self.im - o.im)
+    }
+    fn mul(self, o: Complex) -> Complex {
+        Complex::new(
+            self.re * o.re - self.im * o.im,
+            self.re * o.im + self.im * o.re,
+        )
+    }
+    fn div(self, o: Complex) -> Complex {
+        let scale = 1.0 / (o.re * o.re + o.im * o.im);
+        Complex::new(
+            scale * (self.re * o.re + self.im * o.im),
+            scale * (self.im * o.re - self.re * o.im),
+        )
+    }
+    fn norm(self) -> f64 {
+        self.re * self.re + self.im * self.im
+    }
+    fn sqrt(self) -> Complex {
+        let n = self.norm().sqrt();
+        if n == 0.0 {
+            return Complex::new(0.0, 0.0);
+        }
+        let t1 = (0.5 * (n + self.re.abs())).sqrt();
+        let t2 = 0.5 * self.im / t1;
+        if self.re >= 0.0 {
+            Complex::new(t1, t2)
+        } else {
+            Complex::new(t2.abs(), t1.copysign(self.im))
+        }
+    }
+}
+
+// Fresnel reflectance of a conductor with complex index eta + ik, per channel.
+pub fn fresnel_complex(cos_i: f64, eta: Vec3, k: Vec3) -> Vec3 {
+    let channel = |eta: f64, k: f64| {
+        let cos_i = clamp(cos_i, 0.0, 1.0);
+        let eta = Complex::new(eta, k);
+        let sin2_i = Complex::new(1.0 - cos_i * cos_i, 0.0);
+        let sin2_t = sin2_i.div(eta.mul(eta));
+        let cos_t = Complex::new(1.0, 0.0).sub(sin2_t).sqrt();
+        let ci = Complex::new(cos_i, 0.0);
+        let r_parl = eta.mul(ci).sub(cos_t).div(eta.mul(ci).add(cos_t));
+        let r_perp = ci.sub(eta.mul(cos_t)).div(ci.add(eta.mul(cos_t)));
+        (r_parl.norm() + r_perp.norm()) / 2.0
+    };
+    Vec3::new(
+        channel(eta.x, k.x),
+        channel(eta.y, k.y),
+        channel(eta.z, k.z),
+    )
+}
+
+// A metal: GGX microfacets reflecting with the conductor Fresnel term.
+pub struct MicrofacetConductor {
+    pub distribution: TrowbridgeReitz,
+    pub eta: Vec3,
+    pub k: Vec3,
+}
+
+impl Bsdf for MicrofacetConductor {
+    fn eval(&self, wo: Vec3, wi: Vec3) -> Vec3 {
+        if !same_hemisphere(wo, wi) || self.distribution.effectively_smooth() {
+            return Vec3::zero();
+        }
+        let (cos_o, cos_i) = (wo.z.abs(), wi.z.abs());
+        if cos_i == 0.0 || cos_o == 0.0 {
+            return Vec3::zero();
+        }
+        let wm = wi + wo;
+        if wm.length_squared() == 0.0 {
+            return Vec3::zero();
+        }
+        let wm = Vec3::unit_vector(wm);
+        let f = fresnel_complex(Vec3::dot(wo, wm).abs(), self.eta, self.k);
+        f * (self.distribution.d(wm) * self.distribution.g(wo, wi) / (4.0 * cos_o))
+    }
+    fn sample(&self, wo: Vec3, _uc: f64, u: (f64, f64)) -> Option<BsdfSample> {
+        if self.distribution.effectively_smooth() {
+            return Some(BsdfSample {
+                wi: mirror(wo),
+                weight: fresnel_complex(wo.z.abs(), self.eta, self.k),
+                pdf: 1.0,
+                lobe: Lobe::SPECULAR | Lobe::REFLECTION,
+            });
+        }
+        if wo.z == 0.0 {
+            return None;
+        }
+        let wm = self.distribution.sample_wm(wo, u);
+        let wi = reflect(wo, wm);
+        if !same_hemisphere(wo, wi) {
+            return None;
+        }
+        let pdf = self.pdf(wo, wi);
+        if pdf == 0.0 {
+            return None;
+        }
+        Some(BsdfSample {
+            wi,
+            weight: self.eval(wo, wi) / pdf,
+            pdf,
+            lobe: Lobe::GLOSSY | Lobe::REFLECTION,
+        })
+    }
+    fn pdf(&self, wo: Vec3, wi: Vec3) -> f64 {
+        if !same_hemisphere(wo, wi) || self.distribution.effectively_smooth() {
+            return 0.0;
+        }
+        let wm = wi + wo;
+        if wm.length_squared() == 0.0 {
+            return 0.0;
+        }
+        let mut wm = Vec3::unit_vector(wm);
+        if wm.z < 0.0 {
+            wm *= -1.0;
+        }
+        self.distribution.pdf(wo, wm) / (4.0 * Vec3::dot(wo, wm).abs())
+    }
+    fn lobes(&self) -> Lobe {
+        if self.distribution.effectively_smooth() {
+            Lobe::SPECULAR | Lobe::REFLECTION
+        } else {
+            Lobe::GLOSSY | Lobe::REFLECTION
+        }
+    }
+    fn albedo(&self) -> Vec3 {
+        fresnel_complex(1.0, self.eta, self.k)
+    }
+}
+
+// Rough glass (Walter et al. 2007): GGX microfacets that reflect or refract according to
+// the exact dielectric Fresnel term. Like `SpecularDielectric`, transmitted radiance is not
+// rescaled by eta², which cancels out for light that leaves a closed object again.
+pub struct MicrofacetDielectric {
+    pub distribution: TrowbridgeReitz,
+    // as in `SpecularDielectric`, wo's side over across
+    pub eta: f64,
+}
+
+impl MicrofacetDielectric {
+    // The half vector of a reflection or refraction between wo and wi, facing +z, and the
+    // relative index of wi's side over wo's. None for degenerate configurations.
+    fn half_vector(&self, wo: Vec3, wi: Vec3) -> Option<(Vec3, f64)> {
+        let (cos_o, cos_i) = (wo.z, wi.z);
+        if cos_i == 0.0 || cos_o == 0.0 {
+            return None;
+        }
+        let etap = if cos_o * cos_i > 0.0 {
+            1.0
+        } else if cos_o > 0.0 {
+            1.0 / self.eta
+        } else {
+            self.eta
+        };
+        let wm = wi * etap + wo;
+        if wm.length_squared() == 0.0 {
+            return None;
+        }
+        let mut wm = Vec3::unit_vector(wm);
+        if wm.z < 0.0 {
+            wm *= -1.0;
+        }
+        // microfacets seen from behind don't contribute
+        if Vec3::dot(wm, wi) * cos_i < 0.0 || Vec3::dot(wm, wo) * cos_o < 0.0 {
+            return None;
+        }
+        Some((wm, etap))
+    }
+}
+
+impl Bsdf for MicrofacetDielectric {
+    fn eval(&self, wo: Vec3, wi: Vec3) -> Vec3 {
+        if self.distribution.effectively_smooth() {
+            return Vec3::zero();
+        }
+        let (wm, etap) = match self.half_vector(wo, wi) {
+            Some(h) => h,
+            None => return Vec3::zero(),
+        };
+        let f = fresnel_dielectric(Vec3::dot(wo, wm), 1.0 / self.eta);
+        let d = self.distribution.d(wm);
+        let g = self.distribution.g(wo, wi);
+        let value = if same_hemisphere(wo, wi) {
+            d * g * f / (4.0 * wo.z.abs())
+        } else {
+            let denom = Vec3::dot(wi, wm) + Vec3::dot(wo, wm) / etap;
+            d * (1.0 - f) * g * (Vec3::dot(wi, wm) * Vec3::dot(wo, wm)).abs()
+                / (denom * denom * wo.z.abs())
+        };
+        Vec3::ones() * value
+    }
+    fn sample(&self, wo: Vec3, uc: f64, u: (f64, f64)) -> Option<BsdfSample> {
+        if self.distribution.effectively_smooth() {
+            return SpecularDielectric { eta: self.eta }.sample(wo, uc, u);
+        }
+        let wm = self.distribution.sample_wm(wo, u);
+        let r = fresnel_dielectric(Vec3::dot(wo, wm), 1.0 / self.eta);
+        let (wi, lobe) = if uc < r {
+            let wi = reflect(wo, wm);
+            if !same_hemisphere(wo, wi) {
+                return None;
+            }
+            (wi, Lobe::GLOSSY | Lobe::REFLECTION)
+        } else {
+            let (wi, _) = refract(wo, wm, 1.0 / self.eta)?;
+            if same_hemisphere(wo, wi) || wi.z == 0.0 {
+                return None;
+            }
+            (wi, Lobe::GLOSSY | Lobe::TRANSMISSION)
+        };
+        let pdf = self.pdf(wo, wi);
+        if pdf == 0.0 {
+            return None;
+        }
+        Some(BsdfSample {
+            wi,
+            weight: self.eval(wo, wi) / pdf,
+            pdf,
+            lobe,
+        })
+    }
+    fn pdf(&self, wo: Vec3, wi: Vec3) -> f64 {
+        if self.distribution.effectively_smooth() {
+            return 0.0;
+        }
+        let (wm, etap) = match self.half_vector(wo, wi) {
+            Some(h) => h,
+            None => return 0.0,
+        };
+        let r = fresnel_dielectric(Vec3::dot(wo, wm), 1.0 / self.eta);
+        let pdf_wm = self.distribution.pdf(wo, wm);
+        if same_hemisphere(wo, wi) {
+            pdf_wm / (4.0 * Vec3::dot(wo, wm).abs()) * r
+        } else {
+            let denom = Vec3::dot(wi, wm) + Vec3::dot(wo, wm) / etap;
+            pdf_wm * Vec3::dot(wi, wm).abs() / (denom * denom) * (1.0 - r)
+        }
+    }
+    fn lobes(&self) -> Lobe {
+        if self.distribution.effectively_smooth() {
+            Lobe::SPECULAR | Lobe::REFLECTION | Lobe::TRANSMISSION
+        } else {
+            Lobe::GLOSSY | Lobe::REFLECTION | Lobe::TRANSMISSION
+        }
+    }
+    fn albedo(&self) -> Vec3 {
+        Vec3::ones()
+    }
+}
+
+fn schlick_color(f0: Vec3, cos: f64) -> Vec3 {
+    let m = (1.0 - clamp(cos, 0.0, 1.0)).powi(5);
+    f0 + (Vec3::ones() - f0) * m
+}
+
+// GGX reflection with a Schlick Fresnel term, times |cos| of wi.
+fn ggx_reflection(distribution: &TrowbridgeReitz, f0: Vec3, wo: Vec3, wi: Vec3) -> Vec3 {
+    let wm = wi + wo;
+    if wm.length_squared() == 0.0 || wo.z == 0.0 {
+        return Vec3::zero();
+    }
+    let wm = Vec3::unit_vector(wm);
+    schlick_color(f0, Vec3::dot(wo, wm))
+        * (distribution.d(wm) * distribution.g(wo, wi) / (4.0 * wo.z))
+}
+
+fn ggx_reflection_pdf(distribution: &TrowbridgeReitz, wo: Vec3, wi: Vec3) -> f64 {
+    let wm = wi + wo;
+    if wm.length_squared() == 0.0 {
+        return 0.0;
+    }
+    let wm = Vec3::unit_vector(wm);
+    distribution.pdf(wo, wm) / (4.0 * Vec3::dot(wo, wm).abs())
+}
+
+// Energy compensation (Turquin et al. 2019). Single-scattering GGX drops the light that
+// bounces more than once between microfacets, 70% of it head-on at roughness 1; scaling a
+// lobe by 1 / E(cos θo), its directional albedo when white, gives that back. The albedos are
+// integrated over the visible normals once per thread, on 16 x 16 stratified normals each.
+const ALBEDO_COS: usize = 16;
+const ALBEDO_ROUGHNESS: usize = 16;
+// f0 of the glass's ior from 0 to 0.08, all that `PrincipledParams::specular` covers
+const ALBEDO_F0: usize = 5;
+
+struct AlbedoTables {
+    // a white GGX mirror, by cos θo then roughness
+    conductor: Vec<f64>,
+    // rough glass, by cos θo, roughness then f0, entered from outside and left from inside
+    glass: [Vec<f64>; 2],
+}
+
+thread_local! {
+    static ALBEDO: AlbedoTables = AlbedoTables::new();
+}
+
+impl AlbedoTables {
+    fn new() -> Self {
+        let mut conductor = Vec::new();
+        let mut glass = [Vec::new(), Vec::new()];
+        for i in 0..ALBEDO_COS {
+            let cos = (i as f64 + 0.5) / ALBEDO_COS as f64;
+            let wo = Vec3::new((1.0 - cos * cos).sqrt(), 0.0, cos);
+            for j in 0..ALBEDO_ROUGHNESS {
+                let roughness = j as f64 / (ALBEDO_ROUGHNESS - 1) as f64;
+                let distribution = TrowbridgeReitz::from_roughness(roughness, 0.0);
+                conductor.push(visible_normals_mean(&distribution, wo, |wm| {
+                    let wi = reflect(wo, wm);
+                    if wi.z > 0.0 {
+                        distribution.g(wo, wi)
+                    } else {
+                        0.0
+                    }
+                }));
+                for k in 0..ALBEDO_F0 {
+                    let f0 = (0.08 * k as f64 / (ALBEDO_F0 - 1) as f64).max(1e-4);
+                    let ior = (1.0 + f0.sqrt()) / (1.0 - f0.sqrt());
+                    for (inside, table) in glass.iter_mut().enumerate() {
+                        let eta = if inside == 1 { ior } else { 1.0 / ior };
+                        table.push(visible_normals_mean(&distribution, wo, |wm| {
+                            let f = fresnel_dielectric(Vec3::dot(wo, wm), 1.0 / eta);
+                            let mut e = 0.0;
+                            let wi = reflect(wo, wm);
+                            if wi.z > 0.0 {
+                                e += f * distribution.g(wo, wi);
+                            }
+                            if let Some((wi, _)) = refract(wo, wm, 1.0 / eta) {
+                                if wi.z < 0.0 {
+                                    e += (1.0 - f) * distribution.g(wo, wi);
+                                }
+                            }
+                            e
+                        }));
+                    }
+                }
+            }
+        }
+        Self { conductor, glass }
+    }
+}
+
+// The mean of `g(wm)` / G1(wo) over the normals visible from `wo`: with `g` the masking and
+// shadowing of where each reflects or refracts to, the albedo of the lobe.
+fn visible_normals_mean(distribution: &TrowbridgeReitz, wo: Vec3, g: impl Fn(Vec3) -> f64) -> f64 {
+    let n = 16;
+    let mut sum = 0.0;
+    for a in 0..n {
+        for b in 0..n {
+            let u = ((a as f64 + 0.5) / n as f64, (b as f64 + 0.5) / n as f64);
+            sum += g(distribution.sample_wm(wo, u));
+        }
+    }
+    sum / (n * n) as f64 / distribution.g1(wo)
+}
+
+// Where `x` falls between `n` nodes spread evenly from `first` to `last`: the node below and
+// how far towards the next one.
+fn grid_position(x: f64, n: usize, first: f64, last: f64) -> (usize, f64) {
+    let t = clamp((x - first) / (last - first), 0.0, 1.0) * (n - 1) as f64;
+    let i = (t.floor() as usize).min(n - 2);
+    (i, t - i as f64)
+}
+
+// Roughness as the tables have it, anisotropic lobes taken as isotropic ones of the same area.
+fn table_roughness(distribution: &TrowbridgeReitz) -> f64 {
+    (distribution.alpha_x * distribution.alpha_y).sqrt().sqrt()
+}
+
+fn albedo_position(distribution: &TrowbridgeReitz, cos_o: f64) -> ((usize, f64), (usize, f64)) {
+    let half = 0.5 / ALBEDO_COS as f64;
+    (
+        grid_position(cos_o.abs(), ALBEDO_COS, half, 1.0 - half),
+        grid_position(table_roughness(distribution), ALBEDO_ROUGHNESS, 0.0, 1.0),
+    )
+}
+
+// Directional albedo of a white GGX mirror seen at `cos_o`.
+fn conductor_albedo(distribution: &TrowbridgeReitz, cos_o: f64) -> f64 {
+    let ((i, s), (j, t)) = albedo_position(distribution, cos_o);
+    ALBEDO.with(|tables| {
+        let at = |i: usize, j: usize| tables.conductor[i * ALBEDO_ROUGHNESS + j];
+        (at(i, j) * (1.0 - t) + at(i, j + 1) * t) * (1.0 - s)
+            + (at(i + 1, j) * (1.0 - t) + at(i + 1, j + 1) * t) * s
+    })
+}
+
+// Directional albedo of rough glass seen at `cos_o`, `eta` as in `MicrofacetDielectric`.
+fn glass_albedo(glass: &MicrofacetDielectric, cos_o: f64) -> f64 {
+    let ((i, s), (j, t)) = albedo_position(&glass.distribution, cos_o);
+    let ior = glass.eta.max(1.0 / glass.eta);
+    let f0 = ((ior - 1.0) / (ior + 1.0)).powi(2);
+    let (k, v) = grid_position(f0, ALBEDO_F0, 0.0, 0.08);
+    let inside = (glass.eta > 1.0) as usize;
+    ALBEDO.with(|tables| {
+        let at = |i: usize, j: usize| {
+            let row = &tables.glass[inside][(i * ALBEDO_ROUGHNESS + j) * ALBEDO_F0..];
+            row[k] * (1.0 - v) + row[k + 1] * v
+        };
+        (at(i, j) * (1.0 - t) + at(i, j + 1) * t) * (1.0 - s)
+            + (at(i + 1, j) * (1.0 - t) + at(i + 1, j + 1) * t) * s
+    })
+}
+
+// What a GGX reflection with Fresnel reflectance `f0` is scaled by to make up for its lost
+// bounces: 1 / E for a white mirror, and less the less each bounce keeps.
+fn conductor_compensation(distribution: &TrowbridgeReitz, f0: Vec3, cos_o: f64) -> Vec3 {
+    let e = conductor_albedo(distribution, cos_o);
+    Vec3::ones() + f0 * (1.0 / e - 1.0)
+}
+
+// The parameters of the principled BSDF, all in [0, 1] except the color.
+#[derive(Copy, Clone, Debug)]
+pub struct PrincipledParams {
+    pub base_color: Vec3,
+    pub metallic: f64,
+    pub roughness: f64,
+    // reflectance of the dielectric at normal incidence, 0.08 * specular; 0.5 is an ior of 1.5
+    pub specular: f64,
+    // how much of the base color the dielectric reflection takes
+    pub specular_tint: f64,
+    // a grazing retro-reflective rim, as on cloth
+    pub sheen: f64,
+    pub sheen_tint: f64,
+    // a second, colorless GGX layer on top of everything
+    pub clearcoat: f64,
+    pub clearcoat_gloss: f64,
+    // how much of the dielectric is rough glass instead of a diffuse base
+    pub transmission: f64,
+    pub anisotropy: f64,
+}
+
+impl Default for PrincipledParams {
+    fn default() -> Self {
+        Self {
+            base_color: Vec3::new(0.8, 0.8, 0.8),
+            metallic: 0.0,
+            roughness: 0.5,
+            specular: 0.5,
+            specular_tint: 0.0,
+            sheen: 0.0,
+            sheen_tint: 0.5,
+            clearcoat: 0.0,
+            clearcoat_gloss: 1.0,
+            transmission: 0.0,
+            anisotropy: 0.0,
+        }
+    }
+}
+
+// Burley's principled BSDF (Disney 2012/2015), built as layers: a clearcoat over a blend of
+// a metal, rough glass and a dielectric, which is a GGX specular layer over a diffuse base
+// with sheen. Each layer only gets the light the ones above it let through, so with a white
+// base color it never reflects more than a percent or two above what it receives. The glossy
+// layers are energy compensated, so they keep it all too; only the diffuse base loses some, up
+// to 5% head-on, 10% at 65° and a third at grazing angles, as it only gets what Schlick's
+// Fresnel at the shading normal lets through. The furnace test below holds it to this.
+pub struct PrincipledBsdf {
+    base_color: Vec3,
+    roughness: f64,
+    metallic: f64,
+    transmission: f64,
+    clearcoat: f64,
+    specular_f0: Vec3,
+    sheen: Vec3,
+    distribution: TrowbridgeReitz,
+    clearcoat_distribution: TrowbridgeReitz,
+    glass: MicrofacetDielectric,
+}
+
+impl PrincipledBsdf {
+    // `front_face` tells which side of the glass the ray is on.
+    pub fn new(params: &PrincipledParams, front_face: bool) -> Self {
+        let unit = |x: f64| clamp(x, 0.0, 1.0);
+        let base_color = Vec3::new(
+            params.base_color.x.max(0.0),
+            params.base_color.y.max(0.0),
+            params.base_color.z.max(0.0),
+        );
+        let lum = luminance(base_color);
+        let tint = if lum > 0.0 {
+            base_color / lum
+        } else {
+            Vec3::ones()
+        };
+        let lerp = |a: Vec3, b: Vec3, t: f64| a * (1.0 - t) + b * t;
+        let f0 = 0.08 * unit(params.specular);
+        let ior = (1.0 + f0.max(1e-4).sqrt()) / (1.0 - f0.max(1e-4).sqrt());
+        // glossier than this and the lobes would become delta functions, which can't be mixed
+        let roughness = unit(params.roughness);
+        let distribution =
+            TrowbridgeReitz::from_roughness(roughness.max(0.04), unit(params.anisotropy));
+        let gloss = unit(params.clearcoat_gloss);
+        let cc_alpha = 0.1 * (1.0 - gloss) + 0.002 * gloss;
+        Self {
+            base_color,
+            roughness,
+            metallic: unit(params.metallic),
+            transmission: unit(params.transmission),
+            clearcoat: unit(params.clearcoat),
+            specular_f0: lerp(Vec3::ones(), tint, unit(params.specular_tint)) * f0,
+            sheen: lerp(Vec3::ones(), tint, unit(params.sheen_tint)) * unit(params.sheen),
+            distribution,
+            clearcoat_distribution: TrowbridgeReitz::new(cc_alpha, cc_alpha),
+            glass: MicrofacetDielectric {
+                distribution,
+                eta: if front_face { 1.0 / ior } else { ior },
+            },
+        }
+    }
+
+    // Burley's diffuse: darker at grazing angles when smooth, retro-reflective when rough.
+    // Its albedo is within 4% of Lambert's seen head-on, but at grazing angles it goes from
+    // 0.76 when smooth to 1.3 when rough.
+    fn diffuse(&self, cos_o: f64, cos_i: f64, cos_d: f64) -> f64 {
+        let fd90 = 0.5 + 2.0 * cos_d * cos_d * self.roughness;
+        let fl = 1.0 + (fd90 - 1.0) * (1.0 - cos_i).powi(5);
+        let fv = 1.0 + (fd90 - 1.0) * (1.0 - cos_o).powi(5);
+        fl * fv / PI
+    }
+
+    // Probabilities of sampling the diffuse, specular, metal, glass and clearcoat lobes: the
+    // share of the light each layer receives times a rough estimate of its albedo. The
+    // estimates never drop below 0.05, so no lobe that contributes goes unsampled.
+    fn lobe_weights(&self, cos_o: f64) -> [f64; 5] {
+        let estimate = |c: Vec3| luminance(c).max(0.05);
+        let coat = self.clearcoat * schlick_color(Vec3::ones() * 0.04, cos_o).x;
+        let below = 1.0 - coat;
+        let dielectric = below * (1.0 - self.metallic) * (1.0 - self.transmission);
+        let specular = schlick_color(self.specular_f0, cos_o);
+        let mut weights = [
+            dielectric * estimate((Vec3::ones() - specular) * (self.base_color + self.sheen)),
+            dielectric * estimate(specular),
+            below * self.metallic * estimate(schlick_color(self.base_color, cos_o)),
+            below * (1.0 - self.metallic) * self.transmission,
+            coat,
+        ];
+        let total: f64 = weights.iter().sum();
+        if total > 0.0 {
+            for w in weights.iter_mut() {
+                *w /= total;
+            }
+        }
+        weights
+    }
+}
+
+// the local frame faces wo, but flip both directions if it doesn't
+fn facing(wo: Vec3, w: Vec3) -> Vec3 {
+    if wo.z < 0.0 {
+        Vec3::new(w.x, w.y, -w.z)
+    } else {
+        w
+    }
+}
+
+impl Bsdf for PrincipledBsdf {
+    fn eval(&self, wo: Vec3, wi: Vec3) -> Vec3 {
+        let (wo, wi) = (facing(wo, wo), facing(wo, wi));
+        if wo.z == 0.0 {
+            return Vec3::zero();
+        }
+        let mut f = Vec3::zero();
+        let reflect = same_hemisphere(wo, wi);
+        let dielectric = (1.0 - self.metallic) * (1.0 - self.transmission);
+        if reflect && dielectric > 0.0 {
+            let wh = Vec3::unit_vector(wo + wi);
+            let cos_d = Vec3::dot(wi, wh);
+            let diffuse = self.base_color * self.diffuse(wo.z, wi.z, cos_d)
+                + self.sheen * (1.0 - cos_d).powi(5);
+            let through = Vec3::ones() - schlick_color(self.specular_f0, wo.z);
+            let specular = ggx_reflection(&self.distribution, self.specular_f0, wo, wi)
+                * conductor_compensation(&self.distribution, self.specular_f0, wo.z);
+            f += (diffuse * through * wi.z + specular) * dielectric;
+        }
+        if reflect && self.metallic > 0.0 {
+            f += ggx_reflection(&self.distribution, self.base_color, wo, wi)
+                * conductor_compensation(&self.distribution, self.base_color, wo.z)
+                * self.metallic;
+        }
+        let glass = (1.0 - self.metallic) * self.transmission;
+        if glass > 0.0 {
+            let g = self.glass.eval(wo, wi) / glass_albedo(&self.glass, wo.z);
+            f += if reflect { g } else { g * self.base_color } * glass;
+        }
+        if self.clearcoat > 0.0 {
+            let f0 = Vec3::ones() * 0.04;
+            f *= 1.0 - self.clearcoat * schlick_color(f0, wo.z).x;
+            if reflect {
+                let coat = ggx_reflection(&self.clearcoat_distribution, f0, wo, wi)
+                    * conductor_compensation(&self.clearcoat_distribution, f0, wo.z);
+                f += coat * self.clearcoat;
+            }
+        }
+        f
+    }
+    fn sample(&self, wo: Vec3, uc: f64, u: (f64, f64)) -> Option<BsdfSample> {
+        let original = wo;
+        let wo = facing(original, wo);
+        if wo.z == 0.0 {
+            return None;
+        }
+        let weights = self.lobe_weights(wo.z);
+        let mut lobe = 0;
+        let mut start = 0.0;
+        while lobe < 4 && uc >= start + weights[lobe] {
+            start += weights[lobe];
+            lobe += 1;
+        }
+        if weights[lobe] == 0.0 {
+            return None;
+        }
+        let (wi, kind) = match lobe {
+            0 => (cosine_hemisphere(u), Lobe::DIFFUSE | Lobe::REFLECTION),
+            3 => {
+                let uc = clamp((uc - start) / weights[lobe], 0.0, 1.0);
+                let s = self.glass.sample(wo, uc, u)?;
+                (s.wi, s.lobe)
+            }
+            _ => {
+                let distribution = if lobe == 4 {
+                    &self.clearcoat_distribution
+                } else {
+                    &self.distribution
+                };
+                let wm = distribution.sample_wm(wo, u);
+                (reflect(wo, wm), Lobe::GLOSSY | Lobe::REFLECTION)
+            }
+        };
+        if wi.z == 0.0 || (kind.contains(Lobe::REFLECTION) && !same_hemisphere(wo, wi)) {
+            return None;
+        }
+        let wi = facing(original, wi);
+        let pdf = self.pdf(original, wi);
+        if pdf == 0.0 {
+            return None;
+        }
+        Some(BsdfSample {
+            wi,
+            weight: self.eval(original, wi) / pdf,
+            pdf,
+            lobe: kind,
+        })
+    }
+    fn pdf(&self, wo: Vec3, wi: Vec3) -> f64 {
+        let (wo, wi) = (facing(wo, wo), facing(wo, wi));
+        if wo.z == 0.0 {
+            return 0.0;
+        }
+        let w = self.lobe_weights(wo.z);
+        let mut pdf = w[3] * self.glass.pdf(wo, wi);
+        if same_hemisphere(wo, wi) {
+            pdf += w[0] * wi.z / PI
+                + (w[1] + w[2]) * ggx_reflection_pdf(&self.distribution, wo, wi)
+                + w[4] * ggx_reflection_pdf(&self.clearcoat_distribution, wo, wi);
+        }
+        pdf
+    }
+    fn lobes(&self) -> Lobe {
+        let lobes = Lobe::DIFFUSE | Lobe::GLOSSY | Lobe::REFLECTION;
+        if self.transmission > 0.0 && self.metallic < 1.0 {
+            lobes | Lobe::TRANSMISSION
+        } else {
+            lobes
+        }
+    }
+    fn albedo(&self) -> Vec3 {
+        self.base_color
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{random_double, set_seed};
+
+    // Mean sample weight leaving along `wo`: the share of light from a uniformly white sky
+    // that the surface sends back, what a white furnace shows.
+    fn albedo(bsdf: &dyn Bsdf, wo: Vec3, n: usize) -> Vec3 {
+        let mut sum = Vec3::zero();
+        for _ in 0..n {
+            let u = (random_double(), random_double());
+            if let Some(s) = bsdf.sample(wo, random_double(), u) {
+                sum += s.weight;
+            }
+        }
+        sum / n as f64
+    }
+
+    #[test]
+    fn principled_white_furnace() {
+        set_seed(1);
+        let n = 20_000;
+        // what the diffuse base keeps at least, by cosine of the view angle, see the docs
+        let base = [(1.0, 0.95), (0.7, 0.95), (0.4, 0.9), (0.15, 0.65)];
+        // and the glossy layers, which get back what single scattering loses
+        let glossy: f64 = 0.95;
+        for &metallic in &[0.0, 0.5, 1.0] {
+            for &roughness in &[0.0, 0.3, 0.6, 1.0] {
+                for &transmission in &[0.0, 1.0] {
+                    for &clearcoat in &[0.0, 1.0] {
+                        for &front_face in &[true, false] {
+                            let params = PrincipledParams {
+                                base_color: Vec3::ones(),
+                                metallic,
+                                roughness,
+                                transmission,
+                                clearcoat,
+                                ..PrincipledParams::default()
+                            };
+                            let bsdf = PrincipledBsdf::new(&params, front_face);
+                            let diffuse = metallic < 1.0 && transmission < 1.0;
+                            for &(cos, kept) in &base {
+                                let wo = Vec3::new((1.0f64 - cos * cos).sqrt(), 0.0, cos);
+                                let a = albedo(&bsdf, wo, n);
+                                let least = if diffuse { glossy.min(kept) } else { glossy };
+                                let case =
+                                    format!(
+                                    "metallic {}, roughness {}, transmission {}, clearcoat {}, \
+                                     front face {}, cos {}: albedo {:?}",
+                                    metallic, roughness, transmission, clearcoat, front_face, cos, a
+                                );
+                                for c in &[a.x, a.y, a.z] {
+                                    assert!(*c <= 1.02, "{}", case);
+                                    assert!(*c >= least, "{}, expected {}", case, least);
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
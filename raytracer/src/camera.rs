@@ -1,5 +1,6 @@
-use crate::{degrees_to_radians, random_double_lim, Ray, Vec3};
-#[derive(Copy, Clone)]
+use crate::sampler::sample_1d;
+use crate::{degrees_to_radians, Ray, Vec3};
+#[derive(Copy, Clone, Debug)]
 #[warn(dead_code)]
 pub struct Camera {
     pub origin: Vec3,
@@ -71,7 +72,7 @@ impl Camera {
             dir: self.lower_left_corner + self.horizontal * s + self.vertical * t
                 - self.origin
                 - offset,
-            time: random_double_lim(self.time0, self.time1),
+            time: self.time0 + (self.time1 - self.time0) * sample_1d(),
             //time: 0.0,
         };
         ray
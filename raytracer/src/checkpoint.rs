@@ -0,0 +1,320 @@
+use crate::adaptive::PixelStats;
+use crate::aov::AovSample;
+use crate::hit::Hittable;
+use crate::progressive::Accumulator;
+use crate::rtweekend::seed_pixel_sample;
+use crate::AABB::Aabb;
+use crate::{Ray, Vec3};
+use std::f64::consts::PI;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Read, Write};
+
+const MAGIC: &[u8; 8] = b"RTCKPT01";
+
+// What a checkpoint has to match to be resumed. The random numbers of a sample only depend
+// on (seed, pixel, sample index), so with the seed in `settings` the per-pixel sample counts
+// are the RNG positions: a resumed render continues exactly where the old one stopped.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct RenderKey {
+    pub scene: u64,
+    pub camera: u64,
+    // seed, sampler, path settings and AOVs: everything else that decides what a sample returns
+    pub settings: u64,
+}
+
+// FNV-1a
+pub fn hash_bytes(bytes: &[u8]) -> u64 {
+    let mut h = 0xcbf2_9ce4_8422_2325u64;
+    for b in bytes {
+        h ^= *b as u64;
+        h = h.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    h
+}
+
+// Built-in scenes are code, so besides their name (or the scene file's contents) the scene
+// is fingerprinted by shooting a fixed set of rays through it and hashing what they hit.
+pub fn scene_hash(
+    description: &[u8],
+    world: &dyn Hittable,
+    lights: &dyn Hittable,
+    background: Vec3,
+) -> u64 {
+    let mut bytes = description.to_vec();
+    for v in [background.x, background.y, background.z].iter() {
+        push_f64(&mut bytes, *v);
+    }
+    probe(&mut bytes, world, true);
+    probe(&mut bytes, lights, false);
+    hash_bytes(&bytes)
+}
+
+// Rays between points of a sphere around the bounding box. Values are rounded to f32 so
+// last-bit differences between builds don't count as changes.
+fn probe(bytes: &mut Vec<u8>, world: &dyn Hittable, materials: bool) {
+    const RAYS: usize = 1024;
+    let mut bbox = Aabb {
+        minimum: Vec3::zero(),
+        maximum: Vec3::zero(),
+    };
+    if !world.bounding_box(0.0, 1.0, &mut bbox) {
+        return;
+    }
+    let center = (bbox.minimum + bbox.maximum) / 2.0;
+    let radius = (bbox.maximum - bbox.minimum).length().max(1e-3);
+    // Fibonacci sphere
+    let point = |i: usize| {
+        let z = 1.0 - 2.0 * (i as f64 + 0.5) / RAYS as f64;
+        let phi = PI * (3.0 - 5f64.sqrt()) * i as f64;
+        let r = (1.0 - z * z).sqrt();
+        Vec3::new(r * phi.cos(), r * phi.sin(), z)
+    };
+    // media and materials may draw random numbers, start from a fixed stream
+    seed_pixel_sample(u64::MAX, 0);
+    for i in 0..RAYS {
+        let from = center + point(i) * radius;
+        let to = center + point((i * 7 + 3) % RAYS) * (radius / 2.0);
+        let r = Ray::new(from, to - from, 0.0);
+        let mut values = Vec::new();
+        if materials {
+            if let Some(s) = AovSample::trace(r, world) {
+                values.extend_from_slice(&[s.depth, s.u, s.v]);
+                for v in [s.normal, s.albedo, s.position].iter() {
+                    values.extend_from_slice(&[v.x, v.y, v.z]);
+                }
+            }
+        } else if let Some(rec) = world.hit(r, 0.001, f64::INFINITY) {
+            values.extend_from_slice(&[rec.t, rec.u, rec.v, rec.p.x, rec.p.y, rec.p.z]);
+        }
+        bytes.push(values.is_empty() as u8);
+        for v in values {
+            bytes.extend_from_slice(&(v as f32).to_le_bytes());
+        }
+    }
+}
+
+fn push_f64(bytes: &mut Vec<u8>, v: f64) {
+    bytes.extend_from_slice(&v.to_le_bytes());
+}
+
+// Writes the accumulated samples next to `path` first and renames the file over it,
+// so a crash while writing leaves the previous checkpoint intact.
+pub fn save(path: &str, key: &RenderKey, acc: &Accumulator) -> Result<(), String> {
+    let tmp = format!("{}.tmp", path);
+    let mut bytes = Vec::with_capacity(48 + acc.stats.len() * (44 + 24 * acc.aovs.layers.len()));
+    bytes.extend_from_slice(MAGIC);
+    for v in [key.scene, key.camera, key.settings].iter() {
+        bytes.extend_from_slice(&v.to_le_bytes());
+    }
+    for v in [acc.width, acc.height, acc.aovs.layers.len() as u32].iter() {
+        bytes.extend_from_slice(&v.to_le_bytes());
+    }
+    for s in acc.stats.iter() {
+        bytes.extend_from_slice(&s.n.to_le_bytes());
+        for v in [s.sum.x, s.sum.y, s.sum.z, s.mean, s.m2].iter() {
+            push_f64(&mut bytes, *v);
+        }
+    }
+    for layer in acc.aovs.layers.iter() {
+        for c in layer.data.iter() {
+            for v in [c.x, c.y, c.z].iter() {
+                push_f64(&mut bytes, *v);
+            }
+        }
+    }
+    let write = || -> std::io::Result<()> {
+        let mut out = BufWriter::new(File::create(&tmp)?);
+        out.write_all(&bytes)?;
+        out.flush()?;
+        drop(out);
+        fs::rename(&tmp, path)
+    };
+    write().map_err(|e| format!("failed to write checkpoint `{}`: {}", path, e))
+}
+
+// Fills `acc` from the checkpoint at `path`, which must have been written for the same
+// scene, camera, settings and image size.
+pub fn load(path: &str, key: &RenderKey, acc: &mut Accumulator) -> Result<(), String> {
+    let file = File::open(path).map_err(|e| format!("cannot resume from `{}`: {}", path, e))?;
+    let mut input = BufReader::new(file);
+    let corrupt =
+        |e: std::io::Error| format!("checkpoint `{}` is truncated or corrupt: {}", path, e);
+
+    let mut magic = [0u8; 8];
+    input.read_exact(&mut magic).map_err(corrupt)?;
+    if &magic != MAGIC {
+        return Err(format!("`{}` is not a checkpoint", path));
+    }
+    let saved = RenderKey {
+        scene: read_u64(&mut input).map_err(corrupt)?,
+        camera: read_u64(&mut input).map_err(corrupt)?,
+        settings: read_u64(&mut input).map_err(corrupt)?,
+    };
+    let refuse = |what: &str| {
+        Err(format!(
+            "cannot resume from `{}`: the {} changed since it was written",
+            path, what
+        ))
+    };
+    // the seed also shapes random scenes, so it is checked before the scene itself
+    if saved.settings != key.settings {
+        return refuse("seed, sampler, path settings or AOV list");
+    }
+    if saved.scene != key.scene {
+        return refuse("scene");
+    }
+    if saved.camera != key.camera {
+        return refuse("camera");
+    }
+    let width = read_u32(&mut input).map_err(corrupt)?;
+    let height = read_u32(&mut input).map_err(corrupt)?;
+    if (width, height) != (acc.width, acc.height) {
+        return refuse("image size");
+    }
+    let layers = read_u32(&mut input).map_err(corrupt)?;
+    if layers as usize != acc.aovs.layers.len() {
+        return refuse("AOV list");
+    }
+
+    for s in acc.stats.iter_mut() {
+        let n = read_u32(&mut input).map_err(corrupt)?;
+        let mut v = [0.0; 5];
+        for x in v.iter_mut() {
+            *x = read_f64(&mut input).map_err(corrupt)?;
+        }
+        *s = PixelStats {
+            n,
+            sum: Vec3::new(v[0], v[1], v[2]),
+            mean: v[3],
+            m2: v[4],
+        };
+    }
+    for layer in acc.aovs.layers.iter_mut() {
+        for c in layer.data.iter_mut() {
+            let x = read_f64(&mut input).map_err(corrupt)?;
+            let y = read_f64(&mut input).map_err(corrupt)?;
+            let z = read_f64(&mut input).map_err(corrupt)?;
+            *c = Vec3::new(x, y, z);
+        }
+    }
+    Ok(())
+}
+
+fn read_u32(input: &mut impl Read) -> std::io::Result<u32> {
+    let mut b = [0u8; 4];
+    input.read_exact(&mut b)?;
+    Ok(u32::from_le_bytes(b))
+}
+
+fn read_u64(input: &mut impl Read) -> std::io::Result<u64> {
+    let mut b = [0u8; 8];
+    input.read_exact(&mut b)?;
+    Ok(u64::from_le_bytes(b))
+}
+
+fn read_f64(input: &mut impl Read) -> std::io::Result<f64> {
+    let mut b = [0u8; 8];
+    input.read_exact(&mut b)?;
+    Ok(f64::from_le_bytes(b))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aov::Aov;
+
+    const KEY: RenderKey = RenderKey {
+        scene: 1,
+        camera: 2,
+        settings: 3,
+    };
+
+    fn temp_path(name: &str) -> String {
+        let path =
+            std::env::temp_dir().join(format!("raytracer-{}-{}.ckpt", std::process::id(), name));
+        path.to_string_lossy().to_string()
+    }
+
+    // an accumulator with a different value in every field
+    fn filled(width: u32, height: u32, aovs: &[Aov]) -> Accumulator {
+        let mut acc = Accumulator::new(width, height, aovs);
+        for (i, s) in acc.stats.iter_mut().enumerate() {
+            let v = i as f64;
+            *s = PixelStats {
+                n: i as u32 + 1,
+                sum: Vec3::new(v, v + 0.25, v + 0.5),
+                mean: v / 3.0,
+                m2: v * 1e-3,
+            };
+        }
+        for (l, layer) in acc.aovs.layers.iter_mut().enumerate() {
+            for (i, c) in layer.data.iter_mut().enumerate() {
+                *c = Vec3::new(l as f64, i as f64, -(i as f64) / 7.0);
+            }
+        }
+        acc
+    }
+
+    // Saves `filled(3, 2, [Depth, Normal])` and loads it back with `key` into `into`.
+    fn resume(name: &str, key: &RenderKey, into: &mut Accumulator) -> Result<(), String> {
+        let path = temp_path(name);
+        save(&path, &KEY, &filled(3, 2, &[Aov::Depth, Aov::Normal])).unwrap();
+        let result = load(&path, key, into);
+        let _ = fs::remove_file(&path);
+        result
+    }
+
+    #[test]
+    fn round_trip() {
+        let saved = filled(3, 2, &[Aov::Depth, Aov::Normal]);
+        let mut acc = Accumulator::new(3, 2, &[Aov::Depth, Aov::Normal]);
+        resume("round-trip", &KEY, &mut acc).unwrap();
+        for (a, b) in saved.stats.iter().zip(acc.stats.iter()) {
+            assert_eq!(a.n, b.n);
+            assert_eq!((a.sum.x, a.sum.y, a.sum.z), (b.sum.x, b.sum.y, b.sum.z));
+            assert_eq!((a.mean, a.m2), (b.mean, b.m2));
+        }
+        for (a, b) in saved.aovs.layers.iter().zip(acc.aovs.layers.iter()) {
+            for (a, b) in a.data.iter().zip(b.data.iter()) {
+                assert_eq!((a.x, a.y, a.z), (b.x, b.y, b.z));
+            }
+        }
+    }
+
+    #[test]
+    fn refuses_a_different_render() {
+        let refused = |name: &str, key: RenderKey, width: u32, aovs: &[Aov]| {
+            let mut acc = Accumulator::new(width, 2, aovs);
+            resume(name, &key, &mut acc).unwrap_err()
+        };
+        let aovs = [Aov::Depth, Aov::Normal];
+        let key = |scene, camera, settings| RenderKey {
+            scene,
+            camera,
+            settings,
+        };
+        let e = refused("settings", key(1, 2, 4), 3, &aovs);
+        assert!(e.contains("the seed, sampler"), "{}", e);
+        let e = refused("scene", key(9, 2, 3), 3, &aovs);
+        assert!(e.contains("the scene changed"), "{}", e);
+        let e = refused("camera", key(1, 9, 3), 3, &aovs);
+        assert!(e.contains("the camera changed"), "{}", e);
+        let e = refused("size", KEY, 4, &aovs);
+        assert!(e.contains("the image size changed"), "{}", e);
+        let e = refused("aovs", KEY, 3, &[Aov::Depth]);
+        assert!(e.contains("the AOV list changed"), "{}", e);
+    }
+
+    #[test]
+    fn refuses_a_truncated_file() {
+        let path = temp_path("truncated");
+        save(&path, &KEY, &filled(3, 2, &[Aov::Depth])).unwrap();
+        let bytes = fs::read(&path).unwrap();
+        fs::write(&path, &bytes[..bytes.len() - 5]).unwrap();
+        let mut acc = Accumulator::new(3, 2, &[Aov::Depth]);
+        let result = load(&path, &KEY, &mut acc);
+        let _ = fs::remove_file(&path);
+        let e = result.unwrap_err();
+        assert!(e.contains("truncated or corrupt"), "{}", e);
+    }
+}
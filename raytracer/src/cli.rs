@@ -0,0 +1,390 @@
+use crate::adaptive::AdaptiveOptions;
+use crate::aov::Aov;
+use crate::denoise::{DenoiseOptions, MAX_PASSES};
+use crate::framebuffer::OutputFormat;
+use crate::light::LightSelection;
+use crate::pdf::MisHeuristic;
+use crate::progressive::TileOrder;
+use crate::sampler::SamplerKind;
+use crate::spectrum::ColorSpace;
+use crate::tonemap::{DisplayTransform, ToneMapper};
+use std::fmt::Display;
+use std::str::FromStr;
+
+pub const USAGE: &str = "usage: raytracer [options]
+
+options:
+  -s, --scene <name>       built-in scene to render (default: cornell_box)
+  -f, --scene-file <path>  load the scene from a JSON scene file instead
+      --list-scenes        print the built-in scenes and exit
+  -w, --width <px>         image width in pixels (default: 800)
+      --height <px>        image height in pixels (default: width / aspect)
+  -a, --aspect <ratio>     aspect ratio, e.g. 1.5 or 16:9 (default: 1.0)
+  -n, --spp <n>            samples per pixel (default: 5)
+      --adaptive           keep sampling noisy pixels and stop early on converged ones
+      --min-spp <n>        adaptive: samples every pixel gets (default: 16)
+      --max-spp <n>        adaptive: cap per pixel (default: 4 x --spp, or --min-spp if more)
+      --noise-threshold <e>  adaptive: relative standard error to stop at (default: 0.05)
+      --heatmap <path>     adaptive: also write the per-pixel sample counts
+                           the --min-spp, --max-spp and --noise-threshold options imply --adaptive
+  -d, --max-depth <n>      maximum ray bounces (default: 10)
+      --rr-depth <n>       bounces before Russian roulette may end a path early (default: 3)
+      --mis <heuristic>    weighting of light and BSDF samples: power or balance (default: power)
+      --light-selection <s>  how a light is picked to sample: uniform, power or bvh, which also
+                           favors lights close to and facing the point (default: power)
+      --sampler <name>     sample generator: random, stratified, halton, sobol or blue-noise
+                           (default: sobol)
+      --spectral           trace each path at sampled wavelengths instead of in RGB, for
+                           dispersion
+      --seed <n>           seed for the scene and every sample; same seed, same image (default: 0)
+  -t, --workers <n>        worker threads (default: 8); 0 leaves the rendering to remote workers
+      --listen <addr>      also hand tiles to workers connecting to this address, e.g. 0.0.0.0:7878
+      --local-workers <n>  start n worker processes on this machine (listens on 127.0.0.1 unless
+                           --listen is given)
+      --connect <addr>     work for the coordinator at addr instead of rendering a scene; it sends
+                           all the other options, only -t is taken from this command line
+      --tile-size <px>     side of the square tiles handed to the workers (default: 32)
+      --tile-order <order> scanline, spiral or hilbert (default: spiral)
+      --pass-spp <n>       samples added to the whole frame by each progressive pass (default: 4)
+      --checkpoint <path>  save the accumulated samples there every --checkpoint-interval seconds
+                           and when the render ends
+      --checkpoint-interval <s>  seconds between checkpoints (default: 300)
+      --resume             continue from the --checkpoint file; refused if the scene, camera,
+                           image size, seed, sampler, depths, --mis, --light-selection,
+                           --spectral or AOVs changed
+      --write-interval <s> write the partially converged image every s seconds, 0 to only
+                           write the final one (default: 30)
+  -o, --output <path>      output image, format chosen by extension (default: output/test.png);
+                           .exr, .hdr and .pfm keep the linear radiance, .png, .jpg etc. are 8-bit
+      --color-space <name> primaries of .exr, .hdr and .pfm output: srgb, rec2020, acescg or xyz
+                           (default: srgb); 8-bit output is always sRGB
+      --tonemap <curve>    tone mapper for 8-bit output: clamp, reinhard, reinhard-extended,
+                           aces, hable or agx (default: clamp)
+      --exposure <ev>      exposure in stops applied before tone mapping (default: 0)
+      --white <lum>        white point of reinhard-extended (default: brightest pixel)
+      --aov <passes>       also write first-hit passes: all, or some of depth, normal, albedo,
+                           position, uv, id; EXR layers for .exr output, <name>.<pass>.<ext> otherwise
+      --denoise            filter the image with an a-trous wavelet guided by albedo and normals
+      --denoise-passes <n>         filter iterations, each doubling the radius, 1 to 16 (default: 5)
+      --denoise-sigma-color <s>    color edge-stopping width in local noise deviations (default: 4)
+      --denoise-sigma-normal <s>   normal edge-stopping width (default: 0.3)
+      --denoise-sigma-albedo <s>   albedo edge-stopping width (default: 0.1)
+                           the --denoise-* options imply --denoise; a width of 0 ignores that feature
+  -h, --help               print this message and exit";
+
+#[derive(Clone, Debug)]
+pub struct Options {
+    pub scene: String,
+    pub scene_file: Option<String>,
+    pub image_width: u32,
+    pub image_height: u32,
+    pub aspect_ratio: f64,
+    pub samples_per_pixel: u32,
+    pub max_depth: u32,
+    pub rr_depth: u32,
+    pub mis: MisHeuristic,
+    pub light_selection: LightSelection,
+    pub spectral: bool,
+    pub sampler: SamplerKind,
+    pub seed: u64,
+    pub n_workers: usize,
+    pub listen: Option<String>,
+    pub local_workers: usize,
+    pub connect: Option<String>,
+    pub tile_size: u32,
+    pub tile_order: TileOrder,
+    pub pass_spp: u32,
+    pub write_interval: f64,
+    pub checkpoint: Option<String>,
+    pub checkpoint_interval: f64,
+    pub resume: bool,
+    pub output: String,
+    pub color_space: ColorSpace,
+    pub display: DisplayTransform,
+    pub aovs: Vec<Aov>,
+    pub denoise: Option<DenoiseOptions>,
+    pub adaptive: Option<AdaptiveOptions>,
+    pub heatmap: Option<String>,
+    pub list_scenes: bool,
+    pub help: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            scene: String::from("cornell_box"),
+            scene_file: None,
+            image_width: 800,
+            image_height: 800,
+            aspect_ratio: 1.0,
+            samples_per_pixel: 5,
+            max_depth: 10,
+            rr_depth: 3,
+            mis: MisHeuristic::Power,
+            light_selection: LightSelection::Power,
+            spectral: false,
+            sampler: SamplerKind::Sobol,
+            seed: 0,
+            n_workers: 8,
+            listen: None,
+            local_workers: 0,
+            connect: None,
+            tile_size: 32,
+            tile_order: TileOrder::Spiral,
+            pass_spp: 4,
+            write_interval: 30.0,
+            checkpoint: None,
+            checkpoint_interval: 300.0,
+            resume: false,
+            output: String::from("output/test.png"),
+            color_space: ColorSpace::Srgb,
+            display: DisplayTransform::default(),
+            aovs: Vec::new(),
+            denoise: None,
+            adaptive: None,
+            heatmap: None,
+            list_scenes: false,
+            help: false,
+        }
+    }
+}
+
+impl Options {
+    pub fn parse<I: Iterator<Item = String>>(args: I, scenes: &[&str]) -> Result<Self, String> {
+        let mut opts = Options::default();
+        let mut height: Option<u32> = None;
+        let mut adaptive = false;
+        let mut min_spp: Option<u32> = None;
+        let mut max_spp: Option<u32> = None;
+        let mut threshold: Option<f64> = None;
+        let mut args = args;
+        while let Some(arg) = args.next() {
+            // --flag=value is accepted as well as --flag value
+            let (flag, inline) = match arg.find('=') {
+                Some(pos) if arg.starts_with("--") => {
+                    (arg[..pos].to_string(), Some(arg[pos + 1..].to_string()))
+                }
+                _ => (arg.clone(), None),
+            };
+            let mut value = || -> Result<String, String> {
+                match inline.clone() {
+                    Some(v) => Ok(v),
+                    None => args
+                        .next()
+                        .ok_or_else(|| format!("missing value for `{}`", flag)),
+                }
+            };
+            match flag.as_str() {
+                "-s" | "--scene" => opts.scene = value()?,
+                "-f" | "--scene-file" => opts.scene_file = Some(value()?),
+                "--list-scenes" => opts.list_scenes = true,
+                "-w" | "--width" => opts.image_width = parse_positive(&flag, &value()?)?,
+                "--height" => height = Some(parse_positive(&flag, &value()?)?),
+                "-a" | "--aspect" => opts.aspect_ratio = parse_aspect(&value()?)?,
+                "-n" | "--spp" => opts.samples_per_pixel = parse_positive(&flag, &value()?)?,
+                "--adaptive" => adaptive = true,
+                "--min-spp" => min_spp = Some(parse_positive(&flag, &value()?)?),
+                "--max-spp" => max_spp = Some(parse_positive(&flag, &value()?)?),
+                "--noise-threshold" => threshold = Some(parse_positive(&flag, &value()?)?),
+                "--heatmap" => opts.heatmap = Some(value()?),
+                "-d" | "--max-depth" => opts.max_depth = parse_positive(&flag, &value()?)?,
+                "--rr-depth" => opts.rr_depth = parse_positive(&flag, &value()?)?,
+                "--mis" => opts.mis = value()?.parse::<MisHeuristic>()?,
+                "--light-selection" => opts.light_selection = value()?.parse::<LightSelection>()?,
+                "--spectral" => opts.spectral = true,
+                "--sampler" => opts.sampler = value()?.parse::<SamplerKind>()?,
+                "--seed" => {
+                    let v = value()?;
+                    opts.seed = v
+                        .parse()
+                        .map_err(|e| format!("invalid value `{}` for `{}`: {}", v, flag, e))?;
+                }
+                "-t" | "--workers" => {
+                    let v = value()?;
+                    opts.n_workers = v
+                        .parse()
+                        .map_err(|e| format!("invalid value `{}` for `{}`: {}", v, flag, e))?;
+                }
+                "--listen" => opts.listen = Some(value()?),
+                "--local-workers" => opts.local_workers = parse_positive(&flag, &value()?)?,
+                "--connect" => opts.connect = Some(value()?),
+                "--tile-size" => opts.tile_size = parse_positive(&flag, &value()?)?,
+                "--tile-order" => opts.tile_order = value()?.parse::<TileOrder>()?,
+                "--pass-spp" => opts.pass_spp = parse_positive(&flag, &value()?)?,
+                "--checkpoint" => opts.checkpoint = Some(value()?),
+                "--checkpoint-interval" => {
+                    opts.checkpoint_interval = parse_positive(&flag, &value()?)?
+                }
+                "--resume" => opts.resume = true,
+                "--write-interval" => opts.write_interval = parse_non_negative(&flag, &value()?)?,
+                "-o" | "--output" => opts.output = value()?,
+                "--color-space" => opts.color_space = value()?.parse::<ColorSpace>()?,
+                "--tonemap" => opts.display.tone_mapper = value()?.parse::<ToneMapper>()?,
+                "--exposure" => opts.display.exposure = parse_finite(&flag, &value()?)?,
+                "--aov" => opts.aovs = Aov::parse_list(&value()?)?,
+                "--denoise" => {
+                    opts.denoise.get_or_insert_with(DenoiseOptions::default);
+                }
+                "--denoise-passes" => {
+                    let passes = parse_positive(&flag, &value()?)?;
+                    if passes > MAX_PASSES {
+                        return Err(format!(
+                            "`{}` must be at most {}, got {}",
+                            flag, MAX_PASSES, passes
+                        ));
+                    }
+                    opts.denoise
+                        .get_or_insert_with(DenoiseOptions::default)
+                        .passes = passes;
+                }
+                "--denoise-sigma-color" => {
+                    let sigma = parse_non_negative(&flag, &value()?)?;
+                    opts.denoise
+                        .get_or_insert_with(DenoiseOptions::default)
+                        .sigma_color = sigma;
+                }
+                "--denoise-sigma-normal" => {
+                    let sigma = parse_non_negative(&flag, &value()?)?;
+                    opts.denoise
+                        .get_or_insert_with(DenoiseOptions::default)
+                        .sigma_normal = sigma;
+                }
+                "--denoise-sigma-albedo" => {
+                    let sigma = parse_non_negative(&flag, &value()?)?;
+                    opts.denoise
+                        .get_or_insert_with(DenoiseOptions::default)
+                        .sigma_albedo = sigma;
+                }
+                "--white" => opts.display.white = Some(parse_positive(&flag, &value()?)?),
+                "-h" | "--help" => opts.help = true,
+                _ => return Err(format!("unknown option `{}`", arg)),
+            }
+        }
+
+        if opts.help || opts.list_scenes {
+            return Ok(opts);
+        }
+        if opts.n_workers == 0 && opts.connect.is_some() {
+            return Err(String::from(
+                "a worker needs at least one thread, drop `-t 0`",
+            ));
+        }
+        if opts.n_workers == 0 && opts.listen.is_none() && opts.local_workers == 0 {
+            return Err(String::from(
+                "`-t 0` leaves no one to render, it needs `--listen` or `--local-workers`",
+            ));
+        }
+        // the scene and everything else come from the coordinator
+        if opts.connect.is_some() {
+            return Ok(opts);
+        }
+        // fail before rendering rather than after
+        let format = OutputFormat::from_path(&opts.output)?;
+        if format == OutputFormat::Ldr && opts.color_space != ColorSpace::Srgb {
+            return Err(String::from(
+                "`--color-space` needs float output (.exr, .hdr or .pfm)",
+            ));
+        }
+        if opts.scene_file.is_none() && !scenes.contains(&opts.scene.as_str()) {
+            return Err(format!(
+                "unknown scene `{}` (use --list-scenes to see the available ones)",
+                opts.scene
+            ));
+        }
+        match height {
+            Some(h) => {
+                opts.image_height = h;
+                opts.aspect_ratio = opts.image_width as f64 / h as f64;
+            }
+            None => {
+                opts.image_height = (opts.image_width as f64 / opts.aspect_ratio) as u32;
+                if opts.image_height == 0 {
+                    return Err(format!(
+                        "aspect ratio {} is too wide for a width of {}",
+                        opts.aspect_ratio, opts.image_width
+                    ));
+                }
+            }
+        }
+        if opts.image_height < 2 || opts.image_width < 2 {
+            return Err(String::from("the image must be at least 2x2 pixels"));
+        }
+        if opts.resume && opts.checkpoint.is_none() {
+            return Err(String::from("`--resume` needs `--checkpoint <path>`"));
+        }
+        if adaptive || min_spp.is_some() || max_spp.is_some() || threshold.is_some() {
+            // without a cap of its own, a large --min-spp raises it
+            let max_spp =
+                max_spp.unwrap_or_else(|| (4 * opts.samples_per_pixel).max(min_spp.unwrap_or(0)));
+            let min_spp = min_spp.unwrap_or_else(|| max_spp.min(16));
+            if min_spp > max_spp {
+                return Err(format!(
+                    "`--min-spp` ({}) is larger than `--max-spp` ({})",
+                    min_spp, max_spp
+                ));
+            }
+            opts.adaptive = Some(AdaptiveOptions {
+                min_spp,
+                max_spp,
+                threshold: threshold.unwrap_or(0.05),
+            });
+        }
+        if let Some(heatmap) = &opts.heatmap {
+            if opts.adaptive.is_none() {
+                return Err(String::from("`--heatmap` needs adaptive sampling"));
+            }
+            OutputFormat::from_path(heatmap)?;
+        }
+        Ok(opts)
+    }
+}
+
+fn parse_positive<T>(flag: &str, s: &str) -> Result<T, String>
+where
+    T: FromStr + PartialOrd + Default,
+    T::Err: Display,
+{
+    let n: T = s
+        .parse()
+        .map_err(|e| format!("invalid value `{}` for `{}`: {}", s, flag, e))?;
+    if n <= T::default() {
+        return Err(format!("`{}` must be greater than zero, got {}", flag, s));
+    }
+    Ok(n)
+}
+
+fn parse_finite(flag: &str, s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(v) if v.is_finite() => Ok(v),
+        _ => Err(format!("invalid value `{}` for `{}`", s, flag)),
+    }
+}
+
+fn parse_non_negative(flag: &str, s: &str) -> Result<f64, String> {
+    let v = parse_finite(flag, s)?;
+    if v < 0.0 {
+        return Err(format!("`{}` must not be negative, got {}", flag, s));
+    }
+    Ok(v)
+}
+
+fn parse_aspect(s: &str) -> Result<f64, String> {
+    let ratio = match s.find(':') {
+        Some(pos) => {
+            let w: f64 = s[..pos]
+                .parse()
+                .map_err(|_| format!("invalid aspect ratio `{}`", s))?;
+            let h: f64 = s[pos + 1..]
+                .parse()
+                .map_err(|_| format!("invalid aspect ratio `{}`", s))?;
+            w / h
+        }
+        None => s
+            .parse()
+            .map_err(|_| format!("invalid aspect ratio `{}`", s))?,
+    };
+    if !ratio.is_finite() || ratio <= 0.0 {
+        return Err(format!("aspect ratio must be positive, got `{}`", s));
+    }
+    Ok(ratio)
+}
@@ -1,11 +1,11 @@
 use crate::hit::HitRecord;
 use crate::material::Isotropiuc;
+use crate::medium::{in_shadow_ray, segment};
 use crate::texture::{SolidColor, Texture};
 use crate::Hittable;
 use crate::Material;
 use crate::AABB::Aabb;
 use crate::{random_double, Ray, Vec3};
-use std::f64::INFINITY;
 #[warn(dead_code)]
 pub struct ConstantMedium<T0: Hittable, T1: Material> {
     pub boundary: T0,
@@ -31,39 +31,20 @@ impl<T0: Hittable> ConstantMedium<T0, Isotropiuc<SolidColor>> {
         }
     }
 }
-#[warn(clippy::question_mark)]
 impl<T0: Hittable, T1: Material> Hittable for ConstantMedium<T0, T1> {
     fn hit(&self, r: Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
-        let rec1 = self.boundary.hit(r, -INFINITY, INFINITY);
-        if rec1.is_none() {
+        if in_shadow_ray() {
             return None;
         }
-        let mut rec1 = rec1.unwrap();
-        let rec2 = self.boundary.hit(r, rec1.t + 0.0001, INFINITY);
-        if rec2.is_none() {
-            return None;
-        };
-        let mut rec2 = rec2.unwrap();
-        if rec1.t < t_min {
-            rec1.t = t_min;
-        };
-        if rec2.t > t_max {
-            rec2.t = t_max;
-        };
-        if rec1.t >= rec2.t {
-            return None;
-        };
-        if rec1.t < 0.0 {
-            rec1.t = 0.0;
-        };
+        let (t0, t1) = segment(&self.boundary, r, t_min, t_max)?;
         let ray_length = r.dir.length();
-        let distance_inside_boundary = (rec2.t - rec1.t) * ray_length;
+        let distance_inside_boundary = (t1 - t0) * ray_length;
         let hit_distance = self.neg_inv_density * (random_double().ln());
 
         if hit_distance > distance_inside_boundary {
             return None;
         };
-        let t = rec1.t + hit_distance / ray_length;
+        let t = t0 + hit_distance / ray_length;
         let p = r.at(t);
         Some(HitRecord {
             p,
@@ -73,6 +54,8 @@ impl<T0: Hittable, T1: Material> Hittable for ConstantMedium<T0, T1> {
             u: 0.0,
             v: 0.0,
             front_face: true,
+            medium_weight: Some(Vec3::ones()),
+            id: 0,
         })
     }
 
@@ -80,6 +63,15 @@ impl<T0: Hittable, T1: Material> Hittable for ConstantMedium<T0, T1> {
         self.boundary.bounding_box(time0, time1, output_box)
     }
 
+    fn transmittance(&self, r: Ray, t_min: f64, t_max: f64) -> Vec3 {
+        match segment(&self.boundary, r, t_min, t_max) {
+            Some((t0, t1)) => {
+                Vec3::ones() * ((t1 - t0) * r.dir.length() / self.neg_inv_density).exp()
+            }
+            None => Vec3::ones(),
+        }
+    }
+
     // fn pdf_value(&self, o: Vec3, v: Vec3) -> f64 {
     //     //return random_double();
     //     return 0.1;
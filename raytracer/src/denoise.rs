@@ -0,0 +1,135 @@
+use crate::framebuffer::FrameBuffer;
+use crate::tonemap::luminance;
+use crate::Vec3;
+
+// Edge-avoiding à-trous wavelet filter (Dammertz et al. 2010), guided by the first-hit
+// albedo and normal. Every pass widens the 5x5 B3-spline kernel by skipping 2^i pixels.
+// As in SVGF the color edge-stopping is scaled by the local noise, estimated spatially.
+#[derive(Copy, Clone, Debug)]
+pub struct DenoiseOptions {
+    // at most MAX_PASSES
+    pub passes: u32,
+    // edge-stopping widths; smaller keeps more detail and removes less noise.
+    // sigma_color is in standard deviations of the local noise.
+    pub sigma_color: f64,
+    pub sigma_normal: f64,
+    pub sigma_albedo: f64,
+}
+
+// the last pass skips 2^15 pixels, more than any image is wide
+pub const MAX_PASSES: u32 = 16;
+
+impl Default for DenoiseOptions {
+    fn default() -> Self {
+        Self {
+            passes: 5,
+            sigma_color: 4.0,
+            sigma_normal: 0.3,
+            sigma_albedo: 0.1,
+        }
+    }
+}
+
+const KERNEL: [f64; 5] = [1.0 / 16.0, 1.0 / 4.0, 3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+
+pub fn denoise(
+    color: &FrameBuffer,
+    albedo: &FrameBuffer,
+    normal: &FrameBuffer,
+    opts: &DenoiseOptions,
+) -> FrameBuffer {
+    // Filter the lighting only, so textures stay sharp: divide the albedo out and put it back last.
+    let demodulate = |c: f64, a: f64| match c {
+        c if !c.is_finite() => 0.0,
+        c if a > 1e-3 => c / a,
+        c => c,
+    };
+    let mut current = color.clone();
+    for (c, a) in current.data.iter_mut().zip(albedo.data.iter()) {
+        *c = Vec3::new(
+            demodulate(c.x, a.x),
+            demodulate(c.y, a.y),
+            demodulate(c.z, a.z),
+        );
+    }
+
+    let (width, height) = (color.width as i64, color.height as i64);
+    let mut next = FrameBuffer::new(color.width, color.height);
+    for pass in 0..opts.passes {
+        let step = 1i64 << pass;
+        let deviation = local_deviation(&current);
+        for y in 0..height {
+            for x in 0..width {
+                let p = (y * width + x) as usize;
+                let (lp, np, ap) = (luminance(current.data[p]), normal.data[p], albedo.data[p]);
+                let color_width = opts.sigma_color * deviation[p] + 1e-4;
+                let mut sum = Vec3::zero();
+                let mut weight_sum = 0.0;
+                for (j, ky) in KERNEL.iter().enumerate() {
+                    let qy = y + (j as i64 - 2) * step;
+                    if qy < 0 || qy >= height {
+                        continue;
+                    }
+                    for (i, kx) in KERNEL.iter().enumerate() {
+                        let qx = x + (i as i64 - 2) * step;
+                        if qx < 0 || qx >= width {
+                            continue;
+                        }
+                        let q = (qy * width + qx) as usize;
+                        let mut w = kx
+                            * ky
+                            * gaussian(normal.data[q] - np, opts.sigma_normal)
+                            * gaussian(albedo.data[q] - ap, opts.sigma_albedo);
+                        if opts.sigma_color > 0.0 {
+                            w *= (-(luminance(current.data[q]) - lp).abs() / color_width).exp();
+                        }
+                        sum += current.data[q] * w;
+                        weight_sum += w;
+                    }
+                }
+                // the center tap always has weight kx * ky > 0
+                next.data[p] = sum / weight_sum;
+            }
+        }
+        std::mem::swap(&mut current, &mut next);
+    }
+
+    let remodulate = |c: f64, a: f64| if a > 1e-3 { c * a } else { c };
+    for (c, a) in current.data.iter_mut().zip(albedo.data.iter()) {
+        *c = Vec3::new(
+            remodulate(c.x, a.x),
+            remodulate(c.y, a.y),
+            remodulate(c.z, a.z),
+        );
+    }
+    current
+}
+
+fn gaussian(d: Vec3, sigma: f64) -> f64 {
+    if sigma <= 0.0 {
+        return 1.0;
+    }
+    (-d.length_squared() / (sigma * sigma)).exp()
+}
+
+// Standard deviation of the luminance in the 5x5 window around each pixel.
+fn local_deviation(img: &FrameBuffer) -> Vec<f64> {
+    let (width, height) = (img.width as i64, img.height as i64);
+    let mut out = vec![0.0; img.data.len()];
+    for y in 0..height {
+        for x in 0..width {
+            let (mut sum, mut sum_sq, mut n) = (0.0, 0.0, 0.0);
+            for qy in (y - 2).max(0)..(y + 3).min(height) {
+                for qx in (x - 2).max(0)..(x + 3).min(width) {
+                    let l = luminance(img.data[(qy * width + qx) as usize]);
+                    sum += l;
+                    sum_sq += l * l;
+                    n += 1.0;
+                }
+            }
+            let mean = sum / n;
+            out[(y * width + x) as usize] = (sum_sq / n - mean * mean).max(0.0).sqrt();
+        }
+    }
+    out
+}
@@ -0,0 +1,738 @@
+use crate::adaptive::PixelStats;
+use crate::aov::{Aov, AovBuffers};
+use crate::checkpoint::RenderKey;
+use crate::Vec3;
+use std::collections::{BTreeMap, VecDeque};
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::sync::mpsc::{RecvTimeoutError, Sender};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+// One progressive pass over one tile: render until every pixel has (pass + 1) * pass_spp
+// samples or is done. `stats` are the tile's pixels before the pass, row by row. Jobs are
+// pure functions of their contents, so a job lost with a worker can simply be run again.
+#[derive(Clone)]
+pub struct Job {
+    pub index: usize,
+    pub pass: u32,
+    pub stats: Vec<PixelStats>,
+}
+
+pub struct JobResult {
+    pub index: usize,
+    pub pass: u32,
+    pub stats: Vec<PixelStats>,
+    // sums of the new samples' AOVs over the tile
+    pub aovs: AovBuffers,
+}
+
+// The work shared by the render threads of this process and the connections to workers.
+pub struct JobQueue<T> {
+    state: Mutex<(VecDeque<T>, bool)>,
+    ready: Condvar,
+}
+
+impl<T> JobQueue<T> {
+    pub fn new() -> Self {
+        Self {
+            state: Mutex::new((VecDeque::new(), false)),
+            ready: Condvar::new(),
+        }
+    }
+
+    pub fn push(&self, job: T) {
+        self.state.lock().unwrap().0.push_back(job);
+        self.ready.notify_one();
+    }
+
+    // work handed back by a dropped worker goes first, it is the oldest
+    pub fn push_front(&self, jobs: Vec<T>) {
+        let mut state = self.state.lock().unwrap();
+        for job in jobs.into_iter().rev() {
+            state.0.push_front(job);
+        }
+        self.ready.notify_all();
+    }
+
+    // Blocks until there is a job; None once the queue is closed and empty.
+    pub fn pop(&self) -> Option<T> {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if let Some(job) = state.0.pop_front() {
+                return Some(job);
+            }
+            if state.1 {
+                return None;
+            }
+            state = self.ready.wait(state).unwrap();
+        }
+    }
+
+    pub fn try_pop(&self) -> Option<T> {
+        self.state.lock().unwrap().0.pop_front()
+    }
+
+    pub fn close(&self) {
+        self.state.lock().unwrap().1 = true;
+        self.ready.notify_all();
+    }
+}
+
+// Runs `n` threads that render jobs from `queue` until it is closed.
+pub fn spawn_threads<T, R, F>(
+    n: usize,
+    queue: &Arc<JobQueue<T>>,
+    results: &Sender<R>,
+    render: &Arc<F>,
+) where
+    T: Send + 'static,
+    R: Send + 'static,
+    F: Fn(T) -> R + Send + Sync + 'static,
+{
+    for _ in 0..n {
+        let queue = queue.clone();
+        let results = results.clone();
+        let render = render.clone();
+        thread::spawn(move || {
+            while let Some(job) = queue.pop() {
+                if results.send(render(job)).is_err() {
+                    break;
+                }
+            }
+        });
+    }
+}
+
+// The protocol: every message is a little-endian u32 length, a tag byte and the payload.
+// A worker connects, gets the coordinator's command line, builds the same scene and
+// answers with its render key and thread count. Then jobs and results flow until Finish,
+// and the worker sends a Heartbeat every HEARTBEAT so a silent one can be told from a slow one.
+pub enum Message {
+    Setup(Vec<String>),
+    Ready { key: RenderKey, threads: u32 },
+    Job { id: u64, job: Job },
+    Done { id: u64, result: JobResult },
+    Finish,
+    Reject(String),
+    Heartbeat,
+}
+
+const MAX_MESSAGE: usize = 1 << 30;
+
+const HEARTBEAT: Duration = Duration::from_secs(5);
+// a worker that says nothing for this long is taken for dead and its jobs reassigned
+const SILENCE: Duration = Duration::from_secs(60);
+// building the scene (meshes, textures) comes before the first heartbeat
+const SETUP_SILENCE: Duration = Duration::from_secs(600);
+
+impl Message {
+    fn encode(&self) -> Vec<u8> {
+        let mut e = Encoder { bytes: vec![0; 4] };
+        match self {
+            Message::Setup(args) => {
+                e.u8(1);
+                e.u32(args.len() as u32);
+                for arg in args {
+                    e.str(arg);
+                }
+            }
+            Message::Ready { key, threads } => {
+                e.u8(2);
+                e.u64(key.scene);
+                e.u64(key.camera);
+                e.u64(key.settings);
+                e.u32(*threads);
+            }
+            Message::Job { id, job } => {
+                e.u8(3);
+                e.u64(*id);
+                e.u32(job.index as u32);
+                e.u32(job.pass);
+                e.stats(&job.stats);
+            }
+            Message::Done { id, result } => {
+                e.u8(4);
+                e.u64(*id);
+                e.u32(result.index as u32);
+                e.u32(result.pass);
+                e.stats(&result.stats);
+                e.u32(result.aovs.layers.len() as u32);
+                for layer in result.aovs.layers.iter() {
+                    e.u32(layer.width);
+                    e.u32(layer.height);
+                    for c in layer.data.iter() {
+                        e.vec3(*c);
+                    }
+                }
+            }
+            Message::Finish => e.u8(5),
+            Message::Reject(reason) => {
+                e.u8(6);
+                e.str(reason);
+            }
+            Message::Heartbeat => e.u8(7),
+        }
+        let len = (e.bytes.len() - 4) as u32;
+        e.bytes[..4].copy_from_slice(&len.to_le_bytes());
+        e.bytes
+    }
+
+    // `aovs` are the passes the results carry, both sides derive them from the same options.
+    fn decode(bytes: &[u8], aovs: &[Aov]) -> Result<Self, String> {
+        let mut d = Decoder { bytes, pos: 0 };
+        let message = match d.u8()? {
+            1 => {
+                let n = d.u32()?;
+                let mut args = Vec::new();
+                for _ in 0..n {
+                    args.push(d.str()?);
+                }
+                Message::Setup(args)
+            }
+            2 => Message::Ready {
+                key: RenderKey {
+                    scene: d.u64()?,
+                    camera: d.u64()?,
+                    settings: d.u64()?,
+                },
+                threads: d.u32()?,
+            },
+            3 => Message::Job {
+                id: d.u64()?,
+                job: Job {
+                    index: d.u32()? as usize,
+                    pass: d.u32()?,
+                    stats: d.stats()?,
+                },
+            },
+            4 => {
+                let id = d.u64()?;
+                let index = d.u32()? as usize;
+                let pass = d.u32()?;
+                let stats = d.stats()?;
+                if d.u32()? as usize != aovs.len() {
+                    return Err(String::from("result with the wrong number of AOVs"));
+                }
+                let (mut width, mut height) = (0, 0);
+                let mut layers = Vec::new();
+                for _ in aovs {
+                    width = d.u32()?;
+                    height = d.u32()?;
+                    let mut data = Vec::new();
+                    for _ in 0..width as usize * height as usize {
+                        data.push(d.vec3()?);
+                    }
+                    layers.push(data);
+                }
+                let mut buffers = AovBuffers::new(aovs, width, height);
+                for (layer, data) in buffers.layers.iter_mut().zip(layers) {
+                    layer.data = data;
+                }
+                Message::Done {
+                    id,
+                    result: JobResult {
+                        index,
+                        pass,
+                        stats,
+                        aovs: buffers,
+                    },
+                }
+            }
+            5 => Message::Finish,
+            6 => Message::Reject(d.str()?),
+            7 => Message::Heartbeat,
+            tag => return Err(format!("unknown message tag {}", tag)),
+        };
+        Ok(message)
+    }
+}
+
+fn send(out: &mut impl Write, message: &Message) -> Result<(), String> {
+    out.write_all(&message.encode())
+        .and_then(|_| out.flush())
+        .map_err(|e| e.to_string())
+}
+
+fn receive(input: &mut impl Read, aovs: &[Aov]) -> Result<Message, String> {
+    let mut len = [0u8; 4];
+    input.read_exact(&mut len).map_err(read_error)?;
+    let len = u32::from_le_bytes(len) as usize;
+    if len == 0 || len > MAX_MESSAGE {
+        return Err(format!("bad message length {}", len));
+    }
+    let mut bytes = vec![0; len];
+    input.read_exact(&mut bytes).map_err(read_error)?;
+    Message::decode(&bytes, aovs)
+}
+
+fn read_error(e: std::io::Error) -> String {
+    match e.kind() {
+        // what a read timeout looks like, depending on the platform
+        ErrorKind::WouldBlock | ErrorKind::TimedOut => String::from("it went silent"),
+        _ => e.to_string(),
+    }
+}
+
+struct Encoder {
+    bytes: Vec<u8>,
+}
+
+impl Encoder {
+    fn u8(&mut self, v: u8) {
+        self.bytes.push(v);
+    }
+    fn u32(&mut self, v: u32) {
+        self.bytes.extend_from_slice(&v.to_le_bytes());
+    }
+    fn u64(&mut self, v: u64) {
+        self.bytes.extend_from_slice(&v.to_le_bytes());
+    }
+    fn f64(&mut self, v: f64) {
+        self.bytes.extend_from_slice(&v.to_le_bytes());
+    }
+    fn vec3(&mut self, v: Vec3) {
+        self.f64(v.x);
+        self.f64(v.y);
+        self.f64(v.z);
+    }
+    fn str(&mut self, s: &str) {
+        self.u32(s.len() as u32);
+        self.bytes.extend_from_slice(s.as_bytes());
+    }
+    fn stats(&mut self, stats: &[PixelStats]) {
+        self.u32(stats.len() as u32);
+        for s in stats {
+            self.u32(s.n);
+            self.vec3(s.sum);
+            self.f64(s.mean);
+            self.f64(s.m2);
+        }
+    }
+}
+
+struct Decoder<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Decoder<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], String> {
+        if self.bytes.len() - self.pos < n {
+            return Err(String::from("truncated message"));
+        }
+        self.pos += n;
+        Ok(&self.bytes[self.pos - n..self.pos])
+    }
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+    fn u32(&mut self) -> Result<u32, String> {
+        let mut b = [0u8; 4];
+        b.copy_from_slice(self.take(4)?);
+        Ok(u32::from_le_bytes(b))
+    }
+    fn u64(&mut self) -> Result<u64, String> {
+        let mut b = [0u8; 8];
+        b.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(b))
+    }
+    fn f64(&mut self) -> Result<f64, String> {
+        Ok(f64::from_bits(self.u64()?))
+    }
+    fn vec3(&mut self) -> Result<Vec3, String> {
+        Ok(Vec3::new(self.f64()?, self.f64()?, self.f64()?))
+    }
+    fn str(&mut self) -> Result<String, String> {
+        let n = self.u32()? as usize;
+        String::from_utf8(self.take(n)?.to_vec()).map_err(|e| e.to_string())
+    }
+    fn stats(&mut self) -> Result<Vec<PixelStats>, String> {
+        let n = self.u32()? as usize;
+        let mut stats = Vec::new();
+        for _ in 0..n {
+            stats.push(PixelStats {
+                n: self.u32()?,
+                sum: self.vec3()?,
+                mean: self.f64()?,
+                m2: self.f64()?,
+            });
+        }
+        Ok(stats)
+    }
+}
+
+// The connections of a coordinator, see `serve`.
+pub struct Workers {
+    handles: Arc<Mutex<Vec<JoinHandle<()>>>>,
+}
+
+impl Workers {
+    // Waits until every connected worker has been told the frame is done (or has dropped).
+    // Only returns once `queue` has been closed.
+    pub fn finish(&self) {
+        let handles = std::mem::take(&mut *self.handles.lock().unwrap());
+        for handle in handles {
+            handle.join().ok();
+        }
+    }
+}
+
+// Coordinator side: accepts workers on `listener` for the rest of the process' life and
+// feeds each from `queue`. Results go to `results` like those of the local threads.
+pub fn serve(
+    listener: TcpListener,
+    args: Vec<String>,
+    key: RenderKey,
+    aovs: Vec<Aov>,
+    queue: Arc<JobQueue<Job>>,
+    results: Sender<JobResult>,
+) -> Workers {
+    let handles = Arc::new(Mutex::new(Vec::new()));
+    let workers = Workers {
+        handles: handles.clone(),
+    };
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(_) => continue,
+            };
+            let peer = stream
+                .peer_addr()
+                .map_or_else(|_| String::from("?"), |a| a.to_string());
+            let (args, aovs, queue, results) =
+                (args.clone(), aovs.clone(), queue.clone(), results.clone());
+            let handle = thread::spawn(move || {
+                if let Err(e) = coordinate(stream, &args, key, &aovs, &queue, &results) {
+                    eprintln!("worker {}: {}", peer, e);
+                }
+            });
+            handles.lock().unwrap().push(handle);
+        }
+    });
+    workers
+}
+
+fn coordinate(
+    stream: TcpStream,
+    args: &[String],
+    key: RenderKey,
+    aovs: &[Aov],
+    queue: &JobQueue<Job>,
+    results: &Sender<JobResult>,
+) -> Result<(), String> {
+    stream.set_nodelay(true).ok();
+    stream
+        .set_read_timeout(Some(SETUP_SILENCE))
+        .map_err(|e| e.to_string())?;
+    let mut input = BufReader::new(stream.try_clone().map_err(|e| e.to_string())?);
+    let mut out = BufWriter::new(stream);
+    send(&mut out, &Message::Setup(args.to_vec()))?;
+    let threads = match receive(&mut input, aovs)? {
+        Message::Ready { key: theirs, .. } if theirs != key => {
+            let reason = "its scene, camera or settings differ from the coordinator's";
+            send(&mut out, &Message::Reject(String::from(reason))).ok();
+            return Err(format!("rejected, {}", reason));
+        }
+        Message::Ready { threads, .. } => threads,
+        _ => return Err(String::from("expected a ready message")),
+    };
+    out.get_ref()
+        .set_read_timeout(Some(SILENCE))
+        .map_err(|e| e.to_string())?;
+
+    // a couple of jobs per thread in flight so the worker never waits on the network
+    let window = (2 * threads as usize).max(1);
+    let mut in_flight: BTreeMap<u64, Job> = BTreeMap::new();
+    let mut next_id = 0u64;
+    let outcome = loop {
+        let mut failed = None;
+        while in_flight.len() < window {
+            let job = if in_flight.is_empty() {
+                queue.pop()
+            } else {
+                queue.try_pop()
+            };
+            let job = match job {
+                Some(job) => job,
+                None if in_flight.is_empty() => {
+                    // closed: the render is complete
+                    send(&mut out, &Message::Finish).ok();
+                    return Ok(());
+                }
+                None => break,
+            };
+            next_id += 1;
+            in_flight.insert(next_id, job.clone());
+            if let Err(e) = send(&mut out, &Message::Job { id: next_id, job }) {
+                failed = Some(e);
+                break;
+            }
+        }
+        if let Some(e) = failed {
+            break Err(e);
+        }
+        match receive(&mut input, aovs) {
+            Ok(Message::Done { id, result }) => match in_flight.remove(&id) {
+                Some(job)
+                    if job.index == result.index
+                        && job.pass == result.pass
+                        && job.stats.len() == result.stats.len() =>
+                {
+                    results.send(result).ok();
+                }
+                _ => break Err(format!("unexpected result for job {}", id)),
+            },
+            Ok(Message::Heartbeat) => {}
+            Ok(_) => break Err(String::from("expected a result")),
+            Err(e) => break Err(e),
+        }
+    };
+    // whatever it was working on goes back to the queue for someone else, and a worker
+    // that was only slow finds the connection closed rather than sending stale results
+    out.get_ref().shutdown(Shutdown::Both).ok();
+    let lost = in_flight.len();
+    queue.push_front(in_flight.values().cloned().collect());
+    outcome.map_err(|e| format!("dropped ({}), {} jobs reassigned", e, lost))
+}
+
+// Worker side: connects to the coordinator, retrying for a while in case it isn't up yet,
+// and returns its command line.
+pub fn connect(addr: &str) -> Result<(TcpStream, Vec<String>), String> {
+    let mut attempts = 0;
+    let stream = loop {
+        match TcpStream::connect(addr) {
+            Ok(stream) => break stream,
+            Err(e) if attempts >= 30 => {
+                return Err(format!("cannot connect to `{}`: {}", addr, e));
+            }
+            Err(_) => {
+                attempts += 1;
+                thread::sleep(Duration::from_secs(1));
+            }
+        }
+    };
+    stream.set_nodelay(true).ok();
+    match receive(&mut &stream, &[])? {
+        Message::Setup(args) => Ok((stream, args)),
+        _ => Err(String::from("expected the coordinator's options")),
+    }
+}
+
+// Renders the coordinator's jobs on `threads` threads until it says the frame is done.
+pub fn work<F>(
+    stream: TcpStream,
+    key: RenderKey,
+    threads: usize,
+    aovs: &[Aov],
+    render: F,
+) -> Result<(), String>
+where
+    F: Fn(Job) -> JobResult + Send + Sync + 'static,
+{
+    let mut out = BufWriter::new(stream.try_clone().map_err(|e| e.to_string())?);
+    send(
+        &mut out,
+        &Message::Ready {
+            key,
+            threads: threads as u32,
+        },
+    )?;
+    let out = Arc::new(Mutex::new(out));
+
+    // dropping `stop` ends the heartbeats
+    let (stop, stopped) = std::sync::mpsc::channel::<()>();
+    let heartbeat_out = out.clone();
+    thread::spawn(move || {
+        while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(HEARTBEAT) {
+            if send(&mut *heartbeat_out.lock().unwrap(), &Message::Heartbeat).is_err() {
+                break;
+            }
+        }
+    });
+
+    let queue = Arc::new(JobQueue::new());
+    let (tx, rx) = std::sync::mpsc::channel();
+    let render = Arc::new(move |(id, job): (u64, Job)| (id, render(job)));
+    spawn_threads(threads, &queue, &tx, &render);
+    drop(tx);
+
+    let reader_queue = queue.clone();
+    let reader_aovs = aovs.to_vec();
+    let reader = thread::spawn(move || {
+        let mut input = BufReader::new(stream);
+        let outcome = loop {
+            match receive(&mut input, &reader_aovs) {
+                Ok(Message::Job { id, job }) => reader_queue.push((id, job)),
+                Ok(Message::Finish) => break Ok(()),
+                Ok(Message::Reject(reason)) => break Err(format!("rejected: {}", reason)),
+                Ok(_) => break Err(String::from("unexpected message")),
+                Err(e) => break Err(format!("lost the coordinator: {}", e)),
+            }
+        };
+        reader_queue.close();
+        outcome
+    });
+
+    for (id, result) in rx {
+        // a failed send means the coordinator is gone, the reader will notice too
+        send(&mut *out.lock().unwrap(), &Message::Done { id, result }).ok();
+    }
+    drop(stop);
+    reader
+        .join()
+        .unwrap_or_else(|_| Err(String::from("reader panicked")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeSet;
+    use std::sync::mpsc;
+
+    const KEY: RenderKey = RenderKey {
+        scene: 1,
+        camera: 2,
+        settings: 3,
+    };
+
+    fn stats(n: usize) -> Vec<PixelStats> {
+        (0..n)
+            .map(|i| PixelStats {
+                n: i as u32,
+                sum: Vec3::new(i as f64, 0.5, -1.0),
+                mean: 0.25,
+                m2: i as f64 * 1e-3,
+            })
+            .collect()
+    }
+
+    // the bytes `message` goes over the wire as, read back
+    fn round_trip(message: &Message, aovs: &[Aov]) -> Message {
+        receive(&mut &message.encode()[..], aovs).unwrap()
+    }
+
+    #[test]
+    fn messages_round_trip() {
+        let args = vec![String::from("--spp"), String::from("16")];
+        match round_trip(&Message::Setup(args.clone()), &[]) {
+            Message::Setup(read) => assert_eq!(read, args),
+            _ => panic!("expected setup"),
+        }
+        match round_trip(
+            &Message::Ready {
+                key: KEY,
+                threads: 8,
+            },
+            &[],
+        ) {
+            Message::Ready { key, threads } => assert_eq!((key, threads), (KEY, 8)),
+            _ => panic!("expected ready"),
+        }
+        let job = Job {
+            index: 7,
+            pass: 3,
+            stats: stats(5),
+        };
+        match round_trip(&Message::Job { id: 42, job }, &[]) {
+            Message::Job { id, job } => {
+                assert_eq!((id, job.index, job.pass), (42, 7, 3));
+                for (a, b) in job.stats.iter().zip(stats(5).iter()) {
+                    assert_eq!((a.n, a.mean, a.m2), (b.n, b.mean, b.m2));
+                    assert_eq!((a.sum.x, a.sum.y, a.sum.z), (b.sum.x, b.sum.y, b.sum.z));
+                }
+            }
+            _ => panic!("expected a job"),
+        }
+        let aovs = [Aov::Depth, Aov::Normal];
+        let mut buffers = AovBuffers::new(&aovs, 2, 3);
+        for (l, layer) in buffers.layers.iter_mut().enumerate() {
+            for (i, c) in layer.data.iter_mut().enumerate() {
+                *c = Vec3::new(l as f64, i as f64, 0.5);
+            }
+        }
+        let result = JobResult {
+            index: 7,
+            pass: 3,
+            stats: stats(6),
+            aovs: buffers,
+        };
+        match round_trip(&Message::Done { id: 42, result }, &aovs) {
+            Message::Done { id, result } => {
+                assert_eq!((id, result.index, result.pass), (42, 7, 3));
+                assert_eq!(result.stats.len(), 6);
+                assert_eq!(result.aovs.layers.len(), 2);
+                for (l, layer) in result.aovs.layers.iter().enumerate() {
+                    assert_eq!((layer.width, layer.height), (2, 3));
+                    for (i, c) in layer.data.iter().enumerate() {
+                        assert_eq!((c.x, c.y, c.z), (l as f64, i as f64, 0.5));
+                    }
+                }
+            }
+            _ => panic!("expected a result"),
+        }
+        match round_trip(&Message::Reject(String::from("no")), &[]) {
+            Message::Reject(reason) => assert_eq!(reason, "no"),
+            _ => panic!("expected a rejection"),
+        }
+        assert!(matches!(round_trip(&Message::Finish, &[]), Message::Finish));
+        assert!(matches!(
+            round_trip(&Message::Heartbeat, &[]),
+            Message::Heartbeat
+        ));
+    }
+
+    #[test]
+    fn jobs_of_a_dropped_worker_are_run_again() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let queue = Arc::new(JobQueue::new());
+        for index in 0..6 {
+            queue.push(Job {
+                index,
+                pass: 0,
+                stats: stats(4),
+            });
+        }
+        let (tx, rx) = mpsc::channel();
+        let workers = serve(listener, Vec::new(), KEY, Vec::new(), queue.clone(), tx);
+
+        // a worker that takes a job and dies without answering
+        let (stream, _) = connect(&addr).unwrap();
+        send(
+            &mut &stream,
+            &Message::Ready {
+                key: KEY,
+                threads: 1,
+            },
+        )
+        .unwrap();
+        let lost = match receive(&mut &stream, &[]).unwrap() {
+            Message::Job { job, .. } => job.index,
+            _ => panic!("expected a job"),
+        };
+        drop(stream);
+
+        // and one that does the work
+        let (stream, _) = connect(&addr).unwrap();
+        let worker = thread::spawn(move || {
+            work(stream, KEY, 2, &[], |job: Job| JobResult {
+                index: job.index,
+                pass: job.pass,
+                stats: job.stats,
+                aovs: AovBuffers::new(&[], 0, 0),
+            })
+        });
+        let mut done = BTreeSet::new();
+        while done.len() < 6 {
+            let result = rx.recv_timeout(Duration::from_secs(30)).unwrap();
+            assert!(done.insert(result.index), "job {} ran twice", result.index);
+        }
+        assert!(done.contains(&lost));
+        queue.close();
+        workers.finish();
+        worker.join().unwrap().unwrap();
+    }
+}
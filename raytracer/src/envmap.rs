@@ -0,0 +1,115 @@
+use crate::framebuffer::FrameBuffer;
+use crate::light::{Light, LightSample};
+use crate::pdf::Distribution2D;
+use crate::tonemap::luminance;
+use crate::{clamp, Vec3};
+use std::f64::consts::PI;
+
+// Light from every direction, read from an equirectangular (latitude-longitude) image: the
+// top row looks straight up, the bottom one straight down, and the middle column along +x
+// like the earth texture on a sphere. `rotation` turns it about the y axis (degrees) and
+// `intensity` scales it. Directions are sampled in proportion to their luminance, so a small
+// bright sun in the map doesn't leave a noisy image.
+pub struct EnvironmentMap {
+    pub image: FrameBuffer,
+    pub rotation: f64,
+    pub intensity: f64,
+    distribution: Distribution2D,
+}
+
+impl EnvironmentMap {
+    pub fn new(image: FrameBuffer, rotation: f64, intensity: f64) -> Self {
+        let (width, height) = (image.width as usize, image.height as usize);
+        // the rows near the poles cover less of the sphere
+        let mut func = Vec::with_capacity(width * height);
+        for y in 0..height {
+            let sin_theta = (PI * (y as f64 + 0.5) / height as f64).sin();
+            for x in 0..width {
+                let l = luminance(image.data[y * width + x]);
+                func.push(if l.is_finite() {
+                    l.max(0.0) * sin_theta
+                } else {
+                    0.0
+                });
+            }
+        }
+        Self {
+            distribution: Distribution2D::new(&func, width, height),
+            image,
+            rotation,
+            intensity,
+        }
+    }
+
+    pub fn load(filename: &str, rotation: f64, intensity: f64) -> Result<Self, String> {
+        Ok(Self::new(FrameBuffer::load(filename)?, rotation, intensity))
+    }
+
+    // the point in [0, 1)^2 of the image (x to the right, y down) a world direction looks at
+    fn to_image(&self, d: Vec3) -> (f64, f64) {
+        let d = Vec3::unit_vector(self.rotate(d, -self.rotation));
+        let phi = (-d.z).atan2(d.x) + PI;
+        let theta = clamp(d.y, -1.0, 1.0).acos();
+        ((phi / (2.0 * PI)).fract(), theta / PI)
+    }
+
+    fn to_world(&self, u: f64, v: f64) -> Vec3 {
+        self.rotate(direction(u, v), self.rotation)
+    }
+
+    fn rotate(&self, d: Vec3, degrees: f64) -> Vec3 {
+        let (sin, cos) = degrees.to_radians().sin_cos();
+        Vec3::new(cos * d.x + sin * d.z, d.y, -sin * d.x + cos * d.z)
+    }
+}
+
+impl Light for EnvironmentMap {
+    fn sample(&self, _p: Vec3, u: (f64, f64)) -> Option<LightSample> {
+        let ((x, y), pdf) = self.distribution.sample(u);
+        // the image's area is spread over the sphere, 2 PI^2 sin(theta) per unit of it
+        let sin_theta = (PI * y).sin();
+        if pdf <= 0.0 || sin_theta <= 0.0 {
+            return None;
+        }
+        let pdf = pdf / (2.0 * PI * PI * sin_theta);
+        let wi = self.to_world(x, y);
+        Some(LightSample {
+            wi,
+            distance: f64::INFINITY,
+            li: self.le(wi) / pdf,
+            pdf: Some(pdf),
+        })
+    }
+
+    fn le(&self, d: Vec3) -> Vec3 {
+        let (u, v) = self.to_image(d);
+        let x = ((u * self.image.width as f64) as u32).min(self.image.width - 1);
+        let y = ((v * self.image.height as f64) as u32).min(self.image.height - 1);
+        self.image.get(x, y) * self.intensity
+    }
+
+    fn pdf(&self, d: Vec3) -> f64 {
+        let (u, v) = self.to_image(d);
+        let sin_theta = (PI * v).sin();
+        if sin_theta <= 0.0 {
+            return 0.0;
+        }
+        self.distribution.pdf(u, v) / (2.0 * PI * PI * sin_theta)
+    }
+
+    fn power(&self, scene_radius: f64) -> f64 {
+        // the irradiance of the average radiance, over the scene's cross-section
+        let average = self.distribution.integral() * PI / 2.0;
+        PI * scene_radius * scene_radius * PI * average * self.intensity
+    }
+}
+
+// The direction an unrotated map looks along at the point (u, v) of the image.
+pub fn direction(u: f64, v: f64) -> Vec3 {
+    let (phi, theta) = (2.0 * PI * u, PI * v);
+    Vec3::new(
+        -phi.cos() * theta.sin(),
+        theta.cos(),
+        phi.sin() * theta.sin(),
+    )
+}
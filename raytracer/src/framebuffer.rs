@@ -0,0 +1,714 @@
+use crate::tonemap::{luminance, DisplayTransform};
+use crate::Vec3;
+use image::{ImageBuffer, Rgb, RgbImage};
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Write};
+use std::path::Path;
+
+// Linear radiance per pixel, already divided by the sample count. Rows go top to bottom.
+#[derive(Clone)]
+pub struct FrameBuffer {
+    pub width: u32,
+    pub height: u32,
+    pub data: Vec<Vec3>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum OutputFormat {
+    // anything the image crate can encode, 8 bits after the display transform
+    Ldr,
+    Exr,
+    Hdr,
+    Pfm,
+}
+
+impl OutputFormat {
+    pub fn from_path(filename: &str) -> Result<Self, String> {
+        let ext = Path::new(filename)
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_ascii_lowercase())
+            .unwrap_or_default();
+        match ext.as_str() {
+            "exr" => Ok(OutputFormat::Exr),
+            "hdr" => Ok(OutputFormat::Hdr),
+            "pfm" => Ok(OutputFormat::Pfm),
+            "png" | "jpg" | "jpeg" | "bmp" | "tga" | "tif" | "tiff" | "ppm" => Ok(OutputFormat::Ldr),
+            "" => Err(format!(
+                "output `{}` has no extension to pick the image format from",
+                filename
+            )),
+            _ => Err(format!(
+                "unsupported output format `.{}` (expected .png, .jpg, .bmp, .tga, .tiff, .ppm, .exr, .hdr or .pfm)",
+                ext
+            )),
+        }
+    }
+}
+
+impl FrameBuffer {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            data: vec![Vec3::zero(); (width * height) as usize],
+        }
+    }
+    pub fn get(&self, x: u32, y: u32) -> Vec3 {
+        self.data[(y * self.width + x) as usize]
+    }
+    pub fn set(&mut self, x: u32, y: u32, c: Vec3) {
+        self.data[(y * self.width + x) as usize] = c;
+    }
+    pub fn map(mut self, f: impl Fn(Vec3) -> Vec3) -> Self {
+        for c in self.data.iter_mut() {
+            *c = f(*c);
+        }
+        self
+    }
+
+    // Reads linear radiance back from a .hdr or .exr file.
+    pub fn load(filename: &str) -> Result<Self, String> {
+        let ext = Path::new(filename)
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_ascii_lowercase())
+            .unwrap_or_default();
+        let result = match ext.as_str() {
+            "hdr" => read_hdr(filename),
+            "exr" => read_exr(filename),
+            _ => Err(String::from("expected a .hdr or .exr image")),
+        };
+        result.map_err(|e| format!("failed to read `{}`: {}", filename, e))
+    }
+
+    pub fn to_rgb8(&self, display: &DisplayTransform) -> RgbImage {
+        let max_luminance = self
+            .data
+            .iter()
+            .map(|c| luminance(*c))
+            .filter(|l| l.is_finite())
+            .fold(0.0, f64::max);
+        ImageBuffer::from_fn(self.width, self.height, |x, y| {
+            let c = display.apply(self.get(x, y), max_luminance);
+            let ir = (255.0 * c.x + 0.5) as u8;
+            let ig = (255.0 * c.y + 0.5) as u8;
+            let ib = (255.0 * c.z + 0.5) as u8;
+            Rgb([ir, ig, ib])
+        })
+    }
+
+    // HDR formats get the raw linear radiance; the display transform only applies to 8-bit output.
+    pub fn save(&self, filename: &str, display: &DisplayTransform) -> Result<(), String> {
+        let format = OutputFormat::from_path(filename)?;
+        let result = match format {
+            OutputFormat::Ldr => self
+                .to_rgb8(display)
+                .save(filename)
+                .map_err(|e| e.to_string()),
+            OutputFormat::Exr => write_exr(filename, self.width, self.height, &self.channels()),
+            OutputFormat::Hdr => self.write_hdr(filename),
+            OutputFormat::Pfm => self.write_pfm(filename),
+        };
+        result.map_err(|e| format!("failed to write `{}`: {}", filename, e))
+    }
+
+    pub fn channels(&self) -> Vec<(String, Vec<f32>)> {
+        vec![
+            (
+                String::from("R"),
+                self.data.iter().map(|c| c.x as f32).collect(),
+            ),
+            (
+                String::from("G"),
+                self.data.iter().map(|c| c.y as f32).collect(),
+            ),
+            (
+                String::from("B"),
+                self.data.iter().map(|c| c.z as f32).collect(),
+            ),
+        ]
+    }
+
+    fn write_hdr(&self, filename: &str) -> Result<(), String> {
+        let file = File::create(filename).map_err(|e| e.to_string())?;
+        let pixels: Vec<Rgb<f32>> = self
+            .data
+            .iter()
+            // RGBE has no sign bit, negative or NaN radiance would come out as garbage
+            .map(|c| Rgb([positive(c.x), positive(c.y), positive(c.z)]))
+            .collect();
+        image::codecs::hdr::HdrEncoder::new(BufWriter::new(file))
+            .encode(&pixels, self.width as usize, self.height as usize)
+            .map_err(|e| e.to_string())
+    }
+
+    fn write_pfm(&self, filename: &str) -> Result<(), String> {
+        let file = File::create(filename).map_err(|e| e.to_string())?;
+        let mut out = BufWriter::new(file);
+        // a negative scale means little endian; scanlines are stored bottom to top
+        let mut bytes = format!("PF\n{} {}\n-1.0\n", self.width, self.height).into_bytes();
+        for y in (0..self.height).rev() {
+            for x in 0..self.width {
+                let c = self.get(x, y);
+                for v in [c.x, c.y, c.z].iter() {
+                    bytes.extend_from_slice(&(*v as f32).to_le_bytes());
+                }
+            }
+        }
+        out.write_all(&bytes).map_err(|e| e.to_string())?;
+        out.flush().map_err(|e| e.to_string())
+    }
+}
+
+fn positive(v: f64) -> f32 {
+    if v > 0.0 {
+        v as f32
+    } else {
+        0.0
+    }
+}
+
+// Writes an uncompressed scanline OpenEXR file with one 32-bit float channel per entry.
+// Layers are just dotted channel names ("normal.X"), so extra passes can share the file.
+pub fn write_exr(
+    filename: &str,
+    width: u32,
+    height: u32,
+    channels: &[(String, Vec<f32>)],
+) -> Result<(), String> {
+    // readers expect the channel list sorted by name
+    let mut channels: Vec<&(String, Vec<f32>)> = channels.iter().collect();
+    channels.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let mut header = Vec::new();
+    header.extend_from_slice(&20000630u32.to_le_bytes());
+    header.extend_from_slice(&2u32.to_le_bytes());
+
+    let mut chlist = Vec::new();
+    for (name, _) in channels.iter() {
+        chlist.extend_from_slice(name.as_bytes());
+        chlist.push(0);
+        chlist.extend_from_slice(&2i32.to_le_bytes()); // FLOAT
+        chlist.extend_from_slice(&[0, 0, 0, 0]); // pLinear + reserved
+        chlist.extend_from_slice(&1i32.to_le_bytes()); // xSampling
+        chlist.extend_from_slice(&1i32.to_le_bytes()); // ySampling
+    }
+    chlist.push(0);
+    exr_attribute(&mut header, "channels", "chlist", &chlist);
+    exr_attribute(&mut header, "compression", "compression", &[0]);
+    let mut window = Vec::new();
+    for v in [0, 0, width as i32 - 1, height as i32 - 1].iter() {
+        window.extend_from_slice(&v.to_le_bytes());
+    }
+    exr_attribute(&mut header, "dataWindow", "box2i", &window);
+    exr_attribute(&mut header, "displayWindow", "box2i", &window);
+    exr_attribute(&mut header, "lineOrder", "lineOrder", &[0]);
+    exr_attribute(
+        &mut header,
+        "pixelAspectRatio",
+        "float",
+        &1.0f32.to_le_bytes(),
+    );
+    exr_attribute(&mut header, "screenWindowCenter", "v2f", &[0; 8]);
+    exr_attribute(
+        &mut header,
+        "screenWindowWidth",
+        "float",
+        &1.0f32.to_le_bytes(),
+    );
+    header.push(0);
+
+    // one scanline per block: y, byte count, then each channel's row in turn
+    let line_size = channels.len() * width as usize * 4;
+    let block_size = 8 + line_size;
+    let table_end = header.len() + 8 * height as usize;
+    let mut out = header;
+    for y in 0..height as usize {
+        out.extend_from_slice(&((table_end + y * block_size) as u64).to_le_bytes());
+    }
+    for y in 0..height as usize {
+        out.extend_from_slice(&(y as i32).to_le_bytes());
+        out.extend_from_slice(&(line_size as i32).to_le_bytes());
+        for (_, data) in channels.iter() {
+            for v in data[y * width as usize..(y + 1) * width as usize].iter() {
+                out.extend_from_slice(&v.to_le_bytes());
+            }
+        }
+    }
+
+    let file = File::create(filename).map_err(|e| e.to_string())?;
+    let mut writer = BufWriter::new(file);
+    writer.write_all(&out).map_err(|e| e.to_string())?;
+    writer.flush().map_err(|e| e.to_string())
+}
+
+fn exr_attribute(header: &mut Vec<u8>, name: &str, kind: &str, value: &[u8]) {
+    header.extend_from_slice(name.as_bytes());
+    header.push(0);
+    header.extend_from_slice(kind.as_bytes());
+    header.push(0);
+    header.extend_from_slice(&(value.len() as i32).to_le_bytes());
+    header.extend_from_slice(value);
+}
+
+fn read_hdr(filename: &str) -> Result<FrameBuffer, String> {
+    let file = File::open(filename).map_err(|e| e.to_string())?;
+    let decoder =
+        image::codecs::hdr::HdrDecoder::new(BufReader::new(file)).map_err(|e| e.to_string())?;
+    let meta = decoder.metadata();
+    let pixels = decoder.read_image_hdr().map_err(|e| e.to_string())?;
+    Ok(FrameBuffer {
+        width: meta.width,
+        height: meta.height,
+        data: pixels
+            .iter()
+            .map(|p| Vec3::new(p[0] as f64, p[1] as f64, p[2] as f64))
+            .collect(),
+    })
+}
+
+// Reads the R, G and B channels of a single-part scanline OpenEXR file in half or float
+// precision, uncompressed like `write_exr` writes them or with RLE, ZIPS or ZIP compression.
+fn read_exr(filename: &str) -> Result<FrameBuffer, String> {
+    let bytes = fs::read(filename).map_err(|e| e.to_string())?;
+    let truncated = || String::from("truncated file");
+    let int = |i: usize| -> Result<i32, String> {
+        let end = i.checked_add(4).ok_or_else(truncated)?;
+        let b = bytes.get(i..end).ok_or_else(truncated)?;
+        Ok(i32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    };
+    let string = |i: usize| -> Result<(&str, usize), String> {
+        let rest = bytes.get(i..).ok_or_else(truncated)?;
+        let len = rest.iter().position(|b| *b == 0).ok_or_else(truncated)?;
+        let s = std::str::from_utf8(&bytes[i..i + len]).map_err(|e| e.to_string())?;
+        Ok((s, i + len + 1))
+    };
+    if bytes.len() < 8 || int(0)? != 20000630 {
+        return Err(String::from("not an OpenEXR file"));
+    }
+    // tiled, deep or multi-part; 0x400 only allows longer names
+    if int(4)? & 0x1a00 != 0 {
+        return Err(String::from(
+            "only single-part scanline images are supported",
+        ));
+    }
+
+    // (name, pixel type: 1 half, 2 float) in the file's order
+    let mut channels = Vec::new();
+    let mut compression = None;
+    let mut window = None;
+    let mut i = 8;
+    loop {
+        let (name, next) = string(i)?;
+        if name.is_empty() {
+            i = next;
+            break;
+        }
+        let (_, next) = string(next)?;
+        let size = int(next)?;
+        if size < 0 {
+            return Err(format!("bad size of attribute `{}`", name));
+        }
+        let end = (next + 4)
+            .checked_add(size as usize)
+            .ok_or_else(truncated)?;
+        let value = bytes.get(next + 4..end).ok_or_else(truncated)?;
+        match name {
+            "channels" => {
+                let mut j = next + 4;
+                loop {
+                    let (channel, after) = string(j)?;
+                    if channel.is_empty() {
+                        break;
+                    }
+                    channels.push((channel.to_string(), int(after)?));
+                    j = after + 16;
+                }
+            }
+            "compression" => compression = value.first().cloned(),
+            "dataWindow" => {
+                let v = |k: usize| int(next + 4 + 4 * k);
+                window = Some((v(0)?, v(1)?, v(2)?, v(3)?));
+            }
+            _ => {}
+        }
+        i = end;
+    }
+    // scanlines per block
+    let lines = match compression {
+        Some(0) | Some(1) | Some(2) => 1,
+        Some(3) => 16,
+        _ => {
+            return Err(String::from(
+                "only uncompressed, RLE, ZIPS and ZIP images are supported",
+            ))
+        }
+    };
+    let (x0, y0, x1, y1) = window.ok_or_else(|| String::from("no dataWindow"))?;
+    if x1 < x0 || y1 < y0 {
+        return Err(String::from("empty dataWindow"));
+    }
+    let (width, height) = (
+        (x1 as i64 - x0 as i64 + 1) as usize,
+        (y1 as i64 - y0 as i64 + 1) as usize,
+    );
+    let size = |kind: i32| if kind == 1 { 2 } else { 4 };
+    if channels.iter().any(|(_, kind)| *kind != 1 && *kind != 2) {
+        return Err(String::from("only half and float channels are supported"));
+    }
+    let find = |name: &str| -> Result<usize, String> {
+        channels
+            .iter()
+            .position(|(c, _)| c == name)
+            .ok_or_else(|| format!("no {} channel", name))
+    };
+    let rgb = [find("R")?, find("G")?, find("B")?];
+    let line_size: usize = channels.iter().map(|(_, kind)| size(*kind) * width).sum();
+
+    let mut rows = Vec::new();
+    let mut start = 0;
+    for (_, kind) in channels.iter() {
+        rows.push((start, *kind));
+        start += size(*kind) * width;
+    }
+
+    // the offset table, then blocks of a y coordinate, a byte count and the packed scanlines,
+    // each of them every channel's row in turn
+    let mut data = vec![Vec3::zero(); width * height];
+    for block in 0..(height - 1) / lines + 1 {
+        let b = bytes
+            .get(i + 8 * block..i + 8 * block + 8)
+            .ok_or_else(truncated)?;
+        let offset = u64::from_le_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]]);
+        let offset = offset as usize;
+        let first = int(offset)? as i64 - y0 as i64;
+        if first < 0 || first as usize >= height {
+            return Err(String::from("bad scanline block"));
+        }
+        let first = first as usize;
+        let expected = line_size * lines.min(height - first);
+        let packed = int(offset + 4)?;
+        if packed < 0 {
+            return Err(String::from("bad scanline block"));
+        }
+        let end = (offset + 8)
+            .checked_add(packed as usize)
+            .ok_or_else(truncated)?;
+        let packed = bytes.get(offset + 8..end).ok_or_else(truncated)?;
+        // a block that wouldn't get smaller is stored as it is
+        let unpacked = if packed.len() == expected || compression == Some(0) {
+            packed.to_vec()
+        } else if compression == Some(1) {
+            exr_unpredict(&exr_unrle(packed, expected)?)
+        } else {
+            exr_unpredict(&exr_inflate(packed, expected)?)
+        };
+        if unpacked.len() != expected {
+            return Err(String::from("bad scanline block"));
+        }
+        for (k, line) in unpacked.chunks(line_size).enumerate() {
+            let y = first + k;
+            for x in 0..width {
+                let value = |c: usize| {
+                    let (start, kind) = rows[c];
+                    if kind == 1 {
+                        let o = start + 2 * x;
+                        half_to_f32(u16::from_le_bytes([line[o], line[o + 1]])) as f64
+                    } else {
+                        let o = start + 4 * x;
+                        f32::from_le_bytes([line[o], line[o + 1], line[o + 2], line[o + 3]]) as f64
+                    }
+                };
+                data[y * width + x] = Vec3::new(value(rgb[0]), value(rgb[1]), value(rgb[2]));
+            }
+        }
+    }
+    Ok(FrameBuffer {
+        width: width as u32,
+        height: height as u32,
+        data,
+    })
+}
+
+// Expands OpenEXR's run-length encoding: a negative count byte is followed by that many
+// literal bytes, any other by one byte to repeat count + 1 times.
+fn exr_unrle(packed: &[u8], expected: usize) -> Result<Vec<u8>, String> {
+    let bad = || String::from("bad RLE block");
+    let mut out = Vec::with_capacity(expected);
+    let mut i = 0;
+    while i < packed.len() {
+        let count = packed[i] as i8;
+        i += 1;
+        if count < 0 {
+            let n = -(count as isize) as usize;
+            out.extend_from_slice(packed.get(i..i + n).ok_or_else(bad)?);
+            i += n;
+        } else {
+            let b = *packed.get(i).ok_or_else(bad)?;
+            out.resize(out.len() + count as usize + 1, b);
+            i += 1;
+        }
+        if out.len() > expected {
+            return Err(bad());
+        }
+    }
+    Ok(out)
+}
+
+// Inflates a ZIP block into exactly `expected` bytes.
+fn exr_inflate(packed: &[u8], expected: usize) -> Result<Vec<u8>, String> {
+    use miniz_oxide::inflate::core::{decompress, inflate_flags, DecompressorOxide};
+    use miniz_oxide::inflate::TINFLStatus;
+    let mut out = vec![0; expected];
+    let flags = inflate_flags::TINFL_FLAG_PARSE_ZLIB_HEADER
+        | inflate_flags::TINFL_FLAG_USING_NON_WRAPPING_OUTPUT_BUF;
+    let (status, _, written) =
+        decompress(&mut DecompressorOxide::new(), packed, &mut out, 0, flags);
+    match status {
+        TINFLStatus::Done if written == expected => Ok(out),
+        _ => Err(String::from("bad ZIP block")),
+    }
+}
+
+// Undoes what RLE and ZIP compression do before packing: bytes stored as differences to the
+// previous one, after the even bytes were split from the odd ones.
+fn exr_unpredict(t: &[u8]) -> Vec<u8> {
+    let mut t = t.to_vec();
+    for k in 1..t.len() {
+        t[k] = t[k - 1].wrapping_add(t[k]).wrapping_sub(128);
+    }
+    let half = t.len() - t.len() / 2;
+    let mut out = Vec::with_capacity(t.len());
+    for k in 0..half {
+        out.push(t[k]);
+        if half + k < t.len() {
+            out.push(t[half + k]);
+        }
+    }
+    out
+}
+
+fn half_to_f32(h: u16) -> f32 {
+    let sign = if h & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = ((h >> 10) & 0x1f) as i32;
+    let mantissa = (h & 0x3ff) as f32;
+    sign * match exponent {
+        0 => mantissa * 2f32.powi(-24),
+        31 if mantissa == 0.0 => f32::INFINITY,
+        31 => f32::NAN,
+        _ => (1.0 + mantissa / 1024.0) * 2f32.powi(exponent - 15),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exr_round_trip() {
+        let (width, height) = (7, 5);
+        let mut image = FrameBuffer::new(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                // f32 values, so nothing is lost on the way out
+                let v = (y * width + x) as f64;
+                image.set(x, y, Vec3::new(v / 8.0, 1e-3 * v, 1e4 - v));
+            }
+        }
+        let path = std::env::temp_dir().join(format!("raytracer-{}.exr", std::process::id()));
+        let path = path.to_string_lossy().to_string();
+        write_exr(&path, width, height, &image.channels()).unwrap();
+        let read = FrameBuffer::load(&path);
+        let _ = std::fs::remove_file(&path);
+        let read = read.unwrap();
+        assert_eq!((read.width, read.height), (width, height));
+        for (a, b) in image.data.iter().zip(read.data.iter()) {
+            assert_eq!(a.x as f32, b.x as f32);
+            assert_eq!(a.y as f32, b.y as f32);
+            assert_eq!(a.z as f32, b.z as f32);
+        }
+    }
+
+    // The inverse of `exr_unpredict` followed by RLE.
+    fn rle(raw: &[u8]) -> Vec<u8> {
+        let mut t: Vec<u8> = raw.iter().step_by(2).cloned().collect();
+        t.extend(raw.iter().skip(1).step_by(2));
+        for k in (1..t.len()).rev() {
+            t[k] = t[k].wrapping_sub(t[k - 1]).wrapping_add(128);
+        }
+        let mut out = Vec::new();
+        let mut i = 0;
+        while i < t.len() {
+            let run = t[i..].iter().take(128).take_while(|b| **b == t[i]).count();
+            if run >= 3 {
+                out.push(run as u8 - 1);
+                out.push(t[i]);
+                i += run;
+            } else {
+                let n = (t.len() - i).min(127);
+                out.push((-(n as i8)) as u8);
+                out.extend_from_slice(&t[i..i + n]);
+                i += n;
+            }
+        }
+        out
+    }
+
+    // An RGB float image with its data window at (3, -2), the long-names flag set and every
+    // block packed with `compression`: 1 RLE, 2 ZIPS, 3 ZIP.
+    fn compressed_exr(image: &FrameBuffer, compression: u8) -> Vec<u8> {
+        let (width, height) = (image.width as i32, image.height as i32);
+        let mut out = Vec::new();
+        out.extend_from_slice(&20000630u32.to_le_bytes());
+        out.extend_from_slice(&(2u32 | 0x400).to_le_bytes());
+        let mut chlist = Vec::new();
+        for name in ["B", "G", "R"].iter() {
+            chlist.extend_from_slice(name.as_bytes());
+            chlist.extend_from_slice(&[0, 2, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0]);
+        }
+        chlist.push(0);
+        exr_attribute(&mut out, "channels", "chlist", &chlist);
+        exr_attribute(&mut out, "compression", "compression", &[compression]);
+        let mut window = Vec::new();
+        for v in [3, -2, 3 + width - 1, -2 + height - 1].iter() {
+            window.extend_from_slice(&v.to_le_bytes());
+        }
+        exr_attribute(&mut out, "dataWindow", "box2i", &window);
+        exr_attribute(&mut out, "displayWindow", "box2i", &window);
+        out.push(0);
+
+        let lines = if compression == 3 { 16 } else { 1 };
+        let mut blocks = Vec::new();
+        for first in (0..height).step_by(lines) {
+            let mut raw = Vec::new();
+            for y in first..(first + lines as i32).min(height) {
+                for c in 0..3 {
+                    for x in 0..width {
+                        let v = image.get(x as u32, y as u32);
+                        let v = [v.z, v.y, v.x][c] as f32;
+                        raw.extend_from_slice(&v.to_le_bytes());
+                    }
+                }
+            }
+            let packed = if compression == 1 {
+                rle(&raw)
+            } else {
+                let mut t: Vec<u8> = raw.iter().step_by(2).cloned().collect();
+                t.extend(raw.iter().skip(1).step_by(2));
+                for k in (1..t.len()).rev() {
+                    t[k] = t[k].wrapping_sub(t[k - 1]).wrapping_add(128);
+                }
+                miniz_oxide::deflate::compress_to_vec_zlib(&t, 6)
+            };
+            assert!(packed.len() < raw.len());
+            let mut block = Vec::new();
+            block.extend_from_slice(&(first - 2).to_le_bytes());
+            block.extend_from_slice(&(packed.len() as i32).to_le_bytes());
+            block.extend_from_slice(&packed);
+            blocks.push(block);
+        }
+        let mut offset = out.len() + 8 * blocks.len();
+        for block in blocks.iter() {
+            out.extend_from_slice(&(offset as u64).to_le_bytes());
+            offset += block.len();
+        }
+        for block in blocks {
+            out.extend_from_slice(&block);
+        }
+        out
+    }
+
+    #[test]
+    fn exr_reads_compressed_images() {
+        // flat areas and a gradient, something for RLE to find
+        let (width, height) = (9, 21);
+        let mut image = FrameBuffer::new(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                let v = if x < 4 { 0.5 } else { (x * y) as f64 / 4.0 };
+                image.set(x, y, Vec3::new(v, 2.0 * v, 1.0));
+            }
+        }
+        for compression in 1..4 {
+            let path = std::env::temp_dir().join(format!(
+                "raytracer-{}-{}.exr",
+                std::process::id(),
+                compression
+            ));
+            let path = path.to_string_lossy().to_string();
+            std::fs::write(&path, compressed_exr(&image, compression)).unwrap();
+            let read = FrameBuffer::load(&path);
+            let _ = std::fs::remove_file(&path);
+            let read = read.unwrap();
+            assert_eq!((read.width, read.height), (width, height));
+            for (a, b) in image.data.iter().zip(read.data.iter()) {
+                assert_eq!(
+                    (a.x, a.y, a.z),
+                    (b.x, b.y, b.z),
+                    "compression {}",
+                    compression
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn exr_refuses_corrupt_sizes_and_offsets() {
+        let good = compressed_exr(&FrameBuffer::new(4, 3), 3);
+        let find = |s: &[u8]| good.windows(s.len()).position(|w| w == s).unwrap();
+        let path = std::env::temp_dir().join(format!("raytracer-{}-bad.exr", std::process::id()));
+        let path = path.to_string_lossy().to_string();
+        let read = |bytes: &[u8]| {
+            std::fs::write(&path, bytes).unwrap();
+            let read = read_exr(&path);
+            let _ = std::fs::remove_file(&path);
+            read.err()
+        };
+        assert_eq!(read(&good), None);
+        let patched = |at: usize, value: &[u8]| {
+            let mut bytes = good.clone();
+            bytes[at..at + value.len()].copy_from_slice(value);
+            bytes
+        };
+
+        // the size of the compression attribute
+        let size = find(b"compression\0compression\0") + 24;
+        assert_eq!(
+            read(&patched(size, &(-1i32).to_le_bytes())),
+            Some(String::from("bad size of attribute `compression`"))
+        );
+        assert_eq!(
+            read(&patched(size, &i32::MAX.to_le_bytes())),
+            Some(String::from("truncated file"))
+        );
+
+        // the first entry of the offset table, after the last attribute and the header's end
+        let table = find(b"displayWindow\0box2i\0") + 20 + 4 + 16 + 1;
+        let offset = u64::from_le_bytes([
+            good[table],
+            good[table + 1],
+            good[table + 2],
+            good[table + 3],
+            good[table + 4],
+            good[table + 5],
+            good[table + 6],
+            good[table + 7],
+        ]) as usize;
+        for bad in [u64::MAX, u64::MAX - 3, good.len() as u64].iter() {
+            assert_eq!(
+                read(&patched(table, &bad.to_le_bytes())),
+                Some(String::from("truncated file"))
+            );
+        }
+        // and the packed size of its block
+        assert_eq!(
+            read(&patched(offset + 4, &(-1i32).to_le_bytes())),
+            Some(String::from("bad scanline block"))
+        );
+        assert_eq!(
+            read(&patched(offset + 4, &i32::MAX.to_le_bytes())),
+            Some(String::from("truncated file"))
+        );
+    }
+}
@@ -1,6 +1,8 @@
 use crate::material::{Lambertian, Material, Metal};
 use crate::onb::Onb;
-use crate::{degrees_to_radians, random_int, MovingSphere, Ray, Vec3, AABB::Aabb};
+use crate::sampler::sample_1d;
+use crate::{degrees_to_radians, MovingSphere, Ray, Vec3, AABB::Aabb};
+use std::cell::Cell;
 use std::f64::consts::PI;
 use std::f64::INFINITY;
 use std::ops::{Add, Div, Mul, Sub};
@@ -15,6 +17,11 @@ pub struct HitRecord<'a> {
     pub u: f64,
     pub v: f64,
     pub front_face: bool,
+    // Some for a collision inside a participating medium: what its tracking multiplies the
+    // path throughput by
+    pub medium_weight: Option<Vec3>,
+    // the number of the object it belongs to, given when the scene is built; 0 for none
+    pub id: u32,
 }
 
 impl<'a> HitRecord<'a> {
@@ -37,6 +44,43 @@ pub trait Hittable: Send + Sync {
     fn random(&self, _o: Vec3) -> Vec3 {
         Vec3::new(1.0, 0.0, 0.0)
     }
+    // The fraction of light the participating media let through along `r` between `t_min`
+    // and `t_max`. Surfaces don't count here, shadow rays find those with `hit`.
+    fn transmittance(&self, _r: Ray, _t_min: f64, _t_max: f64) -> Vec3 {
+        Vec3::ones()
+    }
+    // The surface area and, for flat shapes, the normal of the surface (either way up). Light
+    // selection weighs area lights by these.
+    fn area(&self) -> f64 {
+        0.0
+    }
+    fn plane_normal(&self) -> Option<Vec3> {
+        None
+    }
+}
+
+impl Hittable for Arc<dyn Hittable> {
+    fn hit(&self, r: Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        (**self).hit(r, t_min, t_max)
+    }
+    fn bounding_box(&self, time0: f64, time1: f64, output_box: &mut Aabb) -> bool {
+        (**self).bounding_box(time0, time1, output_box)
+    }
+    fn pdf_value(&self, o: Vec3, v: Vec3) -> f64 {
+        (**self).pdf_value(o, v)
+    }
+    fn random(&self, o: Vec3) -> Vec3 {
+        (**self).random(o)
+    }
+    fn transmittance(&self, r: Ray, t_min: f64, t_max: f64) -> Vec3 {
+        (**self).transmittance(r, t_min, t_max)
+    }
+    fn area(&self) -> f64 {
+        (**self).area()
+    }
+    fn plane_normal(&self) -> Option<Vec3> {
+        (**self).plane_normal()
+    }
 }
 
 #[derive(Copy, Clone)]
@@ -96,6 +140,8 @@ impl<T: Material> Hittable for Sphere<T> {
                     u: ui,
                     v: vi,
                     front_face,
+                    medium_weight: None,
+                    id: 0,
                 });
             }
             ti = (-half_b + root) / ai;
@@ -118,6 +164,8 @@ impl<T: Material> Hittable for Sphere<T> {
                     u: ui,
                     v: vi,
                     front_face,
+                    medium_weight: None,
+                    id: 0,
                 });
             }
         }
@@ -140,7 +188,9 @@ impl<T: Material> Hittable for Sphere<T> {
                 return 0.0;
             }
         }
-        let cos_theta_max = 1.0 - self.radius * self.radius / (self.center - o).length_squared();
+        let cos_theta_max = (1.0 - self.radius * self.radius / (self.center - o).length_squared())
+            .max(0.0)
+            .sqrt();
         let solid_angle = 2.0 * PI * (1.0 - cos_theta_max);
 
         1.0 / solid_angle
@@ -158,6 +208,9 @@ impl<T: Material> Hittable for Sphere<T> {
         }
         uvw.local0(Vec3::random_to_sphere(self.radius, distance_squared))
     }
+    fn area(&self) -> f64 {
+        4.0 * PI * self.radius * self.radius
+    }
 }
 #[allow(clippy::float_cmp)]
 #[derive(Default, Clone)]
@@ -176,6 +229,18 @@ impl HittableList {
     pub fn add(&mut self, object: Arc<dyn Hittable>) {
         self.objects.push(object);
     }
+    // Numbers the objects in the order they were added, for the id pass. Put a list through
+    // this before building a BVH of it so each object keeps its own number; hits of an object
+    // that was numbered when it was built keep that one.
+    pub fn tagged(self, ids: &Ids) -> Self {
+        Self {
+            objects: self
+                .objects
+                .into_iter()
+                .map(|object| Arc::new(ids.tag(object)) as Arc<dyn Hittable>)
+                .collect(),
+        }
+    }
 }
 
 impl Hittable for HittableList {
@@ -265,10 +330,20 @@ impl Hittable for HittableList {
         if int_size == 0 {
             return Vec3::new(0.0, 0.0, 0.0);
         }
-        let ran = random_int(0, int_size - 1) as usize;
+        let ran = ((sample_1d() * int_size as f64) as usize).min(int_size as usize - 1);
         let vv = (*self.objects[ran]).random(o);
         vv
     }
+    fn transmittance(&self, r: Ray, t_min: f64, t_max: f64) -> Vec3 {
+        let mut tr = Vec3::ones();
+        for object in self.objects.iter() {
+            tr *= object.transmittance(r, t_min, t_max);
+        }
+        tr
+    }
+    fn area(&self) -> f64 {
+        self.objects.iter().map(|object| object.area()).sum()
+    }
 }
 
 pub struct Translate<T: Hittable> {
@@ -335,6 +410,16 @@ impl<T: Hittable> Hittable for Translate<T> {
     fn random(&self, o: Vec3) -> Vec3 {
         self.ptr.random(o - self.offset)
     }
+    fn transmittance(&self, r: Ray, t_min: f64, t_max: f64) -> Vec3 {
+        let moved_r = Ray::new(r.orig.sub(self.offset), r.dir, r.time);
+        self.ptr.transmittance(moved_r, t_min, t_max)
+    }
+    fn area(&self) -> f64 {
+        self.ptr.area()
+    }
+    fn plane_normal(&self) -> Option<Vec3> {
+        self.ptr.plane_normal()
+    }
 }
 pub struct RotateY<T: Hittable> {
     pub ptr: T,
@@ -408,30 +493,40 @@ impl<T: Hittable> RotateY<T> {
     }
 }
 
-impl<T: Hittable> Hittable for RotateY<T> {
-    fn hit(&self, r: Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
-        let mut origin = r.orig;
-        let mut direction = r.dir;
+impl<T: Hittable> RotateY<T> {
+    // `r` in the object's own frame
+    fn rotate(&self, r: Ray) -> Ray {
+        Ray {
+            orig: self.to_object(r.orig),
+            dir: self.to_object(r.dir),
+            time: r.time,
+        }
+    }
 
-        origin.x = self.cos_theta * r.orig.x - self.sin_theta * r.orig.z;
-        origin.z = self.sin_theta * r.orig.x + self.cos_theta * r.orig.z;
+    fn to_object(&self, v: Vec3) -> Vec3 {
+        Vec3::new(
+            self.cos_theta * v.x - self.sin_theta * v.z,
+            v.y,
+            self.sin_theta * v.x + self.cos_theta * v.z,
+        )
+    }
 
-        direction.x = self.cos_theta * r.dir.x - self.sin_theta * r.dir.z;
-        direction.z = self.sin_theta * r.dir.x + self.cos_theta * r.dir.z;
+    fn to_world(&self, v: Vec3) -> Vec3 {
+        Vec3::new(
+            self.cos_theta * v.x + self.sin_theta * v.z,
+            v.y,
+            -self.sin_theta * v.x + self.cos_theta * v.z,
+        )
+    }
+}
 
-        let rotated_r: Ray = Ray {
-            orig: origin,
-            dir: direction,
-            time: r.time,
-        };
+impl<T: Hittable> Hittable for RotateY<T> {
+    fn hit(&self, r: Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let rotated_r = self.rotate(r);
         match self.ptr.hit(rotated_r, t_min, t_max) {
             Some(rec) => {
-                let mut p = rec.p;
-                let mut normal = rec.normal;
-                p.x = self.cos_theta * rec.p.x + self.sin_theta * rec.p.z;
-                p.z = -self.sin_theta * rec.p.x + self.cos_theta * rec.p.z;
-                normal.x = self.cos_theta * rec.normal.x + self.sin_theta * rec.normal.z;
-                normal.z = -self.sin_theta * rec.normal.x + self.cos_theta * rec.normal.z;
+                let p = self.to_world(rec.p);
+                let normal = self.to_world(rec.normal);
                 let front_face = Vec3::dot(rotated_r.dir, normal) < 0.0;
                 let mut flag = -1.0;
                 if front_face {
@@ -469,6 +564,21 @@ impl<T: Hittable> Hittable for RotateY<T> {
         *output_box = self.bbox;
         self.hasbox
     }
+    fn transmittance(&self, r: Ray, t_min: f64, t_max: f64) -> Vec3 {
+        self.ptr.transmittance(self.rotate(r), t_min, t_max)
+    }
+    fn pdf_value(&self, o: Vec3, v: Vec3) -> f64 {
+        self.ptr.pdf_value(self.to_object(o), self.to_object(v))
+    }
+    fn random(&self, o: Vec3) -> Vec3 {
+        self.to_world(self.ptr.random(self.to_object(o)))
+    }
+    fn area(&self) -> f64 {
+        self.ptr.area()
+    }
+    fn plane_normal(&self) -> Option<Vec3> {
+        self.ptr.plane_normal().map(|n| self.to_world(n))
+    }
 }
 
 pub struct FlipFace<T: Hittable> {
@@ -500,4 +610,73 @@ impl<T: Hittable> Hittable for FlipFace<T> {
     fn bounding_box(&self, time0: f64, time1: f64, output_box: &mut Aabb) -> bool {
         self.ptr.bounding_box(time0, time1, output_box)
     }
+    fn transmittance(&self, r: Ray, t_min: f64, t_max: f64) -> Vec3 {
+        self.ptr.transmittance(r, t_min, t_max)
+    }
+    fn pdf_value(&self, o: Vec3, v: Vec3) -> f64 {
+        self.ptr.pdf_value(o, v)
+    }
+    fn random(&self, o: Vec3) -> Vec3 {
+        self.ptr.random(o)
+    }
+    fn area(&self) -> f64 {
+        self.ptr.area()
+    }
+    fn plane_normal(&self) -> Option<Vec3> {
+        self.ptr.plane_normal()
+    }
+}
+
+// Hands out the numbers of the id pass, 1, 2, 3... in the order objects are built, so the
+// same scene gets the same numbers in every process.
+#[derive(Default)]
+pub struct Ids {
+    last: Cell<u32>,
+}
+
+impl Ids {
+    pub fn tag<T: Hittable>(&self, object: T) -> Tagged<T> {
+        self.last.set(self.last.get() + 1);
+        Tagged::new(object, self.last.get())
+    }
+}
+
+// Gives what it wraps a number for the id pass, kept by hits that don't have one yet.
+pub struct Tagged<T: Hittable> {
+    pub ptr: T,
+    pub id: u32,
+}
+
+impl<T: Hittable> Tagged<T> {
+    pub fn new(p: T, id: u32) -> Self {
+        Self { ptr: p, id }
+    }
+}
+
+impl<T: Hittable> Hittable for Tagged<T> {
+    fn hit(&self, r: Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let mut rec = self.ptr.hit(r, t_min, t_max)?;
+        if rec.id == 0 {
+            rec.id = self.id;
+        }
+        Some(rec)
+    }
+    fn bounding_box(&self, time0: f64, time1: f64, output_box: &mut Aabb) -> bool {
+        self.ptr.bounding_box(time0, time1, output_box)
+    }
+    fn transmittance(&self, r: Ray, t_min: f64, t_max: f64) -> Vec3 {
+        self.ptr.transmittance(r, t_min, t_max)
+    }
+    fn pdf_value(&self, o: Vec3, v: Vec3) -> f64 {
+        self.ptr.pdf_value(o, v)
+    }
+    fn random(&self, o: Vec3) -> Vec3 {
+        self.ptr.random(o)
+    }
+    fn area(&self) -> f64 {
+        self.ptr.area()
+    }
+    fn plane_normal(&self) -> Option<Vec3> {
+        self.ptr.plane_normal()
+    }
 }
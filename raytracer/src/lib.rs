@@ -0,0 +1,47 @@
+#![allow(clippy::new_without_default)]
+// lets the code generated by `static_scene!` name `raytracer::...` inside this crate too
+extern crate self as raytracer;
+
+pub mod AABB;
+pub mod BVH;
+pub mod Boxe;
+pub mod aarect;
+pub mod adaptive;
+pub mod aov;
+pub mod bsdf;
+pub mod camera;
+pub mod checkpoint;
+pub mod cli;
+pub mod constant_medium;
+pub mod denoise;
+pub mod distributed;
+pub mod envmap;
+pub mod framebuffer;
+pub mod hit;
+pub mod light;
+pub mod light_bvh;
+pub mod material;
+pub mod medium;
+pub mod moving_sphere;
+pub mod obj;
+pub mod onb;
+pub mod pdf;
+pub mod perlin;
+pub mod progressive;
+pub mod rtweekend;
+pub mod sampler;
+pub mod scene_file;
+pub mod sky;
+pub mod spectrum;
+pub mod texture;
+pub mod tonemap;
+#[allow(clippy::float_cmp)]
+pub mod vec3;
+
+pub use crate::hit::Hittable;
+pub use crate::material::Material;
+pub use crate::moving_sphere::MovingSphere;
+pub use raytracer_codegen::static_scene;
+pub use rtweekend::*;
+pub use vec3::Ray;
+pub use vec3::Vec3;
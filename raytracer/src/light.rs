@@ -0,0 +1,675 @@
+use crate::hit::{Hittable, HittableList};
+use crate::light_bvh::{LightBounds, LightBvh};
+use crate::onb::Onb;
+use crate::pdf::AliasTable;
+use crate::tonemap::luminance;
+use crate::{clamp, Ray, Vec3, AABB::Aabb};
+use std::f64::consts::PI;
+use std::str::FromStr;
+use std::sync::Arc;
+
+// Lights that are no geometry: points, spots and directions. No ray can hit them, so next
+// event estimation is the only way their light gets into the image, and it needs no MIS.
+// Environment maps, the sky and the sun are sampled the same way, but they're also what rays
+// leaving the scene see, so there MIS weighs the two.
+
+pub struct LightSample {
+    // unit vector towards the light
+    pub wi: Vec3,
+    // how far along `wi` the light is, infinity for a directional one; whatever is closer
+    // casts a shadow
+    pub distance: f64,
+    // the radiance arriving from `wi` divided by the pdf of sampling it
+    pub li: Vec3,
+    // that pdf, per unit solid angle, when BSDF samples can find the light as well and the
+    // two have to be weighted by MIS
+    pub pdf: Option<f64>,
+}
+
+pub trait Light: Send + Sync {
+    // The light arriving at `p`, from a direction picked with `u` when there's a choice.
+    // None when none of it gets there.
+    fn sample(&self, p: Vec3, u: (f64, f64)) -> Option<LightSample>;
+
+    // The radiance a ray leaving the scene along `d` finds, for lights that far away.
+    fn le(&self, _d: Vec3) -> Vec3 {
+        Vec3::zero()
+    }
+
+    // The pdf of `sample` picking direction `d`, per unit solid angle, for those same lights.
+    fn pdf(&self, _d: Vec3) -> f64 {
+        0.0
+    }
+
+    // The luminous power it sends out, or into a scene of `scene_radius` from far away.
+    fn power(&self, scene_radius: f64) -> f64;
+
+    // Where it is and which way it shines, for lights that aren't infinitely far away.
+    fn bounds(&self) -> Option<LightBounds> {
+        None
+    }
+}
+
+// How next event estimation picks a light: each as likely as the others, in proportion to
+// its power, or by a light BVH, which also weighs how close and how turned towards the point
+// it is.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum LightSelection {
+    Uniform,
+    Power,
+    Bvh,
+}
+
+pub const LIGHT_SELECTIONS: [&str; 3] = ["uniform", "power", "bvh"];
+
+impl FromStr for LightSelection {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "uniform" => Ok(LightSelection::Uniform),
+            "power" => Ok(LightSelection::Power),
+            "bvh" => Ok(LightSelection::Bvh),
+            _ => Err(format!(
+                "unknown light selection `{}` (expected one of {})",
+                s,
+                LIGHT_SELECTIONS.join(", ")
+            )),
+        }
+    }
+}
+
+#[derive(Clone)]
+enum Selector {
+    Power(Arc<AliasTable>),
+    // the tree holds the lights with bounds; the others are picked uniformly, together as
+    // likely as the tree
+    Bvh(Arc<LightBvh>, Vec<usize>),
+}
+
+// What next event estimation picks from. The lights are numbered in this order: area, delta,
+// distant.
+#[derive(Default, Clone)]
+pub struct Lights {
+    // emitting geometry that is in the world too; it's sampled by direction and the shadow
+    // ray finds what it emits
+    pub area: HittableList,
+    pub delta: Vec<Arc<dyn Light>>,
+    // infinitely far away, and what rays that leave the scene see in place of the background
+    // color
+    pub distant: Vec<Arc<dyn Light>>,
+    // set up by `prepare`; without one every light is as likely as the others
+    selector: Option<Selector>,
+}
+
+impl Lights {
+    pub fn is_empty(&self) -> bool {
+        self.count() == 0
+    }
+
+    fn count(&self) -> usize {
+        self.area.objects.len() + self.delta.len() + self.distant.len()
+    }
+
+    // the number of distant light `i`
+    pub fn distant_index(&self, i: usize) -> usize {
+        self.area.objects.len() + self.delta.len() + i
+    }
+
+    // Light `k`, other than the area ones.
+    pub fn light(&self, k: usize) -> &dyn Light {
+        let k = k - self.area.objects.len();
+        match self.delta.get(k) {
+            Some(light) => light.as_ref(),
+            None => self.distant[k - self.delta.len()].as_ref(),
+        }
+    }
+
+    // Sets up picking lights by `selection` once they're all in. The power of an area light
+    // is its area times what the world emits on its surface, whatever hides it.
+    pub fn prepare(&mut self, world: &dyn Hittable, selection: LightSelection) {
+        let mut bbox = Aabb::new(Vec3::zero(), Vec3::zero());
+        let scene_radius = if world.bounding_box(0.0, 1.0, &mut bbox) {
+            (bbox.maximum - bbox.minimum).length() / 2.0
+        } else {
+            1.0
+        };
+        let area_power: Vec<f64> = match selection {
+            LightSelection::Uniform => Vec::new(),
+            _ => self
+                .area
+                .objects
+                .iter()
+                .map(|light| PI * light.area() * emitted_radiance(world, light.as_ref()))
+                .collect(),
+        };
+        let power = |k: usize| match area_power.get(k) {
+            Some(power) => *power,
+            None => self.light(k).power(scene_radius),
+        };
+        self.selector = match selection {
+            LightSelection::Uniform => None,
+            LightSelection::Power => {
+                let powers: Vec<f64> = (0..self.count()).map(power).collect();
+                Some(Selector::Power(Arc::new(AliasTable::new(&powers))))
+            }
+            LightSelection::Bvh => {
+                let mut bounded = Vec::new();
+                let mut unbounded = Vec::new();
+                for k in 0..self.count() {
+                    let bounds = match self.area.objects.get(k) {
+                        Some(light) => area_bounds(light.as_ref(), power(k)),
+                        None => self.light(k).bounds(),
+                    };
+                    match bounds {
+                        Some(bounds) => bounded.push((k, bounds)),
+                        None => unbounded.push(k),
+                    }
+                }
+                Some(Selector::Bvh(
+                    Arc::new(LightBvh::new(bounded, self.count())),
+                    unbounded,
+                ))
+            }
+        };
+    }
+
+    // A light for the point `p` with normal `n` (zero in a medium), and the probability of
+    // picking it.
+    pub fn pick(&self, u: f64, p: Vec3, n: Vec3) -> Option<(usize, f64)> {
+        let count = self.count();
+        if count == 0 {
+            return None;
+        }
+        match &self.selector {
+            None => Some((
+                ((u * count as f64) as usize).min(count - 1),
+                1.0 / count as f64,
+            )),
+            Some(Selector::Power(table)) => Some(table.sample(u)),
+            Some(Selector::Bvh(bvh, unbounded)) => {
+                let choices = unbounded.len() + !bvh.is_empty() as usize;
+                if choices == 0 {
+                    return None;
+                }
+                let k = ((u * choices as f64) as usize).min(choices - 1);
+                match unbounded.get(k) {
+                    Some(light) => Some((*light, 1.0 / choices as f64)),
+                    None => {
+                        let u = (u * choices as f64 - k as f64).min(1.0 - f64::EPSILON);
+                        let (light, pmf) = bvh.sample(p, n, u)?;
+                        Some((light, pmf / choices as f64))
+                    }
+                }
+            }
+        }
+    }
+
+    // The probability of `pick` choosing light `k`.
+    pub fn pmf(&self, k: usize, p: Vec3, n: Vec3) -> f64 {
+        match &self.selector {
+            None => 1.0 / self.count() as f64,
+            Some(Selector::Power(table)) => table.pmf(k),
+            Some(Selector::Bvh(bvh, unbounded)) => {
+                let choices = unbounded.len() + !bvh.is_empty() as usize;
+                if choices == 0 {
+                    0.0
+                } else if unbounded.contains(&k) {
+                    1.0 / choices as f64
+                } else {
+                    bvh.pmf(k, p, n) / choices as f64
+                }
+            }
+        }
+    }
+
+    // The density, per unit solid angle, of picking an area light at `p` and sampling
+    // direction `v` towards it, summed over the area lights that could.
+    pub fn area_pdf(&self, p: Vec3, n: Vec3, v: Vec3) -> f64 {
+        match &self.selector {
+            Some(Selector::Bvh(bvh, unbounded)) => {
+                let choices = unbounded.len() + !bvh.is_empty() as usize;
+                let area = self.area.objects.len();
+                let mut pdf = 0.0;
+                for k in unbounded.iter().filter(|k| **k < area) {
+                    pdf += self.area.objects[*k].pdf_value(p, v) / choices as f64;
+                }
+                bvh.visit(Ray::new(p, v, 0.0), p, n, &mut |k, pmf| {
+                    if k < area {
+                        pdf += pmf / choices as f64 * self.area.objects[k].pdf_value(p, v);
+                    }
+                });
+                pdf
+            }
+            _ => {
+                let mut pdf = 0.0;
+                for (k, light) in self.area.objects.iter().enumerate() {
+                    let pmf = self.pmf(k, p, n);
+                    if pmf > 0.0 {
+                        pdf += pmf * light.pdf_value(p, v);
+                    }
+                }
+                pdf
+            }
+        }
+    }
+}
+
+// The luminance the world emits from the surface of `light`, found by looking at it from all
+// around its bounds; the brightest look counts. Only the world right at the light's surface is
+// asked, so an emitter behind glass, in a lamp shade or down a recess still gets its power.
+fn emitted_radiance(world: &dyn Hittable, light: &dyn Hittable) -> f64 {
+    let mut bbox = Aabb::new(Vec3::zero(), Vec3::zero());
+    if !light.bounding_box(0.0, 1.0, &mut bbox) {
+        return 0.0;
+    }
+    let center = (bbox.minimum + bbox.maximum) * 0.5;
+    let radius = (bbox.maximum - bbox.minimum).length() / 2.0 + 1e-3;
+    let mut brightest: f64 = 0.0;
+    for i in (0..27).filter(|i| *i != 13) {
+        // from the faces, edges and corners of a cube around it
+        let offset = Vec3::new(
+            (i % 3) as f64 - 1.0,
+            (i / 3 % 3) as f64 - 1.0,
+            (i / 9) as f64 - 1.0,
+        );
+        let origin = center + Vec3::unit_vector(offset) * (2.0 * radius);
+        for _ in 0..4 {
+            let r = Ray::new(origin, light.random(origin), 0.0);
+            let t = match light.hit(r, 0.001, f64::INFINITY) {
+                Some(light_rec) => light_rec.t,
+                None => continue,
+            };
+            let eps = 1e-6 * (1.0 + t);
+            let rec = match world.hit(r, t - eps, t + eps) {
+                Some(rec) => rec,
+                None => continue,
+            };
+            let le = rec.mat_ptr.emitted(
+                &mut r.clone(),
+                &mut rec.clone(),
+                rec.u,
+                rec.v,
+                &mut rec.p.clone(),
+            );
+            brightest = brightest.max(luminance(le));
+        }
+    }
+    brightest
+}
+
+// The bounds of an area light; flat ones shine from both sides for all anyone knows here,
+// which side the world's emitter faces.
+fn area_bounds(light: &dyn Hittable, power: f64) -> Option<LightBounds> {
+    let mut bbox = Aabb::new(Vec3::zero(), Vec3::zero());
+    if !light.bounding_box(0.0, 1.0, &mut bbox) {
+        return None;
+    }
+    // rays must pass through even the box of a flat light
+    let pad = Vec3::ones() * 1e-4;
+    let bbox = Aabb::new(bbox.minimum - pad, bbox.maximum + pad);
+    Some(match light.plane_normal() {
+        Some(normal) => LightBounds::new(bbox, power, normal, 1.0, 0.0, true),
+        None => LightBounds::new(bbox, power, Vec3::new(0.0, 0.0, 1.0), -1.0, 0.0, false),
+    })
+}
+
+// Shines `intensity` (power per unit solid angle) equally in every direction.
+pub struct PointLight {
+    pub position: Vec3,
+    pub intensity: Vec3,
+}
+
+impl PointLight {
+    pub fn new(position: Vec3, intensity: Vec3) -> Self {
+        Self {
+            position,
+            intensity,
+        }
+    }
+}
+
+impl Light for PointLight {
+    fn sample(&self, p: Vec3, _u: (f64, f64)) -> Option<LightSample> {
+        let d = self.position - p;
+        let distance_squared = d.length_squared();
+        if distance_squared <= 0.0 {
+            return None;
+        }
+        let distance = distance_squared.sqrt();
+        Some(LightSample {
+            wi: d / distance,
+            distance,
+            li: self.intensity / distance_squared,
+            pdf: None,
+        })
+    }
+
+    fn power(&self, _scene_radius: f64) -> f64 {
+        4.0 * PI * luminance(self.intensity)
+    }
+
+    fn bounds(&self) -> Option<LightBounds> {
+        Some(LightBounds::new(
+            Aabb::new(self.position, self.position),
+            self.power(0.0),
+            Vec3::new(0.0, 0.0, 1.0),
+            -1.0,
+            0.0,
+            false,
+        ))
+    }
+}
+
+// A point light shining into a cone around `direction`. Without a profile the intensity is
+// full up to `falloff_start` degrees off the axis and fades out smoothly by `angle`; a
+// profile, like the candela table of an IES file, gives the relative intensity at evenly
+// spaced angles from the axis (first value) to `angle` (last value) instead.
+pub struct SpotLight {
+    pub position: Vec3,
+    pub direction: Vec3,
+    pub intensity: Vec3,
+    pub angle: f64,
+    pub falloff_start: f64,
+    pub profile: Option<Vec<f64>>,
+}
+
+impl SpotLight {
+    pub fn new(
+        position: Vec3,
+        direction: Vec3,
+        intensity: Vec3,
+        angle: f64,
+        falloff_start: f64,
+    ) -> Self {
+        Self {
+            position,
+            direction: Vec3::unit_vector(direction),
+            intensity,
+            angle,
+            falloff_start,
+            profile: None,
+        }
+    }
+
+    // the fraction of `intensity` shining along `w`, a unit vector away from the light
+    fn falloff(&self, w: Vec3) -> f64 {
+        let cos_theta = clamp(Vec3::dot(w, self.direction), -1.0, 1.0);
+        let cos_total = self.angle.to_radians().cos();
+        if cos_theta < cos_total {
+            return 0.0;
+        }
+        match &self.profile {
+            Some(profile) if profile.len() > 1 => {
+                let x = cos_theta.acos() / self.angle.to_radians() * (profile.len() - 1) as f64;
+                let i = (x as usize).min(profile.len() - 2);
+                let f = clamp(x - i as f64, 0.0, 1.0);
+                profile[i] * (1.0 - f) + profile[i + 1] * f
+            }
+            Some(profile) => profile.first().cloned().unwrap_or(0.0),
+            None => {
+                let cos_falloff = self.falloff_start.to_radians().cos();
+                if cos_theta >= cos_falloff {
+                    return 1.0;
+                }
+                let t = (cos_theta - cos_total) / (cos_falloff - cos_total);
+                t * t * (3.0 - 2.0 * t)
+            }
+        }
+    }
+}
+
+impl Light for SpotLight {
+    fn sample(&self, p: Vec3, _u: (f64, f64)) -> Option<LightSample> {
+        let d = self.position - p;
+        let distance_squared = d.length_squared();
+        if distance_squared <= 0.0 {
+            return None;
+        }
+        let distance = distance_squared.sqrt();
+        let wi = d / distance;
+        let falloff = self.falloff(wi * -1.0);
+        if falloff <= 0.0 {
+            return None;
+        }
+        Some(LightSample {
+            wi,
+            distance,
+            li: self.intensity * (falloff / distance_squared),
+            pdf: None,
+        })
+    }
+
+    fn power(&self, _scene_radius: f64) -> f64 {
+        // the solid angle of the cone, the falloff taken as linear in the cosine
+        let cos_total = self.angle.to_radians().cos();
+        let solid_angle = match &self.profile {
+            Some(profile) => {
+                2.0 * PI * (1.0 - cos_total) * profile.iter().sum::<f64>() / profile.len() as f64
+            }
+            None => {
+                let cos_falloff = self.falloff_start.to_radians().cos();
+                2.0 * PI * ((1.0 - cos_falloff) + (cos_falloff - cos_total) / 2.0)
+            }
+        };
+        solid_angle * luminance(self.intensity)
+    }
+
+    fn bounds(&self) -> Option<LightBounds> {
+        Some(LightBounds::new(
+            Aabb::new(self.position, self.position),
+            self.power(0.0),
+            self.direction,
+            1.0,
+            self.angle.to_radians().cos(),
+            false,
+        ))
+    }
+}
+
+// Light from infinitely far away travelling along `direction`, like the sun's. `irradiance`
+// is what it delivers to a surface facing it. With an `angular_radius` (degrees) it comes
+// from a disk of that size in the sky instead of a single direction, and the shadows it casts
+// get soft edges.
+pub struct DirectionalLight {
+    pub direction: Vec3,
+    pub irradiance: Vec3,
+    pub angular_radius: f64,
+}
+
+impl DirectionalLight {
+    pub fn new(direction: Vec3, irradiance: Vec3, angular_radius: f64) -> Self {
+        Self {
+            direction: Vec3::unit_vector(direction),
+            irradiance,
+            angular_radius,
+        }
+    }
+}
+
+impl Light for DirectionalLight {
+    fn sample(&self, _p: Vec3, u: (f64, f64)) -> Option<LightSample> {
+        let to_light = self.direction * -1.0;
+        if self.angular_radius <= 0.0 {
+            return Some(LightSample {
+                wi: to_light,
+                distance: f64::INFINITY,
+                li: self.irradiance,
+                pdf: None,
+            });
+        }
+        // uniform over the cone: a disk of radiance irradiance / (PI sin^2), over a pdf of
+        // 1 / (2 PI (1 - cos))
+        let cos_max = self.angular_radius.to_radians().cos();
+        let cos_theta = 1.0 - u.0 * (1.0 - cos_max);
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * PI * u.1;
+        let frame = Onb::new_from_w(to_light);
+        Some(LightSample {
+            wi: frame.local(phi.cos() * sin_theta, phi.sin() * sin_theta, cos_theta),
+            distance: f64::INFINITY,
+            li: self.irradiance * (2.0 / (1.0 + cos_max)),
+            pdf: None,
+        })
+    }
+
+    fn power(&self, scene_radius: f64) -> f64 {
+        PI * scene_radius * scene_radius * luminance(self.irradiance)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aarect::{XyRect, XzRect};
+    use crate::hit::Sphere;
+    use crate::material::DiffuseLight;
+
+    fn point_lights(lights: &mut Lights) {
+        for (x, brightness) in [(-2.0, 1.0), (0.0, 2.0), (3.0, 5.0)].iter() {
+            let intensity = Vec3::ones() * *brightness;
+            lights.delta.push(Arc::new(PointLight::new(
+                Vec3::new(*x, 4.0, 0.0),
+                intensity,
+            )));
+        }
+    }
+
+    // a sphere and two rectangles, in the world as well, and the point lights
+    fn area_lights() -> (HittableList, Lights) {
+        let mut lights = Lights::default();
+        lights.area.add(Arc::new(Sphere::new(
+            Vec3::new(0.0, 3.0, 0.0),
+            0.5,
+            DiffuseLight::new0(Vec3::ones() * 4.0),
+        )));
+        lights.area.add(Arc::new(XzRect::new(
+            -1.0,
+            1.0,
+            -1.0,
+            1.0,
+            5.0,
+            DiffuseLight::new0(Vec3::ones()),
+        )));
+        lights.area.add(Arc::new(XyRect::new(
+            -2.0,
+            0.0,
+            0.0,
+            1.0,
+            -3.0,
+            DiffuseLight::new0(Vec3::ones() * 2.0),
+        )));
+        point_lights(&mut lights);
+        let world = lights.area.clone();
+        (world, lights)
+    }
+
+    // evenly spread over the sphere
+    fn directions(n: usize) -> Vec<Vec3> {
+        let golden = PI * (3.0 - 5f64.sqrt());
+        (0..n)
+            .map(|i| {
+                let z = 1.0 - (2.0 * i as f64 + 1.0) / n as f64;
+                let r = (1.0 - z * z).sqrt();
+                let phi = golden * i as f64;
+                Vec3::new(r * phi.cos(), r * phi.sin(), z)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn power_selection_picks_by_power() {
+        let mut lights = Lights::default();
+        point_lights(&mut lights);
+        lights.prepare(&HittableList::new(), LightSelection::Power);
+        let (p, n) = (Vec3::zero(), Vec3::new(0.0, 1.0, 0.0));
+        for (k, brightness) in [1.0, 2.0, 5.0].iter().enumerate() {
+            assert!((lights.pmf(k, p, n) - brightness / 8.0).abs() < 1e-12);
+        }
+        let samples = 10_000;
+        let mut counts = [0; 3];
+        for i in 0..samples {
+            let (k, pmf) = lights
+                .pick((i as f64 + 0.5) / samples as f64, p, n)
+                .unwrap();
+            assert_eq!(pmf, lights.pmf(k, p, n));
+            counts[k] += 1;
+        }
+        for (k, count) in counts.iter().enumerate() {
+            let frequency = *count as f64 / samples as f64;
+            assert!(
+                (frequency - lights.pmf(k, p, n)).abs() < 1e-3,
+                "light {}",
+                k
+            );
+        }
+    }
+
+    #[test]
+    fn picks_by_the_pmf_of_each_selection() {
+        let points = [
+            (Vec3::zero(), Vec3::new(0.0, 1.0, 0.0)),
+            (Vec3::new(1.5, 2.0, -1.0), Vec3::zero()),
+        ];
+        for selection in [
+            LightSelection::Uniform,
+            LightSelection::Power,
+            LightSelection::Bvh,
+        ]
+        .iter()
+        {
+            let (world, mut lights) = area_lights();
+            lights.prepare(&world, *selection);
+            for (p, n) in points.iter() {
+                let total: f64 = (0..6).map(|k| lights.pmf(k, *p, *n)).sum();
+                assert!((total - 1.0).abs() < 1e-12, "{:?}", selection);
+                let samples = 10_000;
+                for i in 0..samples {
+                    let u = (i as f64 + 0.5) / samples as f64;
+                    let (k, pmf) = lights.pick(u, *p, *n).unwrap();
+                    assert!(
+                        (pmf - lights.pmf(k, *p, *n)).abs() < 1e-12,
+                        "{:?}",
+                        selection
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn area_pdf_is_the_selection_times_the_shape_pdf() {
+        let points = [
+            (Vec3::zero(), Vec3::new(0.0, 1.0, 0.0)),
+            (Vec3::new(1.5, 2.0, -1.0), Vec3::zero()),
+            (Vec3::new(-1.0, 1.0, -1.0), Vec3::new(0.0, 0.0, -1.0)),
+        ];
+        for selection in [
+            LightSelection::Uniform,
+            LightSelection::Power,
+            LightSelection::Bvh,
+        ]
+        .iter()
+        {
+            let (world, mut lights) = area_lights();
+            lights.prepare(&world, *selection);
+            for (p, n) in points.iter() {
+                let mut seen = 0;
+                for v in directions(2000) {
+                    let expected: f64 = (0..3)
+                        .map(|k| lights.pmf(k, *p, *n) * lights.area.objects[k].pdf_value(*p, v))
+                        .sum();
+                    let pdf = lights.area_pdf(*p, *n, v);
+                    assert!(
+                        (pdf - expected).abs() <= 1e-9 * expected,
+                        "{:?}: {} against {}",
+                        selection,
+                        pdf,
+                        expected
+                    );
+                    seen += (pdf > 0.0) as usize;
+                }
+                assert!(seen > 0, "{:?}", selection);
+            }
+        }
+    }
+}
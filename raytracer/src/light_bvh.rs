@@ -0,0 +1,430 @@
+use crate::{Ray, Vec3, AABB::Aabb};
+use std::f64::consts::PI;
+
+// Where the lights of a node are, how much power they have and which way it goes, bounded
+// loosely enough to hold for all of them: the normals stay within `theta_o` of `w`, and the
+// light leaves at most `theta_e` beyond that. From Conty Estevez and Kulla, "Importance
+// Sampling of Many Lights with Adaptive Tree Splitting" (2018), the way pbrt-v4 has it.
+#[derive(Copy, Clone)]
+pub struct LightBounds {
+    pub bounds: Aabb,
+    pub phi: f64,
+    pub w: Vec3,
+    pub cos_theta_o: f64,
+    pub cos_theta_e: f64,
+    // shining along -w as much as along w
+    pub two_sided: bool,
+}
+
+impl LightBounds {
+    pub fn new(
+        bounds: Aabb,
+        phi: f64,
+        w: Vec3,
+        cos_theta_o: f64,
+        cos_theta_e: f64,
+        two_sided: bool,
+    ) -> Self {
+        Self {
+            bounds,
+            phi,
+            w: Vec3::unit_vector(w),
+            cos_theta_o,
+            cos_theta_e,
+            two_sided,
+        }
+    }
+
+    fn centroid(&self) -> Vec3 {
+        (self.bounds.minimum + self.bounds.maximum) * 0.5
+    }
+
+    fn union(&self, other: &LightBounds) -> LightBounds {
+        if self.phi == 0.0 {
+            return *other;
+        }
+        if other.phi == 0.0 {
+            return *self;
+        }
+        let (w, cos_theta_o) = cone_union(self.w, self.cos_theta_o, other.w, other.cos_theta_o);
+        LightBounds {
+            bounds: Aabb::new(
+                Vec3::new(
+                    self.bounds.minimum.x.min(other.bounds.minimum.x),
+                    self.bounds.minimum.y.min(other.bounds.minimum.y),
+                    self.bounds.minimum.z.min(other.bounds.minimum.z),
+                ),
+                Vec3::new(
+                    self.bounds.maximum.x.max(other.bounds.maximum.x),
+                    self.bounds.maximum.y.max(other.bounds.maximum.y),
+                    self.bounds.maximum.z.max(other.bounds.maximum.z),
+                ),
+            ),
+            phi: self.phi + other.phi,
+            w,
+            cos_theta_o,
+            cos_theta_e: self.cos_theta_e.min(other.cos_theta_e),
+            two_sided: self.two_sided || other.two_sided,
+        }
+    }
+
+    // How much light the lights inside could send to a point `p` with normal `n` (zero for
+    // none, like in a medium), at most: the power over the squared distance, turned by the
+    // smallest angles the bounds allow.
+    pub fn importance(&self, p: Vec3, n: Vec3) -> f64 {
+        let pc = self.centroid();
+        let diagonal = (self.bounds.maximum - self.bounds.minimum).length();
+        let d2 = (p - pc).length_squared().max(diagonal / 2.0);
+        let wi = Vec3::unit_vector(p - pc);
+        let mut cos_theta_w = Vec3::dot(self.w, wi);
+        if self.two_sided {
+            cos_theta_w = cos_theta_w.abs();
+        }
+        // the cone of directions from p the box takes up
+        let radius_squared = diagonal * diagonal / 4.0;
+        let distance_squared = (p - pc).length_squared();
+        let cos_theta_b = if distance_squared < radius_squared {
+            -1.0
+        } else {
+            (1.0 - radius_squared / distance_squared).max(0.0).sqrt()
+        };
+        // cos(max(0, theta_w - theta_o)), then that minus theta_b
+        let (cos_theta_x, sin_theta_x) = cos_sub_clamped(cos_theta_w, self.cos_theta_o);
+        let cos_theta_p = if cos_theta_x > cos_theta_b {
+            1.0
+        } else {
+            cos_theta_x * cos_theta_b + sin_theta_x * sin(cos_theta_b)
+        };
+        if cos_theta_p <= self.cos_theta_e {
+            return 0.0;
+        }
+        let mut importance = self.phi * cos_theta_p / d2;
+        if n.length_squared() > 0.0 {
+            let cos_theta_i = Vec3::dot(wi, Vec3::unit_vector(n)).abs();
+            importance *= cos_sub_clamped(cos_theta_i, cos_theta_b).0;
+        }
+        importance.max(0.0)
+    }
+}
+
+fn sin(cos: f64) -> f64 {
+    (1.0 - cos * cos).max(0.0).sqrt()
+}
+
+// cos and sin of max(0, a - b), from the cosines of angles a and b in [0, PI]
+fn cos_sub_clamped(cos_a: f64, cos_b: f64) -> (f64, f64) {
+    if cos_a > cos_b {
+        return (1.0, 0.0);
+    }
+    let (sin_a, sin_b) = (sin(cos_a), sin(cos_b));
+    (cos_a * cos_b + sin_a * sin_b, sin_a * cos_b - cos_a * sin_b)
+}
+
+// The smallest cone, as its axis and the cosine of its half angle, that holds both cones.
+fn cone_union(wa: Vec3, cos_a: f64, wb: Vec3, cos_b: f64) -> (Vec3, f64) {
+    let (theta_a, theta_b) = (cos_a.acos(), cos_b.acos());
+    let theta_d = crate::clamp(Vec3::dot(wa, wb), -1.0, 1.0).acos();
+    if (theta_d + theta_b).min(PI) <= theta_a {
+        return (wa, cos_a);
+    }
+    if (theta_d + theta_a).min(PI) <= theta_b {
+        return (wb, cos_b);
+    }
+    let theta_o = (theta_a + theta_d + theta_b) / 2.0;
+    if theta_o >= PI {
+        return (wa, -1.0);
+    }
+    // turn wa towards wb until the cone reaches around both
+    let axis = Vec3::cross(wa, wb);
+    if axis.length_squared() == 0.0 {
+        return (wa, -1.0);
+    }
+    let k = Vec3::unit_vector(axis);
+    let theta_r = theta_o - theta_a;
+    let w = wa * theta_r.cos() + Vec3::cross(k, wa) * theta_r.sin();
+    (Vec3::unit_vector(w), theta_o.cos())
+}
+
+struct Node {
+    bounds: LightBounds,
+    // a light's index for a leaf, the second child's node for the others, whose first child
+    // follows them
+    index: usize,
+    leaf: bool,
+}
+
+// A tree over the lights with bounds, picking one for a point by walking down from the root
+// and choosing between the two children in proportion to their importance there.
+pub struct LightBvh {
+    nodes: Vec<Node>,
+    // for each light in it, the turns down to its leaf: bit i set for the second child at
+    // depth i
+    trails: Vec<Option<u64>>,
+}
+
+impl LightBvh {
+    // `lights` are (index, bounds) pairs, the indices below `count`.
+    pub fn new(mut lights: Vec<(usize, LightBounds)>, count: usize) -> Self {
+        let mut bvh = Self {
+            nodes: Vec::new(),
+            trails: vec![None; count],
+        };
+        lights.retain(|(_, b)| b.phi > 0.0);
+        if !lights.is_empty() {
+            bvh.build(&mut lights, 0, 0);
+        }
+        bvh
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    fn build(
+        &mut self,
+        lights: &mut [(usize, LightBounds)],
+        trail: u64,
+        depth: u32,
+    ) -> LightBounds {
+        if lights.len() == 1 {
+            let (index, bounds) = lights[0];
+            self.nodes.push(Node {
+                bounds,
+                index,
+                leaf: true,
+            });
+            self.trails[index] = Some(trail);
+            return bounds;
+        }
+        // split at the median along the widest spread of the centroids
+        let (mut lo, mut hi) = (Vec3::ones() * f64::INFINITY, Vec3::ones() * -f64::INFINITY);
+        for (_, b) in lights.iter() {
+            let c = b.centroid();
+            lo = Vec3::new(lo.x.min(c.x), lo.y.min(c.y), lo.z.min(c.z));
+            hi = Vec3::new(hi.x.max(c.x), hi.y.max(c.y), hi.z.max(c.z));
+        }
+        let extent = hi - lo;
+        let axis = |c: Vec3| {
+            if extent.x >= extent.y && extent.x >= extent.z {
+                c.x
+            } else if extent.y >= extent.z {
+                c.y
+            } else {
+                c.z
+            }
+        };
+        lights.sort_by(|a, b| {
+            axis(a.1.centroid())
+                .partial_cmp(&axis(b.1.centroid()))
+                .unwrap()
+        });
+        let mid = lights.len() / 2;
+        let node = self.nodes.len();
+        self.nodes.push(Node {
+            bounds: lights[0].1,
+            index: 0,
+            leaf: false,
+        });
+        let (first, second) = lights.split_at_mut(mid);
+        let a = self.build(first, trail, depth + 1);
+        self.nodes[node].index = self.nodes.len();
+        let b = self.build(second, trail | (1 << depth), depth + 1);
+        self.nodes[node].bounds = a.union(&b);
+        self.nodes[node].bounds
+    }
+
+    // the chances of going down to the first and to the second child of `node`
+    fn split(&self, node: usize, p: Vec3, n: Vec3) -> Option<(f64, f64)> {
+        let first = self.nodes[node + 1].bounds.importance(p, n);
+        let second = self.nodes[self.nodes[node].index].bounds.importance(p, n);
+        if first + second <= 0.0 {
+            return None;
+        }
+        Some((first / (first + second), second / (first + second)))
+    }
+
+    // A light for the point `p` with normal `n` and the probability of picking it.
+    pub fn sample(&self, p: Vec3, n: Vec3, mut u: f64) -> Option<(usize, f64)> {
+        if self.is_empty() || self.nodes[0].bounds.importance(p, n) <= 0.0 {
+            return None;
+        }
+        let (mut node, mut pmf) = (0, 1.0);
+        while !self.nodes[node].leaf {
+            let (first, second) = self.split(node, p, n)?;
+            if u < first {
+                u = (u / first).min(1.0 - f64::EPSILON);
+                pmf *= first;
+                node += 1;
+            } else {
+                u = ((u - first) / second).min(1.0 - f64::EPSILON);
+                pmf *= second;
+                node = self.nodes[node].index;
+            }
+        }
+        Some((self.nodes[node].index, pmf))
+    }
+
+    // The probability of `sample` picking light `light` for the point `p` with normal `n`.
+    pub fn pmf(&self, light: usize, p: Vec3, n: Vec3) -> f64 {
+        let trail = match self.trails.get(light) {
+            Some(Some(trail)) => *trail,
+            _ => return 0.0,
+        };
+        if self.nodes[0].bounds.importance(p, n) <= 0.0 {
+            return 0.0;
+        }
+        let (mut node, mut pmf, mut depth) = (0, 1.0, 0);
+        while !self.nodes[node].leaf {
+            let (first, second) = match self.split(node, p, n) {
+                Some(split) => split,
+                None => return 0.0,
+            };
+            if trail & (1 << depth) == 0 {
+                pmf *= first;
+                node += 1;
+            } else {
+                pmf *= second;
+                node = self.nodes[node].index;
+            }
+            depth += 1;
+        }
+        pmf
+    }
+
+    // Calls `f` with each light whose bounds `r` passes through and its probability of being
+    // picked for the point `p` with normal `n`.
+    pub fn visit(&self, r: Ray, p: Vec3, n: Vec3, f: &mut impl FnMut(usize, f64)) {
+        if !self.is_empty() && self.nodes[0].bounds.importance(p, n) > 0.0 {
+            self.visit_node(0, 1.0, r, p, n, f);
+        }
+    }
+
+    fn visit_node(
+        &self,
+        node: usize,
+        pmf: f64,
+        r: Ray,
+        p: Vec3,
+        n: Vec3,
+        f: &mut impl FnMut(usize, f64),
+    ) {
+        if pmf <= 0.0 || !self.nodes[node].bounds.bounds.hit(r, 0.001, f64::INFINITY) {
+            return;
+        }
+        if self.nodes[node].leaf {
+            f(self.nodes[node].index, pmf);
+            return;
+        }
+        if let Some((first, second)) = self.split(node, p, n) {
+            self.visit_node(node + 1, pmf * first, r, p, n, f);
+            self.visit_node(self.nodes[node].index, pmf * second, r, p, n, f);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // small lights all around, one spot shining away from the origin and one that's dark;
+    // rays must have some box to pass through
+    fn lights() -> Vec<(usize, LightBounds)> {
+        let pad = Vec3::ones() * 1e-2;
+        let point = |p: Vec3, phi: f64| {
+            LightBounds::new(
+                Aabb::new(p - pad, p + pad),
+                phi,
+                Vec3::new(0.0, 0.0, 1.0),
+                -1.0,
+                0.0,
+                false,
+            )
+        };
+        let mut lights = vec![
+            (0, point(Vec3::new(2.0, 0.0, 0.0), 1.0)),
+            (2, point(Vec3::new(-3.0, 1.0, 0.5), 4.0)),
+            (3, point(Vec3::new(0.0, 5.0, -1.0), 2.0)),
+            (4, point(Vec3::new(1.0, -2.0, 3.0), 0.5)),
+            (5, point(Vec3::new(-1.0, -1.0, -4.0), 3.0)),
+            (6, point(Vec3::new(6.0, 2.0, 2.0), 0.0)),
+        ];
+        let spot = Vec3::new(0.0, 0.0, 2.0);
+        lights.push((
+            7,
+            LightBounds::new(
+                Aabb::new(spot - pad, spot + pad),
+                8.0,
+                Vec3::new(0.0, 0.0, 1.0),
+                1.0,
+                0.5,
+                false,
+            ),
+        ));
+        lights
+    }
+
+    fn points() -> Vec<(Vec3, Vec3)> {
+        vec![
+            (Vec3::zero(), Vec3::zero()),
+            (Vec3::new(0.5, 0.5, 0.0), Vec3::new(0.0, 1.0, 0.0)),
+            (Vec3::new(-2.0, 0.0, 1.0), Vec3::new(1.0, 0.0, 0.0)),
+            (Vec3::new(0.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0)),
+        ]
+    }
+
+    #[test]
+    fn samples_by_its_pmf() {
+        let bvh = LightBvh::new(lights(), 8);
+        for (p, n) in points() {
+            let pmfs: Vec<f64> = (0..8).map(|k| bvh.pmf(k, p, n)).collect();
+            assert!((pmfs.iter().sum::<f64>() - 1.0).abs() < 1e-12);
+            // not in the tree, or with no power
+            assert_eq!(pmfs[1], 0.0);
+            assert_eq!(pmfs[6], 0.0);
+            let samples = 100_000;
+            let mut counts = [0; 8];
+            for i in 0..samples {
+                let (k, pmf) = bvh.sample(p, n, (i as f64 + 0.5) / samples as f64).unwrap();
+                assert!((pmf - pmfs[k]).abs() < 1e-12);
+                counts[k] += 1;
+            }
+            for (k, pmf) in pmfs.iter().enumerate() {
+                let frequency = counts[k] as f64 / samples as f64;
+                assert!((frequency - pmf).abs() < 1e-3, "light {}", k);
+            }
+        }
+    }
+
+    #[test]
+    fn visits_the_lights_a_ray_passes_with_their_pmf() {
+        let lights = lights();
+        let bvh = LightBvh::new(lights.clone(), 8);
+        for (p, n) in points() {
+            for (k, bounds) in lights.iter().filter(|(_, b)| b.phi > 0.0) {
+                let r = Ray::new(p, bounds.centroid() - p, 0.0);
+                let mut found = None;
+                bvh.visit(r, p, n, &mut |light, pmf| {
+                    if light == *k {
+                        found = Some(pmf);
+                    }
+                });
+                let pmf = bvh.pmf(*k, p, n);
+                match found {
+                    Some(found) => assert!((found - pmf).abs() < 1e-12, "light {}", k),
+                    None => assert_eq!(pmf, 0.0, "light {}", k),
+                }
+            }
+            // and nothing along a ray that misses them all
+            let r = Ray::new(Vec3::new(0.0, 0.0, -10.0), Vec3::new(0.0, 0.0, -1.0), 0.0);
+            bvh.visit(r, p, n, &mut |light, _| panic!("visited light {}", light));
+        }
+    }
+
+    #[test]
+    fn without_lights_picks_none() {
+        let bvh = LightBvh::new(vec![lights()[5]], 8);
+        assert!(bvh.is_empty());
+        assert_eq!(bvh.sample(Vec3::zero(), Vec3::zero(), 0.5), None);
+        assert_eq!(bvh.pmf(6, Vec3::zero(), Vec3::zero()), 0.0);
+    }
+}
@@ -1,42 +1,41 @@
-mod AABB;
-mod BVH;
-mod Boxe;
-mod aarect;
-mod camera;
-mod constant_medium;
-mod hit;
-mod material;
-mod moving_sphere;
-mod onb;
-mod pdf;
-mod perlin;
-mod rtweekend;
-mod texture;
-#[allow(clippy::float_cmp)]
-mod vec3;
-
-use crate::aarect::{Triangle, XyRect, XzRect, YzRect};
-use crate::camera::Camera;
-use crate::constant_medium::ConstantMedium;
-use crate::hit::{FlipFace, HitRecord, Hittable, HittableList, RotateY, Sphere, Translate};
-use crate::material::{
-    Dielectric, DiffuseLight, Lambertian, LambertianStatic, Material, Metal, NoMaterial,
-    ScatterRecord,
-};
-use crate::moving_sphere::MovingSphere;
-use crate::pdf::{CosinePdf, HittablePdf, MixturePdf, Pdf};
-use crate::texture::{CheckerTexture, ImageTexture, NoiseTexture, SolidColor};
-use crate::Boxe::Boxes;
-use crate::BVH::BvhNode;
-use image::{ImageBuffer, RgbImage};
 use indicatif::ProgressBar;
-pub use rtweekend::*;
-use std::f64::INFINITY;
+use raytracer::aarect::{XyRect, XzRect, YzRect};
+use raytracer::adaptive::{save_heatmap, AdaptiveOptions, PixelStats};
+use raytracer::aov::{save_with_aovs, Aov, AovBuffers, AovSample};
+use raytracer::bsdf::Lobe;
+use raytracer::camera::Camera;
+use raytracer::checkpoint::{self, RenderKey};
+use raytracer::cli::{Options, USAGE};
+use raytracer::constant_medium::ConstantMedium;
+use raytracer::denoise::denoise;
+use raytracer::distributed::{self, Job, JobQueue, JobResult};
+use raytracer::hit::{
+    FlipFace, HitRecord, Hittable, HittableList, Ids, RotateY, Sphere, Translate,
+};
+use raytracer::light::Lights;
+use raytracer::material::{
+    Dielectric, DiffuseLight, Lambertian, LambertianStatic, Material, Metal, NoMaterial, Principled,
+};
+use raytracer::medium::{self, HeterogeneousMedium, NoiseDensity};
+use raytracer::moving_sphere::MovingSphere;
+use raytracer::obj::ObjModel;
+use raytracer::onb::Onb;
+use raytracer::pdf::MisHeuristic;
+use raytracer::progressive::{tiles, Accumulator};
+use raytracer::sampler::SamplerKind;
+use raytracer::sky::Sky;
+use raytracer::spectrum::{self, Wavelengths};
+use raytracer::texture::{CheckerTexture, ImageTexture, NoiseTexture, SolidColor, Texture};
+use raytracer::Boxe::Boxes;
+use raytracer::BVH::BvhNode;
+use raytracer::{random_double, random_double_lim, Ray, Vec3};
+use raytracer::{rtweekend, sampler, scene_file, static_scene, texture};
+use std::net::TcpListener;
+use std::process;
+use std::process::Command;
 pub use std::sync::mpsc::channel;
 use std::sync::Arc;
-pub use threadpool::ThreadPool;
-pub use vec3::Ray;
-pub use vec3::Vec3;
+use std::time::Instant;
 
 // fn main() {
 //     let x = Vec3::new(1.0, 1.0, 1.0);
@@ -64,6 +63,29 @@ pub use vec3::Vec3;
 //     img.save("output/test.png").unwrap();
 //     bar.finish();
 // }
+pub const SCENES: [(&str, &str); 11] = [
+    ("random_scene", "book 1 cover: a field of random spheres"),
+    (
+        "static_spheres",
+        "data/random_spheres.json built into a static BVH at compile time",
+    ),
+    ("two_spheres", "two checker textured spheres"),
+    ("two_perlin_spheres", "two spheres with perlin noise"),
+    ("earth", "an image textured globe (input/earthmap.jpg)"),
+    ("simple_light", "perlin spheres lit by a rectangle light"),
+    ("cornell_box", "cornell box with the stanford bunny"),
+    ("cornell_smoke", "cornell box with two smoke blocks"),
+    ("final_scene", "book 2 final scene"),
+    (
+        "furnace",
+        "white furnace test: white principled spheres that should vanish into a white sky",
+    ),
+    (
+        "volumes",
+        "cornell box with a perlin noise cloud and a ball of fire",
+    ),
+];
+
 #[allow(clippy::many_single_char_names)]
 #[warn(unused_mut)]
 fn main() {
@@ -73,26 +95,58 @@ fn main() {
     // let mut image_height: u32 = (image_width as f64 / aspect_ratio) as u32;
     // let mut samples_per_pixel: u32 = 10;
     // let mut max_depth: u32 = 5;
-    let aspect_ratio: f64 = 1.0;
-    let image_width: u32 = 800;
-    let image_height: u32 = (image_width as f64 / aspect_ratio) as u32;
-    let samples_per_pixel: u32 = 5;
-    let max_depth: u32 = 10;
-
-    let mut lights = HittableList::default();
-    lights.add(Arc::new(XzRect::new(
-        213.0,
-        343.0,
-        227.0,
-        332.0,
-        554.0,
-        NoMaterial {},
-    )));
-    lights.add(Arc::new(Sphere::new(
-        Vec3::new(190.0, 90.0, 190.0),
-        90.0,
-        NoMaterial {},
-    )));
+    let scene_names: Vec<&str> = SCENES.iter().map(|(name, _)| *name).collect();
+    let mut opts = match Options::parse(std::env::args().skip(1), &scene_names) {
+        Ok(opts) => opts,
+        Err(msg) => {
+            eprintln!("error: {}\n\n{}", msg, USAGE);
+            process::exit(2);
+        }
+    };
+    // a worker renders with the coordinator's options, only its thread count is its own
+    let mut connection = None;
+    if let Some(addr) = &opts.connect {
+        let (stream, args) = match distributed::connect(addr) {
+            Ok(setup) => setup,
+            Err(e) => {
+                eprintln!("error: {}", e);
+                process::exit(1);
+            }
+        };
+        let threads = opts.n_workers;
+        opts = match Options::parse(args.into_iter(), &scene_names) {
+            Ok(opts) => opts,
+            Err(msg) => {
+                eprintln!("error: the coordinator's options: {}", msg);
+                process::exit(2);
+            }
+        };
+        opts.n_workers = threads;
+        connection = Some(stream);
+    }
+    if opts.help {
+        println!("{}", USAGE);
+        return;
+    }
+    if opts.list_scenes {
+        for (name, desc) in SCENES.iter() {
+            println!("{:<20}{}", name, desc);
+        }
+        return;
+    }
+    let aspect_ratio: f64 = opts.aspect_ratio;
+    let image_width: u32 = opts.image_width;
+    let image_height: u32 = opts.image_height;
+    let samples_per_pixel: u32 = opts.samples_per_pixel;
+    let max_depth: u32 = opts.max_depth;
+    let rr_depth: u32 = opts.rr_depth;
+    let mis = opts.mis;
+    let spectral = opts.spectral;
+
+    rtweekend::set_seed(opts.seed);
+
+    // each scene adds its own emitters
+    let mut lights = Lights::default();
     //let mut lights = Arc::new(lights);
     //let lights:Arc<dyn Hittable> = Arc::new(XzRect::new(213.0,343.0,227.0,332.0,554.0 , Arc::new(Metal::new())));
     //World
@@ -230,86 +284,183 @@ fn main() {
     // let dist_to_focus = 10.0;
     // let aperture = 0.1;
     let world: HittableList;
+    // numbers the objects of a built-in scene for the id pass
+    let ids = Ids::default();
     let lookfrom: Vec3;
     let lookat: Vec3;
-    let mut vfov: f64 = 40.0;
+    let vfov: f64;
     let mut aperture: f64 = 0.0;
-    let mut background: Vec3 = Vec3::new(0.0, 0.0, 0.0);
-    let op = 5;
-    match op {
-        0 => {
-            world = random_scene();
-            lookfrom = Vec3::new(13.0, 2.0, 3.0);
-            background = Vec3::new(0.7, 0.8, 1.0);
-            lookat = Vec3::new(0.0, 0.0, 0.0);
-            vfov = 20.0;
-            aperture = 0.1;
-        }
-        1 => {
-            world = two_spheres();
-            lookfrom = Vec3::new(13.0, 2.0, 3.0);
-            background = Vec3::new(0.7, 0.8, 1.0);
-            lookat = Vec3::new(0.0, 0.0, 0.0);
-            vfov = 20.0;
-        }
-        2 => {
-            world = two_perlin_spheres();
-            lookfrom = Vec3::new(13.0, 2.0, 3.0);
-            background = Vec3::new(0.7, 0.8, 1.0);
-            lookat = Vec3::new(0.0, 0.0, 0.0);
-            vfov = 20.0;
-        }
-        3 => {
-            world = earth();
-            lookfrom = Vec3::new(13.0, 2.0, 3.0);
-            background = Vec3::new(0.7, 0.8, 1.0);
-            lookat = Vec3::new(0.0, 0.0, 0.0);
-            vfov = 20.0;
-        }
-        4 => {
-            world = simple_light();
-            lookfrom = Vec3::new(26.0, 3.0, 6.0);
-            background = Vec3::new(0.0, 0.0, 0.0);
-            lookat = Vec3::new(0.0, 2.0, 0.0);
-            vfov = 20.0;
-        }
-        5 => {
-            world = cornell_box();
-            // aspect_ratio = 1.0;
-            // image_width = 600;
-            // image_height = (image_width as f64 / aspect_ratio) as u32;
-            // samples_per_pixel = 200;
-            // let mut lights = Arc::new(HittableList::new());
-            // lights.add(Arc::new(XzRect::new(213.0,343.0,227.0,332.0,554.0 , Arc::new(Metal::new()))));
-            // lights.add(Arc::new(Sphere::new(Vec3::new(190.0,90.0,190.0) , 90.0 , Arc::new(Metal::new()))));
-
-            background = Vec3::new(0.0, 0.0, 0.0);
-            lookfrom = Vec3::new(278.0, 278.0, -800.0);
-            lookat = Vec3::new(278.0, 278.0, 0.0);
-            vfov = 40.0;
-        }
-        6 => {
-            world = cornell_smoke();
-            background = Vec3::new(0.0, 0.0, 0.0);
-            lookfrom = Vec3::new(278.0, 278.0, -800.0);
-            lookat = Vec3::new(278.0, 278.0, 0.0);
-            vfov = 40.0;
-        }
-        7 => {
-            world = final_scene();
-            background = Vec3::new(0.0, 0.0, 0.0);
-            lookfrom = Vec3::new(478.0, 278.0, -600.0);
-            lookat = Vec3::new(278.0, 278.0, 0.0);
-            vfov = 40.0;
-        }
-        _ => {
-            world = final_scene();
-            lookfrom = Vec3::new(478.0, 278.0, -600.0);
-            lookat = Vec3::new(278.0, 278.0, 0.0);
+    let background: Vec3;
+    let mut vup = Vec3::new(0.0, 1.0, 0.0);
+    let mut dist_to_focus: f64 = 10.0;
+    let mut time0: f64 = 0.0;
+    let mut time1: f64 = 1.0;
+    if let Some(path) = &opts.scene_file {
+        let scene = match scene_file::load(path) {
+            Ok(scene) => scene,
+            Err(e) => {
+                eprintln!("error: {}", e);
+                process::exit(1);
+            }
+        };
+        let cam = scene.camera;
+        world = scene.world;
+        lights = scene.lights;
+        background = scene.background;
+        lookfrom = Vec3::new(cam.lookfrom[0], cam.lookfrom[1], cam.lookfrom[2]);
+        lookat = Vec3::new(cam.lookat[0], cam.lookat[1], cam.lookat[2]);
+        vup = Vec3::new(cam.vup[0], cam.vup[1], cam.vup[2]);
+        vfov = cam.vfov;
+        aperture = cam.aperture;
+        dist_to_focus = cam.focus_dist;
+        time0 = cam.time0;
+        time1 = cam.time1;
+    } else {
+        match opts.scene.as_str() {
+            "random_scene" => {
+                world = random_scene();
+                lights = daylight();
+                lookfrom = Vec3::new(13.0, 2.0, 3.0);
+                // the sky is among the lights
+                background = Vec3::zero();
+                lookat = Vec3::new(0.0, 0.0, 0.0);
+                vfov = 20.0;
+                aperture = 0.1;
+            }
+            "static_spheres" => {
+                world = static_spheres();
+                lights = daylight();
+                lookfrom = Vec3::new(13.0, 2.0, 3.0);
+                // the sky is among the lights
+                background = Vec3::zero();
+                lookat = Vec3::new(0.0, 0.0, 0.0);
+                vfov = 20.0;
+                aperture = 0.1;
+            }
+            "two_spheres" => {
+                world = two_spheres();
+                lookfrom = Vec3::new(13.0, 2.0, 3.0);
+                background = Vec3::new(0.7, 0.8, 1.0);
+                lookat = Vec3::new(0.0, 0.0, 0.0);
+                vfov = 20.0;
+            }
+            "two_perlin_spheres" => {
+                world = two_perlin_spheres();
+                lookfrom = Vec3::new(13.0, 2.0, 3.0);
+                background = Vec3::new(0.7, 0.8, 1.0);
+                lookat = Vec3::new(0.0, 0.0, 0.0);
+                vfov = 20.0;
+            }
+            "earth" => {
+                world = earth();
+                lookfrom = Vec3::new(13.0, 2.0, 3.0);
+                background = Vec3::new(0.7, 0.8, 1.0);
+                lookat = Vec3::new(0.0, 0.0, 0.0);
+                vfov = 20.0;
+            }
+            "simple_light" => {
+                world = simple_light();
+                lights.area.add(Arc::new(XyRect::new(
+                    3.0,
+                    5.0,
+                    1.0,
+                    3.0,
+                    -2.0,
+                    NoMaterial {},
+                )));
+                lookfrom = Vec3::new(26.0, 3.0, 6.0);
+                background = Vec3::new(0.0, 0.0, 0.0);
+                lookat = Vec3::new(0.0, 2.0, 0.0);
+                vfov = 20.0;
+            }
+            "cornell_box" => {
+                world = cornell_box(&ids);
+                lights.area.add(Arc::new(XzRect::new(
+                    213.0,
+                    343.0,
+                    227.0,
+                    332.0,
+                    554.0,
+                    NoMaterial {},
+                )));
+                // aspect_ratio = 1.0;
+                // image_width = 600;
+                // image_height = (image_width as f64 / aspect_ratio) as u32;
+                // samples_per_pixel = 200;
+                // let mut lights = Arc::new(HittableList::new());
+                // lights.add(Arc::new(XzRect::new(213.0,343.0,227.0,332.0,554.0 , Arc::new(Metal::new()))));
+                // lights.add(Arc::new(Sphere::new(Vec3::new(190.0,90.0,190.0) , 90.0 , Arc::new(Metal::new()))));
+
+                background = Vec3::new(0.0, 0.0, 0.0);
+                lookfrom = Vec3::new(278.0, 278.0, -800.0);
+                lookat = Vec3::new(278.0, 278.0, 0.0);
+                vfov = 40.0;
+            }
+            "cornell_smoke" => {
+                world = cornell_smoke();
+                lights.area.add(Arc::new(XzRect::new(
+                    123.0,
+                    443.0,
+                    127.0,
+                    442.0,
+                    554.0,
+                    NoMaterial {},
+                )));
+                background = Vec3::new(0.0, 0.0, 0.0);
+                lookfrom = Vec3::new(278.0, 278.0, -800.0);
+                lookat = Vec3::new(278.0, 278.0, 0.0);
+                vfov = 40.0;
+            }
+            "final_scene" => {
+                world = final_scene(&ids);
+                lights.area.add(Arc::new(XzRect::new(
+                    123.0,
+                    423.0,
+                    147.0,
+                    412.0,
+                    554.0,
+                    NoMaterial {},
+                )));
+                background = Vec3::new(0.0, 0.0, 0.0);
+                lookfrom = Vec3::new(478.0, 278.0, -600.0);
+                lookat = Vec3::new(278.0, 278.0, 0.0);
+                vfov = 40.0;
+            }
+            "furnace" => {
+                world = furnace();
+                // nothing to sample but the sky, which comes from the BSDF samples anyway
+                background = Vec3::new(1.0, 1.0, 1.0);
+                lookfrom = Vec3::new(0.0, 0.0, 12.0);
+                lookat = Vec3::new(0.0, 0.0, 0.0);
+                vfov = 30.0;
+            }
+            "volumes" => {
+                world = volumes();
+                // the fire lights the box too, but only its own BSDF samples find it
+                lights.area.add(Arc::new(XzRect::new(
+                    213.0,
+                    343.0,
+                    227.0,
+                    332.0,
+                    554.0,
+                    NoMaterial {},
+                )));
+                background = Vec3::new(0.0, 0.0, 0.0);
+                lookfrom = Vec3::new(278.0, 278.0, -800.0);
+                lookat = Vec3::new(278.0, 278.0, 0.0);
+                vfov = 40.0;
+            }
+            _ => unreachable!("scene names are validated by Options::parse"),
         }
     }
-    let vup = Vec3::new(0.0, 1.0, 0.0);
-    let dist_to_focus: f64 = 10.0;
+
+    // a scene file numbers its objects as it builds them
+    let world = if opts.scene_file.is_some() {
+        world
+    } else {
+        world.tagged(&ids)
+    };
+    lights.prepare(&world, opts.light_selection);
 
     let cam: Camera = Camera::camera_from_where(
         lookfrom,
@@ -319,8 +470,8 @@ fn main() {
         aspect_ratio,
         aperture,
         dist_to_focus,
-        0.0,
-        1.0,
+        time0,
+        time1,
     );
 
     // //视口左下角的坐标
@@ -346,71 +497,261 @@ fn main() {
     // img.save("output/test.png").unwrap();
     // bar.finish();
 
-    let (tx, rx) = channel();
-    let n_jobs: usize = 32;
-    let n_workers = 8;
-    let pool = ThreadPool::new(n_workers);
-    let bar = ProgressBar::new((n_jobs + 1) as u64);
-    let world_in_thread = Arc::new(world);
-
-    for i in 0..n_jobs {
-        let tx = tx.clone();
-        let light_ptr = lights.clone();
-        let world_ptr = world_in_thread.clone();
-        let bars = bar.clone();
-        pool.execute(move || {
-            let row_begin = image_height as usize * i / n_jobs;
-            let row_end = image_height as usize * (i + 1) / n_jobs;
-            let render_height = row_end - row_begin;
-            let mut img: RgbImage = ImageBuffer::new(image_width, render_height as u32);
-            for x in 0..image_width {
-                for (img_y, y) in (row_begin..row_end).enumerate() {
-                    let y = y as u32;
-                    let pixel = img.get_pixel_mut(x, img_y as u32);
-                    let mut pixel_color = Vec3::new(0.0, 0.0, 0.0);
-                    for _ in 0..samples_per_pixel {
-                        let u = (x as f64 + random_double()) / (image_width - 1) as f64;
-                        let v = (image_height as f64 - y as f64 + random_double())
-                            / (image_height - 1) as f64;
-                        let r = cam.get_ray(u, v);
-                        pixel_color += ray_color(r, background, &*world_ptr, &light_ptr, max_depth);
+    // the denoiser is guided by the albedo and normal passes even when they aren't written
+    let mut aovs = opts.aovs.clone();
+    if opts.denoise.is_some() {
+        for aov in [Aov::Albedo, Aov::Normal].iter() {
+            if !aovs.contains(aov) {
+                aovs.push(*aov);
+            }
+        }
+    }
+
+    // without adaptive sampling every pixel simply runs to samples_per_pixel
+    let sampling = opts.adaptive.unwrap_or(AdaptiveOptions {
+        min_spp: samples_per_pixel,
+        max_spp: samples_per_pixel,
+        threshold: 0.0,
+    });
+
+    let pixel_sampler = opts.sampler.build(sampling.max_spp, opts.seed);
+
+    // Progressive rendering: each pass brings every pixel up to another pass_spp samples,
+    // tile by tile. A tile's next pass is queued as soon as its previous one comes back,
+    // behind the rest of the current pass, so the whole frame converges evenly.
+    let tiles = tiles(image_width, image_height, opts.tile_size, opts.tile_order);
+    let pass_spp = opts.pass_spp;
+    let passes = (sampling.max_spp - 1) / pass_spp + 1;
+
+    // identifies the render for checkpoints and for workers joining it
+    let key = {
+        let description = match &opts.scene_file {
+            Some(path) => std::fs::read(path).unwrap_or_default(),
+            None => opts.scene.clone().into_bytes(),
+        };
+        // the stratified sampler's strata depend on the sample budget, the others don't,
+        // so a finished render can be resumed with a higher --spp
+        let mut settings = format!(
+            "seed {} sampler {:?} depth {} rr {} mis {:?} lights {:?} spectral {} aovs {:?}",
+            opts.seed, opts.sampler, max_depth, rr_depth, mis, opts.light_selection, spectral, aovs
+        );
+        if opts.sampler == SamplerKind::Stratified {
+            settings += &format!(" spp {}", sampling.max_spp);
+        }
+        RenderKey {
+            scene: checkpoint::scene_hash(&description, &world, &lights.area, background),
+            camera: checkpoint::hash_bytes(format!("{:?}", cam).as_bytes()),
+            settings: checkpoint::hash_bytes(settings.as_bytes()),
+        }
+    };
+
+    let render = {
+        let tiles = tiles.clone();
+        let aovs = aovs.clone();
+        move |job: Job| {
+            sampler::set_sampler(pixel_sampler.clone());
+            let tile = tiles[job.index];
+            let target = ((job.pass + 1) * pass_spp).min(sampling.max_spp);
+            let mut stats = job.stats;
+            let mut tile_aovs = AovBuffers::new(&aovs, tile.width(), tile.height());
+            let mut aov_samples = Vec::new();
+            for (i, stats) in stats.iter_mut().enumerate() {
+                let (lx, ly) = (i as u32 % tile.width(), i as u32 / tile.width());
+                let (x, y) = (tile.x0 + lx, tile.y0 + ly);
+                aov_samples.clear();
+                while stats.n < target && !stats.done(&sampling) {
+                    sampler::start_pixel_sample(x, y, stats.n);
+                    let (jx, jy) = sampler::sample_2d();
+                    let u = (x as f64 + jx) / (image_width - 1) as f64;
+                    let v = (image_height as f64 - y as f64 + jy) / (image_height - 1) as f64;
+                    let r = cam.get_ray(u, v);
+                    stats.add(ray_color(
+                        r, background, &world, &lights, max_depth, rr_depth, mis, spectral,
+                    ));
+                    // after the beauty sample, so turning AOVs on doesn't change the image
+                    if !aovs.is_empty() {
+                        aov_samples.push(AovSample::trace(r, &world));
                     }
-                    *pixel = write_color(&pixel_color, samples_per_pixel);
+                }
+                if !aovs.is_empty() {
+                    tile_aovs.add(lx, ly, &aov_samples);
                 }
             }
-            tx.send((row_begin..row_end, img))
-                .expect("failed to send result");
-            bars.inc(1);
-        });
-    }
-    let mut img: RgbImage = ImageBuffer::new(image_width, image_height);
-    for (rows, data) in rx.iter().take(n_jobs) {
-        for (idx, row) in rows.enumerate() {
-            for col in 0..image_width {
-                let row = row as u32;
-                let idx = idx as u32;
-                *img.get_pixel_mut(col, row) = *data.get_pixel(col, idx);
+            JobResult {
+                index: job.index,
+                pass: job.pass,
+                stats,
+                aovs: tile_aovs,
             }
         }
+    };
+
+    if let Some(stream) = connection {
+        if let Err(e) = distributed::work(stream, key, opts.n_workers, &aovs, render) {
+            eprintln!("error: {}", e);
+            process::exit(1);
+        }
+        return;
+    }
+
+    let mut acc = Accumulator::new(image_width, image_height, &aovs);
+    if let (true, Some(path)) = (opts.resume, &opts.checkpoint) {
+        if let Err(e) = checkpoint::load(path, &key, &mut acc) {
+            eprintln!("error: {}", e);
+            process::exit(1);
+        }
+        let total: u64 = acc.stats.iter().map(|s| s.n as u64).sum();
+        println!(
+            "resuming from `{}` at {:.2} samples per pixel",
+            path,
+            total as f64 / acc.stats.len() as f64
+        );
     }
-    img.save("output/test.png").unwrap();
-    bar.finish();
-}
-fn write_color(pixel_color: &Vec3, samples_per_pixel: u32) -> image::Rgb<u8> {
-    let mut r: f64 = pixel_color.x;
-    let mut g: f64 = pixel_color.y;
-    let mut b: f64 = pixel_color.z;
 
-    let scale: f64 = 1.0 / (samples_per_pixel as f64);
+    // The local threads and every connected worker take jobs from the same queue.
+    let queue = Arc::new(JobQueue::new());
+    let (tx, rx) = channel();
+    distributed::spawn_threads(opts.n_workers, &queue, &tx, &Arc::new(render));
+    let mut children = Vec::new();
+    let mut remote = None;
+    if opts.listen.is_some() || opts.local_workers > 0 {
+        let addr = opts.listen.as_deref().unwrap_or("127.0.0.1:0");
+        let listener = match TcpListener::bind(addr) {
+            Ok(listener) => listener,
+            Err(e) => {
+                eprintln!("error: cannot listen on `{}`: {}", addr, e);
+                process::exit(1);
+            }
+        };
+        let local_addr = listener.local_addr().expect("listening socket address");
+        println!("waiting for workers on {}", local_addr);
+        let args = std::env::args().skip(1).collect();
+        remote = Some(distributed::serve(
+            listener,
+            args,
+            key,
+            aovs.clone(),
+            queue.clone(),
+            tx.clone(),
+        ));
+        for _ in 0..opts.local_workers {
+            let exe = std::env::current_exe().expect("path of the running executable");
+            let mut command = Command::new(exe);
+            command.arg("--connect").arg(local_addr.to_string());
+            // with -t 0 the workers keep their default thread count
+            if opts.n_workers > 0 {
+                command.arg("--workers").arg(opts.n_workers.to_string());
+            }
+            let child = command.spawn();
+            match child {
+                Ok(child) => children.push(child),
+                Err(e) => eprintln!("warning: cannot start a local worker: {}", e),
+            }
+        }
+    }
+    drop(tx);
 
-    r = (r * scale).sqrt();
-    g = (g * scale).sqrt();
-    b = (b * scale).sqrt();
+    let bar = ProgressBar::new(tiles.len() as u64 * passes as u64);
+    let dispatch = |index: usize, pass: u32, stats: Vec<PixelStats>| {
+        queue.push(Job { index, pass, stats });
+    };
 
-    let ir: u8 = (256.0 * rtweekend::clamp(r, 0.0, 0.999)) as u8;
-    let ig: u8 = (256.0 * rtweekend::clamp(g, 0.0, 0.999)) as u8;
-    let ib: u8 = (256.0 * rtweekend::clamp(b, 0.0, 0.999)) as u8;
-    image::Rgb([ir, ig, ib])
+    // a resumed tile starts at the pass its least sampled pixel is in
+    let mut pending = 0;
+    for (index, tile) in tiles.iter().enumerate() {
+        let stats = acc.tile_stats(tile);
+        let first = (stats.iter().map(|s| s.n).min().unwrap_or(0) / pass_spp).min(passes);
+        bar.inc(first as u64);
+        if first < passes && !stats.iter().all(|s| s.done(&sampling)) {
+            dispatch(index, first, stats);
+            pending += 1;
+        } else {
+            bar.inc((passes - first) as u64);
+        }
+    }
+    let mut last_write = Instant::now();
+    let mut last_checkpoint = Instant::now();
+    while pending > 0 {
+        let JobResult {
+            index,
+            pass,
+            stats,
+            aovs: tile_aovs,
+        } = rx.recv().expect("failed to receive result");
+        pending -= 1;
+        bar.inc(1);
+        acc.merge(&tiles[index], &stats, &tile_aovs);
+        if pass + 1 < passes && !stats.iter().all(|s| s.done(&sampling)) {
+            dispatch(index, pass + 1, stats);
+            pending += 1;
+        } else {
+            // converged early, its remaining passes are skipped
+            bar.inc((passes - pass - 1) as u64);
+        }
+        if pending > 0
+            && opts.write_interval > 0.0
+            && last_write.elapsed().as_secs_f64() >= opts.write_interval
+        {
+            let mut aov_img = acc.aov_image();
+            aov_img.retain(&opts.aovs);
+            let img = acc.image().map(|c| opts.color_space.from_srgb(c));
+            if let Err(e) = save_with_aovs(&opts.output, &img, &aov_img, &opts.display) {
+                eprintln!("warning: {}", e);
+            }
+            last_write = Instant::now();
+        }
+        if let Some(path) = &opts.checkpoint {
+            if pending > 0 && last_checkpoint.elapsed().as_secs_f64() >= opts.checkpoint_interval {
+                if let Err(e) = checkpoint::save(path, &key, &acc) {
+                    eprintln!("warning: {}", e);
+                }
+                last_checkpoint = Instant::now();
+            }
+        }
+    }
+    // workers get told to finish once the queue is closed
+    queue.close();
+    if let Some(remote) = &remote {
+        remote.finish();
+    }
+    for mut child in children {
+        child.wait().ok();
+    }
+    // kept after the render too, so it can be resumed with more samples
+    if let Some(path) = &opts.checkpoint {
+        if let Err(e) = checkpoint::save(path, &key, &acc) {
+            eprintln!("error: {}", e);
+            process::exit(1);
+        }
+    }
+    let mut img = acc.image();
+    let mut aov_img = acc.aov_image();
+    let count_img = acc.counts();
+    bar.finish();
+    if let Some(denoise_opts) = &opts.denoise {
+        let albedo = aov_img.layer(Aov::Albedo).expect("albedo pass");
+        let normal = aov_img.layer(Aov::Normal).expect("normal pass");
+        img = denoise(&img, albedo, normal, denoise_opts);
+    }
+    aov_img.retain(&opts.aovs);
+    if opts.adaptive.is_some() {
+        let total: f64 = count_img.data.iter().map(|c| c.x).sum();
+        println!(
+            "average samples per pixel: {:.2}",
+            total / count_img.data.len() as f64
+        );
+    }
+    if let Some(heatmap) = &opts.heatmap {
+        if let Err(e) = save_heatmap(heatmap, &count_img, &sampling) {
+            eprintln!("error: {}", e);
+            process::exit(1);
+        }
+    }
+    let img = img.map(|c| opts.color_space.from_srgb(c));
+    if let Err(e) = save_with_aovs(&opts.output, &img, &aov_img, &opts.display) {
+        eprintln!("error: {}", e);
+        process::exit(1);
+    }
 }
 
 // fn ray_color(r:Ray) -> Vec3{
@@ -444,161 +785,230 @@ fn write_color(pixel_color: &Vec3, samples_per_pixel: u32) -> image::Rgb<u8> {
 //         return -1.0;
 //     }
 // }
-#[warn(unused_assignments)]
-fn ray_color(
+// Follows a path for up to `depth` bounces, carrying the product of the bounces' weights
+// in `throughput`. At every non-specular bounce the emitters are sampled directly too (next
+// event estimation), and emission that either strategy finds is weighted by `mis` against
+// the pdf the other one had for the same direction. From bounce `rr_depth` on, Russian
+// roulette ends the path with a probability that grows as its throughput drops, and
+// reweights the surviving paths so the estimate stays unbiased.
+//
+// With `wavelengths` the path carries radiance at those instead of RGB, and the colors of
+// the scene are turned into spectra as it meets them.
+#[allow(clippy::too_many_arguments)]
+fn trace_path(
     mut r: Ray,
     background: Vec3,
     world: &dyn Hittable,
-    lights: &HittableList,
+    lights: &Lights,
     depth: u32,
+    rr_depth: u32,
+    mis: MisHeuristic,
+    wavelengths: Option<&Wavelengths>,
 ) -> Vec3 {
-    // let mut rec = HitRecord {
-    //     p: Vec3 {
-    //         x: 0.0,
-    //         y: 0.0,
-    //         z: 0.0,
-    //     },
-    //     normal: Vec3 {
-    //         x: 0.0,
-    //         y: 0.0,
-    //         z: 0.0,
-    //     },
-    //     mat_ptr: &Lambertian::new(Vec3::new(0.0, 0.0, 0.0)),
-    //     t: 0.0,
-    //     u: 0.0,
-    //     v: 0.0,
-    //     front_face: true,
-    // };
-    let mut rec: HitRecord;
-    #[deny(clippy::absurd_extreme_comparisons)]
-    if depth == 0 {
-        return Vec3::new(0.0, 0.0, 0.0);
-    }
-    match world.hit(r, 0.001, INFINITY) {
-        Some(rec_) => {
-            rec = rec_;
+    let reflectance = |c: Vec3| wavelengths.map_or(c, |wl| wl.reflectance(c));
+    let illuminant = |c: Vec3| wavelengths.map_or(c, |wl| wl.illuminant(c));
+    let mut color = Vec3::zero();
+    let mut throughput = Vec3::ones();
+    // the one channel the path carries on in, once something could only be sampled for it;
+    // its estimate stands for all three, hence the factor
+    let mut channel: Option<i32> = None;
+    let mut single_channel = |throughput: &mut Vec3, c: i32| {
+        if channel.is_none() {
+            *throughput = Vec3::new(
+                if c == 0 { 3.0 * throughput.x } else { 0.0 },
+                if c == 1 { 3.0 * throughput.y } else { 0.0 },
+                if c == 2 { 3.0 * throughput.z } else { 0.0 },
+            );
+            channel = Some(c);
         }
-        None => {
-            return background;
+    };
+    let sample_lights = !lights.is_empty();
+    let has_area_lights = !lights.area.objects.is_empty();
+    // the BSDF pdf of the current ray; camera and specular rays can't be light sampled
+    let mut bsdf_pdf: Option<f64> = None;
+    // the normal where it started, which light selection may depend on; none in a medium
+    let mut normal = Vec3::zero();
+    medium::reset_tracked_channel();
+    for bounce in 0..depth {
+        let hit = world.hit(r, 0.001, f64::INFINITY);
+        if let Some(c) = medium::tracked_channel() {
+            single_channel(&mut throughput, c);
+        }
+        let mut rec: HitRecord = match hit {
+            Some(rec) => rec,
+            None => {
+                if lights.distant.is_empty() {
+                    return color + throughput * illuminant(background);
+                }
+                for (i, light) in lights.distant.iter().enumerate() {
+                    let weight = match bsdf_pdf {
+                        Some(pdf) => {
+                            let pmf = lights.pmf(lights.distant_index(i), r.orig, normal);
+                            mis.weight(pdf, pmf * light.pdf(r.dir))
+                        }
+                        None => 1.0,
+                    };
+                    color += throughput * illuminant(light.le(r.dir)) * weight;
+                }
+                return color;
+            }
+        };
+        if let Some(w) = rec.medium_weight {
+            throughput *= w;
+        }
+        let emitted: Vec3 =
+            rec.mat_ptr
+                .emitted(&mut r.clone(), &mut rec.clone(), rec.u, rec.v, &mut rec.p);
+        // glowing media aren't among the lights, nothing else could have found their light
+        let weight = match bsdf_pdf {
+            Some(pdf) if has_area_lights && rec.medium_weight.is_none() => {
+                mis.weight(pdf, lights.area_pdf(r.orig, normal, r.dir))
+            }
+            _ => 1.0,
+        };
+        color += throughput * illuminant(emitted) * weight;
+
+        let bsdf = match rec.mat_ptr.bsdf(&rec) {
+            Some(bsdf) => bsdf,
+            None => return color,
+        };
+        // the other wavelengths would have gone elsewhere
+        if wavelengths.is_some() && bsdf.hero_only() {
+            single_channel(&mut throughput, 0);
+        }
+        let frame = Onb::new_from_w(rec.normal);
+        let wo = frame.to_local(Vec3::unit_vector(r.dir) * -1.0);
+
+        normal = if rec.medium_weight.is_some() {
+            Vec3::zero()
+        } else {
+            rec.normal
+        };
+
+        if sample_lights && !bsdf.lobes().contains(Lobe::SPECULAR) {
+            // one light, by the selection `lights` was prepared with
+            let pick = lights.pick(sampler::sample_1d(), rec.p, normal);
+            match pick {
+                Some((k, _)) if k < lights.area.objects.len() => {
+                    let to_light = Ray::new(
+                        rec.p,
+                        Vec3::unit_vector(lights.area.objects[k].random(rec.p)),
+                        r.time,
+                    );
+                    let light_pdf = lights.area_pdf(rec.p, normal, to_light.dir);
+                    let wi = frame.to_local(to_light.dir);
+                    let f = bsdf.eval(wo, wi);
+                    if light_pdf > 0.0 && f.max_component() > 0.0 {
+                        let light_hit =
+                            medium::shadow_ray(|| world.hit(to_light, 0.001, f64::INFINITY));
+                        if let Some(mut light_rec) = light_hit {
+                            let le = light_rec.mat_ptr.emitted(
+                                &mut to_light.clone(),
+                                &mut light_rec.clone(),
+                                light_rec.u,
+                                light_rec.v,
+                                &mut light_rec.p,
+                            );
+                            if le.max_component() > 0.0 {
+                                let tr = world.transmittance(to_light, 0.001, light_rec.t);
+                                let w = mis.weight(light_pdf, bsdf.pdf(wo, wi));
+                                color += throughput * reflectance(f) * illuminant(le) * tr * w
+                                    / light_pdf;
+                            }
+                        }
+                    }
+                }
+                Some((k, pick_pdf)) => {
+                    if let Some(sample) = lights.light(k).sample(rec.p, sampler::sample_2d()) {
+                        let wi = frame.to_local(sample.wi);
+                        let f = bsdf.eval(wo, wi);
+                        let shadow = Ray::new(rec.p, sample.wi, r.time);
+                        let shadowed =
+                            || world.hit(shadow, 0.001, sample.distance - 0.001).is_some();
+                        if f.max_component() > 0.0 && !medium::shadow_ray(shadowed) {
+                            let tr = world.transmittance(shadow, 0.001, sample.distance);
+                            let w = sample
+                                .pdf
+                                .map_or(1.0, |pdf| mis.weight(pick_pdf * pdf, bsdf.pdf(wo, wi)));
+                            color += throughput * reflectance(f) * illuminant(sample.li) * tr * w
+                                / pick_pdf;
+                        }
+                    }
+                }
+                None => {}
+            }
         }
+
+        let uc = sampler::sample_1d();
+        let sample = match bsdf.sample(wo, uc, sampler::sample_2d()) {
+            Some(sample) => sample,
+            None => return color,
+        };
+        throughput *= reflectance(sample.weight);
+        bsdf_pdf = if sample.lobe.contains(Lobe::SPECULAR) {
+            None
+        } else {
+            Some(sample.pdf)
+        };
+        let scattered = Ray::new(rec.p, frame.local0(sample.wi), r.time);
+
+        if bounce + 1 >= rr_depth {
+            // keep bright paths almost always, but never all of them
+            let survive = throughput.max_component().min(0.95);
+            if survive <= 0.0 || sampler::sample_1d() >= survive {
+                return color;
+            }
+            throughput /= survive;
+        }
+        r = scattered;
     }
-    let mut scattered: Ray = Ray {
-        orig: Vec3 {
-            x: 0.0,
-            y: 0.0,
-            z: 0.0,
-        },
-        dir: Vec3 {
-            x: 0.0,
-            y: 0.0,
-            z: 0.0,
-        },
-        time: 0.0,
-    };
-    // let mut albedo: Vec3 = Vec3 {
-    //     x: 0.0,
-    //     y: 0.0,
-    //     z: 0.0,
-    // };
-    let mut srec: ScatterRecord = ScatterRecord {
-        specular_ray: Ray {
-            orig: Vec3 {
-                x: 0.0,
-                y: 0.0,
-                z: 0.0,
-            },
-            dir: Vec3 {
-                x: 0.0,
-                y: 0.0,
-                z: 0.0,
-            },
-            time: 0.0,
-        },
-        is_specular: false,
-        attenuation: Vec3 {
-            x: 0.0,
-            y: 0.0,
-            z: 0.0,
-        },
-        pdf_ptr: CosinePdf::new(Vec3::new(0.0, 0.0, 0.0)),
-    };
-    let emitted: Vec3 =
-        rec.mat_ptr
-            .emitted(&mut r.clone(), &mut rec.clone(), rec.u, rec.v, &mut rec.p);
-    let pdf_val: f64;
+    color
+}
 
-    if !rec.mat_ptr.scatter(&mut r, &mut rec.clone(), &mut srec) {
-        return emitted;
-    };
-    if srec.is_specular {
-        return srec.attenuation
-            * ray_color(srec.specular_ray, background, world, lights, depth - 1);
+// Radiance along `r` in linear sRGB; `spectral` traces it at three sampled wavelengths.
+#[allow(clippy::too_many_arguments)]
+fn ray_color(
+    r: Ray,
+    background: Vec3,
+    world: &dyn Hittable,
+    lights: &Lights,
+    depth: u32,
+    rr_depth: u32,
+    mis: MisHeuristic,
+    spectral: bool,
+) -> Vec3 {
+    if !spectral {
+        return trace_path(r, background, world, lights, depth, rr_depth, mis, None);
     }
-    // let on_light = Vec3::new(random_double_lim(213.0 , 343.0) , 554.0 , random_double_lim(227.0,332.0));
-    // let mut to_light = on_light - rec.p;
-    // let distance_squared = to_light.length_squared();
-    // to_light = Vec3::unit_vector(to_light);
-    //
-    // if Vec3::dot(to_light , rec.normal) < 0.0 {
-    //     return emitted;
-    // }
-    //
-    // let light_area = (343.0 - 213.0) * (332.0 - 227.0);
-    // let light_cosine = to_light.y.abs();
-    // if light_cosine < 0.000001 {
-    //     return emitted;
-    // }
-    //
-    // pdf_val = distance_squared / (light_cosine * light_area);
+    let wl = Wavelengths::sample(sampler::sample_1d());
+    spectrum::set_wavelengths(Some(wl));
+    let radiance = trace_path(
+        r,
+        background,
+        world,
+        lights,
+        depth,
+        rr_depth,
+        mis,
+        Some(&wl),
+    );
+    spectrum::set_wavelengths(None);
+    spectrum::xyz_to_srgb(wl.to_xyz(radiance))
+}
 
-    // let p:CosinePdf = CosinePdf::new(rec.normal);
-    //
-    // scattered.orig = rec.p;
-    // scattered.dir = p.generate();
-    // scattered.time = r.time;
-    //let p0 = Arc::new(HittablePdf::new(lights.clone() , rec.p));
-    //let p1 = Arc::new(CosinePdf::new(rec.normal));
-    //let mut mixed_pdf = MixturePdf::new(p0, p1);
-    //let mut light_ptr = Arc::new(HittablePdf::new(lights.clone(), rec.p));
-    let light_ptr = HittablePdf::new(lights, rec.p);
-
-    //let mut light_pdf:HittablePdf = HittablePdf::new(lights.clone() , rec.p);
-
-    //let p: MixturePdf = MixturePdf::new(light_ptr, srec.pdf_ptr);
-    let p = MixturePdf::new(&light_ptr, &srec.pdf_ptr);
-    scattered.orig = rec.p;
-    scattered.dir = p.generate();
-    scattered.time = r.time;
-
-    //return emitted + albedo * ray_color(scattered , background , world , depth - 1);
-
-    // let emi:Vec3 = emitted;
-    //
-    // let alb:Vec3 = albedo;
-    //
-    // let res:f64 = rec.mat_ptr.scattering_pdf(&mut r , &mut rec.clone() , &mut scattered);
-    //
-    // let rays:Vec3 = ray_color(scattered, background, world, depth - 1);
-    //
-    // return emi + alb * res * rays / pdf_val;
-
-    //pdf_val = p.value(&mut scattered.dir);
-    //pdf_val = mixed_pdf.value(&mut scattered.dir);
-    pdf_val = p.value(&mut scattered.dir);
-    //return  emitted + albedo * rec.mat_ptr.scattering_pdf(&mut r , &mut rec.clone() , &mut scattered) * ray_color(scattered, background, world, lights.clone(), depth - 1) / pdf_val;
-    let recs = rec
-        .mat_ptr
-        .scattering_pdf(&mut r, &mut rec.clone(), &mut scattered);
-
-    let ans = emitted
-        + ray_color(scattered, background, world, &lights, depth - 1) * srec.attenuation * recs
-            / pdf_val;
-    ans
-    //return Vec3::new(0.0, 0.0, 0.0);
-    // let unit_direction: Vec3 = Vec3::unit_vector(r.dir);
-    // let t: f64 = 0.5 * (unit_direction.y + 1.0);
-    //线性插值
-    //return Vec3::new(1.0, 1.0, 1.0) * (1.0 - t) + Vec3::new(0.5, 0.7, 1.0) * t;
+// An afternoon sky, the sun behind and to the left of the camera of the sphere scenes.
+fn daylight() -> Lights {
+    let sky = Sky::new(35.0, 60.0, 3.0, Vec3::ones() * 0.3, 1.0);
+    let mut lights = Lights::default();
+    lights.distant.push(Arc::new(sky.sun()));
+    lights.distant.push(Arc::new(sky));
+    lights
+}
+
+fn static_spheres() -> HittableList {
+    let mut world = HittableList::new();
+    world.add(Arc::new(static_scene!("../data/random_spheres.json")));
+    world
 }
 
 fn random_scene() -> HittableList {
@@ -791,7 +1201,7 @@ pub fn simple_light() -> HittableList {
     objects
 }
 
-pub fn cornell_box() -> HittableList {
+pub fn cornell_box(ids: &Ids) -> HittableList {
     let mut objects: HittableList = HittableList { objects: vec![] };
 
     let red = Lambertian::new(Vec3::new(0.65, 0.05, 0.05));
@@ -799,7 +1209,7 @@ pub fn cornell_box() -> HittableList {
     let green = Lambertian::new(Vec3::new(0.12, 0.45, 0.15));
     let light = DiffuseLight::new0(Vec3::new(15.0, 15.0, 15.0));
     let allin = Translate::new(
-        get_obj("input/bunny.fine.obj", 1000.0),
+        get_obj("input/bunny.fine.obj", 1000.0, ids),
         Vec3::new(260.0, 50.0, 290.0),
     );
     // let allin = RotateY::new(allin , 90.0);
@@ -917,7 +1327,7 @@ pub fn cornell_smoke() -> HittableList {
     objects
 }
 
-pub fn final_scene() -> HittableList {
+pub fn final_scene(ids: &Ids) -> HittableList {
     let mut boxes1: HittableList = HittableList { objects: vec![] };
 
     let boxes_per_side: i32 = 20;
@@ -940,7 +1350,7 @@ pub fn final_scene() -> HittableList {
     }
 
     let mut objects: HittableList = HittableList { objects: vec![] };
-    objects.add(Arc::new(BvhNode::new(boxes1, 0.0, 1.0)));
+    objects.add(Arc::new(BvhNode::new(boxes1.tagged(ids), 0.0, 1.0)));
 
     let lighting = DiffuseLight::new0(Vec3::new(17.0, 17.0, 17.0));
     // objects.add(Arc::new(XzRect::new(
@@ -1030,53 +1440,129 @@ pub fn final_scene() -> HittableList {
     }
 
     objects.add(Arc::new(Translate::new(
-        RotateY::new(BvhNode::new(boxes2, 0.0, 1.0), 15.0),
+        RotateY::new(BvhNode::new(boxes2.tagged(ids), 0.0, 1.0), 15.0),
         Vec3::new(-100.0, 270.0, 395.0),
     )));
 
     objects
 }
-pub fn get_obj(filename: &str, rate: f64) -> HittableList {
-    let mut objects = HittableList { objects: vec![] };
-    let cornell_box = tobj::load_obj(
-        filename,
-        &tobj::LoadOptions {
-            single_index: true,
-            triangulate: true,
-            ..Default::default()
+// Principled spheres with a white base color and different lobes under a uniform white sky.
+// A BSDF that neither loses nor gains energy makes each sphere invisible; lossy ones show up
+// darker, mostly toward the rim, and a bright one would be a bug.
+pub fn furnace() -> HittableList {
+    let mut objects = HittableList::new();
+    let white: Arc<dyn Texture> = Arc::new(SolidColor::new(Vec3::ones()));
+    let value = |x: f64| -> Arc<dyn Texture> { Arc::new(SolidColor::new(Vec3::ones() * x)) };
+    let plain = Principled::new(white);
+    let rough = Principled {
+        roughness: value(1.0),
+        ..plain.clone()
+    };
+    let spheres = [
+        Principled {
+            roughness: value(0.0),
+            ..plain.clone()
         },
-    );
-    assert!(cornell_box.is_ok());
-    let (models, _materials) = cornell_box.expect("Failed to load OBJ file");
-    for (_i, m) in models.iter().enumerate() {
-        let mut boxes2 = HittableList { objects: vec![] };
-        let mesh = &m.mesh;
-        for v in 0..mesh.indices.len() / 3 {
-            let x1 = mesh.indices[3 * v];
-            let x2 = mesh.indices[3 * v + 1];
-            let x3 = mesh.indices[3 * v + 2];
-            let triange = Triangle::new(
-                Vec3 {
-                    x: rate * mesh.positions[(3 * x1) as usize] as f64,
-                    y: rate * mesh.positions[(3 * x1 + 1) as usize] as f64,
-                    z: rate * mesh.positions[(3 * x1 + 2) as usize] as f64,
-                },
-                Vec3 {
-                    x: rate * mesh.positions[(3 * x2) as usize] as f64,
-                    y: rate * mesh.positions[(3 * x2 + 1) as usize] as f64,
-                    z: rate * mesh.positions[(3 * x2 + 2) as usize] as f64,
-                },
-                Vec3 {
-                    x: rate * mesh.positions[(3 * x3) as usize] as f64,
-                    y: rate * mesh.positions[(3 * x3 + 1) as usize] as f64,
-                    z: rate * mesh.positions[(3 * x3 + 2) as usize] as f64,
-                },
-                Metal::news(Vec3::new(0.99, 0.78, 0.0), 0.1),
-            );
-            boxes2.add(Arc::new(triange));
-        }
-
-        objects.add(Arc::new(BvhNode::new(boxes2, 0.0, 1.0)));
+        rough.clone(),
+        Principled {
+            metallic: value(1.0),
+            roughness: value(0.2),
+            ..plain.clone()
+        },
+        Principled {
+            metallic: value(1.0),
+            roughness: value(0.7),
+            anisotropy: value(0.8),
+            ..plain.clone()
+        },
+        Principled {
+            clearcoat: value(1.0),
+            clearcoat_gloss: value(0.5),
+            ..plain.clone()
+        },
+        Principled {
+            sheen: value(1.0),
+            ..rough
+        },
+        Principled {
+            transmission: value(1.0),
+            roughness: value(0.1),
+            ..plain.clone()
+        },
+        Principled {
+            transmission: value(1.0),
+            roughness: value(0.6),
+            ..plain
+        },
+    ];
+    for (i, material) in spheres.iter().enumerate() {
+        let center = Vec3::new(
+            -3.0 + 2.0 * (i % 4) as f64,
+            if i < 4 { 1.0 } else { -1.0 },
+            0.0,
+        );
+        objects.add(Arc::new(Sphere::new(center, 0.9, material.clone())));
     }
     objects
 }
+
+// A cornell box with a cloud that scatters blue a bit more than red, so it goes yellowish where
+// it is thick, and a ball of fire that absorbs and glows.
+pub fn volumes() -> HittableList {
+    let mut objects = HittableList::new();
+    let red = Lambertian::new(Vec3::new(0.65, 0.05, 0.05));
+    let white = Lambertian::new(Vec3::new(0.73, 0.73, 0.73));
+    let green = Lambertian::new(Vec3::new(0.12, 0.45, 0.15));
+    let light = DiffuseLight::new0(Vec3::new(15.0, 15.0, 15.0));
+    objects.add(Arc::new(YzRect::new(0.0, 555.0, 0.0, 555.0, 555.0, green)));
+    objects.add(Arc::new(YzRect::new(0.0, 555.0, 0.0, 555.0, 0.0, red)));
+    objects.add(Arc::new(FlipFace::new(XzRect::new(
+        213.0, 343.0, 227.0, 332.0, 554.0, light,
+    ))));
+    objects.add(Arc::new(XzRect::new(
+        0.0,
+        555.0,
+        0.0,
+        555.0,
+        0.0,
+        white.clone(),
+    )));
+    objects.add(Arc::new(XzRect::new(
+        0.0,
+        555.0,
+        0.0,
+        555.0,
+        555.0,
+        white.clone(),
+    )));
+    objects.add(Arc::new(XyRect::new(0.0, 555.0, 0.0, 555.0, 555.0, white)));
+
+    let cloud = Sphere::new(Vec3::new(370.0, 240.0, 330.0), 150.0, NoMaterial {});
+    objects.add(Arc::new(HeterogeneousMedium::new(
+        cloud,
+        Arc::new(NoiseDensity::new(0.02, 7, 4.0)),
+        Vec3::new(0.001, 0.001, 0.001),
+        Vec3::new(0.03, 0.04, 0.06),
+        Vec3::zero(),
+    )));
+    let fire = Sphere::new(Vec3::new(150.0, 100.0, 180.0), 90.0, NoMaterial {});
+    objects.add(Arc::new(HeterogeneousMedium::new(
+        fire,
+        Arc::new(NoiseDensity::new(0.04, 7, 2.0)),
+        Vec3::new(0.01, 0.01, 0.01),
+        Vec3::new(0.002, 0.002, 0.002),
+        Vec3::new(6.0, 2.0, 0.4),
+    )));
+    objects
+}
+
+// Loads an OBJ file scaled by `rate`, one BVH per model. Models with an MTL material get a
+// principled material made from it, or a diffuse light for an emissive (`Ke`) one, the others
+// stay gold. Each model takes a number from `ids`. The meshes can be sampled by area, so the
+// list (or a model of it) can go among the area lights too.
+pub fn get_obj(filename: &str, rate: f64, ids: &Ids) -> HittableList {
+    let gold: Arc<dyn Material> = Arc::new(Metal::news(Vec3::new(0.99, 0.78, 0.0), 0.1));
+    ObjModel::load(filename, rate, Some(&gold), ids)
+        .expect("Failed to load OBJ file")
+        .meshes
+}
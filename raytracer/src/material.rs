@@ -1,33 +1,22 @@
+use crate::bsdf::{
+    Bsdf, Dispersive, FuzzyReflection, IsotropicPhase, LambertianBsdf, MicrofacetConductor,
+    MicrofacetDielectric, PrincipledBsdf, PrincipledParams, SpecularDielectric, SpecularReflection,
+    TrowbridgeReitz,
+};
 use crate::hit;
 use crate::hit::HitRecord;
-use crate::pdf::CosinePdf;
-use crate::random_double;
+use crate::spectrum::{self, Dispersion, D_LINE};
 use crate::texture::SolidColor;
-use crate::texture::Texture;
+use crate::texture::{ImageTexture, Texture};
 use crate::Ray;
 use crate::Vec3;
-use std::f64::consts::PI;
+use std::path::Path;
 use std::sync::Arc;
 
 pub trait Material: Send + Sync {
-    fn scatter(
-        &self,
-        _r_in: &mut Ray,
-        _rec: &mut hit::HitRecord,
-        _srec: &mut ScatterRecord,
-        // albedo: &mut Vec3,
-        // scattered: &mut Ray,
-        // pdf:&mut f64
-    ) -> bool {
-        false
-    }
-    fn scattering_pdf(
-        &self,
-        _r_in: &mut Ray,
-        _rec: &mut hit::HitRecord,
-        _scattered: &mut Ray,
-    ) -> f64 {
-        0.0
+    // How the surface scatters light at `rec`, None if it only emits.
+    fn bsdf(&self, _rec: &HitRecord) -> Option<Box<dyn Bsdf>> {
+        None
     }
     fn emitted(
         &self,
@@ -40,6 +29,14 @@ pub trait Material: Send + Sync {
         Vec3::new(0.0, 0.0, 0.0)
     }
 }
+impl<M: Material + ?Sized> Material for Arc<M> {
+    fn bsdf(&self, rec: &HitRecord) -> Option<Box<dyn Bsdf>> {
+        (**self).bsdf(rec)
+    }
+    fn emitted(&self, r_in: &mut Ray, rec: &mut HitRecord, u: f64, v: f64, p: &mut Vec3) -> Vec3 {
+        (**self).emitted(r_in, rec, u, v, p)
+    }
+}
 #[derive(Clone)]
 pub struct Lambertian {
     pub albedo: Arc<dyn Texture>,
@@ -57,95 +54,11 @@ impl Lambertian {
 }
 
 impl Material for Lambertian {
-    fn scatter(
-        &self,
-        _r_in: &mut Ray,
-        rec: &mut HitRecord,
-        srec: &mut ScatterRecord,
-        // mut albedo: &mut Vec3,
-        // scattered: &mut Ray,
-        // pdf:&mut f64,
-    ) -> bool {
-        // let mut scatter_direction: Vec3 = rec.normal + Vec3::random_unit_vector();
-        // if scatter_direction.near_zero() {
-        //     scatter_direction = rec.normal;
-        // }
-        // let ra = Ray {
-        //     orig: rec.p,
-        //     dir: Vec3::unit_vector(scatter_direction),
-        //     time: r_in.time,
-        // };
-        // scattered.dir = ra.dir;
-        // scattered.orig = ra.orig;
-        // scattered.time = ra.time;
-        // albedo.x = self.albedo.value(rec.u , rec.v , &mut rec.p).x;
-        // albedo.y = self.albedo.value(rec.u , rec.v , &mut rec.p).y;
-        // albedo.z = self.albedo.value(rec.u , rec.v , &mut rec.p).z;
-        // *pdf = (Vec3::dot(rec.normal, scattered.dir) / PI);
-        //
-        // return true;
-
-        //another activision
-
-        // let direction = Vec3::random_in_hemisphere(rec.normal);
-        // let ra = Ray{
-        //     orig: rec.p,
-        //     dir: Vec3::unit_vector(direction),
-        //     time: r_in.time,
-        // };
-        //
-        // scattered.orig = ra.orig;
-        // scattered.dir = ra.dir;
-        // scattered.time = ra.time;
-        //
-        // albedo.x = self.albedo.value(rec.u , rec.v , &mut rec.p).x;
-        // albedo.y = self.albedo.value(rec.u , rec.v , &mut rec.p).y;
-        // albedo.z = self.albedo.value(rec.u , rec.v , &mut rec.p).z;
-        //
-        // *pdf = 0.5 / PI;
-        // return true;
-
-        //
-
-        // let mut uvw:Onb = Onb { axis: [Vec3::new(0.0,0.0,0.0) , Vec3::new(0.0,0.0,0.0) , Vec3::new(0.0,0.0,0.0)] };
-        // uvw.build_from_w(&mut rec.normal);
-        // let direction = uvw.local0(random_cosine_direction());
-        // let ra = Ray{
-        //     orig: rec.p,
-        //     dir: Vec3::unit_vector(direction),
-        //     time: r_in.time,
-        // };
-        // scattered.orig = ra.orig;
-        // scattered.dir = ra.dir;
-        // scattered.time = ra.time;
-        //
-        // albedo.x = self.albedo.value(rec.u , rec.v , &mut rec.p).x;
-        // albedo.y = self.albedo.value(rec.u , rec.v , &mut rec.p).y;
-        // albedo.z = self.albedo.value(rec.u , rec.v , &mut rec.p).z;
-        //
-        // *pdf = Vec3::dot(uvw.axis[2] , scattered.dir) / PI;
-        // return true;
-
-        srec.is_specular = false;
-        // srec.attenuation.x = self.albedo.value(rec.u , rec.v , &mut rec.p).x;
-        // srec.attenuation.y = self.albedo.value(rec.u , rec.v , &mut rec.p).y;
-        // srec.attenuation.z = self.albedo.value(rec.u , rec.v , &mut rec.p).z;
-        srec.attenuation = self.albedo.value(rec.u, rec.v, &mut rec.p);
-        srec.pdf_ptr = CosinePdf::new(rec.normal);
-        true
-    }
-    fn scattering_pdf(
-        &self,
-        _r_in: &mut Ray,
-        rec: &mut hit::HitRecord,
-        scattered: &mut Ray,
-    ) -> f64 {
-        let cosine = Vec3::dot(rec.normal, Vec3::unit_vector(scattered.dir));
-        if cosine < 0.0 {
-            0.0
-        } else {
-            cosine / PI
-        }
+    fn bsdf(&self, rec: &HitRecord) -> Option<Box<dyn Bsdf>> {
+        let mut p = rec.p;
+        Some(Box::new(LambertianBsdf {
+            albedo: self.albedo.value(rec.u, rec.v, &mut p),
+        }))
     }
 }
 #[derive(Copy, Clone)]
@@ -164,24 +77,11 @@ impl<T: Texture> LambertianStatic<T> {
     }
 }
 impl<T: Texture> Material for LambertianStatic<T> {
-    fn scatter(&self, _r_in: &mut Ray, rec: &mut HitRecord, mut srec: &mut ScatterRecord) -> bool {
-        srec.is_specular = false;
-        srec.attenuation = self.albedo.value(rec.u, rec.v, &mut rec.p);
-        srec.pdf_ptr = CosinePdf::new(rec.normal);
-        true
-    }
-    fn scattering_pdf(
-        &self,
-        _r_in: &mut Ray,
-        rec: &mut hit::HitRecord,
-        scattered: &mut Ray,
-    ) -> f64 {
-        let cosine = Vec3::dot(rec.normal, Vec3::unit_vector(scattered.dir));
-        if cosine < 0.0 {
-            0.0
-        } else {
-            cosine / PI
-        }
+    fn bsdf(&self, rec: &HitRecord) -> Option<Box<dyn Bsdf>> {
+        let mut p = rec.p;
+        Some(Box::new(LambertianBsdf {
+            albedo: self.albedo.value(rec.u, rec.v, &mut p),
+        }))
     }
 }
 #[derive(Copy, Clone)]
@@ -191,35 +91,17 @@ pub struct Metal {
 }
 
 impl Material for Metal {
-    fn scatter(
-        &self,
-        r_in: &mut Ray,
-        rec: &mut HitRecord,
-        // attenuation: &mut Vec3,
-        // scattered: &mut Ray,
-        // pdf:&mut f64
-        srec: &mut ScatterRecord,
-    ) -> bool {
-        let reflected: Vec3 = Vec3::reflect(Vec3::unit_vector(r_in.dir), rec.normal);
-        // let ra = Ray {
-        //     orig: rec.p,
-        //     dir: reflected + Vec3::random_in_unit_sphere() * self.fuzz,
-        //     time: r_in.time,
-        // };
-        // scattered.dir = ra.dir;
-        // scattered.orig = ra.orig;
-        // scattered.time = ra.time;
-        // attenuation.x = self.albedo.x;
-        // attenuation.y = self.albedo.y;
-        // attenuation.z = self.albedo.z;
-        srec.specular_ray.orig = rec.p;
-        srec.specular_ray.dir = reflected + Vec3::random_in_unit_sphere() * self.fuzz;
-        srec.specular_ray.time = r_in.time;
-        srec.attenuation = self.albedo;
-        srec.is_specular = true;
-        srec.pdf_ptr = CosinePdf::new(Vec3::new(0.0, 0.0, 0.0));
-        //return Vec3::dot(scattered.dir, rec.normal) > 0.0;
-        true
+    fn bsdf(&self, _rec: &HitRecord) -> Option<Box<dyn Bsdf>> {
+        if self.fuzz > 0.0 {
+            Some(Box::new(FuzzyReflection {
+                albedo: self.albedo,
+                fuzz: self.fuzz,
+            }))
+        } else {
+            Some(Box::new(SpecularReflection {
+                albedo: self.albedo,
+            }))
+        }
     }
 }
 #[warn(dead_code)]
@@ -239,87 +121,245 @@ impl Metal {
     }
 }
 
+// A metal with GGX roughness, described by its complex index of refraction per channel:
+// gold is about eta (0.14, 0.42, 1.37), k (3.98, 2.39, 1.60).
 #[derive(Clone, Copy)]
-pub struct Dielectric {
+pub struct Conductor {
+    pub eta: Vec3,
+    pub k: Vec3,
+    pub roughness: f64,
+    pub anisotropy: f64,
+}
+
+impl Conductor {
+    pub fn new(eta: Vec3, k: Vec3, roughness: f64, anisotropy: f64) -> Self {
+        Self {
+            eta,
+            k,
+            roughness,
+            anisotropy,
+        }
+    }
+}
+
+impl Material for Conductor {
+    fn bsdf(&self, _rec: &HitRecord) -> Option<Box<dyn Bsdf>> {
+        Some(Box::new(MicrofacetConductor {
+            distribution: TrowbridgeReitz::from_roughness(self.roughness, self.anisotropy),
+            eta: self.eta,
+            k: self.k,
+        }))
+    }
+}
+
+// Frosted glass; with zero roughness the same as `Dielectric`.
+#[derive(Clone, Copy)]
+pub struct RoughDielectric {
     pub ref_idx: f64,
+    pub roughness: f64,
+    pub anisotropy: f64,
+    pub dispersion: Option<Dispersion>,
 }
 
-impl Material for Dielectric {
-    fn scatter(
-        //我好像把几种方式重在一起了，之后可能会出问题
-        &self,
-        r_in: &mut Ray,
-        rec: &mut HitRecord,
-        // mut attenuation: &mut Vec3,
-        // scattered: &mut Ray,
-        // pdf:&mut f64
-        srec: &mut ScatterRecord,
-    ) -> bool {
-        // attenuation.x = 1.0;
-        // attenuation.y = 1.0;
-        // attenuation.z = 1.0;
-        // scattered.time = r_in.time;
-        // let etai_over_etat: f64;
-        // if rec.front_face {
-        //     etai_over_etat = 1.0 / self.ref_idx;
-        // } else {
-        //     etai_over_etat = self.ref_idx;
-        // }
-        // let unit_direction: Vec3 = Vec3::unit_vector(r_in.dir);
-        // let cos_theta: f64;
-        // if Vec3::dot(unit_direction * -1.0, rec.normal) < 1.0 {
-        //     cos_theta = Vec3::dot(unit_direction * -1.0, rec.normal);
-        // } else {
-        //     cos_theta = 1.0;
-        // }
-        // let sin_theta: f64 = (1.0 - cos_theta * cos_theta).sqrt();
-        // if etai_over_etat * sin_theta > 1.0 {
-        //     let reflected: Vec3 = Vec3::reflect(unit_direction, rec.normal);
-        //     scattered.orig = rec.p;
-        //     scattered.dir = reflected;
-        //     return true;
-        // }
-        // let reflect_prob = Dielectric::schlick(cos_theta, etai_over_etat);
-        // if random_double() < reflect_prob {
-        //     let reflected: Vec3 = Vec3::reflect(unit_direction, rec.normal);
-        //     scattered.orig = rec.p;
-        //     scattered.dir = reflected;
-        //     return true;
-        // }
-        // let refracted: Vec3 = Vec3::refract(unit_direction, rec.normal, etai_over_etat);
-        // scattered.orig = rec.p;
-        // scattered.dir = refracted;
-        // return true;
-        srec.is_specular = true;
-        srec.pdf_ptr = CosinePdf::new(Vec3::new(0.0, 0.0, 0.0));
-        srec.attenuation = Vec3::new(1.0, 1.0, 1.0);
-        //attenuation = &mut Vec3::new(1.0, 1.0, 1.0);
-        let refraction_ratio: f64;
-        if rec.front_face {
-            refraction_ratio = 1.0 / self.ref_idx;
+impl RoughDielectric {
+    pub fn new(ref_idx: f64, roughness: f64, anisotropy: f64) -> Self {
+        Self {
+            ref_idx,
+            roughness,
+            anisotropy,
+            dispersion: None,
+        }
+    }
+
+    pub fn dispersive(dispersion: Dispersion, roughness: f64, anisotropy: f64) -> Self {
+        Self {
+            ref_idx: dispersion.ior(D_LINE),
+            roughness,
+            anisotropy,
+            dispersion: Some(dispersion),
+        }
+    }
+}
+
+impl Material for RoughDielectric {
+    fn bsdf(&self, rec: &HitRecord) -> Option<Box<dyn Bsdf>> {
+        let ref_idx = ior_at_hero(self.ref_idx, &self.dispersion);
+        let eta = if rec.front_face {
+            1.0 / ref_idx
         } else {
-            refraction_ratio = self.ref_idx;
+            ref_idx
         };
+        Some(dispersive(
+            Box::new(MicrofacetDielectric {
+                distribution: TrowbridgeReitz::from_roughness(self.roughness, self.anisotropy),
+                eta,
+            }),
+            &self.dispersion,
+        ))
+    }
+}
 
-        let unit_direction: Vec3 = Vec3::unit_vector(r_in.dir);
-        let cos_theta = Vec3::dot(unit_direction * (-1.0), rec.normal).min(1.0);
-        let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+// The index of refraction at the wavelength the path is traced at, the plain one in RGB.
+fn ior_at_hero(ref_idx: f64, dispersion: &Option<Dispersion>) -> f64 {
+    match (dispersion, spectrum::hero_wavelength()) {
+        (Some(d), Some(lambda)) => d.ior(lambda),
+        _ => ref_idx,
+    }
+}
 
-        let cannot_refract: bool = refraction_ratio * sin_theta > 1.0;
-        let direction: Vec3;
-        if cannot_refract || Dielectric::schlick(cos_theta, refraction_ratio) > random_double() {
-            direction = Vec3::reflect(unit_direction, rec.normal);
-        } else {
-            direction = Vec3::refract(unit_direction, rec.normal, refraction_ratio);
+fn dispersive(bsdf: Box<dyn Bsdf>, dispersion: &Option<Dispersion>) -> Box<dyn Bsdf> {
+    match (dispersion, spectrum::hero_wavelength()) {
+        (Some(_), Some(_)) => Box::new(Dispersive(bsdf)),
+        _ => bsdf,
+    }
+}
+
+// The principled ("Disney") uber-material. Every parameter is a texture; the scalar ones
+// read its first channel. See `PrincipledParams` for what they do.
+#[derive(Clone)]
+pub struct Principled {
+    pub base_color: Arc<dyn Texture>,
+    pub metallic: Arc<dyn Texture>,
+    pub roughness: Arc<dyn Texture>,
+    pub specular: Arc<dyn Texture>,
+    pub specular_tint: Arc<dyn Texture>,
+    pub sheen: Arc<dyn Texture>,
+    pub sheen_tint: Arc<dyn Texture>,
+    pub clearcoat: Arc<dyn Texture>,
+    pub clearcoat_gloss: Arc<dyn Texture>,
+    pub transmission: Arc<dyn Texture>,
+    pub anisotropy: Arc<dyn Texture>,
+}
+
+fn constant(x: f64) -> Arc<dyn Texture> {
+    Arc::new(SolidColor::new(Vec3::ones() * x))
+}
+
+impl Principled {
+    // The default parameters over the given base color: a slightly rough plastic.
+    pub fn new(base_color: Arc<dyn Texture>) -> Self {
+        let d = PrincipledParams::default();
+        Self {
+            base_color,
+            metallic: constant(d.metallic),
+            roughness: constant(d.roughness),
+            specular: constant(d.specular),
+            specular_tint: constant(d.specular_tint),
+            sheen: constant(d.sheen),
+            sheen_tint: constant(d.sheen_tint),
+            clearcoat: constant(d.clearcoat),
+            clearcoat_gloss: constant(d.clearcoat_gloss),
+            transmission: constant(d.transmission),
+            anisotropy: constant(d.anisotropy),
+        }
+    }
+
+    // From a material of an MTL file, with the PBR extension (Pr, Pm, Ps, Pc, Pcr, aniso and
+    // their maps) where present. Without Pr the roughness comes from the Phong exponent Ns,
+    // the ior Ni sets the specular level and the glass illumination models turn on
+    // transmission. Texture files are looked up in `dir`.
+    pub fn from_mtl(mtl: &tobj::Material, dir: &Path) -> Self {
+        let value = |key: &str| {
+            mtl.unknown_param
+                .get(key)
+                .and_then(|v| v.trim().parse::<f64>().ok())
+        };
+        let map = |file: &str| -> Option<Arc<dyn Texture>> {
+            // options such as `-bm 1` come before the file name
+            let file = file.split_whitespace().last()?;
+            let full = dir.join(file);
+            if full.is_file() {
+                Some(Arc::new(ImageTexture::new(&full.to_string_lossy())))
+            } else {
+                eprintln!(
+                    "warning: material `{}`: texture `{}` not found",
+                    mtl.name,
+                    full.display()
+                );
+                None
+            }
         };
+        let param = |key: &str, default: f64| {
+            mtl.unknown_param
+                .get(&format!("map_{}", key))
+                .and_then(|file| map(file))
+                .unwrap_or_else(|| constant(value(key).unwrap_or(default)))
+        };
+        let kd = mtl.diffuse;
+        let mut m = Principled::new(map(&mtl.diffuse_texture).unwrap_or_else(|| {
+            Arc::new(SolidColor::new(Vec3::new(
+                kd[0] as f64,
+                kd[1] as f64,
+                kd[2] as f64,
+            )))
+        }));
+        // the roughness whose GGX highlight is about as wide as a Phong lobe of exponent Ns
+        let ns = (mtl.shininess as f64).max(0.0);
+        m.roughness = param("Pr", (2.0 / (ns + 2.0)).powf(0.25));
+        m.metallic = param("Pm", 0.0);
+        m.sheen = param("Ps", 0.0);
+        m.clearcoat = param("Pc", 0.0);
+        m.clearcoat_gloss = constant(1.0 - value("Pcr").unwrap_or(0.0));
+        m.anisotropy = param("aniso", 0.0);
+        let ior = mtl.optical_density as f64;
+        if ior > 1.0 {
+            m.specular = constant(((ior - 1.0) / (ior + 1.0)).powi(2) / 0.08);
+        }
+        if let Some(4) | Some(6) | Some(7) | Some(9) = mtl.illumination_model {
+            m.transmission = constant(1.0);
+        }
+        m
+    }
+}
+
+impl Default for Principled {
+    fn default() -> Self {
+        Principled::new(Arc::new(SolidColor::new(
+            PrincipledParams::default().base_color,
+        )))
+    }
+}
 
-        // scattered.orig = rec.p;
-        // scattered.dir = direction;
-        // scattered.time = 0.0;
-        srec.specular_ray.orig = rec.p;
-        srec.specular_ray.dir = direction;
-        srec.specular_ray.time = r_in.time;
-        true
+impl Material for Principled {
+    fn bsdf(&self, rec: &HitRecord) -> Option<Box<dyn Bsdf>> {
+        let mut p = rec.p;
+        let mut value = |t: &Arc<dyn Texture>| t.value(rec.u, rec.v, &mut p);
+        let params = PrincipledParams {
+            base_color: value(&self.base_color),
+            metallic: value(&self.metallic).x,
+            roughness: value(&self.roughness).x,
+            specular: value(&self.specular).x,
+            specular_tint: value(&self.specular_tint).x,
+            sheen: value(&self.sheen).x,
+            sheen_tint: value(&self.sheen_tint).x,
+            clearcoat: value(&self.clearcoat).x,
+            clearcoat_gloss: value(&self.clearcoat_gloss).x,
+            transmission: value(&self.transmission).x,
+            anisotropy: value(&self.anisotropy).x,
+        };
+        Some(Box::new(PrincipledBsdf::new(&params, rec.front_face)))
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct Dielectric {
+    pub ref_idx: f64,
+    // a wavelength-dependent index for spectral renders, where it splits light into colors
+    pub dispersion: Option<Dispersion>,
+}
+
+impl Material for Dielectric {
+    fn bsdf(&self, rec: &HitRecord) -> Option<Box<dyn Bsdf>> {
+        let ref_idx = ior_at_hero(self.ref_idx, &self.dispersion);
+        let eta = if rec.front_face {
+            1.0 / ref_idx
+        } else {
+            ref_idx
+        };
+        Some(dispersive(
+            Box::new(SpecularDielectric { eta }),
+            &self.dispersion,
+        ))
     }
 }
 impl Dielectric {
@@ -330,7 +370,17 @@ impl Dielectric {
     }
 
     pub fn new(ref_idx: f64) -> Self {
-        Self { ref_idx }
+        Self {
+            ref_idx,
+            dispersion: None,
+        }
+    }
+
+    pub fn dispersive(dispersion: Dispersion) -> Self {
+        Self {
+            ref_idx: dispersion.ior(D_LINE),
+            dispersion: Some(dispersion),
+        }
     }
 }
 #[derive(Clone)]
@@ -352,18 +402,6 @@ impl DiffuseLight {
 }
 
 impl Material for DiffuseLight {
-    fn scatter(
-        &self,
-        _r_in: &mut Ray,
-        _rec: &mut HitRecord,
-        // attenuation: &mut Vec3,
-        // scattered: &mut Ray,
-        // pdf:&mut f64
-        _srec: &mut ScatterRecord,
-    ) -> bool {
-        false
-    }
-
     fn emitted(
         &self,
         _r_in: &mut Ray,
@@ -391,18 +429,6 @@ impl<T: Texture> DiffuseLightStatic<T> {
 }
 
 impl<T: Texture> Material for DiffuseLightStatic<T> {
-    fn scatter(
-        &self,
-        _r_in: &mut Ray,
-        _rec: &mut HitRecord,
-        // attenuation: &mut Vec3,
-        // scattered: &mut Ray,
-        // pdf:&mut f64
-        _srec: &mut ScatterRecord,
-    ) -> bool {
-        false
-    }
-
     fn emitted(
         &self,
         _r_in: &mut Ray,
@@ -435,42 +461,11 @@ impl<T: Texture> Isotropiuc<T> {
     }
 }
 impl<T: Texture> Material for Isotropiuc<T> {
-    fn scatter(&self, r_in: &mut Ray, rec: &mut HitRecord, srec: &mut ScatterRecord) -> bool {
-        srec.specular_ray.orig = rec.p;
-        srec.specular_ray.dir = Vec3::random_in_unit_sphere();
-        srec.specular_ray.time = r_in.time;
-
-        // scattered.orig = rec.p;
-        // scattered.dir = Vec3::random_in_unit_sphere();
-        // scattered.time = r_in.time;
-        //
-        // attenuation.x = self.albedo.value(rec.u, rec.v, &mut rec.p).x;
-        // attenuation.y = self.albedo.value(rec.u, rec.v, &mut rec.p).y;
-        // attenuation.z = self.albedo.value(rec.u, rec.v, &mut rec.p).z;
-
-        // srec.attenuation.x = self.albedo.value(rec.u, rec.v, &mut rec.p).x;
-        // srec.attenuation.y = self.albedo.value(rec.u, rec.v, &mut rec.p).y;
-        // srec.attenuation.z = self.albedo.value(rec.u, rec.v, &mut rec.p).z;
-        srec.attenuation = self.albedo.value(rec.u, rec.v, &mut rec.p);
-        srec.is_specular = false;
-        srec.pdf_ptr = CosinePdf::new(rec.normal);
-        true
-    }
-    fn scattering_pdf(
-        &self,
-        _r_in: &mut Ray,
-        rec: &mut hit::HitRecord,
-        scattered: &mut Ray,
-    ) -> f64 {
-        let cosine = Vec3::dot(rec.normal, Vec3::unit_vector(scattered.dir));
-        if cosine < 0.0 {
-            0.0
-        } else {
-            cosine / PI
-        }
-        //return random_double();
-        //return random_double_lim(0.0 , 0.5);
-        //return 0.0;
+    fn bsdf(&self, rec: &HitRecord) -> Option<Box<dyn Bsdf>> {
+        let mut p = rec.p;
+        Some(Box::new(IsotropicPhase {
+            albedo: self.albedo.value(rec.u, rec.v, &mut p),
+        }))
     }
     fn emitted(
         &self,
@@ -494,28 +489,11 @@ impl<T: Texture> IsotropiucStatic<T> {
 }
 
 impl<T: Texture> Material for IsotropiucStatic<T> {
-    fn scatter(&self, r_in: &mut Ray, rec: &mut HitRecord, srec: &mut ScatterRecord) -> bool {
-        srec.specular_ray.orig = rec.p;
-        srec.specular_ray.dir = Vec3::random_in_unit_sphere();
-        srec.specular_ray.time = r_in.time;
-
-        srec.attenuation = self.albedo.value(rec.u, rec.v, &mut rec.p);
-        srec.is_specular = false;
-        srec.pdf_ptr = CosinePdf::new(rec.normal);
-        true
-    }
-    fn scattering_pdf(
-        &self,
-        _r_in: &mut Ray,
-        rec: &mut hit::HitRecord,
-        scattered: &mut Ray,
-    ) -> f64 {
-        let cosine = Vec3::dot(rec.normal, Vec3::unit_vector(scattered.dir));
-        if cosine < 0.0 {
-            0.0
-        } else {
-            cosine / PI
-        }
+    fn bsdf(&self, rec: &HitRecord) -> Option<Box<dyn Bsdf>> {
+        let mut p = rec.p;
+        Some(Box::new(IsotropicPhase {
+            albedo: self.albedo.value(rec.u, rec.v, &mut p),
+        }))
     }
     fn emitted(
         &self,
@@ -529,32 +507,10 @@ impl<T: Texture> Material for IsotropiucStatic<T> {
     }
 }
 
-pub struct ScatterRecord {
-    pub specular_ray: Ray,
-    pub is_specular: bool,
-    pub attenuation: Vec3,
-    pub pdf_ptr: CosinePdf,
-}
 #[derive(Clone)]
 pub struct NoMaterial {}
 impl Material for NoMaterial {
-    fn scatter(
-        &self,
-        _r_in: &mut Ray,
-        _rec: &mut hit::HitRecord,
-        _srec: &mut ScatterRecord,
-        // albedo: &mut Vec3,
-        // scattered: &mut Ray,
-        // pdf:&mut f64
-    ) -> bool {
-        unreachable!()
-    }
-    fn scattering_pdf(
-        &self,
-        _r_in: &mut Ray,
-        _rec: &mut hit::HitRecord,
-        _scattered: &mut Ray,
-    ) -> f64 {
+    fn bsdf(&self, _rec: &HitRecord) -> Option<Box<dyn Bsdf>> {
         unreachable!()
     }
     fn emitted(
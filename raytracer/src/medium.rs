@@ -0,0 +1,338 @@
+use crate::hit::{HitRecord, Hittable};
+use crate::material::{Isotropiuc, Material};
+use crate::perlin::Perlin;
+use crate::spectrum;
+use crate::texture::SolidColor;
+use crate::AABB::Aabb;
+use crate::{clamp, random_double, random_int, Ray, Vec3};
+use raytracer_scene::grid;
+use std::cell::Cell;
+use std::sync::Arc;
+
+// Participating media are hittables too: `hit` samples where a ray running through one first
+// collides with it, and `transmittance` how much light gets through it. Shadow rays hide the
+// media from `hit`, find the surface they end at and multiply in the transmittance of
+// everything on the way.
+
+thread_local! {
+    static SHADOW_RAY: Cell<bool> = Cell::new(false);
+}
+
+// Runs `f`, a shadow ray, with the media hidden from `hit`.
+pub fn shadow_ray<T>(f: impl FnOnce() -> T) -> T {
+    SHADOW_RAY.with(|s| s.set(true));
+    let result = f();
+    SHADOW_RAY.with(|s| s.set(false));
+    result
+}
+
+pub fn in_shadow_ray() -> bool {
+    SHADOW_RAY.with(|s| s.get())
+}
+
+thread_local! {
+    static CHANNEL: Cell<Option<i32>> = Cell::new(None);
+}
+
+// A medium whose extinction differs between channels samples distances right for one of them
+// only: the first one a path meets picks that channel, and the path has to carry on in it
+// alone, three times as bright. In spectral mode it's the hero wavelength, the one dispersion
+// keeps too.
+pub fn tracked_channel() -> Option<i32> {
+    CHANNEL.with(|c| c.get())
+}
+
+// Forgets the channel, for a new path.
+pub fn reset_tracked_channel() {
+    CHANNEL.with(|c| c.set(None))
+}
+
+fn track_channel() -> i32 {
+    CHANNEL.with(|c| {
+        let channel = c.get().unwrap_or_else(|| {
+            if spectrum::wavelengths().is_some() {
+                0
+            } else {
+                random_int(0, 2)
+            }
+        });
+        c.set(Some(channel));
+        channel
+    })
+}
+
+// The part of `r` between `t_min` and `t_max` that is inside `boundary`, which has to be
+// convex.
+pub fn segment(boundary: &dyn Hittable, r: Ray, t_min: f64, t_max: f64) -> Option<(f64, f64)> {
+    let rec1 = boundary.hit(r, -f64::INFINITY, f64::INFINITY)?;
+    let rec2 = boundary.hit(r, rec1.t + 0.0001, f64::INFINITY)?;
+    let t0 = rec1.t.max(t_min).max(0.0);
+    let t1 = rec2.t.min(t_max);
+    if t0 >= t1 {
+        return None;
+    }
+    Some((t0, t1))
+}
+
+// How much medium there is at a point, scaling its coefficients.
+pub trait Density: Send + Sync {
+    fn density(&self, p: Vec3) -> f64;
+    // an upper bound of `density`, the majorant the tracking steps with
+    fn max_density(&self) -> f64;
+}
+
+// Densities at the cell centers of a regular grid over `bounds`, trilinearly interpolated;
+// zero outside.
+pub struct GridDensity {
+    pub resolution: [usize; 3],
+    // x varies fastest, then y, then z
+    pub data: Vec<f64>,
+    pub bounds: Aabb,
+    max: f64,
+}
+
+impl GridDensity {
+    pub fn new(resolution: [usize; 3], data: Vec<f64>, bounds: Aabb) -> Result<Self, String> {
+        grid::check(resolution, &data)?;
+        let max = data.iter().cloned().fold(0.0, f64::max);
+        Ok(Self {
+            resolution,
+            data,
+            bounds,
+            max,
+        })
+    }
+
+    pub fn from_vol(filename: &str, bounds: Aabb) -> Result<Self, String> {
+        let (resolution, data) = grid::read_vol(filename)?;
+        Self::new(resolution, data, bounds).map_err(|e| format!("`{}`: {}", filename, e))
+    }
+
+    fn at(&self, x: usize, y: usize, z: usize) -> f64 {
+        self.data[(z * self.resolution[1] + y) * self.resolution[0] + x]
+    }
+}
+
+impl Density for GridDensity {
+    fn density(&self, p: Vec3) -> f64 {
+        let (min, max) = (self.bounds.minimum, self.bounds.maximum);
+        if p.x < min.x || p.y < min.y || p.z < min.z || p.x > max.x || p.y > max.y || p.z > max.z {
+            return 0.0;
+        }
+        // the cell and the offsets into it along each axis
+        let mut cell = [0; 3];
+        let mut f = [0.0; 3];
+        for a in 0..3 {
+            let n = self.resolution[a];
+            let t = (p.get(a as i32) - min.get(a as i32)) / (max.get(a as i32) - min.get(a as i32));
+            let g = clamp(t * n as f64 - 0.5, 0.0, (n - 1) as f64);
+            cell[a] = (g as usize).min(n.saturating_sub(2));
+            f[a] = g - cell[a] as f64;
+        }
+        let next = |a: usize| (cell[a] + 1).min(self.resolution[a] - 1);
+        let mut d = 0.0;
+        for (i, x) in [cell[0], next(0)].iter().enumerate() {
+            for (j, y) in [cell[1], next(1)].iter().enumerate() {
+                for (k, z) in [cell[2], next(2)].iter().enumerate() {
+                    let w = (if i == 0 { 1.0 - f[0] } else { f[0] })
+                        * (if j == 0 { 1.0 - f[1] } else { f[1] })
+                        * (if k == 0 { 1.0 - f[2] } else { f[2] });
+                    d += w * self.at(*x, *y, *z);
+                }
+            }
+        }
+        d
+    }
+
+    fn max_density(&self) -> f64 {
+        self.max
+    }
+}
+
+// Perlin turbulence at `frequency`, cut off at 1 and scaled by `density`: billowy clouds.
+pub struct NoiseDensity {
+    pub noise: Perlin,
+    pub frequency: f64,
+    pub octaves: i32,
+    pub density: f64,
+}
+
+impl NoiseDensity {
+    pub fn new(frequency: f64, octaves: i32, density: f64) -> Self {
+        Self {
+            noise: Perlin::new(),
+            frequency,
+            octaves,
+            density,
+        }
+    }
+}
+
+impl Density for NoiseDensity {
+    fn density(&self, p: Vec3) -> f64 {
+        let mut q = p * self.frequency;
+        self.density * self.noise.turb(&mut q, self.octaves).min(1.0)
+    }
+
+    fn max_density(&self) -> f64 {
+        self.density
+    }
+}
+
+// What an absorbing collision ends the path with: the medium's own glow, if any.
+struct Glow {
+    emit: Vec3,
+}
+
+impl Material for Glow {
+    fn emitted(
+        &self,
+        _r_in: &mut Ray,
+        _rec: &mut HitRecord,
+        _u: f64,
+        _v: f64,
+        _p: &mut Vec3,
+    ) -> Vec3 {
+        self.emit
+    }
+}
+
+// A medium whose density varies inside a convex `boundary`. `sigma_a` and `sigma_s` are the
+// absorption and scattering coefficients per channel at density 1; it scatters isotropically
+// and, where it absorbs, emits `emission` (fire is mostly absorbing and glowing).
+pub struct HeterogeneousMedium<H: Hittable> {
+    pub boundary: H,
+    pub density: Arc<dyn Density>,
+    pub sigma_a: Vec3,
+    pub sigma_s: Vec3,
+    phase: Isotropiuc<SolidColor>,
+    glow: Glow,
+}
+
+impl<H: Hittable> HeterogeneousMedium<H> {
+    pub fn new(
+        boundary: H,
+        density: Arc<dyn Density>,
+        sigma_a: Vec3,
+        sigma_s: Vec3,
+        emission: Vec3,
+    ) -> Self {
+        Self {
+            boundary,
+            density,
+            sigma_a,
+            sigma_s,
+            phase: Isotropiuc::<SolidColor>::new(Vec3::ones()),
+            glow: Glow { emit: emission },
+        }
+    }
+
+    pub fn emission(&self) -> Vec3 {
+        self.glow.emit
+    }
+
+    // the coefficients in the channels the path carries, and their majorant along with them
+    fn coefficients(&self) -> (Vec3, Vec3, f64) {
+        let (sigma_a, sigma_s) = match spectrum::wavelengths() {
+            Some(wl) => (wl.reflectance(self.sigma_a), wl.reflectance(self.sigma_s)),
+            None => (self.sigma_a, self.sigma_s),
+        };
+        let majorant = (sigma_a + sigma_s).max_component() * self.density.max_density();
+        (sigma_a, sigma_s, majorant)
+    }
+}
+
+impl<H: Hittable> Hittable for HeterogeneousMedium<H> {
+    // Delta tracking: steps by the majorant, and at each tentative collision picks absorption,
+    // scattering or a null collision that carries on, in proportion to the coefficients there.
+    // With the same extinction in every channel the averaged coefficients decide and the
+    // collision is weighted by the rest; otherwise one pick can't be right for all channels,
+    // so the medium tracks the path's channel and the path drops the others.
+    fn hit(&self, r: Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        if in_shadow_ray() {
+            return None;
+        }
+        let (t0, t1) = segment(&self.boundary, r, t_min, t_max)?;
+        let (sigma_a, sigma_s, _) = self.coefficients();
+        let extinction = sigma_a + sigma_s;
+        let lowest = extinction.x.min(extinction.y).min(extinction.z);
+        let channel = if extinction.max_component() - lowest > 1e-9 * lowest {
+            Some(track_channel())
+        } else {
+            None
+        };
+        let pick = |v: Vec3| match channel {
+            Some(c) => v.get(c),
+            None => (v.x + v.y + v.z) / 3.0,
+        };
+        let majorant = pick(extinction) * self.density.max_density();
+        if majorant <= 0.0 {
+            return None;
+        }
+        let ray_length = r.dir.length();
+        let mut t = t0;
+        loop {
+            t -= (1.0 - random_double()).ln() / (majorant * ray_length);
+            if t >= t1 {
+                return None;
+            }
+            let p = r.at(t);
+            let d = self.density.density(p);
+            let (absorb, scatter) = (sigma_a * d, sigma_s * d);
+            let u = random_double() * majorant;
+            let (event, mat_ptr): (Vec3, &dyn Material) = if u < pick(absorb) {
+                (absorb / pick(absorb), &self.glow)
+            } else if u < pick(absorb) + pick(scatter) {
+                (scatter / pick(scatter), &self.phase)
+            } else {
+                continue;
+            };
+            return Some(HitRecord {
+                p,
+                normal: Vec3::new(1.0, 0.0, 0.0),
+                mat_ptr,
+                t,
+                u: 0.0,
+                v: 0.0,
+                front_face: true,
+                medium_weight: Some(event),
+                id: 0,
+            });
+        }
+    }
+
+    fn bounding_box(&self, time0: f64, time1: f64, output_box: &mut Aabb) -> bool {
+        self.boundary.bounding_box(time0, time1, output_box)
+    }
+
+    // Ratio tracking: the same steps, each scaling the transmittance by the chance that it
+    // was a null collision.
+    fn transmittance(&self, r: Ray, t_min: f64, t_max: f64) -> Vec3 {
+        let (t0, t1) = match segment(&self.boundary, r, t_min, t_max) {
+            Some(s) => s,
+            None => return Vec3::ones(),
+        };
+        let (sigma_a, sigma_s, majorant) = self.coefficients();
+        if majorant <= 0.0 {
+            return Vec3::ones();
+        }
+        let ray_length = r.dir.length();
+        let mut tr = Vec3::ones();
+        let mut t = t0;
+        loop {
+            t -= (1.0 - random_double()).ln() / (majorant * ray_length);
+            if t >= t1 {
+                return tr;
+            }
+            let d = self.density.density(r.at(t));
+            tr *= Vec3::ones() - (sigma_a + sigma_s) * (d / majorant);
+            // Russian roulette, so deep inside a dense medium not every step gets taken
+            if tr.max_component() < 0.1 {
+                if random_double() < 0.5 {
+                    return Vec3::zero();
+                }
+                tr *= 2.0;
+            }
+        }
+    }
+}
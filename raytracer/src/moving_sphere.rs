@@ -96,6 +96,8 @@ impl<T: Material> Hittable for MovingSphere<T> {
             u: ui,
             v: vi,
             front_face,
+            medium_weight: None,
+            id: 0,
         })
     }
 
@@ -0,0 +1,127 @@
+use crate::aarect::Triangle;
+use crate::hit::{Hittable, HittableList, Ids};
+use crate::material::{DiffuseLight, Material, Principled};
+use crate::Vec3;
+use crate::BVH::BvhNode;
+use std::path::Path;
+use std::sync::Arc;
+
+// A Wavefront OBJ model: a bvh of triangles per object in the file, each numbered from `ids`
+// for the id pass. Faces take their MTL material, a diffuse light where it has a `Ke`
+// emission, or `fallback` without one.
+pub struct ObjModel {
+    pub meshes: HittableList,
+    // the triangles that glow, to sample one light each
+    pub emitters: Vec<Arc<dyn Hittable>>,
+}
+
+impl ObjModel {
+    pub fn load(
+        filename: &str,
+        scale: f64,
+        fallback: Option<&Arc<dyn Material>>,
+        ids: &Ids,
+    ) -> Result<Self, String> {
+        let (models, materials) = tobj::load_obj(
+            filename,
+            &tobj::LoadOptions {
+                single_index: true,
+                triangulate: true,
+                ..Default::default()
+            },
+        )
+        .map_err(|e| format!("cannot load `{}`: {}", filename, e))?;
+        let dir = Path::new(filename)
+            .parent()
+            .unwrap_or_else(|| Path::new(""));
+        // (material, whether it glows)
+        let materials: Vec<(Arc<dyn Material>, bool)> = match materials {
+            Ok(materials) => materials
+                .iter()
+                .map(|m| match mtl_emission(m) {
+                    Some(emit) => (
+                        Arc::new(DiffuseLight::new0(emit)) as Arc<dyn Material>,
+                        true,
+                    ),
+                    None => (
+                        Arc::new(Principled::from_mtl(m, dir)) as Arc<dyn Material>,
+                        false,
+                    ),
+                })
+                .collect(),
+            Err(_) => Vec::new(),
+        };
+
+        let mut meshes = HittableList::new();
+        let mut emitters: Vec<Arc<dyn Hittable>> = Vec::new();
+        for model in models.iter() {
+            let mesh = &model.mesh;
+            let (material, glows) = match mesh.material_id.and_then(|id| materials.get(id)) {
+                Some((material, glows)) => (material, *glows),
+                None => match fallback {
+                    Some(material) => (material, false),
+                    None => {
+                        return Err(format!(
+                            "`{}`: `{}` has no MTL material, give it a `material`",
+                            filename, model.name
+                        ))
+                    }
+                },
+            };
+            let position = |i: u32| {
+                let i = 3 * i as usize;
+                Vec3::new(
+                    mesh.positions[i] as f64,
+                    mesh.positions[i + 1] as f64,
+                    mesh.positions[i + 2] as f64,
+                ) * scale
+            };
+            let texcoord = |i: u32| {
+                let i = 2 * i as usize;
+                (mesh.texcoords[i] as f64, mesh.texcoords[i + 1] as f64)
+            };
+            let mut triangles = HittableList::new();
+            for face in mesh.indices.chunks(3) {
+                let (p0, p1, p2) = (position(face[0]), position(face[1]), position(face[2]));
+                let triangle = if mesh.texcoords.is_empty() {
+                    Triangle::new(p0, p1, p2, material.clone())
+                } else {
+                    let uv = [texcoord(face[0]), texcoord(face[1]), texcoord(face[2])];
+                    Triangle::new_with_uv(p0, p1, p2, uv, material.clone())
+                };
+                let triangle: Arc<dyn Hittable> = Arc::new(triangle);
+                if glows && triangle.area() > 0.0 {
+                    emitters.push(triangle.clone());
+                }
+                triangles.add(triangle);
+            }
+            if !triangles.objects.is_empty() {
+                meshes.add(Arc::new(ids.tag(BvhNode::new(triangles, 0.0, 1.0))));
+            }
+        }
+        if meshes.objects.is_empty() {
+            return Err(format!("`{}` has no faces", filename));
+        }
+        Ok(Self { meshes, emitters })
+    }
+}
+
+// an MTL material's `Ke`, if it glows
+fn mtl_emission(mtl: &tobj::Material) -> Option<Vec3> {
+    let ke: Vec<f64> = mtl
+        .unknown_param
+        .get("Ke")?
+        .split_whitespace()
+        .map(|v| v.parse::<f64>().ok())
+        .collect::<Option<_>>()?;
+    let emit = match ke[..] {
+        [r, g, b] => Vec3::new(r, g, b),
+        [v] => Vec3::new(v, v, v),
+        _ => return None,
+    };
+    if emit.max_component() > 0.0 {
+        Some(emit)
+    } else {
+        None
+    }
+}
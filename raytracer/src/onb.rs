@@ -5,6 +5,13 @@ pub struct Onb {
 }
 
 impl Onb {
+    pub fn new_from_w(mut w: Vec3) -> Self {
+        let mut uvw = Onb {
+            axis: [Vec3::zero(); 3],
+        };
+        uvw.build_from_w(&mut w);
+        uvw
+    }
     pub fn get(&self, i: usize) -> Vec3 {
         self.axis[i]
     }
@@ -14,6 +21,14 @@ impl Onb {
     pub fn local0(&self, a: Vec3) -> Vec3 {
         self.axis[0] * a.x + self.axis[1] * a.y + self.axis[2] * a.z
     }
+    // world -> local, the inverse of `local0`
+    pub fn to_local(&self, v: Vec3) -> Vec3 {
+        Vec3::new(
+            Vec3::dot(v, self.axis[0]),
+            Vec3::dot(v, self.axis[1]),
+            Vec3::dot(v, self.axis[2]),
+        )
+    }
     pub fn build_from_w(&mut self, n: &mut Vec3) {
         self.axis[2] = Vec3::unit_vector(*n);
         let a: Vec3;
@@ -1,7 +1,9 @@
 use crate::hit::Hittable;
 use crate::onb::Onb;
-use crate::{random_double, Vec3};
+use crate::sampler::{sample_1d, sample_2d};
+use crate::{clamp, Vec3};
 use std::f64::consts::PI;
+use std::str::FromStr;
 
 pub trait Pdf: Sync + Send {
     fn value(&self, direction: &mut Vec3) -> f64;
@@ -9,8 +11,7 @@ pub trait Pdf: Sync + Send {
 }
 
 pub fn random_cosine_direction() -> Vec3 {
-    let r1 = random_double();
-    let r2 = random_double();
+    let (r1, r2) = sample_2d();
     let z = (1.0 - r2).sqrt();
 
     let phi = 2.0 * PI * r1;
@@ -93,7 +94,7 @@ impl<'a, T1: Pdf, T2: Pdf> Pdf for MixturePdf<'a, T1, T2> {
 
     fn generate(&self) -> Vec3 {
         let vv: Vec3;
-        if random_double() < 0.5 {
+        if sample_1d() < 0.5 {
             vv = self.p1.generate();
         } else {
             vv = self.p2.generate();
@@ -112,3 +113,286 @@ impl Pdf for NoPdf {
         unreachable!()
     }
 }
+
+// How a direction that both light sampling and BSDF sampling could have produced splits
+// its contribution between the two strategies.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum MisHeuristic {
+    Power,
+    Balance,
+}
+
+pub const MIS_HEURISTICS: [&str; 2] = ["power", "balance"];
+
+impl FromStr for MisHeuristic {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "power" => Ok(MisHeuristic::Power),
+            "balance" => Ok(MisHeuristic::Balance),
+            _ => Err(format!(
+                "unknown MIS heuristic `{}` (expected one of {})",
+                s,
+                MIS_HEURISTICS.join(", ")
+            )),
+        }
+    }
+}
+
+impl MisHeuristic {
+    // Weight of a sample drawn with density `pdf` when the other strategy has `other`.
+    pub fn weight(self, pdf: f64, other: f64) -> f64 {
+        let (a, b) = match self {
+            MisHeuristic::Power => (pdf * pdf, other * other),
+            MisHeuristic::Balance => (pdf, other),
+        };
+        if a + b > 0.0 {
+            a / (a + b)
+        } else {
+            0.0
+        }
+    }
+}
+
+// A piecewise-constant density over [0, 1) proportional to `func`, sampled by inverting its
+// CDF. An all-zero `func` samples uniformly.
+pub struct Distribution1D {
+    pub func: Vec<f64>,
+    cdf: Vec<f64>,
+    // the integral of `func` over [0, 1)
+    pub integral: f64,
+}
+
+impl Distribution1D {
+    pub fn new(func: Vec<f64>) -> Self {
+        let n = func.len();
+        let mut cdf = vec![0.0; n + 1];
+        for i in 0..n {
+            cdf[i + 1] = cdf[i] + func[i].abs() / n as f64;
+        }
+        let integral = cdf[n];
+        for (i, c) in cdf.iter_mut().enumerate().skip(1) {
+            *c = if integral > 0.0 {
+                *c / integral
+            } else {
+                i as f64 / n as f64
+            };
+        }
+        Self {
+            func,
+            cdf,
+            integral,
+        }
+    }
+
+    // The sample in [0, 1), its density and the piece it fell into.
+    pub fn sample(&self, u: f64) -> (f64, f64, usize) {
+        // the last piece whose CDF is <= u, skipping empty pieces at the front
+        let n = self.func.len();
+        let offset = match self.cdf.binary_search_by(|c| c.partial_cmp(&u).unwrap()) {
+            Ok(i) => i,
+            Err(i) => i - 1,
+        }
+        .min(n - 1);
+        let offset = (offset..n)
+            .find(|i| self.cdf[*i + 1] > self.cdf[*i])
+            .unwrap_or(offset);
+        let width = self.cdf[offset + 1] - self.cdf[offset];
+        let du = if width > 0.0 {
+            clamp((u - self.cdf[offset]) / width, 0.0, 1.0)
+        } else {
+            0.0
+        };
+        (
+            ((offset as f64 + du) / n as f64).min(1.0 - f64::EPSILON),
+            self.pdf_of(offset),
+            offset,
+        )
+    }
+
+    pub fn pdf(&self, x: f64) -> f64 {
+        let n = self.func.len();
+        self.pdf_of(((x * n as f64) as usize).min(n - 1))
+    }
+
+    fn pdf_of(&self, offset: usize) -> f64 {
+        if self.integral > 0.0 {
+            self.func[offset].abs() / self.integral
+        } else {
+            1.0
+        }
+    }
+}
+
+// A piecewise-constant density over [0, 1)^2 from a row-major table of `width` x `height`
+// values: a row is picked from the marginal density, then a column within it.
+pub struct Distribution2D {
+    conditional: Vec<Distribution1D>,
+    marginal: Distribution1D,
+}
+
+impl Distribution2D {
+    pub fn new(func: &[f64], width: usize, height: usize) -> Self {
+        let conditional: Vec<Distribution1D> = (0..height)
+            .map(|y| Distribution1D::new(func[y * width..(y + 1) * width].to_vec()))
+            .collect();
+        let marginal = Distribution1D::new(conditional.iter().map(|c| c.integral).collect());
+        Self {
+            conditional,
+            marginal,
+        }
+    }
+
+    // (x, y) and its density.
+    pub fn sample(&self, u: (f64, f64)) -> ((f64, f64), f64) {
+        let (y, pdf_y, row) = self.marginal.sample(u.1);
+        let (x, pdf_x, _) = self.conditional[row].sample(u.0);
+        ((x, y), pdf_x * pdf_y)
+    }
+
+    pub fn pdf(&self, x: f64, y: f64) -> f64 {
+        let n = self.conditional.len();
+        let row = ((y * n as f64) as usize).min(n - 1);
+        self.marginal.pdf_of(row) * self.conditional[row].pdf(x)
+    }
+
+    // the integral of the table over [0, 1)^2
+    pub fn integral(&self) -> f64 {
+        self.marginal.integral
+    }
+}
+
+// Picks one of a list of items with probability proportional to its weight, in constant time
+// (Vose's alias method). All-zero weights pick uniformly.
+pub struct AliasTable {
+    // the chance of keeping a bin's own item rather than its alias
+    prob: Vec<f64>,
+    alias: Vec<usize>,
+    pmf: Vec<f64>,
+}
+
+impl AliasTable {
+    pub fn new(weights: &[f64]) -> Self {
+        let n = weights.len();
+        let total: f64 = weights.iter().map(|w| w.max(0.0)).sum();
+        let pmf: Vec<f64> = if total > 0.0 {
+            weights.iter().map(|w| w.max(0.0) / total).collect()
+        } else {
+            vec![1.0 / n as f64; n]
+        };
+        let mut prob: Vec<f64> = pmf.iter().map(|p| p * n as f64).collect();
+        let mut alias: Vec<usize> = (0..n).collect();
+        let (mut small, mut large): (Vec<usize>, Vec<usize>) = (0..n).partition(|i| prob[*i] < 1.0);
+        while let (Some(s), Some(l)) = (small.pop(), large.pop()) {
+            alias[s] = l;
+            prob[l] += prob[s] - 1.0;
+            if prob[l] < 1.0 {
+                small.push(l);
+            } else {
+                large.push(l);
+            }
+        }
+        // what's left is 1 up to rounding
+        for i in small.into_iter().chain(large) {
+            prob[i] = 1.0;
+        }
+        Self { prob, alias, pmf }
+    }
+
+    // The item and the probability of picking it.
+    pub fn sample(&self, u: f64) -> (usize, f64) {
+        let n = self.prob.len();
+        let x = u * n as f64;
+        let bin = (x as usize).min(n - 1);
+        let i = if x - (bin as f64) < self.prob[bin] {
+            bin
+        } else {
+            self.alias[bin]
+        };
+        (i, self.pmf[i])
+    }
+
+    pub fn pmf(&self, i: usize) -> f64 {
+        self.pmf[i]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn alias_table_picks_by_its_pmf() {
+        let weights = [3.0, 0.0, 1.0, 6.0, 0.5, 2.5];
+        let table = AliasTable::new(&weights);
+        let total: f64 = weights.iter().sum();
+        let n = 100_000;
+        let mut counts = vec![0; weights.len()];
+        for k in 0..n {
+            let (i, pmf) = table.sample((k as f64 + 0.5) / n as f64);
+            assert_eq!(pmf, table.pmf(i));
+            counts[i] += 1;
+        }
+        for (i, w) in weights.iter().enumerate() {
+            assert!((table.pmf(i) - w / total).abs() < 1e-12);
+            // evenly spaced u: off by at most a step per bin
+            let frequency = counts[i] as f64 / n as f64;
+            assert!((frequency - table.pmf(i)).abs() < 1e-3, "item {}", i);
+        }
+        assert_eq!(counts[1], 0);
+    }
+
+    #[test]
+    fn alias_table_without_weights_is_uniform() {
+        let table = AliasTable::new(&[0.0; 4]);
+        for i in 0..4 {
+            assert_eq!(table.pmf(i), 0.25);
+            assert_eq!(table.sample((i as f64 + 0.5) / 4.0).0, i);
+        }
+    }
+
+    #[test]
+    fn distribution_2d_samples_by_its_pdf() {
+        let (width, height) = (4, 3);
+        let func = [
+            1.0, 2.0, 0.0, 4.0, //
+            0.0, 0.0, 0.0, 0.0, //
+            3.0, 1.0, 1.0, 0.5,
+        ];
+        let dist = Distribution2D::new(&func, width, height);
+        // the density is a density
+        let integral: f64 = func.iter().sum::<f64>() / (width * height) as f64;
+        assert!((dist.integral() - integral).abs() < 1e-12);
+        let mut sum = 0.0;
+        for y in 0..height {
+            for x in 0..width {
+                let (u, v) = (
+                    (x as f64 + 0.5) / width as f64,
+                    (y as f64 + 0.5) / height as f64,
+                );
+                let pdf = dist.pdf(u, v);
+                assert!((pdf - func[y * width + x] / integral).abs() < 1e-12);
+                sum += pdf / (width * height) as f64;
+            }
+        }
+        assert!((sum - 1.0).abs() < 1e-12);
+
+        let n = 400;
+        let mut counts = vec![0; width * height];
+        for i in 0..n {
+            for j in 0..n {
+                let u = ((i as f64 + 0.5) / n as f64, (j as f64 + 0.5) / n as f64);
+                let ((x, y), pdf) = dist.sample(u);
+                assert!(pdf > 0.0);
+                assert!((pdf - dist.pdf(x, y)).abs() < 1e-12);
+                let cell = (y * height as f64) as usize * width + (x * width as f64) as usize;
+                counts[cell] += 1;
+            }
+        }
+        for (cell, count) in counts.iter().enumerate() {
+            let expected = func[cell] / integral / (width * height) as f64;
+            let frequency = *count as f64 / (n * n) as f64;
+            assert!((frequency - expected).abs() < 5e-3, "cell {}", cell);
+        }
+    }
+}
@@ -0,0 +1,192 @@
+use crate::adaptive::PixelStats;
+use crate::aov::{Aov, AovBuffers};
+use crate::framebuffer::FrameBuffer;
+use crate::Vec3;
+use std::str::FromStr;
+
+// A rectangle of pixels, x0..x1 by y0..y1.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Tile {
+    pub x0: u32,
+    pub y0: u32,
+    pub x1: u32,
+    pub y1: u32,
+}
+
+impl Tile {
+    pub fn width(&self) -> u32 {
+        self.x1 - self.x0
+    }
+    pub fn height(&self) -> u32 {
+        self.y1 - self.y0
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum TileOrder {
+    // rows of tiles from the top
+    Scanline,
+    // outwards from the center, where the subject usually is
+    Spiral,
+    // along a Hilbert curve, consecutive tiles stay close to each other
+    Hilbert,
+}
+
+pub const TILE_ORDERS: [&str; 3] = ["scanline", "spiral", "hilbert"];
+
+impl FromStr for TileOrder {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "scanline" => Ok(TileOrder::Scanline),
+            "spiral" => Ok(TileOrder::Spiral),
+            "hilbert" => Ok(TileOrder::Hilbert),
+            _ => Err(format!(
+                "unknown tile order `{}` (expected one of {})",
+                s,
+                TILE_ORDERS.join(", ")
+            )),
+        }
+    }
+}
+
+// Cuts the image into `size` x `size` tiles (smaller at the right and bottom edges),
+// listed in the order they should be rendered.
+pub fn tiles(width: u32, height: u32, size: u32, order: TileOrder) -> Vec<Tile> {
+    let nx = (width - 1) / size + 1;
+    let ny = (height - 1) / size + 1;
+    let tile = |tx: u32, ty: u32| Tile {
+        x0: tx * size,
+        y0: ty * size,
+        x1: ((tx + 1) * size).min(width),
+        y1: ((ty + 1) * size).min(height),
+    };
+    let mut coords: Vec<(u32, u32)> = (0..ny)
+        .flat_map(|ty| (0..nx).map(move |tx| (tx, ty)))
+        .collect();
+    match order {
+        TileOrder::Scanline => {}
+        TileOrder::Spiral => coords = spiral(nx, ny),
+        TileOrder::Hilbert => {
+            let mut n = 1;
+            while n < nx.max(ny) {
+                n *= 2;
+            }
+            coords.sort_by_key(|&(tx, ty)| hilbert_index(n, tx, ty));
+        }
+    }
+    coords.iter().map(|&(tx, ty)| tile(tx, ty)).collect()
+}
+
+// Walks a square spiral from the center tile, keeping the steps that land inside the grid.
+fn spiral(nx: u32, ny: u32) -> Vec<(u32, u32)> {
+    let total = (nx * ny) as usize;
+    let mut out = Vec::with_capacity(total);
+    let (mut x, mut y) = (((nx - 1) / 2) as i64, ((ny - 1) / 2) as i64);
+    let (mut dx, mut dy) = (1i64, 0i64);
+    let mut leg = 1;
+    while out.len() < total {
+        // two legs per length: right, down, left (longer), up (longer)...
+        for _ in 0..2 {
+            for _ in 0..leg {
+                if x >= 0 && y >= 0 && x < nx as i64 && y < ny as i64 {
+                    out.push((x as u32, y as u32));
+                }
+                x += dx;
+                y += dy;
+            }
+            let turned = (-dy, dx);
+            dx = turned.0;
+            dy = turned.1;
+        }
+        leg += 1;
+    }
+    out
+}
+
+// Position of (x, y) along the Hilbert curve filling an n x n grid, n a power of two.
+fn hilbert_index(n: u32, mut x: u32, mut y: u32) -> u64 {
+    let mut d = 0u64;
+    let mut s = n / 2;
+    while s > 0 {
+        let rx = (x & s > 0) as u32;
+        let ry = (y & s > 0) as u32;
+        d += s as u64 * s as u64 * ((3 * rx) ^ ry) as u64;
+        // rotate the quadrant so the curve stays continuous
+        if ry == 0 {
+            if rx == 1 {
+                x = s - 1 - (x & (s - 1));
+                y = s - 1 - (y & (s - 1));
+            }
+            std::mem::swap(&mut x, &mut y);
+        }
+        x &= s - 1;
+        y &= s - 1;
+        s /= 2;
+    }
+    d
+}
+
+// Everything rendered so far: per-pixel sample statistics for the beauty pass and running
+// sums of the AOV samples. Passes add samples tile by tile; the image can be resolved at
+// any point, which is how partial results get written while the render is still going.
+pub struct Accumulator {
+    pub width: u32,
+    pub height: u32,
+    pub stats: Vec<PixelStats>,
+    pub aovs: AovBuffers,
+}
+
+impl Accumulator {
+    pub fn new(width: u32, height: u32, aovs: &[Aov]) -> Self {
+        Self {
+            width,
+            height,
+            stats: vec![PixelStats::new(); (width * height) as usize],
+            aovs: AovBuffers::new(aovs, width, height),
+        }
+    }
+
+    // The statistics of a tile's pixels, row by row.
+    pub fn tile_stats(&self, tile: &Tile) -> Vec<PixelStats> {
+        let mut out = Vec::with_capacity((tile.width() * tile.height()) as usize);
+        for y in tile.y0..tile.y1 {
+            let row = (y * self.width) as usize;
+            out.extend_from_slice(&self.stats[row + tile.x0 as usize..row + tile.x1 as usize]);
+        }
+        out
+    }
+
+    // Stores a tile's updated statistics and adds the AOV sums of its new samples.
+    pub fn merge(&mut self, tile: &Tile, stats: &[PixelStats], aovs: &AovBuffers) {
+        let w = tile.width() as usize;
+        for (i, y) in (tile.y0..tile.y1).enumerate() {
+            let row = (y * self.width) as usize;
+            self.stats[row + tile.x0 as usize..row + tile.x1 as usize]
+                .copy_from_slice(&stats[i * w..(i + 1) * w]);
+        }
+        self.aovs.add_tile(aovs, tile.x0, tile.y0);
+    }
+
+    pub fn image(&self) -> FrameBuffer {
+        self.map(|s| s.color())
+    }
+
+    // samples taken per pixel, in x
+    pub fn counts(&self) -> FrameBuffer {
+        self.map(|s| Vec3::new(s.n as f64, 0.0, 0.0))
+    }
+
+    // AOV means
+    pub fn aov_image(&self) -> AovBuffers {
+        self.aovs.resolve(&self.counts())
+    }
+
+    fn map(&self, f: impl Fn(&PixelStats) -> Vec3) -> FrameBuffer {
+        FrameBuffer {
+            width: self.width,
+            height: self.height,
+            data: self.stats.iter().map(f).collect(),
+        }
+    }
+}
@@ -1,19 +1,84 @@
 use crate::Vec3;
-use rand::Rng;
+use std::cell::Cell;
 use std::f64::consts::PI;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+// PCG32 (XSH RR). Every random number in the renderer comes from the calling thread's
+// generator, which the render loop reseeds from (seed, pixel, sample) before each sample,
+// so images don't depend on thread scheduling.
+#[derive(Copy, Clone, Debug)]
+pub struct Pcg32 {
+    state: u64,
+    inc: u64,
+}
+
+impl Pcg32 {
+    pub fn new(seed: u64, stream: u64) -> Self {
+        let mut rng = Pcg32 {
+            state: 0,
+            inc: (stream << 1) | 1,
+        };
+        rng.next_u32();
+        rng.state = rng.state.wrapping_add(seed);
+        rng.next_u32();
+        rng
+    }
+    pub fn next_u32(&mut self) -> u32 {
+        let old = self.state;
+        self.state = old
+            .wrapping_mul(6_364_136_223_846_793_005)
+            .wrapping_add(self.inc);
+        let xorshifted = (((old >> 18) ^ old) >> 27) as u32;
+        xorshifted.rotate_right((old >> 59) as u32)
+    }
+    // uniform in [0, 1) with 53 random bits
+    pub fn next_f64(&mut self) -> f64 {
+        let hi = (self.next_u32() as u64) << 21;
+        let lo = (self.next_u32() >> 11) as u64;
+        (hi ^ lo) as f64 / (1u64 << 53) as f64
+    }
+}
+
+static SEED: AtomicU64 = AtomicU64::new(0);
+
+thread_local! {
+    static RNG: Cell<Pcg32> = Cell::new(Pcg32::new(0, 0));
+}
+
+// SplitMix64 finalizer, spreads consecutive pixel indices over the whole seed space
+pub(crate) fn mix(mut z: u64) -> u64 {
+    z = z.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+// Sets the global seed and restarts the calling thread's generator from it;
+// call before building the scene so random scenes, Perlin tables and BVHs repeat too.
+pub fn set_seed(seed: u64) {
+    SEED.store(seed, Ordering::Relaxed);
+    RNG.with(|rng| rng.set(Pcg32::new(mix(seed), 0)));
+}
+
+pub fn seed_pixel_sample(pixel: u64, sample: u64) {
+    let seed = SEED.load(Ordering::Relaxed);
+    RNG.with(|rng| rng.set(Pcg32::new(mix(seed ^ mix(pixel)), sample)));
+}
 
 pub fn random_double() -> f64 {
-    let mut dou = rand::thread_rng();
-    dou.gen()
+    RNG.with(|rng| {
+        let mut r = rng.get();
+        let x = r.next_f64();
+        rng.set(r);
+        x
+    })
 }
 
 pub fn random_double_lim(min: f64, max: f64) -> f64 {
-    let mut dou = rand::thread_rng();
-    dou.gen_range(min, max)
+    min + (max - min) * random_double()
 }
 pub fn random_int(min: i32, max: i32) -> i32 {
-    let mut ins = rand::thread_rng();
-    ins.gen_range(min, max + 1)
+    min + (random_double() * (max - min + 1) as f64) as i32
 }
 pub fn degrees_to_radians(degrees: f64) -> f64 {
     degrees * PI / 180.0
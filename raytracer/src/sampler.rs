@@ -0,0 +1,477 @@
+use crate::rtweekend::{mix, random_double, seed_pixel_sample, Pcg32};
+use std::cell::RefCell;
+use std::str::FromStr;
+use std::sync::Arc;
+
+// Sample generators: `get_1d`/`get_2d` return coordinate `dim` (and `dim + 1`) of
+// sample `index` inside pixel `pixel`. They are pure functions of their arguments, so
+// one instance is shared by every worker thread and results don't depend on scheduling.
+pub trait Sampler: Send + Sync {
+    fn get_1d(&self, pixel: (u32, u32), index: u32, dim: u32) -> f64;
+    fn get_2d(&self, pixel: (u32, u32), index: u32, dim: u32) -> (f64, f64) {
+        (
+            self.get_1d(pixel, index, dim),
+            self.get_1d(pixel, index, dim + 1),
+        )
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum SamplerKind {
+    Random,
+    Stratified,
+    Halton,
+    Sobol,
+    BlueNoise,
+}
+
+pub const SAMPLERS: [&str; 5] = ["random", "stratified", "halton", "sobol", "blue-noise"];
+
+impl FromStr for SamplerKind {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "random" | "independent" => Ok(SamplerKind::Random),
+            "stratified" | "jittered" => Ok(SamplerKind::Stratified),
+            "halton" => Ok(SamplerKind::Halton),
+            "sobol" => Ok(SamplerKind::Sobol),
+            "blue-noise" => Ok(SamplerKind::BlueNoise),
+            _ => Err(format!(
+                "unknown sampler `{}` (expected one of {})",
+                s,
+                SAMPLERS.join(", ")
+            )),
+        }
+    }
+}
+
+impl SamplerKind {
+    // `spp` is the most samples any pixel will take; only the stratified sampler needs it.
+    pub fn build(self, spp: u32, seed: u64) -> Arc<dyn Sampler> {
+        match self {
+            SamplerKind::Random => Arc::new(RandomSampler {}),
+            SamplerKind::Stratified => Arc::new(StratifiedSampler::new(spp, seed)),
+            SamplerKind::Halton => Arc::new(HaltonSampler::new(seed)),
+            SamplerKind::Sobol => Arc::new(SobolSampler::new(seed)),
+            SamplerKind::BlueNoise => Arc::new(BlueNoiseSampler::new(seed)),
+        }
+    }
+}
+
+// The sampler and position of the sample the calling thread is tracing. Every call to
+// `sample_1d`/`sample_2d` takes the next dimension(s): the camera uses 0-4 (pixel, lens,
+// time), then each bounce takes what its BSDF and light sampling ask for, in order.
+struct Cursor {
+    sampler: Option<Arc<dyn Sampler>>,
+    pixel: (u32, u32),
+    index: u32,
+    dim: u32,
+}
+
+thread_local! {
+    static CURSOR: RefCell<Cursor> = RefCell::new(Cursor {
+        sampler: None,
+        pixel: (0, 0),
+        index: 0,
+        dim: 0,
+    });
+}
+
+pub fn set_sampler(sampler: Arc<dyn Sampler>) {
+    CURSOR.with(|c| c.borrow_mut().sampler = Some(sampler));
+}
+
+// Starts sample `index` of pixel (x, y); the random number generator is reseeded too,
+// for the code that still draws independent randoms.
+pub fn start_pixel_sample(x: u32, y: u32, index: u32) {
+    seed_pixel_sample((y as u64) << 32 | x as u64, index as u64);
+    CURSOR.with(|c| {
+        let mut c = c.borrow_mut();
+        c.pixel = (x, y);
+        c.index = index;
+        c.dim = 0;
+    });
+}
+
+// Outside of a render (scene setup, no sampler installed) these are plain random numbers.
+pub fn sample_1d() -> f64 {
+    CURSOR.with(|c| {
+        let mut c = c.borrow_mut();
+        let dim = c.dim;
+        c.dim += 1;
+        match &c.sampler {
+            Some(s) => s.get_1d(c.pixel, c.index, dim),
+            None => random_double(),
+        }
+    })
+}
+
+pub fn sample_2d() -> (f64, f64) {
+    CURSOR.with(|c| {
+        let mut c = c.borrow_mut();
+        let dim = c.dim;
+        c.dim += 2;
+        match &c.sampler {
+            Some(s) => s.get_2d(c.pixel, c.index, dim),
+            None => (random_double(), random_double()),
+        }
+    })
+}
+
+fn hash(a: u64, b: u64, c: u64) -> u32 {
+    (mix(a ^ mix(b ^ mix(c))) >> 32) as u32
+}
+
+fn pixel_key(pixel: (u32, u32)) -> u64 {
+    (pixel.1 as u64) << 32 | pixel.0 as u64
+}
+
+fn to_unit(x: u32) -> f64 {
+    x as f64 / 4_294_967_296.0
+}
+
+// Independent uniform randoms, what every sample used before.
+pub struct RandomSampler {}
+
+impl Sampler for RandomSampler {
+    fn get_1d(&self, _pixel: (u32, u32), _index: u32, _dim: u32) -> f64 {
+        random_double()
+    }
+}
+
+// Jittered strata: 1D dimensions split 0..1 into `spp` strata, 2D ones use correlated
+// multi-jittering (Kensler 2013), which is also stratified along both axes. Each pixel and
+// dimension visits its strata in its own random order.
+pub struct StratifiedSampler {
+    pub spp: u32,
+    pub seed: u64,
+}
+
+impl StratifiedSampler {
+    pub fn new(spp: u32, seed: u64) -> Self {
+        Self {
+            spp: spp.max(1),
+            seed,
+        }
+    }
+}
+
+impl Sampler for StratifiedSampler {
+    fn get_1d(&self, pixel: (u32, u32), index: u32, dim: u32) -> f64 {
+        let p = hash(self.seed, pixel_key(pixel), dim as u64);
+        let n = self.spp;
+        let stratum = permute(index % n, n, p);
+        (stratum as f64 + rand_float(index, p ^ 0xa399_d265)) / n as f64
+    }
+
+    fn get_2d(&self, pixel: (u32, u32), index: u32, dim: u32) -> (f64, f64) {
+        let p = hash(self.seed, pixel_key(pixel), dim as u64);
+        let n = self.spp;
+        let m = ((n as f64).sqrt() as u32).max(1);
+        let rows = (n - 1) / m + 1;
+        let s = permute(index % n, n, p.wrapping_mul(0x5163_3e2d));
+        let sx = permute(s % m, m, p.wrapping_mul(0xa511_e9b3));
+        let sy = permute(s / m, rows, p.wrapping_mul(0x63d8_3595));
+        let jx = rand_float(s, p.wrapping_mul(0xa399_d265));
+        let jy = rand_float(s, p.wrapping_mul(0x711a_d6a5));
+        (
+            ((s % m) as f64 + (sy as f64 + jx) / rows as f64) / m as f64,
+            ((s / m) as f64 + (sx as f64 + jy) / m as f64) / rows as f64,
+        )
+    }
+}
+
+// Kensler's hashed permutation of 0..l, a different one for every `p`.
+fn permute(mut i: u32, l: u32, p: u32) -> u32 {
+    let mut w = l - 1;
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+    loop {
+        i ^= p;
+        i = i.wrapping_mul(0xe170_893d);
+        i ^= p >> 16;
+        i ^= (i & w) >> 4;
+        i ^= p >> 8;
+        i = i.wrapping_mul(0x0929_eb3f);
+        i ^= p >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | p >> 27);
+        i = i.wrapping_mul(0x6935_fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dc_b303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e50_1cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860_a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < l {
+            break;
+        }
+    }
+    (i.wrapping_add(p)) % l
+}
+
+fn rand_float(mut i: u32, p: u32) -> f64 {
+    i ^= p;
+    i ^= i >> 17;
+    i ^= i >> 10;
+    i = i.wrapping_mul(0xb365_34e5);
+    i ^= i >> 12;
+    i ^= i >> 21;
+    i = i.wrapping_mul(0x93fc_4795);
+    i ^= 0xdf6e_307f;
+    i ^= i >> 17;
+    i = i.wrapping_mul(1 | p >> 18);
+    to_unit(i)
+}
+
+const PRIMES: [u32; 64] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71, 73, 79, 83, 89, 97,
+    101, 103, 107, 109, 113, 127, 131, 137, 139, 149, 151, 157, 163, 167, 173, 179, 181, 191, 193,
+    197, 199, 211, 223, 227, 229, 233, 239, 241, 251, 257, 263, 269, 271, 277, 281, 283, 293, 307,
+    311,
+];
+
+// Dimension d is the radical inverse in the d-th prime base, with every digit position
+// randomly permuted per pixel. Dimensions past the prime table fall back to random numbers.
+pub struct HaltonSampler {
+    pub seed: u64,
+}
+
+impl HaltonSampler {
+    pub fn new(seed: u64) -> Self {
+        Self { seed }
+    }
+}
+
+impl Sampler for HaltonSampler {
+    fn get_1d(&self, pixel: (u32, u32), index: u32, dim: u32) -> f64 {
+        match PRIMES.get(dim as usize) {
+            Some(&base) => {
+                let p = hash(self.seed, pixel_key(pixel), dim as u64);
+                scrambled_radical_inverse(base, index, p)
+            }
+            None => random_double(),
+        }
+    }
+}
+
+fn scrambled_radical_inverse(base: u32, mut index: u32, p: u32) -> f64 {
+    let inv_base = 1.0 / base as f64;
+    let mut inv_base_m = 1.0;
+    let mut value = 0.0;
+    let mut digit_index = 0u32;
+    // keep going past the last nonzero digit, permuted zeros jitter the point inside its stratum
+    while inv_base_m > 1e-10 {
+        let digit = index % base;
+        index /= base;
+        inv_base_m *= inv_base;
+        let permuted = permute(digit, base, p ^ digit_index.wrapping_mul(0x9e37_79b9));
+        value += permuted as f64 * inv_base_m;
+        digit_index += 1;
+    }
+    value.min(1.0 - f64::EPSILON / 2.0)
+}
+
+// Owen-scrambled Sobol' points (Burley 2020, "Practical Hash-based Owen Scrambling"):
+// each pair of dimensions is the first two Sobol' dimensions, shuffled and scrambled with
+// its own per-pixel seed, which keeps the 2D stratification at every power of two.
+pub struct SobolSampler {
+    pub seed: u64,
+}
+
+impl SobolSampler {
+    pub fn new(seed: u64) -> Self {
+        Self { seed }
+    }
+}
+
+impl Sampler for SobolSampler {
+    fn get_1d(&self, pixel: (u32, u32), index: u32, dim: u32) -> f64 {
+        let seed = hash(self.seed, pixel_key(pixel), dim as u64);
+        to_unit(owen_sobol_1d(index, seed))
+    }
+
+    fn get_2d(&self, pixel: (u32, u32), index: u32, dim: u32) -> (f64, f64) {
+        let seed = hash(self.seed, pixel_key(pixel), dim as u64);
+        let (x, y) = owen_sobol_2d(index, seed);
+        (to_unit(x), to_unit(y))
+    }
+}
+
+fn sobol(index: u32, dim: u32) -> u32 {
+    if dim == 0 {
+        return index.reverse_bits();
+    }
+    // direction numbers of x + 1: v_k = v_(k-1) ^ (v_(k-1) >> 1)
+    let (mut x, mut v) = (0u32, 1u32 << 31);
+    let mut i = index;
+    while i != 0 {
+        if i & 1 != 0 {
+            x ^= v;
+        }
+        v ^= v >> 1;
+        i >>= 1;
+    }
+    x
+}
+
+fn laine_karras_permutation(mut x: u32, seed: u32) -> u32 {
+    x = x.wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50_b47c);
+    x ^= x.wrapping_mul(0xb82f_1e52);
+    x ^= x.wrapping_mul(0xc7af_e638);
+    x ^= x.wrapping_mul(0x8d22_f6e6);
+    x
+}
+
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+    laine_karras_permutation(x.reverse_bits(), seed).reverse_bits()
+}
+
+fn owen_sobol_1d(index: u32, seed: u32) -> u32 {
+    let index = nested_uniform_scramble(index, seed);
+    nested_uniform_scramble(sobol(index, 0), mix(seed as u64 ^ 1) as u32)
+}
+
+fn owen_sobol_2d(index: u32, seed: u32) -> (u32, u32) {
+    let index = nested_uniform_scramble(index, seed);
+    (
+        nested_uniform_scramble(sobol(index, 0), mix(seed as u64 ^ 1) as u32),
+        nested_uniform_scramble(sobol(index, 1), mix(seed as u64 ^ 2) as u32),
+    )
+}
+
+const TILE: usize = 64;
+
+// Blue-noise dithered sampling (Georgiev and Fajardo 2016): every pixel uses the same
+// scrambled Sobol' sequence, shifted toroidally by a blue-noise mask, so the leftover error
+// of neighbouring pixels is negatively correlated and reads as fine grain instead of blotches.
+pub struct BlueNoiseSampler {
+    pub seed: u64,
+    // TILE x TILE values in 0..1, each value present once
+    pub mask: Vec<f64>,
+}
+
+impl BlueNoiseSampler {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            mask: blue_noise_mask(seed),
+        }
+    }
+
+    // every dimension reads the mask at its own offset so the shifts aren't correlated
+    fn shift(&self, pixel: (u32, u32), dim: u32) -> f64 {
+        let h = hash(self.seed, 0x5eed, dim as u64) as usize;
+        let x = (pixel.0 as usize + h) % TILE;
+        let y = (pixel.1 as usize + (h >> 8)) % TILE;
+        self.mask[y * TILE + x]
+    }
+}
+
+impl Sampler for BlueNoiseSampler {
+    fn get_1d(&self, pixel: (u32, u32), index: u32, dim: u32) -> f64 {
+        let seed = hash(self.seed, 0, dim as u64);
+        (to_unit(owen_sobol_1d(index, seed)) + self.shift(pixel, dim)).fract()
+    }
+
+    fn get_2d(&self, pixel: (u32, u32), index: u32, dim: u32) -> (f64, f64) {
+        let seed = hash(self.seed, 0, dim as u64);
+        let (x, y) = owen_sobol_2d(index, seed);
+        (
+            (to_unit(x) + self.shift(pixel, dim)).fract(),
+            (to_unit(y) + self.shift(pixel, dim + 1)).fract(),
+        )
+    }
+}
+
+// Void-and-cluster (Ulichney 1993) on a torus: ranks every cell so that the first k of
+// them are spread as evenly as possible for every k.
+fn blue_noise_mask(seed: u64) -> Vec<f64> {
+    const RADIUS: i64 = 8;
+    let n = TILE * TILE;
+    let sigma: f64 = 1.5;
+    let splat = |energy: &mut Vec<f64>, p: usize, sign: f64| {
+        let (px, py) = ((p % TILE) as i64, (p / TILE) as i64);
+        for dy in -RADIUS..=RADIUS {
+            for dx in -RADIUS..=RADIUS {
+                let x = (px + dx).rem_euclid(TILE as i64) as usize;
+                let y = (py + dy).rem_euclid(TILE as i64) as usize;
+                let w = (-((dx * dx + dy * dy) as f64) / (2.0 * sigma * sigma)).exp();
+                energy[y * TILE + x] += sign * w;
+            }
+        }
+    };
+    // the tightest cluster is the set cell with the most energy, the largest void the empty
+    // cell with the least
+    let tightest = |pattern: &[bool], energy: &[f64]| {
+        (0..n)
+            .filter(|&i| pattern[i])
+            .fold((0, f64::NEG_INFINITY), |best, i| {
+                if energy[i] > best.1 {
+                    (i, energy[i])
+                } else {
+                    best
+                }
+            })
+            .0
+    };
+    let largest_void = |pattern: &[bool], energy: &[f64]| {
+        (0..n)
+            .filter(|&i| !pattern[i])
+            .fold((0, f64::INFINITY), |best, i| {
+                if energy[i] < best.1 {
+                    (i, energy[i])
+                } else {
+                    best
+                }
+            })
+            .0
+    };
+
+    // a random tenth of the cells, relaxed by moving the tightest point into the largest void
+    let mut rng = Pcg32::new(mix(seed), 0x0b1e);
+    let mut pattern = vec![false; n];
+    let mut energy = vec![0.0; n];
+    let mut ones = 0;
+    while ones < n / 10 {
+        let p = rng.next_u32() as usize % n;
+        if !pattern[p] {
+            pattern[p] = true;
+            splat(&mut energy, p, 1.0);
+            ones += 1;
+        }
+    }
+    for _ in 0..n {
+        let cluster = tightest(&pattern, &energy);
+        pattern[cluster] = false;
+        splat(&mut energy, cluster, -1.0);
+        let void = largest_void(&pattern, &energy);
+        pattern[void] = true;
+        splat(&mut energy, void, 1.0);
+        if void == cluster {
+            break;
+        }
+    }
+
+    let mut rank = vec![0; n];
+    let (mut removing, mut removing_energy) = (pattern.clone(), energy.clone());
+    for r in (0..ones).rev() {
+        let cluster = tightest(&removing, &removing_energy);
+        removing[cluster] = false;
+        splat(&mut removing_energy, cluster, -1.0);
+        rank[cluster] = r;
+    }
+    for r in ones..n {
+        let void = largest_void(&pattern, &energy);
+        pattern[void] = true;
+        splat(&mut energy, void, 1.0);
+        rank[void] = r;
+    }
+    rank.iter().map(|&r| (r as f64 + 0.5) / n as f64).collect()
+}
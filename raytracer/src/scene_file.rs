@@ -0,0 +1,937 @@
+use crate::aarect::{Triangle, XyRect, XzRect, YzRect};
+use crate::constant_medium::ConstantMedium;
+use crate::envmap::EnvironmentMap;
+use crate::hit::{FlipFace, Hittable, HittableList, Ids, RotateY, Sphere, Translate};
+use crate::light::{DirectionalLight, Light, Lights, PointLight, SpotLight};
+use crate::material::{
+    Conductor, Dielectric, DiffuseLight, Isotropiuc, Lambertian, Material, Metal, NoMaterial,
+    Principled, RoughDielectric,
+};
+use crate::medium::{Density, GridDensity, HeterogeneousMedium, NoiseDensity};
+use crate::moving_sphere::MovingSphere;
+use crate::obj::ObjModel;
+use crate::sky::Sky;
+use crate::spectrum::{Dispersion, D_LINE};
+use crate::texture::{CheckerTexture, ImageTexture, NoiseTexture, SolidColor, Texture};
+use crate::Boxe::Boxes;
+use crate::Vec3;
+use crate::AABB::Aabb;
+use crate::BVH::BvhNode;
+pub use raytracer_scene::CameraDesc;
+use raytracer_scene::{
+    Color, DensityDesc, DispersionDesc, MaterialDesc, ObjectDesc, Param, SceneDesc, TextureDesc,
+};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+// The format is described in `raytracer_scene`, shared with `static_scene!`.
+
+pub struct SceneFile {
+    pub world: HittableList,
+    pub lights: Lights,
+    pub background: Vec3,
+    pub camera: CameraDesc,
+}
+
+#[derive(Debug)]
+pub struct SceneError {
+    pub file: String,
+    pub line: usize,
+    pub column: usize,
+    pub msg: String,
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.line > 0 {
+            write!(
+                f,
+                "{}:{}:{}: {}",
+                self.file, self.line, self.column, self.msg
+            )
+        } else {
+            write!(f, "{}: {}", self.file, self.msg)
+        }
+    }
+}
+
+pub fn load(filename: &str) -> Result<SceneFile, SceneError> {
+    let error = |line: usize, column: usize, msg: String| SceneError {
+        file: filename.to_string(),
+        line,
+        column,
+        msg,
+    };
+    let text = fs::read_to_string(filename).map_err(|e| error(0, 0, e.to_string()))?;
+    let desc =
+        raytracer_scene::parse(&text).map_err(|(line, column, msg)| error(line, column, msg))?;
+
+    let base_dir = Path::new(filename)
+        .parent()
+        .map(Path::to_path_buf)
+        .unwrap_or_default();
+    let mut builder = Builder {
+        base_dir,
+        textures: BTreeMap::new(),
+        materials: BTreeMap::new(),
+        ids: Ids::default(),
+    };
+    builder.build(desc).map_err(|msg| error(0, 0, msg))
+}
+
+struct Builder {
+    base_dir: PathBuf,
+    textures: BTreeMap<String, Arc<dyn Texture>>,
+    materials: BTreeMap<String, Arc<dyn Material>>,
+    // numbers each shape of the world for the id pass
+    ids: Ids,
+}
+
+fn vec3(v: [f64; 3]) -> Vec3 {
+    Vec3::new(v[0], v[1], v[2])
+}
+
+fn index_of_refraction(
+    ior: &Option<f64>,
+    dispersion: &Option<DispersionDesc>,
+    path: &str,
+) -> Result<(f64, Option<Dispersion>), String> {
+    let dispersion = match (ior, dispersion) {
+        (Some(ior), None) => return Ok((*ior, None)),
+        (None, Some(DispersionDesc::Cauchy { a, b })) => Dispersion::Cauchy { a: *a, b: *b },
+        (None, Some(DispersionDesc::Sellmeier { b, c })) => Dispersion::Sellmeier { b: *b, c: *c },
+        (Some(_), Some(_)) => {
+            return Err(format!(
+                "{}: `ior` and `dispersion` are mutually exclusive",
+                path
+            ))
+        }
+        (None, None) => return Err(format!("{}: expected either `ior` or `dispersion`", path)),
+    };
+    Ok((dispersion.ior(D_LINE), Some(dispersion)))
+}
+
+// The first part of a light's shape that can't be sampled, if any, as its path and type.
+fn unsamplable(desc: &ObjectDesc, path: &str) -> Option<(String, &'static str)> {
+    match desc {
+        ObjectDesc::Sphere { .. }
+        | ObjectDesc::XyRect { .. }
+        | ObjectDesc::XzRect { .. }
+        | ObjectDesc::YzRect { .. }
+        | ObjectDesc::Triangle { .. }
+        | ObjectDesc::Obj { .. } => None,
+        ObjectDesc::Translate { object, .. }
+        | ObjectDesc::RotateY { object, .. }
+        | ObjectDesc::FlipFace { object } => unsamplable(object, &format!("{}.object", path)),
+        ObjectDesc::List { objects } | ObjectDesc::Bvh { objects } => objects
+            .iter()
+            .enumerate()
+            .find_map(|(i, object)| unsamplable(object, &format!("{}.objects[{}]", path, i))),
+        ObjectDesc::MovingSphere { .. } => Some((path.to_string(), "moving_sphere")),
+        ObjectDesc::Box { .. } => Some((path.to_string(), "box")),
+        ObjectDesc::ConstantMedium { .. } => Some((path.to_string(), "constant_medium")),
+        ObjectDesc::HeterogeneousMedium { .. } => Some((path.to_string(), "heterogeneous_medium")),
+        // not geometry, `light` takes them
+        ObjectDesc::PointLight { .. }
+        | ObjectDesc::SpotLight { .. }
+        | ObjectDesc::DirectionalLight { .. }
+        | ObjectDesc::EnvironmentMap { .. }
+        | ObjectDesc::Sky { .. } => None,
+    }
+}
+
+// The light `desc` describes if it's one that isn't geometry, None for the others.
+fn light(desc: &ObjectDesc, path: &str) -> Result<Option<Arc<dyn Light>>, String> {
+    let color = |c: &Color, name: &str| {
+        if c.iter().any(|v| *v < 0.0 || !v.is_finite()) {
+            return Err(format!("{}: `{}` must not be negative", path, name));
+        }
+        Ok(vec3(*c))
+    };
+    let direction = |d: &[f64; 3]| {
+        if vec3(*d).length_squared() <= 0.0 {
+            return Err(format!("{}: `direction` must not be zero", path));
+        }
+        Ok(vec3(*d))
+    };
+    Ok(Some(match desc {
+        ObjectDesc::PointLight {
+            position,
+            intensity,
+        } => Arc::new(PointLight::new(
+            vec3(*position),
+            color(intensity, "intensity")?,
+        )),
+        ObjectDesc::SpotLight {
+            position,
+            direction: d,
+            intensity,
+            angle,
+            falloff_start,
+            profile,
+        } => {
+            if !(*angle > 0.0 && *angle <= 180.0) {
+                return Err(format!("{}: `angle` must be in (0, 180]", path));
+            }
+            let mut spot = SpotLight::new(
+                vec3(*position),
+                direction(d)?,
+                color(intensity, "intensity")?,
+                *angle,
+                falloff_start.unwrap_or(*angle),
+            );
+            match (falloff_start, profile) {
+                (Some(_), Some(_)) => {
+                    return Err(format!(
+                        "{}: `falloff_start` and `profile` are mutually exclusive",
+                        path
+                    ))
+                }
+                (Some(start), None) if !(*start >= 0.0 && start <= angle) => {
+                    return Err(format!("{}: `falloff_start` must be in [0, angle]", path))
+                }
+                (None, Some(profile)) => {
+                    if profile.is_empty() || profile.iter().any(|v| *v < 0.0 || !v.is_finite()) {
+                        return Err(format!(
+                            "{}: `profile` needs values that aren't negative",
+                            path
+                        ));
+                    }
+                    spot.profile = Some(profile.clone());
+                }
+                _ => {}
+            }
+            Arc::new(spot)
+        }
+        ObjectDesc::DirectionalLight {
+            direction: d,
+            irradiance,
+            angular_radius,
+        } => {
+            if !(*angular_radius >= 0.0 && *angular_radius < 90.0) {
+                return Err(format!("{}: `angular_radius` must be in [0, 90)", path));
+            }
+            Arc::new(DirectionalLight::new(
+                direction(d)?,
+                color(irradiance, "irradiance")?,
+                *angular_radius,
+            ))
+        }
+        _ => return Ok(None),
+    }))
+}
+
+impl Builder {
+    fn build(&mut self, desc: SceneDesc) -> Result<SceneFile, String> {
+        for (name, tex) in desc.textures.iter() {
+            let texture = self.texture(tex, &format!("textures.{}", name))?;
+            self.textures.insert(name.clone(), texture);
+        }
+        for (name, mat) in desc.materials.iter() {
+            let material = self.material(mat, &format!("materials.{}", name))?;
+            self.materials.insert(name.clone(), material);
+        }
+
+        let mut world = HittableList::new();
+        for (i, object) in desc.objects.iter().enumerate() {
+            world.add(self.object(object, &format!("objects[{}]", i), false)?);
+        }
+        let mut lights = Lights::default();
+        for (i, object) in desc.lights.iter().enumerate() {
+            let path = format!("lights[{}]", i);
+            if let ObjectDesc::EnvironmentMap {
+                file,
+                rotation,
+                intensity,
+            } = object
+            {
+                if !lights.distant.is_empty() {
+                    return Err(format!(
+                        "{}: there can only be one environment map or sky",
+                        path
+                    ));
+                }
+                if desc.background.is_some() {
+                    return Err(format!(
+                        "{}: an environment map replaces `background`, drop one of them",
+                        path
+                    ));
+                }
+                if *intensity < 0.0 || !intensity.is_finite() {
+                    return Err(format!("{}: `intensity` must not be negative", path));
+                }
+                let full = self.base_dir.join(file);
+                if !full.is_file() {
+                    return Err(format!("{}: image `{}` not found", path, full.display()));
+                }
+                let env = EnvironmentMap::load(&full.to_string_lossy(), *rotation, *intensity)
+                    .map_err(|e| format!("{}: {}", path, e))?;
+                lights.distant.push(Arc::new(env));
+                continue;
+            }
+            if let ObjectDesc::Sky {
+                sun_elevation,
+                sun_azimuth,
+                turbidity,
+                ground_albedo,
+                intensity,
+                sun,
+            } = object
+            {
+                if !lights.distant.is_empty() {
+                    return Err(format!(
+                        "{}: there can only be one environment map or sky",
+                        path
+                    ));
+                }
+                if desc.background.is_some() {
+                    return Err(format!(
+                        "{}: a sky replaces `background`, drop one of them",
+                        path
+                    ));
+                }
+                if !(*sun_elevation >= 0.0 && *sun_elevation <= 90.0) {
+                    return Err(format!("{}: `sun_elevation` must be in [0, 90]", path));
+                }
+                if !(*turbidity >= 1.7 && *turbidity <= 10.0) {
+                    return Err(format!("{}: `turbidity` must be in [1.7, 10]", path));
+                }
+                if ground_albedo.iter().any(|v| !(*v >= 0.0 && *v <= 1.0)) {
+                    return Err(format!("{}: `ground_albedo` must be in [0, 1]", path));
+                }
+                if *intensity < 0.0 || !intensity.is_finite() {
+                    return Err(format!("{}: `intensity` must not be negative", path));
+                }
+                let sky = Sky::new(
+                    *sun_elevation,
+                    *sun_azimuth,
+                    *turbidity,
+                    vec3(*ground_albedo),
+                    *intensity,
+                );
+                if *sun {
+                    lights.distant.push(Arc::new(sky.sun()));
+                }
+                lights.distant.push(Arc::new(sky));
+                continue;
+            }
+            if let Some((path, kind)) = unsamplable(object, &path) {
+                return Err(format!(
+                    "{}: a `{}` can't be sampled as a light, describe it with rects or triangles",
+                    path, kind
+                ));
+            }
+            if let Some(emitters) = self.emitters(object, &path)? {
+                for emitter in emitters {
+                    lights.area.add(emitter);
+                }
+                continue;
+            }
+            match light(object, &path)? {
+                Some(light) => lights.delta.push(light),
+                None => lights.area.add(self.object(object, &path, true)?),
+            }
+        }
+        Ok(SceneFile {
+            world,
+            lights,
+            background: vec3(desc.background.unwrap_or_default()),
+            camera: desc.camera,
+        })
+    }
+
+    fn texture(&self, desc: &TextureDesc, path: &str) -> Result<Arc<dyn Texture>, String> {
+        Ok(match desc {
+            TextureDesc::Solid { color } => Arc::new(SolidColor::new(vec3(*color))),
+            TextureDesc::Checker { even, odd } => {
+                Arc::new(CheckerTexture::new(vec3(*even), vec3(*odd)))
+            }
+            TextureDesc::Noise { scale } => Arc::new(NoiseTexture::new0(*scale)),
+            TextureDesc::Image { file } => {
+                let full = self.base_dir.join(file);
+                if !full.is_file() {
+                    return Err(format!("{}: image `{}` not found", path, full.display()));
+                }
+                Arc::new(ImageTexture::new(&full.to_string_lossy()))
+            }
+        })
+    }
+
+    // Exactly one of an inline color or a named texture must be given.
+    fn color_or_texture(
+        &self,
+        color: &Option<Color>,
+        texture: &Option<String>,
+        color_field: &str,
+        path: &str,
+    ) -> Result<Arc<dyn Texture>, String> {
+        match (color, texture) {
+            (Some(c), None) => Ok(Arc::new(SolidColor::new(vec3(*c)))),
+            (None, Some(name)) => self
+                .textures
+                .get(name)
+                .cloned()
+                .ok_or_else(|| format!("{}: unknown texture `{}`", path, name)),
+            (Some(_), Some(_)) => Err(format!(
+                "{}: `{}` and `texture` are mutually exclusive",
+                path, color_field
+            )),
+            (None, None) => Err(format!(
+                "{}: expected either `{}` or `texture`",
+                path, color_field
+            )),
+        }
+    }
+
+    fn density(
+        &self,
+        desc: &DensityDesc,
+        boundary: &dyn Hittable,
+        path: &str,
+    ) -> Result<Arc<dyn Density>, String> {
+        Ok(match desc {
+            DensityDesc::Grid {
+                resolution,
+                data,
+                file,
+            } => {
+                let mut bounds = Aabb::new(Vec3::zero(), Vec3::zero());
+                if !boundary.bounding_box(0.0, 1.0, &mut bounds) {
+                    return Err(format!(
+                        "{}: the boundary has no bounding box to span",
+                        path
+                    ));
+                }
+                let grid = match (resolution, data, file) {
+                    (Some(resolution), Some(data), None) => {
+                        GridDensity::new(*resolution, data.clone(), bounds)
+                    }
+                    (None, None, Some(file)) => {
+                        let full = self.base_dir.join(file);
+                        if !full.is_file() {
+                            return Err(format!("{}: grid `{}` not found", path, full.display()));
+                        }
+                        GridDensity::from_vol(&full.to_string_lossy(), bounds)
+                    }
+                    (_, Some(_), Some(_)) => {
+                        Err(String::from("`data` and `file` are mutually exclusive"))
+                    }
+                    (None, Some(_), None) => Err(String::from("`data` needs `resolution`")),
+                    (Some(_), None, Some(_)) => {
+                        Err(String::from("a `file` grid has its own `resolution`"))
+                    }
+                    (_, None, None) => Err(String::from("expected either `data` or `file`")),
+                };
+                Arc::new(grid.map_err(|e| format!("{}: {}", path, e))?)
+            }
+            DensityDesc::Noise {
+                frequency,
+                octaves,
+                density,
+            } => {
+                if *density <= 0.0 || *octaves < 1 {
+                    return Err(format!(
+                        "{}: `density` and `octaves` must be positive",
+                        path
+                    ));
+                }
+                Arc::new(NoiseDensity::new(*frequency, *octaves, *density))
+            }
+        })
+    }
+
+    fn param(
+        &self,
+        param: &Option<Param>,
+        default: &Arc<dyn Texture>,
+        path: &str,
+    ) -> Result<Arc<dyn Texture>, String> {
+        Ok(match param {
+            None => default.clone(),
+            Some(Param::Value(x)) => Arc::new(SolidColor::new(Vec3::ones() * *x)),
+            Some(Param::Color(c)) => Arc::new(SolidColor::new(vec3(*c))),
+            Some(Param::Texture(name)) => self
+                .textures
+                .get(name)
+                .cloned()
+                .ok_or_else(|| format!("{}: unknown texture `{}`", path, name))?,
+        })
+    }
+
+    fn material(&self, desc: &MaterialDesc, path: &str) -> Result<Arc<dyn Material>, String> {
+        Ok(match desc {
+            MaterialDesc::Lambertian { albedo, texture } => Arc::new(Lambertian::news(
+                self.color_or_texture(albedo, texture, "albedo", path)?,
+            )),
+            MaterialDesc::Metal { albedo, fuzz } => Arc::new(Metal::news(vec3(*albedo), *fuzz)),
+            MaterialDesc::Dielectric { ior, dispersion } => {
+                let (ref_idx, dispersion) = index_of_refraction(ior, dispersion, path)?;
+                Arc::new(Dielectric {
+                    ref_idx,
+                    dispersion,
+                })
+            }
+            MaterialDesc::Conductor {
+                eta,
+                k,
+                roughness,
+                anisotropy,
+            } => Arc::new(Conductor::new(
+                vec3(*eta),
+                vec3(*k),
+                *roughness,
+                *anisotropy,
+            )),
+            MaterialDesc::RoughDielectric {
+                ior,
+                dispersion,
+                roughness,
+                anisotropy,
+            } => {
+                let (ref_idx, dispersion) = index_of_refraction(ior, dispersion, path)?;
+                Arc::new(RoughDielectric {
+                    ref_idx,
+                    dispersion,
+                    roughness: *roughness,
+                    anisotropy: *anisotropy,
+                })
+            }
+            MaterialDesc::Principled(p) => {
+                let d = Principled::default();
+                Arc::new(Principled {
+                    base_color: self.param(&p.base_color, &d.base_color, path)?,
+                    metallic: self.param(&p.metallic, &d.metallic, path)?,
+                    roughness: self.param(&p.roughness, &d.roughness, path)?,
+                    specular: self.param(&p.specular, &d.specular, path)?,
+                    specular_tint: self.param(&p.specular_tint, &d.specular_tint, path)?,
+                    sheen: self.param(&p.sheen, &d.sheen, path)?,
+                    sheen_tint: self.param(&p.sheen_tint, &d.sheen_tint, path)?,
+                    clearcoat: self.param(&p.clearcoat, &d.clearcoat, path)?,
+                    clearcoat_gloss: self.param(&p.clearcoat_gloss, &d.clearcoat_gloss, path)?,
+                    transmission: self.param(&p.transmission, &d.transmission, path)?,
+                    anisotropy: self.param(&p.anisotropy, &d.anisotropy, path)?,
+                })
+            }
+            MaterialDesc::DiffuseLight { emit, texture } => Arc::new(DiffuseLight::new(
+                self.color_or_texture(emit, texture, "emit", path)?,
+            )),
+            MaterialDesc::Isotropic { albedo, texture } => Arc::new(Isotropiuc::new0(
+                self.color_or_texture(albedo, texture, "albedo", path)?,
+            )),
+        })
+    }
+
+    // Light entries and medium boundaries never shade anything, so their material may be omitted.
+    fn material_ref(
+        &self,
+        name: &Option<String>,
+        path: &str,
+        optional: bool,
+    ) -> Result<Arc<dyn Material>, String> {
+        match name {
+            Some(name) => self
+                .materials
+                .get(name)
+                .cloned()
+                .ok_or_else(|| format!("{}: unknown material `{}`", path, name)),
+            None if optional => Ok(Arc::new(NoMaterial {})),
+            None => Err(format!("{}: missing field `material`", path)),
+        }
+    }
+
+    fn list(
+        &self,
+        objects: &[ObjectDesc],
+        path: &str,
+        optional: bool,
+    ) -> Result<HittableList, String> {
+        let mut list = HittableList::new();
+        for (i, object) in objects.iter().enumerate() {
+            list.add(self.object(object, &format!("{}.objects[{}]", path, i), optional)?);
+        }
+        Ok(list)
+    }
+
+    fn obj(
+        &self,
+        file: &str,
+        scale: f64,
+        material: &Option<String>,
+        path: &str,
+        optional: bool,
+    ) -> Result<ObjModel, String> {
+        if !(scale > 0.0 && scale.is_finite()) {
+            return Err(format!("{}: `scale` must be positive", path));
+        }
+        let full = self.base_dir.join(file);
+        if !full.is_file() {
+            return Err(format!("{}: model `{}` not found", path, full.display()));
+        }
+        let fallback = match material {
+            Some(_) => Some(self.material_ref(material, path, optional)?),
+            None if optional => Some(Arc::new(NoMaterial {}) as Arc<dyn Material>),
+            None => None,
+        };
+        // light shapes and boundaries aren't seen by the id pass, so they don't take numbers
+        let unseen = Ids::default();
+        let ids = if optional { &unseen } else { &self.ids };
+        ObjModel::load(&full.to_string_lossy(), scale, fallback.as_ref(), ids)
+            .map_err(|e| format!("{}: {}", path, e))
+    }
+
+    // The glowing triangles of an OBJ model in "lights", each a light of its own so they are
+    // picked by their own power and distance; None when `desc` isn't a model, or a moved one.
+    fn emitters(
+        &self,
+        desc: &ObjectDesc,
+        path: &str,
+    ) -> Result<Option<Vec<Arc<dyn Hittable>>>, String> {
+        let inner = |object: &ObjectDesc| self.emitters(object, &format!("{}.object", path));
+        let emitters = match desc {
+            ObjectDesc::Obj { file, scale, .. } => {
+                let model = self.obj(file, *scale, &None, path, true)?;
+                if model.emitters.is_empty() {
+                    return Err(format!(
+                        "{}: `{}` has no material with a `Ke` emission",
+                        path, file
+                    ));
+                }
+                model.emitters
+            }
+            ObjectDesc::Translate { offset, object } => match inner(object)? {
+                Some(emitters) => emitters
+                    .into_iter()
+                    .map(|e| Arc::new(Translate::new(e, vec3(*offset))) as Arc<dyn Hittable>)
+                    .collect(),
+                None => return Ok(None),
+            },
+            ObjectDesc::RotateY { angle, object } => match inner(object)? {
+                Some(emitters) => emitters
+                    .into_iter()
+                    .map(|e| Arc::new(RotateY::new(e, *angle)) as Arc<dyn Hittable>)
+                    .collect(),
+                None => return Ok(None),
+            },
+            ObjectDesc::FlipFace { object } => match inner(object)? {
+                Some(emitters) => emitters
+                    .into_iter()
+                    .map(|e| Arc::new(FlipFace::new(e)) as Arc<dyn Hittable>)
+                    .collect(),
+                None => return Ok(None),
+            },
+            _ => return Ok(None),
+        };
+        Ok(Some(emitters))
+    }
+
+    fn object(
+        &self,
+        desc: &ObjectDesc,
+        path: &str,
+        optional: bool,
+    ) -> Result<Arc<dyn Hittable>, String> {
+        let object: Arc<dyn Hittable> = match desc {
+            ObjectDesc::Sphere {
+                center,
+                radius,
+                material,
+            } => Arc::new(Sphere::new(
+                vec3(*center),
+                *radius,
+                self.material_ref(material, path, optional)?,
+            )),
+            ObjectDesc::MovingSphere {
+                center0,
+                center1,
+                time0,
+                time1,
+                radius,
+                material,
+            } => {
+                if time0 >= time1 {
+                    return Err(format!("{}: `time0` must be less than `time1`", path));
+                }
+                Arc::new(MovingSphere::new(
+                    vec3(*center0),
+                    vec3(*center1),
+                    *time0,
+                    *time1,
+                    *radius,
+                    self.material_ref(material, path, optional)?,
+                ))
+            }
+            ObjectDesc::XyRect {
+                x0,
+                x1,
+                y0,
+                y1,
+                k,
+                material,
+            } => Arc::new(XyRect::new(
+                *x0,
+                *x1,
+                *y0,
+                *y1,
+                *k,
+                self.material_ref(material, path, optional)?,
+            )),
+            ObjectDesc::XzRect {
+                x0,
+                x1,
+                z0,
+                z1,
+                k,
+                material,
+            } => Arc::new(XzRect::new(
+                *x0,
+                *x1,
+                *z0,
+                *z1,
+                *k,
+                self.material_ref(material, path, optional)?,
+            )),
+            ObjectDesc::YzRect {
+                y0,
+                y1,
+                z0,
+                z1,
+                k,
+                material,
+            } => Arc::new(YzRect::new(
+                *y0,
+                *y1,
+                *z0,
+                *z1,
+                *k,
+                self.material_ref(material, path, optional)?,
+            )),
+            ObjectDesc::Triangle {
+                p0,
+                p1,
+                p2,
+                material,
+            } => Arc::new(Triangle::new(
+                vec3(*p0),
+                vec3(*p1),
+                vec3(*p2),
+                self.material_ref(material, path, optional)?,
+            )),
+            ObjectDesc::Obj {
+                file,
+                scale,
+                material,
+            } => Arc::new(self.obj(file, *scale, material, path, optional)?.meshes),
+            ObjectDesc::Box { min, max, material } => Arc::new(Boxes::new(
+                vec3(*min),
+                vec3(*max),
+                self.material_ref(material, path, optional)?,
+            )),
+            ObjectDesc::ConstantMedium {
+                boundary,
+                density,
+                albedo,
+                texture,
+            } => {
+                if *density <= 0.0 {
+                    return Err(format!("{}: `density` must be positive", path));
+                }
+                let boundary = self.object(boundary, &format!("{}.boundary", path), true)?;
+                let albedo = self.color_or_texture(albedo, texture, "albedo", path)?;
+                Arc::new(ConstantMedium::<
+                    Arc<dyn Hittable>,
+                    Isotropiuc<Arc<dyn Texture>>,
+                >::new0(boundary, *density, albedo))
+            }
+            ObjectDesc::HeterogeneousMedium {
+                boundary,
+                density,
+                sigma_a,
+                sigma_s,
+                emission,
+            } => {
+                let values = sigma_a.iter().chain(sigma_s).chain(emission);
+                if values.clone().any(|v| *v < 0.0 || !v.is_finite()) {
+                    return Err(format!(
+                        "{}: `sigma_a`, `sigma_s` and `emission` must not be negative",
+                        path
+                    ));
+                }
+                if sigma_a.iter().chain(sigma_s).all(|v| *v == 0.0) {
+                    return Err(format!("{}: a medium needs `sigma_a` or `sigma_s`", path));
+                }
+                let boundary = self.object(boundary, &format!("{}.boundary", path), true)?;
+                let density = self.density(density, &boundary, &format!("{}.density", path))?;
+                Arc::new(HeterogeneousMedium::new(
+                    boundary,
+                    density,
+                    vec3(*sigma_a),
+                    vec3(*sigma_s),
+                    vec3(*emission),
+                ))
+            }
+            ObjectDesc::Translate { offset, object } => Arc::new(Translate::new(
+                self.object(object, &format!("{}.object", path), optional)?,
+                vec3(*offset),
+            )),
+            ObjectDesc::RotateY { angle, object } => Arc::new(RotateY::new(
+                self.object(object, &format!("{}.object", path), optional)?,
+                *angle,
+            )),
+            ObjectDesc::FlipFace { object } => Arc::new(FlipFace::new(self.object(
+                object,
+                &format!("{}.object", path),
+                optional,
+            )?)),
+            ObjectDesc::PointLight { .. }
+            | ObjectDesc::SpotLight { .. }
+            | ObjectDesc::DirectionalLight { .. }
+            | ObjectDesc::EnvironmentMap { .. }
+            | ObjectDesc::Sky { .. } => {
+                return Err(format!(
+                    "{}: lights that aren't geometry go in `lights`",
+                    path
+                ))
+            }
+            ObjectDesc::List { objects } => Arc::new(self.list(objects, path, optional)?),
+            ObjectDesc::Bvh { objects } => {
+                if objects.is_empty() {
+                    return Err(format!("{}: a bvh needs at least one object", path));
+                }
+                Arc::new(BvhNode::new(self.list(objects, path, optional)?, 0.0, 1.0))
+            }
+        };
+        Ok(match desc {
+            // these number what they hold
+            ObjectDesc::Obj { .. }
+            | ObjectDesc::Translate { .. }
+            | ObjectDesc::RotateY { .. }
+            | ObjectDesc::FlipFace { .. }
+            | ObjectDesc::List { .. }
+            | ObjectDesc::Bvh { .. } => object,
+            _ if optional => object,
+            _ => Arc::new(self.ids.tag(object)),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Ray;
+
+    fn build(text: &str) -> Result<SceneFile, String> {
+        let desc = raytracer_scene::parse(text).map_err(|(_, _, msg)| msg)?;
+        let mut builder = Builder {
+            base_dir: PathBuf::from("."),
+            textures: BTreeMap::new(),
+            materials: BTreeMap::new(),
+            ids: Ids::default(),
+        };
+        builder.build(desc)
+    }
+
+    // Checks that `text` is turned down with an error mentioning `expected`.
+    fn rejects(text: &str, expected: &str) {
+        match build(text) {
+            Ok(_) => panic!("accepted, expected `{}`", expected),
+            Err(msg) => assert!(msg.contains(expected), "`{}` lacks `{}`", msg, expected),
+        }
+    }
+
+    #[test]
+    fn loads_the_example_scenes() {
+        for name in ["cornell_smoke", "random_spheres", "textured_spheres"].iter() {
+            let file = format!("{}/../data/{}.json", env!("CARGO_MANIFEST_DIR"), name);
+            let scene = load(&file).unwrap_or_else(|e| panic!("{}", e));
+            assert!(!scene.world.objects.is_empty(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn rejects_an_unknown_texture() {
+        rejects(
+            r#"{
+                "materials": { "m": { "type": "lambertian", "texture": "wood" } },
+                "objects": []
+            }"#,
+            "materials.m: unknown texture `wood`",
+        );
+    }
+
+    #[test]
+    fn rejects_ior_together_with_dispersion() {
+        rejects(
+            r#"{
+                "materials": { "glass": { "type": "dielectric", "ior": 1.5,
+                    "dispersion": { "type": "cauchy", "a": 1.5, "b": 0.004 } } },
+                "objects": []
+            }"#,
+            "`ior` and `dispersion` are mutually exclusive",
+        );
+    }
+
+    #[test]
+    fn rejects_a_background_with_an_environment_map_or_sky() {
+        rejects(
+            r#"{
+                "background": [0.7, 0.8, 1.0],
+                "objects": [],
+                "lights": [{ "type": "environment_map", "file": "sky.hdr" }]
+            }"#,
+            "lights[0]: an environment map replaces `background`",
+        );
+        rejects(
+            r#"{
+                "background": [0.7, 0.8, 1.0],
+                "objects": [],
+                "lights": [{ "type": "sky", "sun_elevation": 30 }]
+            }"#,
+            "lights[0]: a sky replaces `background`",
+        );
+    }
+
+    #[test]
+    fn rejects_a_light_that_cant_be_sampled() {
+        rejects(
+            r#"{
+                "materials": { "lamp": { "type": "diffuse_light", "emit": [4, 4, 4] } },
+                "objects": [],
+                "lights": [
+                    { "type": "sphere", "center": [0, 2, 0], "radius": 1, "material": "lamp" },
+                    { "type": "translate", "offset": [0, 1, 0], "object":
+                        { "type": "box", "min": [0, 0, 0], "max": [1, 1, 1], "material": "lamp" } }
+                ]
+            }"#,
+            "lights[1].object: a `box` can't be sampled as a light",
+        );
+    }
+
+    #[test]
+    fn every_shape_gets_an_id_of_its_own() {
+        let scene = build(
+            r#"{
+                "materials": { "white": { "type": "lambertian", "albedo": [0.5, 0.5, 0.5] } },
+                "objects": [
+                    { "type": "bvh", "objects": [
+                        { "type": "sphere", "center": [0, 0, 0], "radius": 1, "material": "white" },
+                        { "type": "sphere", "center": [4, 0, 0], "radius": 1, "material": "white" }
+                    ] },
+                    { "type": "translate", "offset": [8, 0, 0], "object":
+                        { "type": "sphere", "center": [0, 0, 0], "radius": 1, "material": "white" } }
+                ]
+            }"#,
+        )
+        .unwrap();
+        let ids: Vec<u32> = [0.0, 4.0, 8.0]
+            .iter()
+            .map(|&x| {
+                let r = Ray::new(Vec3::new(x, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0), 0.0);
+                scene.world.hit(r, 0.001, f64::INFINITY).unwrap().id
+            })
+            .collect();
+        assert_eq!(ids, vec![1, 2, 3]);
+    }
+}
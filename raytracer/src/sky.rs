@@ -0,0 +1,279 @@
+use crate::envmap::{self, EnvironmentMap};
+use crate::framebuffer::FrameBuffer;
+use crate::light::{Light, LightSample};
+use crate::onb::Onb;
+use crate::spectrum::{cie_xyz, d65, xyz_to_srgb};
+use crate::tonemap::luminance;
+use crate::{clamp, Vec3};
+use std::f64::consts::PI;
+
+// Daylight from the analytic model of Preetham, Shirley and Smits (1999): the luminance and
+// chromaticity of the clear sky as functions of where the sun is and of the turbidity, how
+// hazy the air is (2 is very clear, 10 a hazy summer day). The model ends at the horizon;
+// below it is the ground, a diffuse surface of `ground_albedo` lit by the sky and the sun.
+// The sun isn't part of the sky, `sun` makes the light that goes with it.
+
+// cd/m^2 per unit of radiance; it puts the clear noon sky somewhere around 0.3 and a white
+// surface in full sun a little below 1
+const UNIT: f64 = 30_000.0;
+// above the atmosphere, in cd/m^2
+const SUN_LUMINANCE: f64 = 1.88e9;
+// as seen from the earth, in degrees
+pub const SUN_ANGULAR_RADIUS: f64 = 0.2666;
+// of the image the sky is baked into for sampling it
+const MAP_WIDTH: u32 = 128;
+const MAP_HEIGHT: u32 = 64;
+
+// The unit vector towards a sun `elevation` degrees above the horizon and `azimuth` degrees
+// around the y axis, from +x towards +z.
+pub fn sun_direction(elevation: f64, azimuth: f64) -> Vec3 {
+    let (elevation, azimuth) = (elevation.to_radians(), azimuth.to_radians());
+    Vec3::new(
+        elevation.cos() * azimuth.cos(),
+        elevation.sin(),
+        elevation.cos() * azimuth.sin(),
+    )
+}
+
+pub struct Sky {
+    pub sun_direction: Vec3,
+    pub turbidity: f64,
+    pub ground_albedo: Vec3,
+    pub intensity: f64,
+    // the sun's angle from the zenith
+    theta_sun: f64,
+    // the Perez coefficients A to E of the luminance Y and the chromaticities x and y
+    perez: [[f64; 5]; 3],
+    // Y, x and y at the zenith, Y in cd/m^2
+    zenith: [f64; 3],
+    sun: Vec3,
+    ground: Vec3,
+    // the sky baked into an image, to pick directions in proportion to it
+    map: EnvironmentMap,
+}
+
+impl Sky {
+    pub fn new(
+        elevation: f64,
+        azimuth: f64,
+        turbidity: f64,
+        ground_albedo: Vec3,
+        intensity: f64,
+    ) -> Self {
+        let t = turbidity;
+        let theta_sun = (90.0 - elevation).to_radians();
+        #[rustfmt::skip]
+        let perez = [
+            [0.1787 * t - 1.4630, -0.3554 * t + 0.4275, -0.0227 * t + 5.3251,
+             0.1206 * t - 2.5771, -0.0670 * t + 0.3703],
+            [-0.0193 * t - 0.2592, -0.0665 * t + 0.0008, -0.0004 * t + 0.2125,
+             -0.0641 * t - 0.8989, -0.0033 * t + 0.0452],
+            [-0.0167 * t - 0.2608, -0.0950 * t + 0.0092, -0.0079 * t + 0.2102,
+             -0.0441 * t - 1.6537, -0.0109 * t + 0.0529],
+        ];
+        let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * theta_sun);
+        let luminance = ((4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192) * 1000.0;
+        let chromaticity = |m: [[f64; 4]; 3]| {
+            let th = [theta_sun.powi(3), theta_sun.powi(2), theta_sun, 1.0];
+            let tt = [t * t, t, 1.0];
+            (0..3)
+                .map(|i| (0..4).map(|j| tt[i] * m[i][j] * th[j]).sum::<f64>())
+                .sum::<f64>()
+        };
+        #[rustfmt::skip]
+        let zenith = [
+            luminance,
+            chromaticity([
+                [0.00166, -0.00375, 0.00209, 0.0],
+                [-0.02903, 0.06377, -0.03202, 0.00394],
+                [0.11693, -0.21196, 0.06052, 0.25886],
+            ]),
+            chromaticity([
+                [0.00275, -0.00610, 0.00317, 0.0],
+                [-0.04214, 0.08970, -0.04153, 0.00516],
+                [0.15346, -0.26756, 0.06670, 0.26688],
+            ]),
+        ];
+        let mut sky = Self {
+            sun_direction: sun_direction(elevation, azimuth),
+            turbidity,
+            ground_albedo,
+            intensity,
+            theta_sun,
+            perez,
+            zenith,
+            sun: sun_radiance(theta_sun, turbidity),
+            // both filled in below
+            ground: Vec3::zero(),
+            map: EnvironmentMap::new(FrameBuffer::new(1, 1), 0.0, 1.0),
+        };
+
+        // the upper half first: the ground is lit by it
+        let mut image = FrameBuffer::new(MAP_WIDTH, MAP_HEIGHT);
+        let sun = SunLight::new(sky.sun_direction, sky.sun, SUN_ANGULAR_RADIUS);
+        let mut irradiance = sun.radiance * (sun.solid_angle() * sun.direction.y);
+        let pixel = 2.0 * PI * PI / (MAP_WIDTH * MAP_HEIGHT) as f64;
+        for y in 0..MAP_HEIGHT / 2 {
+            for x in 0..MAP_WIDTH {
+                let (u, v) = (
+                    (x as f64 + 0.5) / MAP_WIDTH as f64,
+                    (y as f64 + 0.5) / MAP_HEIGHT as f64,
+                );
+                let d = envmap::direction(u, v);
+                let l = sky.sky(d);
+                irradiance += l * (d.y * (PI * v).sin() * pixel);
+                image.set(x, y, l);
+            }
+        }
+        sky.ground = ground_albedo * irradiance / PI;
+        for y in MAP_HEIGHT / 2..MAP_HEIGHT {
+            for x in 0..MAP_WIDTH {
+                image.set(x, y, sky.ground);
+            }
+        }
+        sky.map = EnvironmentMap::new(image, 0.0, 1.0);
+        sky
+    }
+
+    // the sun disk that goes with the sky
+    pub fn sun(&self) -> SunLight {
+        SunLight::new(
+            self.sun_direction,
+            self.sun * self.intensity,
+            SUN_ANGULAR_RADIUS,
+        )
+    }
+
+    // the model's radiance from `w`, a unit vector above the horizon
+    fn sky(&self, w: Vec3) -> Vec3 {
+        let cos_theta = w.y.max(1e-3);
+        let gamma = clamp(Vec3::dot(w, self.sun_direction), -1.0, 1.0).acos();
+        let f = |i: usize| {
+            let [a, b, c, d, e] = self.perez[i];
+            let perez = |cos_theta: f64, gamma: f64| {
+                (1.0 + a * (b / cos_theta).exp())
+                    * (1.0 + c * (d * gamma).exp() + e * gamma.cos() * gamma.cos())
+            };
+            self.zenith[i] * perez(cos_theta, gamma) / perez(1.0, self.theta_sun)
+        };
+        let (luminance, x, y) = (f(0), f(1), f(2));
+        if luminance <= 0.0 || y <= 0.0 {
+            return Vec3::zero();
+        }
+        let xyz = Vec3::new(x / y, 1.0, (1.0 - x - y) / y) * luminance;
+        let rgb = xyz_to_srgb(xyz) / UNIT;
+        Vec3::new(rgb.x.max(0.0), rgb.y.max(0.0), rgb.z.max(0.0))
+    }
+}
+
+impl Light for Sky {
+    fn sample(&self, p: Vec3, u: (f64, f64)) -> Option<LightSample> {
+        let sample = self.map.sample(p, u)?;
+        let pdf = sample.pdf?;
+        Some(LightSample {
+            li: self.le(sample.wi) / pdf,
+            ..sample
+        })
+    }
+
+    fn le(&self, d: Vec3) -> Vec3 {
+        let d = Vec3::unit_vector(d);
+        let l = if d.y > 0.0 { self.sky(d) } else { self.ground };
+        l * self.intensity
+    }
+
+    fn pdf(&self, d: Vec3) -> f64 {
+        self.map.pdf(d)
+    }
+
+    fn power(&self, scene_radius: f64) -> f64 {
+        self.map.power(scene_radius) * self.intensity
+    }
+}
+
+// The sun's light after crossing the atmosphere, with Rayleigh scattering and the aerosols of
+// the turbidity taking their share of it along the way (the appendix of the same paper;
+// ozone and water vapour are left out). Above the atmosphere it's taken to be white.
+fn sun_radiance(theta_sun: f64, turbidity: f64) -> Vec3 {
+    // how much air it crosses, relative to straight down
+    let mass = 1.0 / (theta_sun.cos() + 0.15 * (93.885 - theta_sun.to_degrees()).powf(-1.253));
+    let beta = 0.04608 * turbidity - 0.04586;
+    let (mut xyz, mut white) = (Vec3::zero(), 0.0);
+    for i in 0..=40 {
+        let lambda = 380.0 + 10.0 * i as f64;
+        let micrometers = lambda / 1000.0;
+        let depth = 0.008735 * micrometers.powf(-4.08) + beta * micrometers.powf(-1.3);
+        let c = cie_xyz(lambda) * d65(lambda);
+        xyz += c * (-depth * mass).exp();
+        white += c.y;
+    }
+    xyz_to_srgb(xyz / white) * (SUN_LUMINANCE / UNIT)
+}
+
+// A disk of `radiance` in the sky, `angular_radius` degrees across around `direction` (the way
+// towards it). Unlike a directional light with an angular radius, rays that leave the scene
+// can see it.
+pub struct SunLight {
+    pub direction: Vec3,
+    pub radiance: Vec3,
+    pub angular_radius: f64,
+}
+
+impl SunLight {
+    pub fn new(direction: Vec3, radiance: Vec3, angular_radius: f64) -> Self {
+        Self {
+            direction: Vec3::unit_vector(direction),
+            radiance,
+            angular_radius,
+        }
+    }
+
+    fn solid_angle(&self) -> f64 {
+        let s = (self.angular_radius.to_radians() / 2.0).sin();
+        4.0 * PI * s * s
+    }
+
+    fn inside(&self, d: Vec3) -> bool {
+        Vec3::dot(Vec3::unit_vector(d), self.direction) >= self.angular_radius.to_radians().cos()
+    }
+}
+
+impl Light for SunLight {
+    fn sample(&self, _p: Vec3, u: (f64, f64)) -> Option<LightSample> {
+        // uniform over the cone, 1 - cos written so that it survives a tiny radius
+        let one_minus_cos_max = self.solid_angle() / (2.0 * PI);
+        let cos_theta = 1.0 - u.0 * one_minus_cos_max;
+        let sin_theta = (u.0 * one_minus_cos_max * (1.0 + cos_theta))
+            .max(0.0)
+            .sqrt();
+        let phi = 2.0 * PI * u.1;
+        let frame = Onb::new_from_w(self.direction);
+        let pdf = 1.0 / self.solid_angle();
+        Some(LightSample {
+            wi: frame.local(phi.cos() * sin_theta, phi.sin() * sin_theta, cos_theta),
+            distance: f64::INFINITY,
+            li: self.radiance / pdf,
+            pdf: Some(pdf),
+        })
+    }
+
+    fn le(&self, d: Vec3) -> Vec3 {
+        if self.inside(d) {
+            self.radiance
+        } else {
+            Vec3::zero()
+        }
+    }
+
+    fn pdf(&self, d: Vec3) -> f64 {
+        if self.inside(d) {
+            1.0 / self.solid_angle()
+        } else {
+            0.0
+        }
+    }
+
+    fn power(&self, scene_radius: f64) -> f64 {
+        PI * scene_radius * scene_radius * luminance(self.radiance) * self.solid_angle()
+    }
+}
@@ -0,0 +1,270 @@
+use crate::Vec3;
+use std::cell::Cell;
+use std::str::FromStr;
+
+// Spectral rendering. Instead of RGB, a path carries its radiance at three wavelengths: a
+// hero wavelength drawn uniformly from the visible range and two more a third of the range
+// further on, wrapping around (hero wavelength sampling, Wilkie et al. 2014). They travel in
+// the x, y and z of the same `Vec3`s the RGB renderer uses. Colors are turned into spectra
+// where they enter a path and the radiance into XYZ where it leaves.
+
+pub const LAMBDA_MIN: f64 = 380.0;
+pub const LAMBDA_MAX: f64 = 780.0;
+
+// the Fraunhofer d line, where glass catalogs quote n_d; dispersive glass has that index
+// when rendering in RGB
+pub const D_LINE: f64 = 587.56;
+
+thread_local! {
+    static WAVELENGTHS: Cell<Option<Wavelengths>> = Cell::new(None);
+}
+
+// The wavelengths the current path is traced at, for whatever scatters depending on them;
+// None when rendering in RGB.
+pub fn wavelengths() -> Option<Wavelengths> {
+    WAVELENGTHS.with(|w| w.get())
+}
+
+pub fn set_wavelengths(wavelengths: Option<Wavelengths>) {
+    WAVELENGTHS.with(|w| w.set(wavelengths));
+}
+
+// The first of them, the one dispersive materials refract.
+pub fn hero_wavelength() -> Option<f64> {
+    wavelengths().map(|w| w.lambda[0])
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Wavelengths {
+    pub lambda: [f64; 3],
+}
+
+impl Wavelengths {
+    pub fn sample(u: f64) -> Self {
+        let range = LAMBDA_MAX - LAMBDA_MIN;
+        let hero = LAMBDA_MIN + u * range;
+        let mut lambda = [hero; 3];
+        for (j, l) in lambda.iter_mut().enumerate().skip(1) {
+            *l = hero + j as f64 * range / 3.0;
+            if *l > LAMBDA_MAX {
+                *l -= range;
+            }
+        }
+        Self { lambda }
+    }
+
+    fn map(&self, f: impl Fn(f64) -> f64) -> Vec3 {
+        Vec3::new(f(self.lambda[0]), f(self.lambda[1]), f(self.lambda[2]))
+    }
+
+    // An RGB reflectance, or anything else that scales one, at each wavelength.
+    pub fn reflectance(&self, c: Vec3) -> Vec3 {
+        self.map(|l| smits(c, l))
+    }
+
+    // An RGB emission: its reflectance spectrum lit by D65, so that (1, 1, 1) is white.
+    pub fn illuminant(&self, c: Vec3) -> Vec3 {
+        self.map(|l| smits(c, l) * d65(l))
+    }
+
+    // Radiance at the wavelengths to the XYZ of the whole spectrum: each wavelength is a
+    // uniform sample of the range, so XYZ is the mean of L * cmf / pdf over the three.
+    pub fn to_xyz(&self, l: Vec3) -> Vec3 {
+        let range = LAMBDA_MAX - LAMBDA_MIN;
+        let sum = cie_xyz(self.lambda[0]) * l.x
+            + cie_xyz(self.lambda[1]) * l.y
+            + cie_xyz(self.lambda[2]) * l.z;
+        sum * (range / 3.0 / CIE_Y_D65)
+    }
+}
+
+// ∫ D65(λ) ȳ(λ) dλ over the range, so that a white emitter has a luminance Y of 1
+const CIE_Y_D65: f64 = 105.693_624_880_524;
+
+fn lobe(x: f64, mu: f64, sigma_below: f64, sigma_above: f64) -> f64 {
+    let sigma = if x < mu { sigma_below } else { sigma_above };
+    let t = (x - mu) / sigma;
+    (-0.5 * t * t).exp()
+}
+
+// The CIE 1931 2° color matching functions, as the multi-lobe fit of Wyman et al. 2013.
+pub fn cie_xyz(lambda: f64) -> Vec3 {
+    Vec3::new(
+        1.056 * lobe(lambda, 599.8, 37.9, 31.0) + 0.362 * lobe(lambda, 442.0, 16.0, 26.7)
+            - 0.065 * lobe(lambda, 501.1, 20.4, 26.2),
+        0.821 * lobe(lambda, 568.8, 46.9, 40.5) + 0.286 * lobe(lambda, 530.9, 16.3, 31.1),
+        1.217 * lobe(lambda, 437.0, 11.8, 36.0) + 0.681 * lobe(lambda, 459.0, 26.0, 13.8),
+    )
+}
+
+// CIE standard illuminant D65 from 380 to 780 nm in 10 nm steps, 1 at 560 nm.
+const D65: [f64; 41] = [
+    0.4998, 0.5465, 0.8275, 0.9149, 0.9343, 0.8668, 1.0486, 1.1701, 1.1781, 1.1486, 1.1592, 1.0881,
+    1.0935, 1.0780, 1.0479, 1.0769, 1.0441, 1.0405, 1.0000, 0.9633, 0.9579, 0.8869, 0.9001, 0.8960,
+    0.8770, 0.8329, 0.8370, 0.8003, 0.8021, 0.8228, 0.7828, 0.6972, 0.7161, 0.7435, 0.6160, 0.6989,
+    0.7509, 0.6359, 0.4642, 0.6681, 0.6338,
+];
+
+pub fn d65(lambda: f64) -> f64 {
+    let t = crate::clamp((lambda - 380.0) / 10.0, 0.0, 40.0);
+    let i = (t as usize).min(39);
+    let f = t - i as f64;
+    D65[i] * (1.0 - f) + D65[i + 1] * f
+}
+
+// Smits' basis spectra (1999) in 10 bins from 380 to 720 nm: white, cyan, magenta, yellow,
+// red, green and blue.
+#[rustfmt::skip]
+const SMITS: [[f64; 10]; 7] = [
+    [1.0000, 1.0000, 0.9999, 0.9993, 0.9992, 0.9998, 1.0000, 1.0000, 1.0000, 1.0000],
+    [0.9710, 0.9426, 1.0007, 1.0007, 1.0007, 1.0007, 0.1564, 0.0000, 0.0000, 0.0000],
+    [1.0000, 1.0000, 0.9685, 0.2229, 0.0000, 0.0458, 0.8369, 1.0000, 1.0000, 0.9959],
+    [0.0001, 0.0000, 0.1088, 0.6651, 1.0000, 1.0000, 0.9996, 0.9586, 0.9685, 0.9840],
+    [0.1012, 0.0515, 0.0000, 0.0000, 0.0000, 0.0000, 0.8325, 1.0149, 1.0149, 1.0149],
+    [0.0000, 0.0000, 0.0273, 0.7937, 1.0000, 0.9418, 0.1719, 0.0000, 0.0000, 0.0025],
+    [1.0000, 1.0000, 0.8916, 0.3323, 0.0000, 0.0000, 0.0003, 0.0369, 0.0483, 0.0496],
+];
+const WHITE: usize = 0;
+const CYAN: usize = 1;
+const MAGENTA: usize = 2;
+const YELLOW: usize = 3;
+const RED: usize = 4;
+const GREEN: usize = 5;
+const BLUE: usize = 6;
+
+// a basis spectrum interpolated between the bin centers
+fn basis(spectrum: usize, lambda: f64) -> f64 {
+    let t = crate::clamp((lambda - 380.0) / 34.0 - 0.5, 0.0, 9.0);
+    let i = (t as usize).min(8);
+    let f = t - i as f64;
+    SMITS[spectrum][i] * (1.0 - f) + SMITS[spectrum][i + 1] * f
+}
+
+// Smits' RGB to spectrum conversion: the smallest component as white, the difference to the
+// middle one as the secondary color of the other two, the rest as the largest primary.
+// Scaling the color scales the spectrum, so it also works for anything proportional to one.
+pub fn smits(c: Vec3, lambda: f64) -> f64 {
+    let (r, g, b) = (c.x, c.y, c.z);
+    let s = |spectrum: usize| basis(spectrum, lambda);
+    if r <= g && r <= b {
+        r * s(WHITE)
+            + if g <= b {
+                (g - r) * s(CYAN) + (b - g) * s(BLUE)
+            } else {
+                (b - r) * s(CYAN) + (g - b) * s(GREEN)
+            }
+    } else if g <= r && g <= b {
+        g * s(WHITE)
+            + if r <= b {
+                (r - g) * s(MAGENTA) + (b - r) * s(BLUE)
+            } else {
+                (b - g) * s(MAGENTA) + (r - b) * s(RED)
+            }
+    } else {
+        b * s(WHITE)
+            + if r <= g {
+                (r - b) * s(YELLOW) + (g - r) * s(GREEN)
+            } else {
+                (g - b) * s(YELLOW) + (r - g) * s(RED)
+            }
+    }
+}
+
+// Wavelength-dependent index of refraction. As in glass catalogs, the formulas take the
+// wavelength in micrometers.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Dispersion {
+    // n = a + b / λ²
+    Cauchy { a: f64, b: f64 },
+    // n² = 1 + Σ b_i λ² / (λ² - c_i)
+    Sellmeier { b: [f64; 3], c: [f64; 3] },
+}
+
+impl Dispersion {
+    pub fn ior(&self, lambda_nm: f64) -> f64 {
+        let l2 = (lambda_nm / 1000.0) * (lambda_nm / 1000.0);
+        match self {
+            Dispersion::Cauchy { a, b } => a + b / l2,
+            Dispersion::Sellmeier { b, c } => {
+                let n2 = 1.0 + (0..3).map(|i| b[i] * l2 / (l2 - c[i])).sum::<f64>();
+                n2.max(1.0).sqrt()
+            }
+        }
+    }
+}
+
+// The primaries float output is written in.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ColorSpace {
+    Srgb,
+    Rec2020,
+    AcesCg,
+    Xyz,
+}
+
+pub const COLOR_SPACES: [&str; 4] = ["srgb", "rec2020", "acescg", "xyz"];
+
+impl FromStr for ColorSpace {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "srgb" | "rec709" => Ok(ColorSpace::Srgb),
+            "rec2020" => Ok(ColorSpace::Rec2020),
+            "acescg" => Ok(ColorSpace::AcesCg),
+            "xyz" => Ok(ColorSpace::Xyz),
+            _ => Err(format!(
+                "unknown color space `{}` (expected one of {})",
+                s,
+                COLOR_SPACES.join(", ")
+            )),
+        }
+    }
+}
+
+type Matrix = [[f64; 3]; 3];
+
+fn mul(m: &Matrix, v: Vec3) -> Vec3 {
+    Vec3::new(
+        m[0][0] * v.x + m[0][1] * v.y + m[0][2] * v.z,
+        m[1][0] * v.x + m[1][1] * v.y + m[1][2] * v.z,
+        m[2][0] * v.x + m[2][1] * v.y + m[2][2] * v.z,
+    )
+}
+
+const SRGB_TO_XYZ: Matrix = [
+    [0.412_456_4, 0.357_576_1, 0.180_437_5],
+    [0.212_672_9, 0.715_152_2, 0.072_175_0],
+    [0.019_333_9, 0.119_192_0, 0.950_304_1],
+];
+const XYZ_TO_SRGB: Matrix = [
+    [3.240_454_2, -1.537_138_5, -0.498_531_4],
+    [-0.969_266_0, 1.876_010_8, 0.041_556_0],
+    [0.055_643_4, -0.204_025_9, 1.057_225_2],
+];
+const XYZ_TO_REC2020: Matrix = [
+    [1.716_651_2, -0.355_670_8, -0.253_366_3],
+    [-0.666_684_4, 1.616_481_2, 0.015_768_5],
+    [0.017_639_9, -0.042_770_6, 0.942_103_1],
+];
+// ACES AP1 primaries, with a Bradford adaptation from D65 to the ACES white point
+const XYZ_TO_ACESCG: Matrix = [
+    [1.660_585_3, -0.315_295_6, -0.241_509_3],
+    [-0.659_926_1, 1.608_391_5, 0.017_298_6],
+    [0.009_002_6, -0.003_566_9, 0.913_643_3],
+];
+
+// The linear sRGB (Rec. 709) the renderer works in.
+pub fn xyz_to_srgb(xyz: Vec3) -> Vec3 {
+    mul(&XYZ_TO_SRGB, xyz)
+}
+
+impl ColorSpace {
+    pub fn from_srgb(&self, c: Vec3) -> Vec3 {
+        match self {
+            ColorSpace::Srgb => c,
+            ColorSpace::Rec2020 => mul(&XYZ_TO_REC2020, mul(&SRGB_TO_XYZ, c)),
+            ColorSpace::AcesCg => mul(&XYZ_TO_ACESCG, mul(&SRGB_TO_XYZ, c)),
+            ColorSpace::Xyz => mul(&SRGB_TO_XYZ, c),
+        }
+    }
+}
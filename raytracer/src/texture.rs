@@ -7,6 +7,11 @@ use std::sync::Arc;
 pub trait Texture: Send + Sync {
     fn value(&self, u: f64, v: f64, p: &mut Vec3) -> Vec3;
 }
+impl<T: Texture + ?Sized> Texture for Arc<T> {
+    fn value(&self, u: f64, v: f64, p: &mut Vec3) -> Vec3 {
+        (**self).value(u, v, p)
+    }
+}
 #[derive(Copy, Clone)]
 pub struct SolidColor {
     pub color_value: Vec3,
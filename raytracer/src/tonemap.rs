@@ -0,0 +1,206 @@
+use crate::{clamp, Vec3};
+use std::str::FromStr;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ToneMapper {
+    // no curve, values above 1 are clipped
+    Clamp,
+    Reinhard,
+    ReinhardExtended,
+    Aces,
+    Hable,
+    Agx,
+}
+
+pub const TONE_MAPPERS: [&str; 6] = [
+    "clamp",
+    "reinhard",
+    "reinhard-extended",
+    "aces",
+    "hable",
+    "agx",
+];
+
+impl FromStr for ToneMapper {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "clamp" | "none" => Ok(ToneMapper::Clamp),
+            "reinhard" => Ok(ToneMapper::Reinhard),
+            "reinhard-extended" => Ok(ToneMapper::ReinhardExtended),
+            "aces" => Ok(ToneMapper::Aces),
+            "hable" | "uncharted2" => Ok(ToneMapper::Hable),
+            "agx" => Ok(ToneMapper::Agx),
+            _ => Err(format!(
+                "unknown tone mapper `{}` (expected one of {})",
+                s,
+                TONE_MAPPERS.join(", ")
+            )),
+        }
+    }
+}
+
+// Turns linear scene radiance into display-referred sRGB in 0..1, ready for quantization.
+#[derive(Copy, Clone, Debug)]
+pub struct DisplayTransform {
+    pub tone_mapper: ToneMapper,
+    // in stops, the radiance is scaled by 2^exposure before the curve
+    pub exposure: f64,
+    // luminance mapped to pure white by reinhard-extended; None picks the brightest pixel
+    pub white: Option<f64>,
+}
+
+impl Default for DisplayTransform {
+    fn default() -> Self {
+        Self {
+            tone_mapper: ToneMapper::Clamp,
+            exposure: 0.0,
+            white: None,
+        }
+    }
+}
+
+impl DisplayTransform {
+    // `max_luminance` is the brightest pixel of the image, before exposure.
+    pub fn apply(&self, c: Vec3, max_luminance: f64) -> Vec3 {
+        let scale = 2f64.powf(self.exposure);
+        // NaNs from degenerate paths would otherwise poison the whole curve
+        let c = Vec3::new(finite(c.x), finite(c.y), finite(c.z)) * scale;
+        let mapped = match self.tone_mapper {
+            ToneMapper::Clamp => c,
+            ToneMapper::Reinhard => {
+                let l = luminance(c);
+                c * (1.0 / (1.0 + l))
+            }
+            ToneMapper::ReinhardExtended => {
+                let white = self.white.unwrap_or(max_luminance * scale).max(1e-6);
+                let l = luminance(c);
+                c * ((1.0 + l / (white * white)) / (1.0 + l))
+            }
+            ToneMapper::Aces => aces(c),
+            ToneMapper::Hable => {
+                let w = 11.2;
+                let white_scale = 1.0 / hable_partial(w);
+                let exposure_bias = 2.0;
+                Vec3::new(
+                    hable_partial(c.x * exposure_bias),
+                    hable_partial(c.y * exposure_bias),
+                    hable_partial(c.z * exposure_bias),
+                ) * white_scale
+            }
+            ToneMapper::Agx => agx(c),
+        };
+        Vec3::new(
+            srgb_oetf(clamp(mapped.x, 0.0, 1.0)),
+            srgb_oetf(clamp(mapped.y, 0.0, 1.0)),
+            srgb_oetf(clamp(mapped.z, 0.0, 1.0)),
+        )
+    }
+}
+
+fn finite(v: f64) -> f64 {
+    if v.is_finite() {
+        v.max(0.0)
+    } else {
+        0.0
+    }
+}
+
+// Rec. 709 weights, the primaries the renderer implicitly works in
+pub fn luminance(c: Vec3) -> f64 {
+    0.2126 * c.x + 0.7152 * c.y + 0.0722 * c.z
+}
+
+pub fn srgb_oetf(v: f64) -> f64 {
+    if v <= 0.003_130_8 {
+        12.92 * v
+    } else {
+        1.055 * v.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+fn mat3(m: [[f64; 3]; 3], c: Vec3) -> Vec3 {
+    Vec3::new(
+        m[0][0] * c.x + m[0][1] * c.y + m[0][2] * c.z,
+        m[1][0] * c.x + m[1][1] * c.y + m[1][2] * c.z,
+        m[2][0] * c.x + m[2][1] * c.y + m[2][2] * c.z,
+    )
+}
+
+// Stephen Hill's fit of the ACES RRT + sRGB ODT, including the AP1 round trip.
+fn aces(c: Vec3) -> Vec3 {
+    const INPUT: [[f64; 3]; 3] = [
+        [0.59719, 0.35458, 0.04823],
+        [0.07600, 0.90834, 0.01566],
+        [0.02840, 0.13383, 0.83777],
+    ];
+    const OUTPUT: [[f64; 3]; 3] = [
+        [1.60475, -0.53108, -0.07367],
+        [-0.10208, 1.10813, -0.00605],
+        [-0.00327, -0.07276, 1.07602],
+    ];
+    let fit = |v: f64| {
+        (v * (v + 0.024_578_6) - 0.000_090_537) / (v * (0.983_729 * v + 0.432_951) + 0.238_081)
+    };
+    let v = mat3(INPUT, c);
+    mat3(OUTPUT, Vec3::new(fit(v.x), fit(v.y), fit(v.z)))
+}
+
+// John Hable's Uncharted 2 curve.
+fn hable_partial(x: f64) -> f64 {
+    let (a, b, c, d, e, f) = (0.15, 0.50, 0.10, 0.20, 0.02, 0.30);
+    ((x * (a * x + c * b) + d * e) / (x * (a * x + b) + d * f)) - e / f
+}
+
+// Minimal AgX (Benjamin Wrensch's fit of Troy Sobotka's curve), base look.
+fn agx(c: Vec3) -> Vec3 {
+    const INSET: [[f64; 3]; 3] = [
+        [
+            0.842_479_062_253_094,
+            0.078_433_599_999_999_2,
+            0.079_223_745_147_764_3,
+        ],
+        [
+            0.042_328_242_261_012_3,
+            0.878_468_636_469_772,
+            0.079_166_127_460_543_4,
+        ],
+        [0.042_375_654_905_705_1, 0.078_433_6, 0.879_142_973_793_104],
+    ];
+    const OUTSET: [[f64; 3]; 3] = [
+        [
+            1.196_879_005_120_17,
+            -0.098_020_881_140_136_8,
+            -0.099_029_744_079_720_5,
+        ],
+        [
+            -0.052_896_851_757_456_2,
+            1.151_903_129_904_17,
+            -0.098_961_176_844_843_3,
+        ],
+        [
+            -0.052_971_635_514_443_8,
+            -0.098_043_450_117_124_1,
+            1.151_073_672_641_16,
+        ],
+    ];
+    let (min_ev, max_ev) = (-12.473_931_188, 4.026_068_812);
+    let contrast = |v: f64| {
+        let v = (clamp(v.max(1e-10).log2(), min_ev, max_ev) - min_ev) / (max_ev - min_ev);
+        let x2 = v * v;
+        let x4 = x2 * x2;
+        15.5 * x4 * x2 - 40.14 * x4 * v + 31.96 * x4 - 6.868 * x2 * v + 0.4298 * x2 + 0.1191 * v
+            - 0.00232
+    };
+    let v = mat3(INSET, c);
+    let v = mat3(
+        OUTSET,
+        Vec3::new(contrast(v.x), contrast(v.y), contrast(v.z)),
+    );
+    // the curve ends in a 2.2 display encoding, undo it so the sRGB OETF can be applied
+    Vec3::new(
+        v.x.max(0.0).powf(2.2),
+        v.y.max(0.0).powf(2.2),
+        v.z.max(0.0).powf(2.2),
+    )
+}
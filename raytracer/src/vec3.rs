@@ -1,3 +1,4 @@
+use crate::sampler::sample_2d;
 use crate::{random_double, random_double_lim};
 use std::f64::consts::PI;
 use std::ops::{Add, AddAssign, Div, DivAssign, Mul, MulAssign, Sub, SubAssign};
@@ -47,6 +48,9 @@ impl Vec3 {
         let s: f64 = 1e-8;
         self.x < s && self.y < s && self.z < s
     }
+    pub fn max_component(&self) -> f64 {
+        self.x.max(self.y).max(self.z)
+    }
     pub fn new(x: f64, y: f64, z: f64) -> Self {
         Self { x, y, z }
     }
@@ -280,8 +284,7 @@ impl Vec3 {
 
 impl Vec3 {
     pub fn random_to_sphere(radius: f64, distance_squared: f64) -> Vec3 {
-        let r1 = random_double();
-        let r2 = random_double();
+        let (r1, r2) = sample_2d();
         let z = 1.0 + r2 * ((1.0 - radius * radius / distance_squared).abs().sqrt() - 1.0);
 
         let phi = 2.0 * PI * r1;
@@ -337,18 +340,20 @@ impl Vec3 {
         r_out_perp + r_out_parallel
     }
 
+    // Shirley's concentric mapping rather than rejection, so one 2D sample is always enough
+    // and stratified samples stay stratified on the disk.
     pub fn random_in_unit_disk() -> Vec3 {
-        loop {
-            let p: Vec3 = Vec3::new(
-                random_double_lim(-1.0, 1.0),
-                random_double_lim(-1.0, 1.0),
-                0.0,
-            );
-            if p.length_squared() >= 1.0 {
-                continue;
-            }
-            return p;
+        let (u, v) = sample_2d();
+        let (a, b) = (2.0 * u - 1.0, 2.0 * v - 1.0);
+        if a == 0.0 && b == 0.0 {
+            return Vec3::new(0.0, 0.0, 0.0);
         }
+        let (r, theta) = if a.abs() > b.abs() {
+            (a, PI / 4.0 * (b / a))
+        } else {
+            (b, PI / 2.0 - PI / 4.0 * (a / b))
+        };
+        Vec3::new(r * theta.cos(), r * theta.sin(), 0.0)
     }
 }
 // #[cfg(test)]
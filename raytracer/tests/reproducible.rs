@@ -0,0 +1,90 @@
+use std::path::PathBuf;
+use std::process::Command;
+
+// The same seed has to give the same image however many threads render it.
+
+fn scene() -> String {
+    format!("{}/../data/cornell_smoke.json", env!("CARGO_MANIFEST_DIR"))
+}
+
+fn output(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("raytracer-{}-{}.pfm", std::process::id(), name))
+}
+
+// Renders the scene with `args` and returns the pixels of the PFM it wrote.
+fn render(name: &str, threads: u32, args: &[&str]) -> Vec<f32> {
+    let out = output(name);
+    let status = Command::new(env!("CARGO_BIN_EXE_raytracer"))
+        .args([
+            "-f",
+            &scene(),
+            "-w",
+            "24",
+            "--height",
+            "24",
+            "-n",
+            "8",
+            "-d",
+            "8",
+        ])
+        .args(["--seed", "7", "-t", &threads.to_string()])
+        .args(args)
+        .arg("-o")
+        .arg(&out)
+        .status()
+        .expect("failed to run the renderer");
+    assert!(status.success(), "the render failed");
+    let bytes = std::fs::read(&out).expect("no image was written");
+    let _ = std::fs::remove_file(&out);
+    pixels(&bytes)
+}
+
+// the floats of a PFM file: three lines of header, then the raster
+fn pixels(bytes: &[u8]) -> Vec<f32> {
+    let mut offset = 0;
+    for _ in 0..3 {
+        offset += bytes[offset..].iter().position(|b| *b == b'\n').unwrap() + 1;
+    }
+    let header = String::from_utf8_lossy(&bytes[..offset]);
+    let little_endian = header.lines().nth(2).unwrap().trim().starts_with('-');
+    bytes[offset..]
+        .chunks(4)
+        .map(|c| {
+            let b = [c[0], c[1], c[2], c[3]];
+            if little_endian {
+                f32::from_le_bytes(b)
+            } else {
+                f32::from_be_bytes(b)
+            }
+        })
+        .collect()
+}
+
+fn assert_same(a: &[f32], b: &[f32]) {
+    assert_eq!(a.len(), b.len());
+    assert!(a.iter().any(|v| *v > 0.0), "the image is black");
+    for (i, (x, y)) in a.iter().zip(b.iter()).enumerate() {
+        assert!(
+            x.to_bits() == y.to_bits(),
+            "value {} differs: {} vs {}",
+            i,
+            x,
+            y
+        );
+    }
+}
+
+#[test]
+fn threads_give_the_same_image() {
+    let one = render("one", 1, &[]);
+    let three = render("three", 3, &[]);
+    assert_same(&one, &three);
+}
+
+#[test]
+fn threads_give_the_same_adaptive_image() {
+    let args = ["--adaptive", "--min-spp", "4", "--max-spp", "16"];
+    let one = render("adaptive-one", 1, &args);
+    let three = render("adaptive-three", 3, &args);
+    assert_same(&one, &three);
+}